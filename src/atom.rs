@@ -122,6 +122,10 @@ impl Atom {
         }
     }
 
+    pub fn blocker(&self) -> Option<Blocker> {
+        self.block
+    }
+
     pub fn slot(&self) -> Option<&str> {
         self.slot.as_deref()
     }
@@ -138,13 +142,34 @@ impl Atom {
         self.repo.as_deref()
     }
 
+    /// Return a copy of the atom with its category and package replaced.
+    pub(crate) fn with_key(&self, category: &str, package: &str) -> Self {
+        Atom {
+            category: category.to_string(),
+            package: package.to_string(),
+            ..self.clone()
+        }
+    }
+
+    /// Return a copy of the atom with its slot replaced.
+    pub(crate) fn with_slot(&self, slot: &str) -> Self {
+        Atom {
+            slot: Some(slot.to_string()),
+            ..self.clone()
+        }
+    }
+
     pub fn env(&self, var: &str) -> Result<String> {
         match self.version() {
             Some(v) => match var {
                 "P" => Ok(format!("{}-{}", self.package, v.base())),
                 "PN" => Ok(self.package.clone()),
                 "PV" => Ok(v.base().into()),
-                "PR" => Ok(format!("r{}", v.revision().unwrap())),
+                "PR" => match v.revision() {
+                    Some(r) => Ok(format!("r{r}")),
+                    // unrevisioned packages default to r0
+                    None => Ok("r0".to_string()),
+                },
                 "PVR" => Ok(v.as_str().into()),
                 "PF" => Ok(format!("{}-{v}", self.package)),
                 "CATEGORY" => Ok(self.category.clone()),
@@ -318,6 +343,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_atom_env() {
+        let atom = Atom::from_str("=cat/pkg-1-r2").unwrap();
+        assert_eq!(atom.env("PR").unwrap(), "r2");
+        assert_eq!(atom.env("PF").unwrap(), "pkg-1-r2");
+        // unrevisioned packages default to r0
+        let atom = Atom::from_str("=cat/pkg-1").unwrap();
+        assert_eq!(atom.env("PR").unwrap(), "r0");
+        assert_eq!(atom.env("PVR").unwrap(), "1");
+        assert!(Atom::from_str("cat/pkg").unwrap().env("PR").is_err());
+    }
+
     #[test]
     fn test_atom_cpv() {
         let mut atom: Atom;
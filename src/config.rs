@@ -5,8 +5,12 @@ use std::sync::{Arc, RwLock};
 
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
+use tracing::warn;
 
 use crate::macros::build_from_paths;
+use crate::repo::changes::RepoChanges;
+use crate::repo::search::SearchIndex;
+use crate::sync::ProgressFn;
 use crate::{Error, Result};
 
 mod repo;
@@ -97,7 +101,7 @@ static CURRENT_CONFIG: Lazy<RwLock<Arc<Config>>> = Lazy::new(|| RwLock::new(Defa
 impl Config {
     pub fn new(name: &str, prefix: &str, create: bool) -> Result<Config> {
         let path = ConfigPath::new(name, prefix, create)?;
-        let repos = repo::Config::new(&path.config, &path.db, create)?;
+        let repos = repo::Config::new(&path.config, &path.db, &path.cache, create)?;
         let config = Config { path, repos };
         Config::make_current(config.clone());
         Ok(config)
@@ -110,6 +114,40 @@ impl Config {
     pub fn make_current(config: Config) {
         *CURRENT_CONFIG.write().unwrap() = Arc::new(config)
     }
+
+    /// Sync the given repos, or all configured repos if none are passed, and update the search
+    /// index for the ones that were synced. The changes to each synced repo are returned.
+    pub fn sync<S: AsRef<str>>(&mut self, repos: Vec<S>) -> Result<Vec<RepoChanges>> {
+        self.sync_with(repos, 0, &|_| ())
+    }
+
+    /// Sync repos concurrently with a given job limit and progress callback, see
+    /// [`Config::sync`].
+    pub fn sync_with<S: AsRef<str>>(
+        &mut self,
+        repos: Vec<S>,
+        jobs: usize,
+        progress: ProgressFn,
+    ) -> Result<Vec<RepoChanges>> {
+        let names: Vec<String> = match &repos {
+            names if !names.is_empty() => names.iter().map(|s| s.as_ref().to_string()).collect(),
+            _ => self.repos.configs.keys().cloned().collect(),
+        };
+        let result = self.repos.sync_with(repos, jobs, progress);
+
+        // index failures are logged so they don't replace the sync result
+        if !self.path.cache.as_os_str().is_empty() {
+            let names: Vec<&String> = names
+                .iter()
+                .filter(|s| self.repos.repos.contains_key(s.as_str()))
+                .collect();
+            if let Err(e) = SearchIndex::update_config(self, &names) {
+                warn!("failed updating search index: {e}");
+            }
+        }
+
+        result
+    }
 }
 
 #[cfg(test)]
@@ -152,4 +190,13 @@ mod tests {
         let config = Config::new("pkgcraft", "", false).unwrap();
         assert_eq!(config.path.config, PathBuf::from("/etc/pkgcraft"));
     }
+
+    #[test]
+    fn test_sync() {
+        // search index failures don't affect the sync result
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut config = Config::default();
+        config.path.cache = file.path().to_path_buf();
+        assert!(config.sync(Vec::<&str>::new()).unwrap().is_empty());
+    }
 }
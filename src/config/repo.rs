@@ -6,12 +6,16 @@ use std::str::FromStr;
 use std::sync::Arc;
 
 use indexmap::IndexMap;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use tracing::warn;
 
+use crate::repo::changes::{RepoChanges, Snapshot};
 use crate::repo::ebuild::TempRepo;
+use crate::repo::index::git_head;
+use crate::repo::set::RepoSet;
 use crate::repo::Repo;
-use crate::sync::Syncer;
+use crate::sync::{self, Hook, HookContext, Progress, ProgressFn, SyncPhase, SyncStatus, Syncer};
 use crate::{Error, Result};
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -20,6 +24,11 @@ pub struct RepoConfig {
     pub format: String,
     pub priority: i32,
     sync: Option<Syncer>,
+    /// OpenPGP keyring used to verify synced content, unverified if unset.
+    pub keyring: Option<PathBuf>,
+    /// Actions run after syncing the repo, before any global hooks.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<Hook>,
 }
 
 impl RepoConfig {
@@ -37,10 +46,23 @@ impl RepoConfig {
         Ok(repo_conf)
     }
 
-    fn sync(&self) -> Result<()> {
+    /// Load the repo, using persistent data within a given cache directory if one exists.
+    fn load(&self, name: &str, cache: Option<&Path>) -> Result<Repo> {
+        let mut repo = Repo::from_format(name, &self.location, &self.format)?;
+        if let Some(path) = cache {
+            repo.set_cache_dir(path);
+        }
+        Ok(repo)
+    }
+
+    /// Sync the repo, returning false if it was already up to date.
+    fn sync(&self, cache: Option<&Path>, progress: &Progress) -> Result<bool> {
         match &self.sync {
-            Some(syncer) => syncer.sync(&self.location),
-            None => Ok(()),
+            Some(syncer) => {
+                let keyring = self.keyring.as_deref();
+                syncer.sync(&self.location, &self.format, keyring, cache, progress)
+            }
+            None => Ok(false),
         }
     }
 }
@@ -61,19 +83,49 @@ impl Ord for RepoConfig {
     }
 }
 
+/// Global sync settings loaded from sync.toml in the config dir.
+#[derive(Debug, Default, Deserialize)]
+struct SyncConfig {
+    #[serde(default)]
+    hooks: Vec<Hook>,
+}
+
+impl SyncConfig {
+    fn new(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = fs::read_to_string(path)
+            .map_err(|e| Error::Config(format!("failed loading sync config {path:?}: {e}")))?;
+        toml::from_str(&data)
+            .map_err(|e| Error::Config(format!("failed loading sync config toml {path:?}: {e}")))
+    }
+}
+
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct Config {
     config_dir: PathBuf,
     repo_dir: PathBuf,
+    cache_dir: PathBuf,
     #[serde(skip)]
     pub configs: IndexMap<String, RepoConfig>,
     #[serde(skip)]
     pub repos: IndexMap<String, Arc<Repo>>,
+    /// Actions run after syncing any repo, following the repo's own hooks.
+    #[serde(skip)]
+    pub hooks: Vec<Hook>,
 }
 
 impl Config {
-    pub fn new<P: AsRef<Path>>(config_dir: P, db_dir: P, create: bool) -> Result<Config> {
+    pub fn new<P: AsRef<Path>>(
+        config_dir: P,
+        db_dir: P,
+        cache_dir: P,
+        create: bool,
+    ) -> Result<Config> {
         let (config_dir, db_dir) = (config_dir.as_ref(), db_dir.as_ref());
+        let cache_dir = cache_dir.as_ref().to_path_buf();
+        let hooks = SyncConfig::new(&config_dir.join("sync.toml"))?.hooks;
         let config_dir = config_dir.join("repos");
         let repo_dir = db_dir.join("repos");
 
@@ -115,7 +167,7 @@ impl Config {
         let mut repos = IndexMap::<String, Arc<Repo>>::new();
         for (name, config) in configs.iter() {
             // ignore unsynced or nonexistent repos
-            match Repo::from_format(&name, &config.location, &config.format) {
+            match config.load(name, Some(&cache_dir)) {
                 Ok(repo) => {
                     repos.insert(name.clone(), Arc::new(repo));
                 }
@@ -126,11 +178,18 @@ impl Config {
         Ok(Config {
             config_dir,
             repo_dir,
+            cache_dir,
             configs,
             repos,
+            hooks,
         })
     }
 
+    /// Return the cache directory for sync state, if one is configured.
+    fn cache(&self) -> Option<&Path> {
+        Some(self.cache_dir.as_path()).filter(|p| !p.as_os_str().is_empty())
+    }
+
     pub fn add(&mut self, name: &str, uri: &str) -> Result<()> {
         if let Some(c) = self.configs.get(name) {
             return Err(Error::Config(format!("existing repo: {name:?} @ {:?}", &c.location)));
@@ -139,7 +198,7 @@ impl Config {
         let mut config: RepoConfig = Default::default();
         let path = Path::new(uri);
 
-        let repo = match path.exists() {
+        let mut repo = match path.exists() {
             true => {
                 // add local, external repo
                 let path = path.canonicalize().map_err(|e| {
@@ -152,7 +211,7 @@ impl Config {
             false => {
                 config.location = self.repo_dir.join(name);
                 config.sync = Some(Syncer::from_str(uri)?);
-                config.sync()?;
+                config.sync(self.cache(), &Progress::new(name, &|_| ()))?;
 
                 let (format, repo) = Repo::from_path(name, &config.location)?;
                 config.format = format.to_string();
@@ -172,19 +231,25 @@ impl Config {
                 repo
             }
         };
+        if let Some(path) = self.cache() {
+            repo.set_cache_dir(path);
+        }
 
-        let (configs, repos) = (&mut self.configs, &mut self.repos);
-        configs.insert(name.to_string(), config);
+        self.configs.insert(name.to_string(), config);
         // re-sort configs by RepoConfig ordering
-        configs.sort_by(|_k1, v1, _k2, v2| v1.cmp(v2));
-        repos.insert(name.to_string(), Arc::new(repo));
-        // use sorted configs to re-sort repos
+        self.configs.sort_by(|_k1, v1, _k2, v2| v1.cmp(v2));
+        self.repos.insert(name.to_string(), Arc::new(repo));
+        Self::sort_repos(&self.configs, &mut self.repos);
+        Ok(())
+    }
+
+    /// Sort repos using the config ordering.
+    fn sort_repos(configs: &IndexMap<String, RepoConfig>, repos: &mut IndexMap<String, Arc<Repo>>) {
         repos.sort_by(|k1, _v1, k2, _v2| {
             let k1_index = configs.get_index_of(k1).unwrap();
             let k2_index = configs.get_index_of(k2).unwrap();
             k1_index.cmp(&k2_index)
         });
-        Ok(())
     }
 
     pub fn create(&mut self, name: &str) -> Result<()> {
@@ -233,6 +298,20 @@ impl Config {
         Ok(())
     }
 
+    /// Return a combined view of the configured repos, optionally restricted to a given format.
+    ///
+    /// Repos with higher priority values take precedence over lower priority ones.
+    pub fn repo_set(&self, format: Option<&str>) -> RepoSet {
+        let repos = self
+            .repos
+            .values()
+            .rev()
+            .filter(|r| format.map(|f| r.format() == f).unwrap_or(true))
+            .cloned()
+            .collect();
+        RepoSet::new("repos", repos)
+    }
+
     fn repo_from_id<S: AsRef<str>>(&self, id: S) -> Result<&Repo> {
         let id = id.as_ref();
         match self.repos.get(id) {
@@ -249,32 +328,294 @@ impl Config {
         }
     }
 
-    // TODO: add concurrent syncing support with output progress
-    pub fn sync<S: AsRef<str>>(&mut self, repos: Vec<S>) -> Result<()> {
+    /// Sync the given repos, or all configured repos if none are passed, returning the changes
+    /// to each successfully synced repo.
+    pub fn sync<S: AsRef<str>>(&mut self, repos: Vec<S>) -> Result<Vec<RepoChanges>> {
+        self.sync_with(repos, 0, &|_| ())
+    }
+
+    /// Sync repos concurrently, running at most `jobs` syncs at once (one per core when 0) and
+    /// forwarding progress updates to a callback.
+    pub fn sync_with<S: AsRef<str>>(
+        &mut self,
+        repos: Vec<S>,
+        jobs: usize,
+        progress: ProgressFn,
+    ) -> Result<Vec<RepoChanges>> {
         let repos: Vec<&str> = match &repos {
             names if !names.is_empty() => names.iter().map(|s| s.as_ref()).collect(),
             // sync all configured repos if none were passed
             _ => self.configs.keys().map(|s| s.as_str()).collect(),
         };
 
-        let mut failed: Vec<(&str, Error)> = Vec::new();
+        let mut targets = vec![];
         for name in repos {
             let repo_config = self.config_from_id(name)?;
-            if let Err(e) = repo_config.sync() {
-                failed.push((name, e));
+            // repos without syncers are managed externally
+            if repo_config.sync.is_some() {
+                targets.push((name, repo_config, self.repos.get(name)));
             }
         }
 
-        match failed.is_empty() {
-            true => Ok(()),
-            false => {
-                let errors = failed
-                    .iter()
-                    .map(|(name, e)| format!("{name}: {e}"))
-                    .collect::<Vec<String>>()
-                    .join("\n\t");
-                Err(Error::Config(format!("failed syncing:\n\t{errors}")))
+        let cache = self.cache();
+        let snapshot = |repo: Option<&Repo>| -> Result<Snapshot> {
+            match repo {
+                Some(Repo::Ebuild(r)) => Snapshot::new(r),
+                _ => Ok(Snapshot::default()),
+            }
+        };
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build()
+            .map_err(|e| Error::Config(format!("failed creating thread pool: {e}")))?;
+        let results: Vec<_> = pool.install(|| {
+            targets
+                .into_par_iter()
+                .map(|(name, repo_config, repo)| {
+                    let progress = Progress::new(name, progress);
+                    progress.phase(SyncPhase::Started);
+                    let result = snapshot(repo.map(|r| r.as_ref())).and_then(|old| {
+                        let updated = repo_config.sync(cache, &progress)?;
+                        // reload synced repos so their contents reflect the updated files
+                        let repo = repo_config.load(name, cache)?;
+                        Ok((old, repo, updated))
+                    });
+                    (name, repo_config, progress, result)
+                })
+                .collect()
+        });
+
+        let mut failed: Vec<String> = Vec::new();
+        let mut synced: Vec<(&str, Repo)> = Vec::new();
+        let mut changes = vec![];
+        for (name, repo_config, progress, result) in results {
+            let commit = git_head(&repo_config.location);
+            let (status, error) = match &result {
+                Ok((_, _, true)) => (SyncStatus::Updated, None),
+                Ok(_) => (SyncStatus::Unchanged, None),
+                Err(e) => (SyncStatus::Failed, Some(e.to_string())),
+            };
+            let ctx = HookContext {
+                name,
+                path: &repo_config.location,
+                repo: result.as_ref().ok().map(|(_, repo, _)| repo),
+                status,
+                error,
+                commit: commit.clone(),
+            };
+
+            // Hooks run sequentially since regenerating metadata uses the global shell and
+            // before determining changes so those reflect any regenerated metadata.
+            let global = self.hooks.iter().filter(|h| !repo_config.hooks.contains(h));
+            let hook_errors: Vec<_> = repo_config
+                .hooks
+                .iter()
+                .chain(global)
+                .filter_map(|hook| hook.run(&ctx).err())
+                .map(|e| e.to_string())
+                .collect();
+
+            // hook failures for synced repos are returned with their changes
+            let result = result.and_then(|(old, repo, _)| {
+                let new = snapshot(Some(&repo))?;
+                let diff = old
+                    .diff(name, &new)
+                    .with_commit(commit)
+                    .with_hook_errors(hook_errors.clone());
+                Ok((repo, diff))
+            });
+            match result {
+                Ok((repo, diff)) => {
+                    progress.phase(SyncPhase::Finished);
+                    synced.push((name, repo));
+                    changes.push(diff);
+                }
+                Err(e) => {
+                    progress.phase(SyncPhase::Failed);
+                    failed.push(format!("{name}: {e}"));
+                    failed.extend(
+                        hook_errors
+                            .iter()
+                            .map(|e| format!("{name}: hook failed: {e}")),
+                    );
+                }
+            }
+        }
+
+        if !synced.is_empty() {
+            for (name, repo) in synced {
+                self.repos.insert(name.to_string(), Arc::new(repo));
             }
+            // newly synced repos are appended so they must be re-sorted
+            Self::sort_repos(&self.configs, &mut self.repos);
+        }
+
+        match failed.is_empty() {
+            true => Ok(changes),
+            false => Err(Error::Config(format!("failed syncing:\n\t{}", failed.join("\n\t")))),
         }
     }
+
+    /// Restore a repo to its state before its last sync. Calling this again undoes the
+    /// rollback.
+    pub fn rollback(&mut self, name: &str) -> Result<()> {
+        let repo_config = self.config_from_id(name)?;
+        sync::rollback(&repo_config.location)?;
+        let repo = repo_config.load(name, self.cache())?;
+        self.repos.insert(name.to_string(), Arc::new(repo));
+        Self::sort_repos(&self.configs, &mut self.repos);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::Mutex;
+
+    use tempfile::TempDir;
+
+    use crate::macros::assert_err_re;
+    use crate::sync::SyncProgress;
+
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        let dir = TempDir::new().unwrap();
+        let (config_dir, db_dir, cache_dir) =
+            (dir.path().join("config"), dir.path().join("db"), dir.path().join("cache"));
+        let t = TempRepo::new("a", None::<&str>, None).unwrap();
+        fs::create_dir_all(config_dir.join("repos")).unwrap();
+        let data = format!("location = {:?}\nformat = \"ebuild\"\npriority = 0\n", t.repo.path());
+        fs::write(config_dir.join("repos/a"), data).unwrap();
+
+        // persistent package indexes are only used when the cache dir exists
+        let config = Config::new(&config_dir, &db_dir, &cache_dir, true).unwrap();
+        match config.repos["a"].as_ref() {
+            Repo::Ebuild(r) => assert!(r.index_path().is_none()),
+            _ => panic!("invalid repo format"),
+        }
+        fs::create_dir_all(&cache_dir).unwrap();
+        let config = Config::new(&config_dir, &db_dir, &cache_dir, true).unwrap();
+        match config.repos["a"].as_ref() {
+            Repo::Ebuild(r) => assert!(r.index_path().unwrap().starts_with(&cache_dir)),
+            _ => panic!("invalid repo format"),
+        }
+    }
+
+    #[test]
+    fn test_sync_with() {
+        let (t1, t2) = (
+            TempRepo::new("a", None::<&str>, None).unwrap(),
+            TempRepo::new("b", None::<&str>, None).unwrap(),
+        );
+        t1.create_ebuild("cat/a-1", None).unwrap();
+        t2.create_ebuild("cat/b-1", None).unwrap();
+
+        let mut config = Config::default();
+        for (name, t) in [("a", &t1), ("b", &t2)] {
+            let path = t.repo.path();
+            let repo_config = RepoConfig {
+                location: path.to_path_buf(),
+                format: "ebuild".to_string(),
+                priority: 0,
+                sync: Some(Syncer::from_str(path.to_str().unwrap()).unwrap()),
+                keyring: None,
+                hooks: vec![],
+            };
+            config.configs.insert(name.to_string(), repo_config);
+        }
+
+        // all repos are synced concurrently when none are specified
+        let events = Mutex::new(vec![]);
+        let callback = |p: &SyncProgress| {
+            events
+                .lock()
+                .unwrap()
+                .push(format!("{}: {:?}", p.repo(), p.phase()));
+        };
+        let changes = config.sync_with(Vec::<&str>::new(), 2, &callback).unwrap();
+        let mut events = events.into_inner().unwrap();
+        events.sort();
+        assert_eq!(events, ["a: Finished", "a: Started", "b: Finished", "b: Started"]);
+        let changes: Vec<_> = changes
+            .iter()
+            .map(|c| (c.repo(), c.versions_added()))
+            .collect();
+        assert_eq!(
+            changes,
+            [("a", &["cat/a-1".to_string()][..]), ("b", &["cat/b-1".to_string()][..])]
+        );
+        assert_eq!(config.repos.keys().collect::<Vec<_>>(), ["a", "b"]);
+
+        // unchanged repos
+        let changes = config.sync(vec!["b"]).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].is_empty());
+
+        // failures are reported per repo
+        assert_err_re!(config.sync(vec!["c"]), "nonexistent repo: \"c\"$");
+        config.configs.get_mut("a").unwrap().location = t1.repo.path().join("nonexistent");
+        let events = Mutex::new(vec![]);
+        let callback = |p: &SyncProgress| events.lock().unwrap().push(p.phase());
+        let r = config.sync_with(vec!["a"], 1, &callback);
+        assert_err_re!(r, "failed syncing:\n\ta: invalid repo: .+: missing profiles dir$");
+        assert_eq!(events.into_inner().unwrap(), [SyncPhase::Started, SyncPhase::Failed]);
+    }
+
+    #[test]
+    fn test_sync_hooks() {
+        let dir = TempDir::new().unwrap();
+        let (config_dir, db_dir, cache_dir) =
+            (dir.path().join("config"), dir.path().join("db"), dir.path().join("cache"));
+        let log = dir.path().join("log");
+        let script = dir.path().join("hook");
+        let data =
+            format!("#!/bin/sh\necho \"$1 $PKGCRAFT_REPO $PKGCRAFT_SYNC_RESULT\" >> {log:?}\n");
+        fs::write(&script, data).unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let hook = |arg: &str| Hook::from_str(&format!("{} {arg}", script.display())).unwrap();
+
+        // global hooks are loaded from the sync config
+        fs::create_dir_all(&config_dir).unwrap();
+        fs::write(config_dir.join("sync.toml"), "hooks = 1").unwrap();
+        assert!(Config::new(&config_dir, &db_dir, &cache_dir, true).is_err());
+        let data = format!("hooks = [{:?}, \"manifests\"]", hook("global").to_string());
+        fs::write(config_dir.join("sync.toml"), data).unwrap();
+        let mut config = Config::new(&config_dir, &db_dir, &cache_dir, true).unwrap();
+        assert_eq!(config.hooks, [hook("global"), Hook::Manifests]);
+
+        // repo hooks run before global ones, skipping duplicates
+        let t = TempRepo::new("a", None::<&str>, None).unwrap();
+        t.create_ebuild("cat/a-1", None).unwrap();
+        let path = t.repo.path();
+        let repo_config = RepoConfig {
+            location: path.to_path_buf(),
+            format: "ebuild".to_string(),
+            priority: 0,
+            sync: Some(Syncer::from_str(path.to_str().unwrap()).unwrap()),
+            keyring: None,
+            hooks: vec![Hook::Manifests, hook("repo")],
+        };
+        config.configs.insert("a".to_string(), repo_config);
+        config.sync(vec!["a"]).unwrap();
+        let data = fs::read_to_string(&log).unwrap();
+        assert_eq!(data.lines().collect::<Vec<_>>(), ["repo a unchanged", "global a unchanged"]);
+
+        // hook failures are returned with the changes of the synced repo
+        config.configs.get_mut("a").unwrap().hooks = vec![Hook::from_str("false").unwrap()];
+        let changes = config.sync(vec!["a"]).unwrap();
+        assert_eq!(changes[0].hook_errors(), ["false: exit status: 1"]);
+        assert_eq!(changes[0].to_string(), "a:\n  hook failed: false: exit status: 1");
+        assert!(config.repos.contains_key("a"));
+
+        // hooks run for failed syncs as well
+        fs::remove_file(&log).unwrap();
+        config.configs.get_mut("a").unwrap().location = path.join("nonexistent");
+        assert!(config.sync(vec!["a"]).is_err());
+        let data = fs::read_to_string(&log).unwrap();
+        assert_eq!(data.lines().collect::<Vec<_>>(), ["global a failed"]);
+    }
 }
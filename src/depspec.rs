@@ -1,24 +1,165 @@
+use indexmap::IndexSet;
+
 use crate::atom::Atom;
 
 pub mod license;
 pub mod pkgdep;
 pub mod required_use;
+pub mod restrict;
 pub mod src_uri;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Uri {
     pub uri: String,
     pub rename: Option<String>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum DepSpec {
     Strings(Vec<String>),
     Atoms(Vec<Atom>),
     Uris(Vec<Uri>),
+    List(Vec<DepSpec>),
     AllOf(Box<DepSpec>),
     AnyOf(Box<DepSpec>),
     ExactlyOneOf(Box<DepSpec>), // REQUIRED_USE only
     AtMostOneOf(Box<DepSpec>),  // REQUIRED_USE only
     ConditionalUse(String, bool, Box<DepSpec>),
 }
+
+impl DepSpec {
+    /// Flatten a dependency tree into its atoms, each paired with the USE conditionals it's
+    /// nested under in "flag?" or "!flag?" form.
+    pub fn flatten_atoms(&self) -> Vec<(Vec<String>, &Atom)> {
+        let mut atoms = vec![];
+        self.flatten_atoms_with(&mut vec![], &mut atoms);
+        atoms
+    }
+
+    /// Evaluate USE conditionals against a set of enabled flags, returning the remaining
+    /// dependency tree if anything is left.
+    pub fn evaluate(&self, enabled: &IndexSet<String>) -> Option<DepSpec> {
+        match self {
+            Self::ConditionalUse(flag, negate, d) => match enabled.contains(flag) != *negate {
+                true => d.evaluate(enabled),
+                false => None,
+            },
+            Self::List(vals) => {
+                let mut evaluated: Vec<DepSpec> = vec![];
+                for d in vals.iter().filter_map(|d| d.evaluate(enabled)) {
+                    // merge adjacent values exposed by evaluated conditionals
+                    let vals = match d {
+                        Self::List(vals) => vals,
+                        d => vec![d],
+                    };
+                    for d in vals {
+                        match (evaluated.last_mut(), d) {
+                            (Some(Self::Atoms(a)), Self::Atoms(b)) => a.extend(b),
+                            (Some(Self::Strings(a)), Self::Strings(b)) => a.extend(b),
+                            (Some(Self::Uris(a)), Self::Uris(b)) => a.extend(b),
+                            (_, d) => evaluated.push(d),
+                        }
+                    }
+                }
+                match evaluated.len() {
+                    0 => None,
+                    1 => evaluated.pop(),
+                    _ => Some(Self::List(evaluated)),
+                }
+            }
+            Self::AllOf(d) => d.evaluate(enabled).map(|d| Self::AllOf(Box::new(d))),
+            Self::AnyOf(d) => d.evaluate(enabled).map(|d| Self::AnyOf(Box::new(d))),
+            Self::ExactlyOneOf(d) => d.evaluate(enabled).map(|d| Self::ExactlyOneOf(Box::new(d))),
+            Self::AtMostOneOf(d) => d.evaluate(enabled).map(|d| Self::AtMostOneOf(Box::new(d))),
+            d => Some(d.clone()),
+        }
+    }
+
+    /// Return all string values in a dependency tree, e.g. for evaluated RESTRICT or PROPERTIES.
+    pub fn strings(&self) -> Vec<&str> {
+        match self {
+            Self::Strings(vals) => vals.iter().map(|s| s.as_str()).collect(),
+            Self::List(vals) => vals.iter().flat_map(|d| d.strings()).collect(),
+            Self::AllOf(d)
+            | Self::AnyOf(d)
+            | Self::ExactlyOneOf(d)
+            | Self::AtMostOneOf(d)
+            | Self::ConditionalUse(_, _, d) => d.strings(),
+            Self::Atoms(_) | Self::Uris(_) => vec![],
+        }
+    }
+
+    fn flatten_atoms_with<'a>(
+        &'a self,
+        conditionals: &mut Vec<String>,
+        atoms: &mut Vec<(Vec<String>, &'a Atom)>,
+    ) {
+        match self {
+            Self::Atoms(vals) => atoms.extend(vals.iter().map(|a| (conditionals.clone(), a))),
+            Self::List(vals) => {
+                for d in vals {
+                    d.flatten_atoms_with(conditionals, atoms);
+                }
+            }
+            Self::AllOf(d) | Self::AnyOf(d) | Self::ExactlyOneOf(d) | Self::AtMostOneOf(d) => {
+                d.flatten_atoms_with(conditionals, atoms)
+            }
+            Self::ConditionalUse(flag, negate, d) => {
+                match negate {
+                    true => conditionals.push(format!("!{flag}?")),
+                    false => conditionals.push(format!("{flag}?")),
+                }
+                d.flatten_atoms_with(conditionals, atoms);
+                conditionals.pop();
+            }
+            Self::Strings(_) | Self::Uris(_) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eapi;
+
+    use super::*;
+
+    #[test]
+    fn test_flatten_atoms() {
+        let deps =
+            pkgdep::parse("a/b u1? ( c/d !u2? ( e/f ) ) || ( g/h )", &eapi::EAPI_LATEST).unwrap();
+        let atoms: Vec<(Vec<String>, String)> = deps
+            .flatten_atoms()
+            .into_iter()
+            .map(|(c, a)| (c, a.to_string()))
+            .collect();
+        let expected: Vec<(Vec<String>, String)> =
+            [(vec![], "a/b"), (vec!["u1?"], "c/d"), (vec!["u1?", "!u2?"], "e/f"), (vec![], "g/h")]
+                .into_iter()
+                .map(|(c, a)| (c.into_iter().map(String::from).collect(), a.to_string()))
+                .collect();
+        assert_eq!(atoms, expected);
+
+        // non-atom depspecs are ignored
+        let license = license::parse("u? ( l1 l2 )").unwrap();
+        assert!(license.flatten_atoms().is_empty());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let eapi = &*eapi::EAPI_LATEST;
+        let enabled: IndexSet<String> = ["u1", "u2"].iter().map(|s| s.to_string()).collect();
+        let deps =
+            pkgdep::parse("a/b u1? ( c/d !u2? ( e/f ) ) || ( g/h u3? ( i/j ) )", eapi).unwrap();
+        let expected = pkgdep::parse("a/b c/d || ( g/h )", eapi).unwrap();
+        assert_eq!(deps.evaluate(&enabled).unwrap(), expected);
+
+        // fully disabled trees evaluate to nothing
+        let deps = pkgdep::parse("u3? ( a/b ) !u1? ( c/d )", eapi).unwrap();
+        assert!(deps.evaluate(&enabled).is_none());
+
+        // string values
+        let restrict = restrict::parse("test u1? ( mirror ) u3? ( fetch )").unwrap();
+        let restrict = restrict.evaluate(&enabled).unwrap();
+        assert_eq!(restrict.strings(), ["test", "mirror"]);
+    }
+}
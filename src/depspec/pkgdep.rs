@@ -28,27 +28,37 @@ peg::parser! {
             = deps:dep(eapi) ++ " " { DepSpec::Atoms(deps) }
 
         rule all_of(eapi: &'static Eapi) -> DepSpec
-            = "(" _ e:expr(eapi) _ ")" {
+            = "(" _ e:exprs(eapi) _ ")" {
                 DepSpec::AllOf(Box::new(e))
             }
 
         rule any_of(eapi: &'static Eapi) -> DepSpec
-            = "||" _ "(" _ e:expr(eapi) _ ")" {
+            = "||" _ "(" _ e:exprs(eapi) _ ")" {
                 DepSpec::AnyOf(Box::new(e))
             }
 
         rule conditional(eapi: &'static Eapi) -> DepSpec
-            = negate:"!"? u:useflag() "?" _ "(" _ e:expr(eapi) _ ")" {
+            = negate:"!"? u:useflag() "?" _ "(" _ e:exprs(eapi) _ ")" {
                 DepSpec::ConditionalUse(u.to_string(), negate.is_some(), Box::new(e))
             }
 
-        pub rule expr(eapi: &'static Eapi) -> DepSpec
+        rule expr(eapi: &'static Eapi) -> DepSpec
             = conditional(eapi) / any_of(eapi) / all_of(eapi) / deps(eapi)
+
+        // Sequences of expressions are collapsed when only a single expression exists.
+        pub rule exprs(eapi: &'static Eapi) -> DepSpec
+            = e:expr(eapi) ++ _ {
+                let mut e = e;
+                match e.len() {
+                    1 => e.pop().unwrap(),
+                    _ => DepSpec::List(e),
+                }
+            }
     }
 }
 
 // export depspec parser
-pub use depspec::expr as parse;
+pub use depspec::exprs as parse;
 
 #[cfg(test)]
 mod tests {
@@ -76,6 +86,41 @@ mod tests {
         for (s, expected) in [
             ("a/b", DepSpec::Atoms(vec![atom("a/b")])),
             ("a/b c/d", DepSpec::Atoms(vec![atom("a/b"), atom("c/d")])),
+            ("( a/b )", DepSpec::AllOf(Box::new(DepSpec::Atoms(vec![atom("a/b")])))),
+            (
+                "|| ( a/b c/d )",
+                DepSpec::AnyOf(Box::new(DepSpec::Atoms(vec![atom("a/b"), atom("c/d")]))),
+            ),
+            (
+                "u? ( a/b )",
+                DepSpec::ConditionalUse(
+                    "u".to_string(),
+                    false,
+                    Box::new(DepSpec::Atoms(vec![atom("a/b")])),
+                ),
+            ),
+            (
+                "a/b !u? ( c/d )",
+                DepSpec::List(vec![
+                    DepSpec::Atoms(vec![atom("a/b")]),
+                    DepSpec::ConditionalUse(
+                        "u".to_string(),
+                        true,
+                        Box::new(DepSpec::Atoms(vec![atom("c/d")])),
+                    ),
+                ]),
+            ),
+            (
+                "u? ( a/b || ( c/d e/f ) )",
+                DepSpec::ConditionalUse(
+                    "u".to_string(),
+                    false,
+                    Box::new(DepSpec::List(vec![
+                        DepSpec::Atoms(vec![atom("a/b")]),
+                        DepSpec::AnyOf(Box::new(DepSpec::Atoms(vec![atom("c/d"), atom("e/f")]))),
+                    ])),
+                ),
+            ),
         ] {
             result = parse(&s, &eapi::EAPI_LATEST);
             assert!(result.is_ok(), "{s} failed: {}", result.err().unwrap());
@@ -0,0 +1,108 @@
+use peg;
+
+use super::DepSpec;
+use crate::macros::vec_str;
+
+peg::parser! {
+    pub grammar depspec() for str {
+        rule _ = [' ']
+
+        // RESTRICT and PROPERTIES tokens, e.g. test, mirror, or live.
+        rule name() -> &'input str
+            = s:$(quiet!{
+                ['a'..='z' | 'A'..='Z' | '0'..='9' | '_']
+                ['a'..='z' | 'A'..='Z' | '0'..='9' | '+' | '_' | '.' | '-']*
+            } / expected!("restriction name")
+            ) { s }
+
+        rule useflag() -> &'input str
+            = s:$(quiet!{
+                ['a'..='z' | 'A'..='Z' | '0'..='9']
+                ['a'..='z' | 'A'..='Z' | '0'..='9' | '+' | '_' | '@' | '-']*
+            } / expected!("useflag name")
+            ) { s }
+
+        // names can't be directly followed by a conditional marker
+        rule names() -> DepSpec
+            = names:(n:name() !"?" { n }) ++ " " { DepSpec::Strings(vec_str!(names)) }
+
+        rule all_of() -> DepSpec
+            = "(" _ e:exprs() _ ")" {
+                DepSpec::AllOf(Box::new(e))
+            }
+
+        rule conditional() -> DepSpec
+            = negate:"!"? u:useflag() "?" _ "(" _ e:exprs() _ ")" {
+                DepSpec::ConditionalUse(u.to_string(), negate.is_some(), Box::new(e))
+            }
+
+        rule expr() -> DepSpec
+            = conditional() / all_of() / names()
+
+        // Sequences of expressions are collapsed when only a single expression exists.
+        pub rule exprs() -> DepSpec
+            = e:expr() ++ _ {
+                let mut e = e;
+                match e.len() {
+                    1 => e.pop().unwrap(),
+                    _ => DepSpec::List(e),
+                }
+            }
+    }
+}
+
+// export depspec parser
+pub use depspec::exprs as parse;
+
+#[cfg(test)]
+mod tests {
+    use crate::depspec::DepSpec;
+    use crate::macros::vec_str;
+
+    use super::parse;
+
+    #[test]
+    fn test_parse_restrict() {
+        // invalid data
+        for s in ["", "(", ")", "( )", "( r1)", "|| ( r1 )", "use? r1", "!use ( r1 )"] {
+            assert!(parse(s).is_err(), "{s:?} didn't fail");
+        }
+
+        // good data
+        for (s, expected) in [
+            ("test", DepSpec::Strings(vec_str!(["test"]))),
+            ("test mirror", DepSpec::Strings(vec_str!(["test", "mirror"]))),
+            ("( test )", DepSpec::AllOf(Box::new(DepSpec::Strings(vec_str!(["test"]))))),
+            (
+                "test ( mirror )",
+                DepSpec::List(vec![
+                    DepSpec::Strings(vec_str!(["test"])),
+                    DepSpec::AllOf(Box::new(DepSpec::Strings(vec_str!(["mirror"])))),
+                ]),
+            ),
+            (
+                "!use? ( test )",
+                DepSpec::ConditionalUse(
+                    "use".to_string(),
+                    true,
+                    Box::new(DepSpec::Strings(vec_str!(["test"]))),
+                ),
+            ),
+            (
+                "strip use? ( test mirror )",
+                DepSpec::List(vec![
+                    DepSpec::Strings(vec_str!(["strip"])),
+                    DepSpec::ConditionalUse(
+                        "use".to_string(),
+                        false,
+                        Box::new(DepSpec::Strings(vec_str!(["test", "mirror"]))),
+                    ),
+                ]),
+            ),
+        ] {
+            let result = parse(s);
+            assert!(result.is_ok(), "{s} failed: {}", result.err().unwrap());
+            assert_eq!(result.unwrap(), expected);
+        }
+    }
+}
@@ -1,7 +1,10 @@
+use std::fs::File;
+use std::io::{self, Write};
 use std::ops::Deref;
 use std::path::Path;
 use std::str::FromStr;
 
+use md5::Digest;
 use nix::{sys::stat, unistd};
 use walkdir::{DirEntry, WalkDir};
 
@@ -117,3 +120,16 @@ pub(crate) fn is_hidden(entry: &DirEntry) -> bool {
         .map(|s| s.starts_with('.'))
         .unwrap_or(false)
 }
+
+/// Calculate the hex-encoded digest of a file's contents using a given hash algorithm.
+pub(crate) fn digest<D: Digest + Write>(path: &Path) -> Result<String> {
+    let err = |e: io::Error| Error::IO(format!("failed hashing {path:?}: {e}"));
+    let mut file = File::open(path).map_err(err)?;
+    let mut hasher = D::new();
+    io::copy(&mut file, &mut hasher).map_err(err)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
@@ -14,7 +14,7 @@ pub mod pkg;
 pub mod pkgsh;
 pub mod repo;
 pub mod restrict;
-mod sync;
+pub mod sync;
 #[cfg(test)]
 pub(crate) mod test;
 pub mod utils;
@@ -5,12 +5,36 @@ use crate::{atom, eapi};
 
 pub mod ebuild;
 pub mod fake;
+mod owned;
+pub use owned::OwnedPkg;
+pub mod vdb;
 
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, PartialEq)]
 pub enum Pkg<'a> {
     Ebuild(ebuild::Pkg<'a>),
     Fake(fake::Pkg<'a>),
+    Vdb(vdb::Pkg<'a>),
+}
+
+impl Pkg<'_> {
+    /// Return the package's slot if it's known.
+    pub fn slot(&self) -> Option<&str> {
+        match self {
+            Pkg::Ebuild(ref pkg) => pkg.metadata().ok().map(|m| m.slot()),
+            Pkg::Fake(_) => None,
+            Pkg::Vdb(ref pkg) => pkg.metadata().ok().map(|m| m.slot()),
+        }
+    }
+
+    /// Return the package's subslot if it's known.
+    pub fn subslot(&self) -> Option<&str> {
+        match self {
+            Pkg::Ebuild(ref pkg) => pkg.metadata().ok().map(|m| m.subslot()),
+            Pkg::Fake(_) => None,
+            Pkg::Vdb(ref pkg) => pkg.metadata().ok().map(|m| m.subslot()),
+        }
+    }
 }
 
 pub trait Package: fmt::Debug + fmt::Display {
@@ -28,6 +52,7 @@ impl<'a> Package for Pkg<'a> {
         match self {
             Pkg::Ebuild(ref pkg) => pkg.atom(),
             Pkg::Fake(ref pkg) => pkg.atom(),
+            Pkg::Vdb(ref pkg) => pkg.atom(),
         }
     }
 
@@ -35,6 +60,7 @@ impl<'a> Package for Pkg<'a> {
         match self {
             Pkg::Ebuild(ref pkg) => pkg.eapi(),
             Pkg::Fake(ref pkg) => pkg.eapi(),
+            Pkg::Vdb(ref pkg) => pkg.eapi(),
         }
     }
 
@@ -42,6 +68,7 @@ impl<'a> Package for Pkg<'a> {
         match self {
             Pkg::Ebuild(ref pkg) => Box::new(pkg.repo()),
             Pkg::Fake(ref pkg) => Box::new(pkg.repo()),
+            Pkg::Vdb(ref pkg) => Box::new(pkg.repo()),
         }
     }
 }
@@ -51,6 +78,7 @@ impl fmt::Display for Pkg<'_> {
         match self {
             Pkg::Ebuild(ref pkg) => write!(f, "{}", pkg),
             Pkg::Fake(ref pkg) => write!(f, "{}", pkg),
+            Pkg::Vdb(ref pkg) => write!(f, "{}", pkg),
         }
     }
 }
@@ -2,11 +2,15 @@ use std::io::{self, prelude::*};
 use std::path::{Path, PathBuf};
 use std::{fmt, fs};
 
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use regex::Regex;
 
 use crate::{atom, eapi, pkg, repo, Error, Result};
 
+mod metadata;
+pub use metadata::{DepClass, Metadata};
+pub mod xml;
+
 static EAPI_LINE_RE: Lazy<Regex> =
     Lazy::new(|| Regex::new("^EAPI=['\"]?(?P<EAPI>[A-Za-z0-9+_.-]*)['\"]?[\t ]*(?:#.*)?").unwrap());
 
@@ -16,6 +20,8 @@ pub struct Pkg<'a> {
     atom: &'a atom::Atom,
     eapi: &'static eapi::Eapi,
     repo: &'a repo::ebuild::Repo,
+    meta: OnceCell<Metadata>,
+    xml: OnceCell<xml::Metadata>,
 }
 
 impl PartialEq for Pkg<'_> {
@@ -36,6 +42,8 @@ impl<'a> Pkg<'a> {
             atom,
             eapi,
             repo,
+            meta: OnceCell::new(),
+            xml: OnceCell::new(),
         })
     }
 
@@ -43,12 +51,38 @@ impl<'a> Pkg<'a> {
         &self.path
     }
 
+    /// Return the path to the package's metadata cache file.
+    pub fn cache_path(&self) -> PathBuf {
+        let (cat, pf) = (self.atom.category(), self.atom.env("PF").unwrap());
+        self.repo
+            .path()
+            .join(format!("metadata/md5-cache/{cat}/{pf}"))
+    }
+
+    /// Return the package metadata, loading it from the repo's metadata cache on first access.
+    pub fn metadata(&self) -> Result<&Metadata> {
+        self.meta
+            .get_or_try_init(|| Metadata::load(self.cache_path()))
+    }
+
+    /// Return the package's metadata.xml data, loading it on first access.
+    pub fn xml(&self) -> Result<&xml::Metadata> {
+        self.xml
+            .get_or_try_init(|| xml::Metadata::load(self.path.with_file_name("metadata.xml")))
+    }
+
+    /// Return the metadata.xml data for the package's category.
+    pub fn category_xml(&self) -> Result<xml::CategoryMetadata> {
+        let path = self.repo.path().join(self.atom.category());
+        xml::CategoryMetadata::load(path.join("metadata.xml"))
+    }
+
     pub fn ebuild(&self) -> String {
         // IO errors should be caught on initialization in new().
         fs::read_to_string(&self.path).unwrap()
     }
 
-    fn get_eapi<P: AsRef<Path>>(path: P) -> Result<&'static eapi::Eapi> {
+    pub(crate) fn get_eapi<P: AsRef<Path>>(path: P) -> Result<&'static eapi::Eapi> {
         let mut eapi = &*eapi::EAPI0;
         let path = path.as_ref();
         let f = fs::File::open(path).map_err(|e| Error::IO(e.to_string()))?;
@@ -142,4 +176,32 @@ mod tests {
         assert_eq!(pkg.path(), &path);
         assert!(!pkg.ebuild().is_empty());
     }
+
+    #[test]
+    fn test_xml() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let (atom, path) = t.create_ebuild("cat/pkg-1", None).unwrap();
+        let pkg = Pkg::new(&atom, &t.repo).unwrap();
+
+        // missing files
+        assert!(pkg.xml().unwrap().maintainers().is_empty());
+        assert!(pkg.category_xml().unwrap().long_description().is_none());
+
+        let data = indoc::indoc! {r#"
+            <pkgmetadata>
+                <maintainer type="person"><email>a@b.org</email></maintainer>
+            </pkgmetadata>
+        "#};
+        fs::write(path.with_file_name("metadata.xml"), data).unwrap();
+        let cat_data = "<catmetadata><longdescription>desc</longdescription></catmetadata>";
+        fs::write(t.repo.path().join("cat/metadata.xml"), cat_data).unwrap();
+        let pkg = Pkg::new(&atom, &t.repo).unwrap();
+        assert_eq!(pkg.xml().unwrap().maintainers()[0].email(), "a@b.org");
+        assert_eq!(pkg.category_xml().unwrap().long_description(), Some("desc"));
+
+        // invalid files
+        fs::write(path.with_file_name("metadata.xml"), "<pkgmetadata><a></pkgmetadata>").unwrap();
+        let pkg = Pkg::new(&atom, &t.repo).unwrap();
+        assert!(pkg.xml().is_err());
+    }
 }
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::{fmt, fs};
+
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+
+use crate::depspec::{pkgdep, DepSpec};
+use crate::eapi::{self, Eapi};
+use crate::peg::peg_error;
+use crate::{Error, Result};
+
+/// Package dependency classes as defined by PMS.
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, DeserializeFromStr, SerializeDisplay,
+)]
+pub enum DepClass {
+    Depend,
+    Rdepend,
+    Pdepend,
+    Bdepend,
+    Idepend,
+}
+
+impl DepClass {
+    /// All dependency classes in their conventional order.
+    pub const ALL: [DepClass; 5] = [
+        DepClass::Depend,
+        DepClass::Rdepend,
+        DepClass::Pdepend,
+        DepClass::Bdepend,
+        DepClass::Idepend,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DepClass::Depend => "DEPEND",
+            DepClass::Rdepend => "RDEPEND",
+            DepClass::Pdepend => "PDEPEND",
+            DepClass::Bdepend => "BDEPEND",
+            DepClass::Idepend => "IDEPEND",
+        }
+    }
+}
+
+impl fmt::Display for DepClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for DepClass {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "DEPEND" => Ok(DepClass::Depend),
+            "RDEPEND" => Ok(DepClass::Rdepend),
+            "PDEPEND" => Ok(DepClass::Pdepend),
+            "BDEPEND" => Ok(DepClass::Bdepend),
+            "IDEPEND" => Ok(DepClass::Idepend),
+            _ => Err(Error::InvalidValue(format!("invalid dependency class: {s}"))),
+        }
+    }
+}
+
+/// Package metadata as stored in a repo's md5-cache.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    data: HashMap<String, String>,
+}
+
+impl Metadata {
+    /// Load metadata from a given md5-cache file.
+    pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .map_err(|e| Error::IO(format!("failed reading metadata: {path:?}: {e}")))?;
+        Metadata::from_str(&data)
+    }
+
+    /// Load metadata from a package database entry where each key is stored in a separate file.
+    ///
+    /// Installed file lists and linkage data are skipped since they can be large and aren't
+    /// package metadata.
+    pub(crate) fn load_dir<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let entries = fs::read_dir(path)
+            .map_err(|e| Error::IO(format!("failed reading metadata: {path:?}: {e}")))?;
+        let mut data = HashMap::new();
+        for entry in entries {
+            let entry = entry.map_err(|e| Error::IO(format!("{path:?}: {e}")))?;
+            let key = match entry.file_name().to_str() {
+                Some("CONTENTS" | "NEEDED") => continue,
+                Some(k) if k.chars().all(|c| c.is_ascii_uppercase() || c == '_') => k.to_string(),
+                _ => continue,
+            };
+            let value = fs::read_to_string(entry.path())
+                .map_err(|e| Error::IO(format!("failed reading metadata: {path:?}: {e}")))?;
+            data.insert(key, value.trim().to_string());
+        }
+        Ok(Metadata { data })
+    }
+
+    /// Get the raw value for a given metadata key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.data.get(key).map(|s| s.as_str())
+    }
+
+    fn get_list(&self, key: &str) -> Vec<&str> {
+        match self.get(key) {
+            None => vec![],
+            Some(s) => s.split_whitespace().collect(),
+        }
+    }
+
+    pub fn eapi(&self) -> Result<&'static Eapi> {
+        eapi::get_eapi(self.get("EAPI").unwrap_or("0"))
+    }
+
+    pub fn description(&self) -> &str {
+        self.get("DESCRIPTION").unwrap_or_default()
+    }
+
+    pub fn homepage(&self) -> Vec<&str> {
+        self.get_list("HOMEPAGE")
+    }
+
+    pub fn slot(&self) -> &str {
+        let slot = self.get("SLOT").unwrap_or_default();
+        slot.split_once('/').map_or(slot, |(s, _)| s)
+    }
+
+    /// Return the package subslot, defaulting to the slot if unset.
+    pub fn subslot(&self) -> &str {
+        let slot = self.get("SLOT").unwrap_or_default();
+        slot.split_once('/').map_or(slot, |(_, s)| s)
+    }
+
+    pub fn keywords(&self) -> Vec<&str> {
+        self.get_list("KEYWORDS")
+    }
+
+    pub fn iuse(&self) -> Vec<&str> {
+        self.get_list("IUSE")
+    }
+
+    pub fn inherit(&self) -> Vec<&str> {
+        self.get_list("INHERIT")
+    }
+
+    /// Return the full set of inherited eclasses, pulled from the md5-cache eclass checksums if
+    /// they exist, otherwise from INHERITED as recorded for installed packages.
+    pub fn inherited(&self) -> Vec<&str> {
+        match self.get("_eclasses_") {
+            Some(s) => s.split_whitespace().step_by(2).collect(),
+            None => self.get_list("INHERITED"),
+        }
+    }
+
+    /// Return the parsed dependencies for a given dependency class if any exist.
+    pub fn deps(&self, class: DepClass) -> Result<Option<DepSpec>> {
+        let s = match self.get(class.as_str()) {
+            Some(s) if !s.trim().is_empty() => s.split_whitespace().collect::<Vec<_>>().join(" "),
+            _ => return Ok(None),
+        };
+        let deps = pkgdep::parse(&s, self.eapi()?)
+            .map_err(|e| peg_error(format!("invalid {class}: {s:?}"), s.as_str(), e))?;
+        Ok(Some(deps))
+    }
+}
+
+impl FromStr for Metadata {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut data = HashMap::new();
+        for line in s.lines() {
+            match line.split_once('=') {
+                Some((k, v)) => {
+                    data.insert(k.to_string(), v.to_string());
+                }
+                None => {
+                    return Err(Error::InvalidValue(format!("invalid metadata line: {line:?}")))
+                }
+            }
+        }
+        Ok(Metadata { data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dep_class() {
+        for class in DepClass::ALL {
+            assert_eq!(DepClass::from_str(class.as_str()).unwrap(), class);
+        }
+        assert!(DepClass::from_str("depend").is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let data = indoc::indoc! {"
+            EAPI=8
+            DESCRIPTION=testing package
+            SLOT=1/2
+            KEYWORDS=amd64 ~arm64
+            DEPEND=a/b u? ( >=c/d-1:= )
+        "};
+        let meta = Metadata::from_str(data).unwrap();
+        assert_eq!(meta.eapi().unwrap(), &*eapi::EAPI8);
+        assert_eq!(meta.description(), "testing package");
+        assert_eq!(meta.slot(), "1");
+        assert_eq!(meta.subslot(), "2");
+        assert_eq!(meta.keywords(), ["amd64", "~arm64"]);
+        assert!(meta.iuse().is_empty());
+        let deps = meta.deps(DepClass::Depend).unwrap().unwrap();
+        assert_eq!(deps.flatten_atoms().len(), 2);
+        assert!(meta.deps(DepClass::Rdepend).unwrap().is_none());
+
+        // subslot defaults to slot
+        let meta = Metadata::from_str("SLOT=0").unwrap();
+        assert_eq!(meta.subslot(), "0");
+
+        // inherited eclasses
+        let meta = Metadata::from_str("_eclasses_=a\t123\tb\t456").unwrap();
+        assert_eq!(meta.inherited(), ["a", "b"]);
+        let meta = Metadata::from_str("INHERITED=a b").unwrap();
+        assert_eq!(meta.inherited(), ["a", "b"]);
+
+        // invalid data
+        assert!(Metadata::from_str("EAPI").is_err());
+        let meta = Metadata::from_str("DEPEND=( a/b").unwrap();
+        assert!(meta.deps(DepClass::Depend).is_err());
+    }
+}
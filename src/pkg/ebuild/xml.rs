@@ -0,0 +1,437 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::{fmt, fs, io};
+
+use indexmap::IndexMap;
+use roxmltree::{Document, Node};
+
+use crate::{Error, Result};
+
+/// Return the whitespace-normalized text content of a node and its descendants.
+pub(crate) fn text(node: Node) -> String {
+    let s: String = node
+        .descendants()
+        .filter(|n| n.is_text())
+        .filter_map(|n| n.text())
+        .collect();
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Return the text content of a node's optional, non-empty child element.
+fn child_text(node: Node, name: &str) -> Option<String> {
+    node.children()
+        .find(|n| n.has_tag_name(name))
+        .map(text)
+        .filter(|s| !s.is_empty())
+}
+
+/// Determine if a node is in English, either explicitly or by default.
+fn is_english(node: &Node) -> bool {
+    matches!(node.attribute("lang"), None | Some("en"))
+}
+
+/// Load a metadata.xml file's contents, treating missing files as empty.
+fn load<P: AsRef<Path>>(path: P) -> Result<Option<String>> {
+    let path = path.as_ref();
+    match fs::read_to_string(path) {
+        Ok(s) => Ok(Some(s)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::IO(format!("failed reading {path:?}: {e}"))),
+    }
+}
+
+fn parse(s: &str) -> Result<Document<'_>> {
+    Document::parse(s).map_err(|e| Error::InvalidValue(format!("invalid metadata.xml: {e}")))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MaintainerType {
+    Person,
+    Project,
+    Unknown,
+}
+
+impl FromStr for MaintainerType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "person" => Ok(MaintainerType::Person),
+            "project" => Ok(MaintainerType::Project),
+            "unknown" => Ok(MaintainerType::Unknown),
+            _ => Err(Error::InvalidValue(format!("invalid maintainer type: {s}"))),
+        }
+    }
+}
+
+impl fmt::Display for MaintainerType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MaintainerType::Person => write!(f, "person"),
+            MaintainerType::Project => write!(f, "project"),
+            MaintainerType::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Maintainer {
+    email: String,
+    name: Option<String>,
+    description: Option<String>,
+    maint_type: MaintainerType,
+    proxied: bool,
+}
+
+impl Maintainer {
+    fn from_node(node: Node) -> Result<Self> {
+        let email = child_text(node, "email")
+            .ok_or_else(|| Error::InvalidValue("maintainer missing email".to_string()))?;
+        let maint_type = match node.attribute("type") {
+            Some(s) => s.parse()?,
+            None => MaintainerType::Unknown,
+        };
+        Ok(Maintainer {
+            email,
+            name: child_text(node, "name"),
+            description: node
+                .children()
+                .filter(|n| n.has_tag_name("description") && is_english(n))
+                .map(text)
+                .next(),
+            maint_type,
+            proxied: node.attribute("proxied") == Some("yes"),
+        })
+    }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn maint_type(&self) -> MaintainerType {
+        self.maint_type
+    }
+
+    pub fn proxied(&self) -> bool {
+        self.proxied
+    }
+}
+
+impl fmt::Display for Maintainer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{name} <{}>", self.email),
+            None => write!(f, "{}", self.email),
+        }
+    }
+}
+
+/// Upstream project identifier, e.g. a GitHub repo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteId {
+    site: String,
+    name: String,
+}
+
+impl RemoteId {
+    pub fn site(&self) -> &str {
+        &self.site
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Upstream {
+    remote_ids: Vec<RemoteId>,
+    bugs_to: Option<String>,
+    changelog: Option<String>,
+    doc: Option<String>,
+}
+
+impl Upstream {
+    fn from_node(node: Node) -> Result<Self> {
+        let mut upstream = Upstream::default();
+        for n in node.children().filter(|n| n.is_element()) {
+            match n.tag_name().name() {
+                "remote-id" => {
+                    let site = n
+                        .attribute("type")
+                        .ok_or_else(|| Error::InvalidValue("remote-id missing type".to_string()))?;
+                    upstream.remote_ids.push(RemoteId {
+                        site: site.to_string(),
+                        name: text(n),
+                    });
+                }
+                "bugs-to" => upstream.bugs_to = Some(text(n)),
+                "changelog" => upstream.changelog = Some(text(n)),
+                "doc" if is_english(&n) => upstream.doc = Some(text(n)),
+                _ => (),
+            }
+        }
+        Ok(upstream)
+    }
+
+    pub fn remote_ids(&self) -> &[RemoteId] {
+        &self.remote_ids
+    }
+
+    pub fn bugs_to(&self) -> Option<&str> {
+        self.bugs_to.as_deref()
+    }
+
+    pub fn changelog(&self) -> Option<&str> {
+        self.changelog.as_deref()
+    }
+
+    pub fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+}
+
+/// Package metadata stored in a package's metadata.xml file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    maintainers: Vec<Maintainer>,
+    upstream: Option<Upstream>,
+    local_use: IndexMap<String, String>,
+    long_description: Option<String>,
+    slots: IndexMap<String, String>,
+    subslots: Option<String>,
+    stabilize_allarches: bool,
+}
+
+impl Metadata {
+    /// Load a package's metadata.xml file, missing files result in empty metadata.
+    pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        match load(path)? {
+            Some(s) => {
+                Self::from_str(&s).map_err(|e| Error::InvalidValue(format!("{path:?}: {e}")))
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn maintainers(&self) -> &[Maintainer] {
+        &self.maintainers
+    }
+
+    pub fn upstream(&self) -> Option<&Upstream> {
+        self.upstream.as_ref()
+    }
+
+    /// Return the descriptions for local USE flags.
+    pub fn local_use(&self) -> &IndexMap<String, String> {
+        &self.local_use
+    }
+
+    pub fn long_description(&self) -> Option<&str> {
+        self.long_description.as_deref()
+    }
+
+    /// Return the descriptions for individual slots.
+    pub fn slots(&self) -> &IndexMap<String, String> {
+        &self.slots
+    }
+
+    /// Return the description of the package's subslot usage.
+    pub fn subslots(&self) -> Option<&str> {
+        self.subslots.as_deref()
+    }
+
+    /// Determine if the package can be stabilized on all arches at once.
+    pub fn stabilize_allarches(&self) -> bool {
+        self.stabilize_allarches
+    }
+}
+
+impl FromStr for Metadata {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let doc = parse(s)?;
+        let mut meta = Metadata::default();
+        for node in doc.root_element().children().filter(|n| n.is_element()) {
+            match node.tag_name().name() {
+                "maintainer" => meta.maintainers.push(Maintainer::from_node(node)?),
+                "upstream" => meta.upstream = Some(Upstream::from_node(node)?),
+                "longdescription" if is_english(&node) => meta.long_description = Some(text(node)),
+                "stabilize-allarches" => meta.stabilize_allarches = true,
+                "use" if is_english(&node) => {
+                    for n in node.children().filter(|n| n.has_tag_name("flag")) {
+                        if let Some(name) = n.attribute("name") {
+                            meta.local_use.insert(name.to_string(), text(n));
+                        }
+                    }
+                }
+                "slots" if is_english(&node) => {
+                    for n in node.children().filter(|n| n.is_element()) {
+                        match (n.tag_name().name(), n.attribute("name")) {
+                            ("slot", Some(name)) => {
+                                meta.slots.insert(name.to_string(), text(n));
+                            }
+                            ("subslots", _) => meta.subslots = Some(text(n)),
+                            _ => (),
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(meta)
+    }
+}
+
+/// Category metadata stored in a category's metadata.xml file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CategoryMetadata {
+    long_description: Option<String>,
+}
+
+impl CategoryMetadata {
+    /// Load a category's metadata.xml file, missing files result in empty metadata.
+    pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        match load(path)? {
+            Some(s) => {
+                Self::from_str(&s).map_err(|e| Error::InvalidValue(format!("{path:?}: {e}")))
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn long_description(&self) -> Option<&str> {
+        self.long_description.as_deref()
+    }
+}
+
+impl FromStr for CategoryMetadata {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let doc = parse(s)?;
+        let long_description = doc
+            .root_element()
+            .children()
+            .filter(|n| n.has_tag_name("longdescription") && is_english(n))
+            .map(text)
+            .next();
+        Ok(CategoryMetadata { long_description })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::macros::assert_err_re;
+
+    use super::*;
+
+    #[test]
+    fn test_pkg_metadata() {
+        let data = indoc::indoc! {r#"
+            <?xml version="1.0" encoding="UTF-8"?>
+            <!DOCTYPE pkgmetadata SYSTEM "https://www.gentoo.org/dtd/metadata.dtd">
+            <pkgmetadata>
+                <maintainer type="person" proxied="yes">
+                    <email>jane@example.org</email>
+                    <name>Jane Doe</name>
+                    <description>Primary maintainer</description>
+                </maintainer>
+                <maintainer type="project">
+                    <email>proj@example.org</email>
+                </maintainer>
+                <longdescription lang="en">
+                    A long
+                    description.
+                </longdescription>
+                <longdescription lang="de">Eine Beschreibung.</longdescription>
+                <use>
+                    <flag name="ssl">Enable <pkg>dev-libs/openssl</pkg> support</flag>
+                    <flag name="doc">Build docs</flag>
+                </use>
+                <use lang="de">
+                    <flag name="ssl">SSL</flag>
+                </use>
+                <slots>
+                    <slot name="1">Legacy API</slot>
+                    <subslots>Soname version</subslots>
+                </slots>
+                <stabilize-allarches/>
+                <upstream>
+                    <remote-id type="github">pkgcraft/pkgcraft</remote-id>
+                    <remote-id type="pypi">pkgcraft</remote-id>
+                    <bugs-to>https://github.com/pkgcraft/pkgcraft/issues</bugs-to>
+                    <changelog>https://example.org/changes</changelog>
+                    <doc lang="en">https://example.org/docs</doc>
+                </upstream>
+            </pkgmetadata>
+        "#};
+        let meta = Metadata::from_str(data).unwrap();
+
+        let m = &meta.maintainers()[0];
+        assert_eq!(m.email(), "jane@example.org");
+        assert_eq!(m.name(), Some("Jane Doe"));
+        assert_eq!(m.description(), Some("Primary maintainer"));
+        assert_eq!(m.maint_type(), MaintainerType::Person);
+        assert!(m.proxied());
+        assert_eq!(m.to_string(), "Jane Doe <jane@example.org>");
+        let m = &meta.maintainers()[1];
+        assert_eq!(m.maint_type(), MaintainerType::Project);
+        assert_eq!(m.name(), None);
+        assert!(!m.proxied());
+
+        assert_eq!(meta.long_description(), Some("A long description."));
+        assert_eq!(meta.local_use().get("ssl").unwrap(), "Enable dev-libs/openssl support");
+        assert_eq!(meta.local_use().len(), 2);
+        assert_eq!(meta.slots().get("1").unwrap(), "Legacy API");
+        assert_eq!(meta.subslots(), Some("Soname version"));
+        assert!(meta.stabilize_allarches());
+
+        let upstream = meta.upstream().unwrap();
+        let ids: Vec<_> = upstream
+            .remote_ids()
+            .iter()
+            .map(|r| (r.site(), r.name()))
+            .collect();
+        assert_eq!(ids, [("github", "pkgcraft/pkgcraft"), ("pypi", "pkgcraft")]);
+        assert_eq!(upstream.bugs_to(), Some("https://github.com/pkgcraft/pkgcraft/issues"));
+        assert_eq!(upstream.changelog(), Some("https://example.org/changes"));
+        assert_eq!(upstream.doc(), Some("https://example.org/docs"));
+
+        // minimal
+        let meta = Metadata::from_str("<pkgmetadata/>").unwrap();
+        assert_eq!(meta, Metadata::default());
+
+        // invalid data
+        assert_err_re!(
+            Metadata::from_str("<pkgmetadata><a></pkgmetadata>"),
+            "^invalid metadata.xml: "
+        );
+        assert!(Metadata::from_str("<pkgmetadata><maintainer/></pkgmetadata>").is_err());
+        let s = "<pkgmetadata><maintainer type=\"a\"><email>a</email></maintainer></pkgmetadata>";
+        assert!(Metadata::from_str(s).is_err());
+    }
+
+    #[test]
+    fn test_category_metadata() {
+        let data = indoc::indoc! {r#"
+            <catmetadata>
+                <longdescription lang="en">Test  category</longdescription>
+                <longdescription lang="de">Testkategorie</longdescription>
+            </catmetadata>
+        "#};
+        let meta = CategoryMetadata::from_str(data).unwrap();
+        assert_eq!(meta.long_description(), Some("Test category"));
+    }
+}
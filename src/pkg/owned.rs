@@ -0,0 +1,202 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use once_cell::sync::OnceCell;
+use tracing::warn;
+
+use crate::pkg::ebuild::Metadata;
+use crate::pkg::{self, Pkg};
+use crate::repo::{self, Repository};
+use crate::{atom, eapi, Result};
+
+/// Owned package handle sharing its repo, so it can be stored long-term or sent across threads.
+///
+/// Nothing is read from disk on creation, the EAPI and metadata are loaded on first access.
+#[derive(Debug, Clone)]
+pub struct OwnedPkg {
+    atom: atom::Atom,
+    repo: Arc<repo::Repo>,
+    eapi: OnceCell<&'static eapi::Eapi>,
+    meta: OnceCell<Metadata>,
+}
+
+impl PartialEq for OwnedPkg {
+    fn eq(&self, other: &Self) -> bool {
+        self.atom == other.atom && self.repo.id() == other.repo.id()
+    }
+}
+
+impl Eq for OwnedPkg {}
+
+impl OwnedPkg {
+    pub(crate) fn new(atom: atom::Atom, repo: Arc<repo::Repo>) -> Self {
+        OwnedPkg {
+            atom,
+            repo,
+            eapi: OnceCell::new(),
+            meta: OnceCell::new(),
+        }
+    }
+
+    /// Load the package's EAPI from its ebuild or database entry.
+    fn load_eapi(&self) -> Result<&'static eapi::Eapi> {
+        match (self.repo.as_ref(), self.path()) {
+            (repo::Repo::Ebuild(_), Some(path)) => pkg::ebuild::Pkg::get_eapi(path),
+            (repo::Repo::Vdb(_), Some(path)) => pkg::vdb::Pkg::get_eapi(path),
+            _ => Ok(&*eapi::EAPI_LATEST),
+        }
+    }
+
+    /// Return the path to the package's ebuild file or database entry if it exists on disk.
+    pub fn path(&self) -> Option<PathBuf> {
+        let (cat, pkg) = (self.atom.category(), self.atom.package());
+        match self.repo.as_ref() {
+            repo::Repo::Ebuild(r) => {
+                let ver = self.atom.version().unwrap();
+                Some(r.path().join(format!("{cat}/{pkg}/{pkg}-{ver}.ebuild")))
+            }
+            repo::Repo::Fake(_) => None,
+            repo::Repo::Vdb(r) => Some(
+                r.path()
+                    .join(format!("{cat}/{}", self.atom.env("PF").ok()?)),
+            ),
+        }
+    }
+
+    /// Return the package metadata, loading it on first access. Fake packages have no metadata.
+    pub fn metadata(&self) -> Result<&Metadata> {
+        self.meta.get_or_try_init(|| match self.repo.as_ref() {
+            repo::Repo::Ebuild(r) => {
+                let pf = self.atom.env("PF")?;
+                let path = r.path().join("metadata/md5-cache");
+                Metadata::load(path.join(format!("{}/{pf}", self.atom.category())))
+            }
+            repo::Repo::Fake(_) => Ok(Metadata::default()),
+            repo::Repo::Vdb(_) => Metadata::load_dir(self.path().unwrap()),
+        })
+    }
+
+    /// Return a borrowed package for APIs that operate on them, e.g. restrictions.
+    pub fn pkg(&self) -> Result<Pkg> {
+        Ok(match self.repo.as_ref() {
+            repo::Repo::Ebuild(r) => Pkg::Ebuild(pkg::ebuild::Pkg::new(&self.atom, r)?),
+            repo::Repo::Fake(r) => Pkg::Fake(pkg::fake::Pkg::new(&self.atom, r)),
+            repo::Repo::Vdb(r) => Pkg::Vdb(pkg::vdb::Pkg::new(&self.atom, r)?),
+        })
+    }
+}
+
+impl fmt::Display for OwnedPkg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}::{}", self.atom, self.repo.id())
+    }
+}
+
+impl pkg::Package for OwnedPkg {
+    type Repo = Arc<repo::Repo>;
+
+    fn atom(&self) -> &atom::Atom {
+        &self.atom
+    }
+
+    /// Return the package's EAPI, falling back to EAPI 0 when it can't be determined.
+    fn eapi(&self) -> &eapi::Eapi {
+        self.eapi.get_or_init(|| {
+            self.load_eapi().unwrap_or_else(|e| {
+                warn!("{self}: {e}");
+                &*eapi::EAPI0
+            })
+        })
+    }
+
+    fn repo(&self) -> Self::Repo {
+        self.repo.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread};
+
+    use crate::pkg::Package;
+    use crate::repo::ebuild::TempRepo;
+    use crate::repo::fake;
+
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_threads() {
+        assert_send_sync::<OwnedPkg>();
+
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        for (cpv, desc) in [("cat/a-1", "a"), ("cat/b-1", "b"), ("cat/b-2", "b2")] {
+            t.create_ebuild(cpv, None).unwrap();
+            t.create_metadata(cpv, &[("EAPI", "8"), ("DESCRIPTION", desc)])
+                .unwrap();
+        }
+        let (_, repo) = repo::Repo::from_path("test", t.repo.path()).unwrap();
+        let repo = Arc::new(repo);
+
+        let pkgs: Vec<OwnedPkg> = repo.clone().iter_owned().collect();
+        assert_eq!(pkgs.len(), 3);
+        assert_eq!(pkgs[0].to_string(), "cat/a-1::test");
+        assert_eq!(pkgs[0].path().unwrap(), t.repo.path().join("cat/a/a-1.ebuild"));
+        assert_eq!(pkgs[0].eapi(), &*eapi::EAPI_LATEST);
+        assert_eq!(pkgs[0].repo().id(), "test");
+        assert_eq!(pkgs[0], pkgs[0].clone());
+        assert_ne!(pkgs[0], pkgs[1]);
+
+        // packages outlive the original repo reference and load metadata on worker threads
+        drop(repo);
+        let handles: Vec<_> = pkgs
+            .into_iter()
+            .map(|p| thread::spawn(move || p.metadata().unwrap().description().to_string()))
+            .collect();
+        let descs: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(descs, ["a", "b", "b2"]);
+    }
+
+    #[test]
+    fn test_lazy() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let (_, path) = t.create_ebuild("cat/a-1", None).unwrap();
+        let (_, repo) = repo::Repo::from_path("test", t.repo.path()).unwrap();
+        let pkg = Arc::new(repo).iter_owned().next().unwrap();
+
+        // nothing is loaded until first access
+        fs::write(&path, "EAPI=7\n").unwrap();
+        assert!(pkg.eapi.get().is_none() && pkg.meta.get().is_none());
+        assert_eq!(pkg.eapi(), &*eapi::EAPI7);
+        assert!(pkg.metadata().is_err());
+
+        // unreadable ebuilds fall back to EAPI 0
+        let cpv = atom::parse::cpv("cat/b-1").unwrap();
+        let pkg = OwnedPkg::new(cpv, pkg.repo());
+        assert_eq!(pkg.eapi(), &*eapi::EAPI0);
+
+        // installed packages only read their EAPI file
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("cat/c-1");
+        fs::create_dir_all(&path).unwrap();
+        let repo = repo::Repo::from_format("vdb", dir.path(), "vdb").unwrap();
+        let pkg = Arc::new(repo).iter_owned().next().unwrap();
+        fs::write(path.join("EAPI"), "6\n").unwrap();
+        assert_eq!(pkg.eapi(), &*eapi::EAPI6);
+        assert!(pkg.meta.get().is_none());
+    }
+
+    #[test]
+    fn test_fake() {
+        let r = fake::Repo::new("fake", ["cat/pkg-1"]).unwrap();
+        let repo = Arc::new(repo::Repo::Fake(r));
+        let pkgs: Vec<OwnedPkg> = repo.iter_owned().collect();
+        assert_eq!(pkgs.len(), 1);
+        let pkg = &pkgs[0];
+        assert!(pkg.path().is_none());
+        assert!(pkg.metadata().unwrap().description().is_empty());
+        assert_eq!(pkg.pkg().unwrap().atom(), pkg.atom());
+    }
+}
@@ -0,0 +1,80 @@
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+
+use once_cell::sync::OnceCell;
+
+use crate::pkg::ebuild::Metadata;
+use crate::{atom, eapi, pkg, repo, Error, Result};
+
+#[derive(Debug, Clone)]
+pub struct Pkg<'a> {
+    path: PathBuf,
+    atom: &'a atom::Atom,
+    eapi: &'static eapi::Eapi,
+    repo: &'a repo::vdb::Repo,
+    meta: OnceCell<Metadata>,
+}
+
+impl PartialEq for Pkg<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl Eq for Pkg<'_> {}
+
+impl<'a> Pkg<'a> {
+    pub(crate) fn new(atom: &'a atom::Atom, repo: &'a repo::vdb::Repo) -> Result<Self> {
+        let (cat, pf) = (atom.category(), atom.env("PF")?);
+        let path = repo.path().join(format!("{cat}/{pf}"));
+        let eapi = Pkg::get_eapi(&path)?;
+        Ok(Pkg {
+            path,
+            atom,
+            eapi,
+            repo,
+            meta: OnceCell::new(),
+        })
+    }
+
+    /// Get the EAPI recorded in a package database entry, defaulting to EAPI 0 if unset.
+    pub(crate) fn get_eapi<P: AsRef<Path>>(path: P) -> Result<&'static eapi::Eapi> {
+        let path = path.as_ref().join("EAPI");
+        match fs::read_to_string(&path) {
+            Ok(s) => eapi::get_eapi(s.trim()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => eapi::get_eapi("0"),
+            Err(e) => Err(Error::IO(format!("failed reading {path:?}: {e}"))),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the metadata recorded when the package was installed, loading it on first access.
+    pub fn metadata(&self) -> Result<&Metadata> {
+        self.meta.get_or_try_init(|| Metadata::load_dir(&self.path))
+    }
+}
+
+impl fmt::Display for Pkg<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.atom)
+    }
+}
+
+impl<'a> pkg::Package for Pkg<'a> {
+    type Repo = &'a repo::vdb::Repo;
+
+    fn atom(&self) -> &atom::Atom {
+        self.atom
+    }
+
+    fn eapi(&self) -> &eapi::Eapi {
+        self.eapi
+    }
+
+    fn repo(&self) -> Self::Repo {
+        self.repo
+    }
+}
@@ -1,14 +1,15 @@
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::{io, str};
 
-use indexmap::IndexSet;
+use indexmap::{IndexMap, IndexSet};
 use scallop::builtins::{ExecStatus, ScopedOptions};
 use scallop::variables::*;
 use scallop::{functions, source, Error, Result};
 
 use crate::eapi::Eapi;
+use crate::repo::eclass::Eclass;
 
 pub mod builtins;
 mod install;
@@ -193,6 +194,10 @@ pub struct BuildData {
     pub inherit: Vec<String>,
     /// Full set of eclasses inherited by an ebuild.
     pub inherited: IndexSet<String>,
+    /// Eclass directories mapped to their repo ids, ordered by precedence.
+    pub eclass_dirs: Vec<(String, PathBuf)>,
+    /// Sourced eclasses including their providing repo and checksum for cache entries.
+    pub eclasses: IndexMap<String, Eclass>,
 
     // ebuild metadata fields
     pub iuse: VecDeque<String>,
@@ -207,7 +212,7 @@ pub struct BuildData {
 }
 
 impl BuildData {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         let mut data = BuildData::default();
         // set build state defaults
         data.insopts.push("-m0644".into());
@@ -226,7 +231,7 @@ impl BuildData {
         install::Install::new(self)
     }
 
-    fn get_deque(&mut self, name: &str) -> &mut VecDeque<String> {
+    pub(crate) fn get_deque(&mut self, name: &str) -> &mut VecDeque<String> {
         match name {
             "IUSE" => &mut self.iuse,
             "REQUIRED_USE" => &mut self.required_use,
@@ -240,8 +245,41 @@ impl BuildData {
             s => panic!("unknown field name: {s}"),
         }
     }
+
+    /// Set the repo an ebuild is sourced from, resolving eclass lookups across its masters.
+    pub fn set_repo(&mut self, repo: &crate::repo::ebuild::Repo) -> crate::Result<()> {
+        self.repo = repo.path().to_string_lossy().to_string();
+        self.eclass_dirs = repo.eclass_dirs()?;
+        Ok(())
+    }
+
+    /// Return the named eclass from the highest precedence repo providing it, falling back to
+    /// the repo path when it was set directly without resolving its masters.
+    pub fn find_eclass(&self, name: &str) -> Option<Eclass> {
+        match (self.eclass_dirs.is_empty(), self.repo.is_empty()) {
+            (true, false) => {
+                let dirs = [(self.repo.clone(), Path::new(&self.repo).join("eclass"))];
+                crate::repo::ebuild::find_eclass(&dirs, name)
+            }
+            _ => crate::repo::ebuild::find_eclass(&self.eclass_dirs, name),
+        }
+    }
+
+    /// Set the package being built, pulling its final USE flags and effective IUSE from its
+    /// configuration.
+    pub fn set_pkg(&mut self, pkg: &crate::repo::configured::Pkg) -> crate::Result<()> {
+        use crate::pkg::Package;
+        self.set_repo(pkg.repo().repo())?;
+        self.eapi = pkg.pkg().metadata()?.eapi()?;
+        self.iuse_effective = pkg.iuse_effective()?.into_iter().collect();
+        self.use_ = pkg.use_()?.iter().cloned().collect();
+        Ok(())
+    }
 }
 
+/// Package variables set before sourcing an ebuild.
+const PKG_VARS: [&str; 7] = ["P", "PN", "PV", "PR", "PVR", "PF", "CATEGORY"];
+
 thread_local! {
     pub static BUILD_DATA: RefCell<BuildData> = RefCell::new(BuildData::new());
 }
@@ -335,6 +373,15 @@ impl<'a> PkgShell<'a> {
         })
     }
 
+    /// Source a package's ebuild with its package variables bound.
+    pub fn source_pkg(&mut self, pkg: &crate::pkg::ebuild::Pkg) -> crate::Result<()> {
+        let err = |e: Error| crate::Error::InvalidValue(format!("failed sourcing: {e}"));
+        for var in PKG_VARS {
+            bind(var, pkg.env(var)?, None, None).map_err(err)?;
+        }
+        self.source_ebuild(pkg.path()).map_err(err)
+    }
+
     pub fn reset(&mut self) {
         self.sh.reset()
     }
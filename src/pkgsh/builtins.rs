@@ -258,6 +258,19 @@ pub(crate) static BUILTINS_MAP: Lazy<EapiBuiltinsMap> = Lazy::new(|| {
     builtins_map
 });
 
+/// Return the builtins supported by any EAPI, used when creating a shell.
+pub(crate) fn all() -> Vec<&'static Builtin> {
+    let mut builtins: Vec<&Builtin> = BUILTINS_MAP
+        .values()
+        .flat_map(|scopes| scopes.values())
+        .flat_map(|map| map.values())
+        .map(|b| &b.builtin)
+        .collect();
+    builtins.sort_by_key(|b| b.name);
+    builtins.dedup_by_key(|b| b.name);
+    builtins
+}
+
 static NONFATAL: Lazy<AtomicBool> = Lazy::new(|| AtomicBool::new(false));
 
 static VERSION_RE: Lazy<Regex> = Lazy::new(|| {
@@ -41,8 +41,15 @@ pub(crate) fn run(args: &[&str]) -> Result<ExecStatus> {
                 unbind(var)?;
             }
 
+            let file = d
+                .borrow()
+                .find_eclass(&eclass)
+                .ok_or_else(|| Error::Builtin(format!("unknown eclass: {eclass}")))?;
+
             eclass_var.bind(&eclass, None, None)?;
-            source::file(&format!("{}/eclass/{eclass}.eclass", d.borrow().repo)).unwrap();
+            let path = file.path().to_string_lossy().to_string();
+            source::file(&path)
+                .map_err(|e| Error::Builtin(format!("failed loading eclass: {eclass}: {e}")))?;
 
             let mut d = d.borrow_mut();
             // append metadata keys that incrementally accumulate
@@ -54,6 +61,7 @@ pub(crate) fn run(args: &[&str]) -> Result<ExecStatus> {
             }
 
             inherited_var.append(&format!(" {eclass}"))?;
+            d.eclasses.insert(eclass.clone(), file);
             d.inherited.insert(eclass);
         }
 
@@ -80,11 +88,30 @@ pub(super) static BUILTIN: Lazy<PkgBuiltin> = Lazy::new(|| {
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+
     use super::super::assert_invalid_args;
     use super::run as inherit;
+    use crate::pkgsh::BuildData;
+    use crate::repo::ebuild::TempRepo;
 
     #[test]
     fn invalid_args() {
         assert_invalid_args(inherit, &[0]);
     }
+
+    #[test]
+    fn repo_path() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let dir = t.repo.path().join("eclass");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("e1.eclass"), "# e1\n").unwrap();
+
+        // eclasses are found when the repo path is set directly
+        let mut data = BuildData::default();
+        assert!(data.find_eclass("e1").is_none());
+        data.repo = t.repo.path().to_string_lossy().to_string();
+        assert_eq!(data.find_eclass("e1").unwrap().path(), dir.join("e1.eclass"));
+        assert!(data.find_eclass("e2").is_none());
+    }
 }
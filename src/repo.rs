@@ -1,15 +1,33 @@
 use std::fmt;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use indexmap::{IndexMap, IndexSet};
 use once_cell::sync::Lazy;
 use tracing::warn;
 
-use crate::pkg::Pkg;
+use crate::pkg::{OwnedPkg, Pkg};
 use crate::{atom, Error, Result};
 
+pub(crate) mod cache;
+pub mod changes;
+pub mod configured;
 pub(crate) mod ebuild;
+pub mod eclass;
 pub(crate) mod fake;
+pub mod glsa;
+pub mod index;
+pub mod manifest;
+pub mod mask;
+pub mod news;
+pub mod profile;
+pub mod revdeps;
+pub mod search;
+pub mod set;
+pub mod stabilize;
+pub mod updates;
+pub mod use_desc;
+pub(crate) mod vdb;
 
 type VersionMap = IndexMap<String, IndexSet<String>>;
 type PkgMap = IndexMap<String, VersionMap>;
@@ -107,6 +125,7 @@ impl<'a> FromIterator<&'a str> for PkgCache {
 pub enum Repo {
     Ebuild(ebuild::Repo),
     Fake(fake::Repo),
+    Vdb(vdb::Repo),
 }
 
 impl Repo {
@@ -120,6 +139,9 @@ impl Repo {
     }
 
     /// Try to load a repo from a given path.
+    ///
+    /// Package databases are skipped since any directory could be one, so they must be loaded
+    /// via their format explicitly.
     pub(crate) fn from_path<P, S>(id: S, path: P) -> Result<(&'static str, Self)>
     where
         P: AsRef<Path>,
@@ -128,7 +150,10 @@ impl Repo {
         let path = path.as_ref();
         let id = id.as_ref();
 
-        for format in SUPPORTED_FORMATS.iter() {
+        for format in SUPPORTED_FORMATS
+            .iter()
+            .filter(|f| **f != vdb::Repo::FORMAT)
+        {
             if let Ok(repo) = Self::from_format(id, path, format) {
                 return Ok((format, repo));
             }
@@ -152,18 +177,49 @@ impl Repo {
         match format {
             ebuild::Repo::FORMAT => Ok(Repo::Ebuild(ebuild::Repo::from_path(id, path)?)),
             fake::Repo::FORMAT => Ok(Repo::Fake(fake::Repo::from_path(id, path)?)),
+            vdb::Repo::FORMAT => Ok(Repo::Vdb(vdb::Repo::from_path(id, path)?)),
             _ => Err(Error::RepoInit(format!("{id} repo: unknown format: {format}"))),
         }
     }
 
+    /// Use persistent data within a given cache directory for repo formats supporting it.
+    pub(crate) fn set_cache_dir(&mut self, cache: &Path) {
+        if let Repo::Ebuild(repo) = self {
+            repo.set_cache_dir(cache);
+        }
+    }
+
+    /// Return the repo's format.
+    pub fn format(&self) -> &'static str {
+        match self {
+            Repo::Ebuild(_) => ebuild::Repo::FORMAT,
+            Repo::Fake(_) => fake::Repo::FORMAT,
+            Repo::Vdb(_) => vdb::Repo::FORMAT,
+        }
+    }
+
     pub fn iter(&self) -> PackageIter {
         self.into_iter()
     }
+
+    /// Return an iterator of owned packages sharing the repo.
+    pub fn iter_owned(self: Arc<Self>) -> OwnedPackageIter {
+        OwnedPackageIter { repo: self, idx: 0 }
+    }
+
+    fn pkgs(&self) -> &PkgCache {
+        match self {
+            Repo::Ebuild(ref repo) => repo.pkgs(),
+            Repo::Fake(ref repo) => repo.pkgs(),
+            Repo::Vdb(ref repo) => repo.pkgs(),
+        }
+    }
 }
 
 pub enum PackageIter<'a> {
     Ebuild(ebuild::PkgIter<'a>),
     Fake(fake::PkgIter<'a>),
+    Vdb(vdb::PkgIter<'a>),
 }
 
 impl<'a> IntoIterator for &'a Repo {
@@ -174,6 +230,7 @@ impl<'a> IntoIterator for &'a Repo {
         match self {
             Repo::Ebuild(ref repo) => PackageIter::Ebuild(repo.into_iter()),
             Repo::Fake(ref repo) => PackageIter::Fake(repo.into_iter()),
+            Repo::Vdb(ref repo) => PackageIter::Vdb(repo.into_iter()),
         }
     }
 }
@@ -185,16 +242,34 @@ impl<'a> Iterator for PackageIter<'a> {
         match self {
             PackageIter::Ebuild(iter) => iter.next().map(Pkg::Ebuild),
             PackageIter::Fake(iter) => iter.next().map(Pkg::Fake),
+            PackageIter::Vdb(iter) => iter.next().map(Pkg::Vdb),
         }
     }
 }
 
+/// Iterator of owned packages that can be moved across threads.
+pub struct OwnedPackageIter {
+    repo: Arc<Repo>,
+    idx: usize,
+}
+
+impl Iterator for OwnedPackageIter {
+    type Item = OwnedPkg;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let atom = self.repo.pkgs().atoms.get_index(self.idx)?;
+        self.idx += 1;
+        Some(OwnedPkg::new(atom.clone(), self.repo.clone()))
+    }
+}
+
 // externally supported repo formats
 #[rustfmt::skip]
 static SUPPORTED_FORMATS: Lazy<IndexSet<&'static str>> = Lazy::new(|| {
     [
         ebuild::Repo::FORMAT,
         fake::Repo::FORMAT,
+        vdb::Repo::FORMAT,
     ].iter().cloned().collect()
 });
 
@@ -212,6 +287,7 @@ impl fmt::Display for Repo {
         match self {
             Repo::Ebuild(ref repo) => write!(f, "{}", repo),
             Repo::Fake(ref repo) => write!(f, "{}", repo),
+            Repo::Vdb(ref repo) => write!(f, "{}", repo),
         }
     }
 }
@@ -222,6 +298,7 @@ impl Repository for Repo {
         match self {
             Repo::Ebuild(ref repo) => repo.categories(),
             Repo::Fake(ref repo) => repo.categories(),
+            Repo::Vdb(ref repo) => repo.categories(),
         }
     }
 
@@ -229,6 +306,7 @@ impl Repository for Repo {
         match self {
             Repo::Ebuild(ref repo) => repo.packages(cat),
             Repo::Fake(ref repo) => repo.packages(cat),
+            Repo::Vdb(ref repo) => repo.packages(cat),
         }
     }
 
@@ -236,6 +314,7 @@ impl Repository for Repo {
         match self {
             Repo::Ebuild(ref repo) => repo.versions(cat, pkg),
             Repo::Fake(ref repo) => repo.versions(cat, pkg),
+            Repo::Vdb(ref repo) => repo.versions(cat, pkg),
         }
     }
 
@@ -243,6 +322,7 @@ impl Repository for Repo {
         match self {
             Repo::Ebuild(ref repo) => repo.id(),
             Repo::Fake(ref repo) => repo.id(),
+            Repo::Vdb(ref repo) => repo.id(),
         }
     }
 
@@ -250,6 +330,7 @@ impl Repository for Repo {
         match self {
             Repo::Ebuild(ref repo) => repo.len(),
             Repo::Fake(ref repo) => repo.len(),
+            Repo::Vdb(ref repo) => repo.len(),
         }
     }
 
@@ -257,6 +338,7 @@ impl Repository for Repo {
         match self {
             Repo::Ebuild(ref repo) => repo.is_empty(),
             Repo::Fake(ref repo) => repo.is_empty(),
+            Repo::Vdb(ref repo) => repo.is_empty(),
         }
     }
 }
@@ -271,6 +353,7 @@ impl<T: AsRef<Path>> Contains<T> for Repo {
         match self {
             Repo::Ebuild(ref repo) => repo.contains(path),
             Repo::Fake(ref repo) => repo.contains(path),
+            Repo::Vdb(ref repo) => repo.contains(path),
         }
     }
 }
@@ -282,6 +365,7 @@ macro_rules! make_contains {
                 match self {
                     Repo::Ebuild(ref repo) => repo.contains(obj),
                     Repo::Fake(ref repo) => repo.contains(obj),
+                    Repo::Vdb(ref repo) => repo.contains(obj),
                 }
             }
         }
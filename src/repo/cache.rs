@@ -0,0 +1,269 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use md5::{Digest, Md5};
+use scallop::variables::string_value;
+use scallop::{functions, Shell};
+
+use crate::files::{is_file, is_hidden, sorted_dir_list};
+use crate::pkg::ebuild::{Metadata, Pkg};
+use crate::pkg::Package;
+use crate::pkgsh::{builtins, BuildData, PkgShell, BUILD_DATA};
+use crate::repo::ebuild::{self, find_eclass};
+use crate::{Error, Result};
+
+/// Metadata keys pulled from ebuild variables.
+const KEYS: [&str; 15] = [
+    "BDEPEND",
+    "DEPEND",
+    "DESCRIPTION",
+    "HOMEPAGE",
+    "IDEPEND",
+    "IUSE",
+    "KEYWORDS",
+    "LICENSE",
+    "PDEPEND",
+    "PROPERTIES",
+    "RDEPEND",
+    "REQUIRED_USE",
+    "RESTRICT",
+    "SLOT",
+    "SRC_URI",
+];
+
+fn md5(path: &Path) -> Result<String> {
+    let data = fs::read(path).map_err(|e| Error::IO(format!("failed reading {path:?}: {e}")))?;
+    Ok(format!("{:x}", Md5::digest(&data)))
+}
+
+/// Determine if a package's md5-cache entry is missing or outdated compared to its ebuild and
+/// inherited eclasses.
+fn is_stale(pkg: &Pkg, eclass_dirs: &[(String, PathBuf)]) -> bool {
+    let meta = match Metadata::load(pkg.cache_path()) {
+        Ok(meta) => meta,
+        Err(_) => return true,
+    };
+    if meta.get("_md5_") != md5(pkg.path()).ok().as_deref() {
+        return true;
+    }
+    let eclasses: Vec<&str> = meta
+        .get("_eclasses_")
+        .unwrap_or_default()
+        .split_whitespace()
+        .collect();
+    eclasses
+        .chunks(2)
+        .any(|x| match (x, find_eclass(eclass_dirs, x[0])) {
+            ([_, chksum], Some(eclass)) => eclass.chksum() != *chksum,
+            _ => true,
+        })
+}
+
+/// Source a package's ebuild, returning its md5-cache entry.
+fn source(sh: &mut Shell, repo: &ebuild::Repo, pkg: &Pkg) -> Result<String> {
+    let eapi = Pkg::get_eapi(pkg.path())?;
+    let mut data = BuildData::default();
+    data.eapi = eapi;
+    data.set_repo(repo)?;
+
+    let mut pkgsh = PkgShell::new(sh, data);
+    let entry = pkgsh.source_pkg(pkg).map(|_| {
+        BUILD_DATA.with(|d| {
+            let mut d = d.borrow_mut();
+            let mut meta = BTreeMap::new();
+            for key in KEYS {
+                let val = match eapi.incremental_keys().contains(key) {
+                    true => d
+                        .get_deque(key)
+                        .iter()
+                        .map(|s| s.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    false => string_value(key).unwrap_or_default(),
+                };
+                meta.insert(key, val.split_whitespace().collect::<Vec<_>>().join(" "));
+            }
+
+            let mut phases: Vec<&str> = eapi
+                .phases()
+                .keys()
+                .filter(|phase| functions::find(phase).is_some())
+                .map(|phase| phase.split_once('_').map_or(*phase, |(_, name)| name))
+                .collect();
+            phases.sort_unstable();
+            let phases = match phases.is_empty() {
+                true => "-".to_string(),
+                false => phases.join(" "),
+            };
+
+            meta.insert("DEFINED_PHASES", phases);
+            meta.insert("EAPI", eapi.as_str().to_string());
+            meta.insert("INHERIT", d.inherit.join(" "));
+            let eclasses: Vec<&str> = d
+                .eclasses
+                .values()
+                .flat_map(|e| [e.name(), e.chksum()])
+                .collect();
+            meta.insert("_eclasses_", eclasses.join("\t"));
+
+            meta.into_iter()
+                .filter(|(_, val)| !val.is_empty())
+                .map(|(key, val)| format!("{key}={val}\n"))
+                .collect::<String>()
+        })
+    });
+
+    pkgsh.reset();
+    Ok(format!("{}_md5_={}\n", entry?, md5(pkg.path())?))
+}
+
+/// Atomically write an md5-cache entry.
+fn write(path: &Path, entry: &str) -> Result<()> {
+    let err = |e: std::io::Error| Error::IO(format!("failed writing {path:?}: {e}"));
+    fs::create_dir_all(path.parent().unwrap()).map_err(err)?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{name}.tmp"));
+    fs::write(&tmp, entry)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(err)
+}
+
+/// Regenerate the missing or outdated md5-cache entries for a repo and remove the entries of
+/// nonexistent packages, returning the number of regenerated entries.
+///
+/// Ebuilds are sourced sequentially since the underlying shell is global to the process.
+pub(crate) fn regen(repo: &ebuild::Repo) -> Result<usize> {
+    let eclass_dirs = repo.eclass_dirs()?;
+    let pkgs: Vec<_> = repo.iter().collect();
+
+    let dir = repo.path().join("metadata/md5-cache");
+    if dir.exists() {
+        let entries: HashSet<PathBuf> = pkgs.iter().map(|p| p.cache_path()).collect();
+        let walker = sorted_dir_list(&dir).min_depth(2).max_depth(2);
+        for entry in walker.into_iter().filter_entry(|e| !is_hidden(e)) {
+            let entry = entry.map_err(|e| Error::IO(format!("failed reading {dir:?}: {e}")))?;
+            if is_file(&entry) && !entries.contains(entry.path()) {
+                fs::remove_file(entry.path())
+                    .map_err(|e| Error::IO(format!("failed removing {:?}: {e}", entry.path())))?;
+            }
+        }
+    }
+
+    let stale: Vec<_> = pkgs.iter().filter(|p| is_stale(p, &eclass_dirs)).collect();
+    if stale.is_empty() {
+        return Ok(0);
+    }
+
+    let mut sh = Shell::new("pkgcraft", Some(builtins::all()));
+    let mut errors = vec![];
+    for pkg in &stale {
+        if let Err(e) = source(&mut sh, repo, pkg).and_then(|s| write(&pkg.cache_path(), &s)) {
+            errors.push(format!("{}: {e}", pkg.atom()));
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(stale.len()),
+        false => Err(Error::InvalidValue(format!(
+            "failed regenerating metadata:\n\t{}",
+            errors.join("\n\t")
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusty_fork::rusty_fork_test;
+
+    use crate::repo::ebuild::TempRepo;
+
+    use super::*;
+
+    #[test]
+    fn test_regen() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let (_, path) = t.create_ebuild("cat/pkg-1", None).unwrap();
+        let eclass_dir = t.repo.path().join("eclass");
+        fs::create_dir_all(&eclass_dir).unwrap();
+        fs::write(eclass_dir.join("e1.eclass"), "# e1\n").unwrap();
+        let eclass_dirs = t.repo.eclass_dirs().unwrap();
+        let chksum = t.repo.eclass("e1").unwrap().chksum().to_string();
+        let pkg = t.repo.iter().next().unwrap();
+
+        // missing entry
+        assert!(is_stale(&pkg, &eclass_dirs));
+
+        // outdated ebuild
+        let md5 = md5(&path).unwrap();
+        t.create_metadata("cat/pkg-1", &[("_md5_", "0")]).unwrap();
+        assert!(is_stale(&pkg, &eclass_dirs));
+
+        // outdated and nonexistent eclasses
+        let eclasses = format!("e1\t{chksum}");
+        t.create_metadata("cat/pkg-1", &[("_eclasses_", &eclasses), ("_md5_", &md5)])
+            .unwrap();
+        assert!(!is_stale(&pkg, &eclass_dirs));
+        fs::write(eclass_dir.join("e1.eclass"), "# modified\n").unwrap();
+        assert!(is_stale(&pkg, &eclass_dirs));
+        t.create_metadata("cat/pkg-1", &[("_eclasses_", "e2\t0"), ("_md5_", &md5)])
+            .unwrap();
+        assert!(is_stale(&pkg, &eclass_dirs));
+
+        // up to date entries are left alone while orphaned ones are removed
+        let entry = t.create_metadata("cat/pkg-1", &[("_md5_", &md5)]).unwrap();
+        let orphan = t.create_metadata("cat/pkg-2", &[("_md5_", &md5)]).unwrap();
+        assert_eq!(regen(&t.repo).unwrap(), 0);
+        assert!(entry.exists());
+        assert!(!orphan.exists());
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_source() {
+            let t = TempRepo::new("test", None::<&str>, None).unwrap();
+            let eclass_dir = t.repo.path().join("eclass");
+            fs::create_dir_all(&eclass_dir).unwrap();
+            fs::write(eclass_dir.join("e1.eclass"), "IUSE=\"b\"\nDEPEND=\"cat/dep\"\n").unwrap();
+            let (_, path) = t.create_ebuild("cat/pkg-1", None).unwrap();
+            let data = indoc::indoc! {r#"
+                EAPI=8
+                inherit e1
+                DESCRIPTION="testing regen"
+                SLOT="0"
+                IUSE="a"
+                src_install() { :; }
+            "#};
+            fs::write(&path, data).unwrap();
+
+            // entries are generated from the sourced ebuild and its eclasses
+            assert_eq!(regen(&t.repo).unwrap(), 1);
+            let pkg = t.repo.iter().next().unwrap();
+            let meta = Metadata::load(pkg.cache_path()).unwrap();
+            let chksum = t.repo.eclass("e1").unwrap().chksum().to_string();
+            let md5 = md5(&path).unwrap();
+            let eclasses = format!("e1\t{chksum}");
+            for (key, val) in [
+                ("DEFINED_PHASES", "install"),
+                ("DEPEND", "cat/dep"),
+                ("DESCRIPTION", "testing regen"),
+                ("EAPI", "8"),
+                ("INHERIT", "e1"),
+                ("IUSE", "a b"),
+                ("SLOT", "0"),
+                ("_eclasses_", &eclasses),
+                ("_md5_", &md5),
+            ] {
+                assert_eq!(meta.get(key), Some(val), "invalid {key}");
+            }
+            assert!(meta.get("RDEPEND").is_none());
+            assert_eq!(regen(&t.repo).unwrap(), 0);
+
+            // regenerating again creates another shell in the same process
+            fs::write(&path, data.replace("testing regen", "updated")).unwrap();
+            assert_eq!(regen(&t.repo).unwrap(), 1);
+            let meta = Metadata::load(pkg.cache_path()).unwrap();
+            assert_eq!(meta.description(), "updated");
+        }
+    }
+}
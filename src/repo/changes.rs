@@ -0,0 +1,402 @@
+use std::fmt;
+
+use indexmap::{IndexMap, IndexSet};
+use tracing::warn;
+
+use crate::pkg::Package;
+use crate::repo::{ebuild, Repository};
+use crate::restrict::Restrict;
+use crate::Result;
+
+/// State of an ebuild repo captured before a sync, used to report what changed afterwards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    keys: IndexSet<String>,
+    cpvs: IndexMap<String, Vec<String>>,
+    masks: Option<IndexSet<String>>,
+    news: IndexSet<String>,
+}
+
+impl Default for Snapshot {
+    /// Return the snapshot of an empty repo.
+    fn default() -> Self {
+        Snapshot {
+            keys: IndexSet::new(),
+            cpvs: IndexMap::new(),
+            masks: Some(IndexSet::new()),
+            news: IndexSet::new(),
+        }
+    }
+}
+
+impl Snapshot {
+    /// Capture the package versions and their keywords, masked atoms, and news items of a repo.
+    pub fn new(repo: &ebuild::Repo) -> Result<Self> {
+        let id = repo.id();
+        let pkgs = repo.par_map(Restrict::True, 0, |pkg| {
+            let keywords = match pkg.metadata() {
+                Ok(m) => m.keywords().iter().map(|s| s.to_string()).collect(),
+                Err(e) => {
+                    warn!("{id}: {}: {e}", pkg.atom());
+                    vec![]
+                }
+            };
+            (pkg.atom().key(), pkg.atom().cpv(), keywords)
+        })?;
+        let mut keys = IndexSet::new();
+        let mut cpvs = IndexMap::new();
+        for (key, cpv, keywords) in pkgs {
+            keys.insert(key);
+            cpvs.insert(cpv, keywords);
+        }
+
+        let masks = match repo.package_mask() {
+            Ok(mask) => Some(
+                mask.entries()
+                    .iter()
+                    .flat_map(|e| e.atoms())
+                    .map(|a| a.to_string())
+                    .collect(),
+            ),
+            Err(e) => {
+                warn!("{id}: {e}");
+                None
+            }
+        };
+
+        let news = match repo.news() {
+            Ok(news) => news.iter().map(|n| n.name().to_string()).collect(),
+            Err(e) => {
+                warn!("{id}: {e}");
+                IndexSet::new()
+            }
+        };
+
+        Ok(Snapshot {
+            keys,
+            cpvs,
+            masks,
+            news,
+        })
+    }
+
+    /// Compare the snapshot against a newer one of the same repo.
+    pub fn diff<S: AsRef<str>>(&self, repo: S, new: &Snapshot) -> RepoChanges {
+        let added = |old: &IndexSet<&str>, new: &IndexSet<&str>| -> Vec<String> {
+            new.difference(old).map(|s| s.to_string()).collect()
+        };
+        let old_keys: IndexSet<&str> = self.keys.iter().map(|s| s.as_str()).collect();
+        let new_keys: IndexSet<&str> = new.keys.iter().map(|s| s.as_str()).collect();
+        let old_cpvs: IndexSet<&str> = self.cpvs.keys().map(|s| s.as_str()).collect();
+        let new_cpvs: IndexSet<&str> = new.cpvs.keys().map(|s| s.as_str()).collect();
+        let old_news: IndexSet<&str> = self.news.iter().map(|s| s.as_str()).collect();
+        let new_news: IndexSet<&str> = new.news.iter().map(|s| s.as_str()).collect();
+
+        let mut keywords = vec![];
+        for (cpv, new_kws) in &new.cpvs {
+            if let Some(old_kws) = self.cpvs.get(cpv) {
+                let change = KeywordChange {
+                    cpv: cpv.clone(),
+                    added: new_kws
+                        .iter()
+                        .filter(|k| !old_kws.contains(k))
+                        .cloned()
+                        .collect(),
+                    removed: old_kws
+                        .iter()
+                        .filter(|k| !new_kws.contains(k))
+                        .cloned()
+                        .collect(),
+                };
+                if !change.added.is_empty() || !change.removed.is_empty() {
+                    keywords.push(change);
+                }
+            }
+        }
+
+        // skip diffing masks if they failed loading for either snapshot
+        let (masks_added, masks_removed) = match (&self.masks, &new.masks) {
+            (Some(old), Some(new)) => {
+                let old_masks: IndexSet<&str> = old.iter().map(|s| s.as_str()).collect();
+                let new_masks: IndexSet<&str> = new.iter().map(|s| s.as_str()).collect();
+                (added(&old_masks, &new_masks), added(&new_masks, &old_masks))
+            }
+            _ => (vec![], vec![]),
+        };
+
+        RepoChanges {
+            repo: repo.as_ref().to_string(),
+            pkgs_added: added(&old_keys, &new_keys),
+            pkgs_removed: added(&new_keys, &old_keys),
+            versions_added: added(&old_cpvs, &new_cpvs),
+            versions_removed: added(&new_cpvs, &old_cpvs),
+            keywords,
+            masks_added,
+            masks_removed,
+            news: added(&old_news, &new_news),
+            commit: None,
+            hook_errors: vec![],
+        }
+    }
+}
+
+/// Keywords added to or removed from an existing package version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeywordChange {
+    cpv: String,
+    added: Vec<String>,
+    removed: Vec<String>,
+}
+
+impl KeywordChange {
+    pub fn cpv(&self) -> &str {
+        &self.cpv
+    }
+
+    pub fn added(&self) -> &[String] {
+        &self.added
+    }
+
+    pub fn removed(&self) -> &[String] {
+        &self.removed
+    }
+}
+
+/// Changes to a repo between two snapshots, e.g. before and after a sync.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RepoChanges {
+    repo: String,
+    pkgs_added: Vec<String>,
+    pkgs_removed: Vec<String>,
+    versions_added: Vec<String>,
+    versions_removed: Vec<String>,
+    keywords: Vec<KeywordChange>,
+    masks_added: Vec<String>,
+    masks_removed: Vec<String>,
+    news: Vec<String>,
+    commit: Option<String>,
+    hook_errors: Vec<String>,
+}
+
+impl RepoChanges {
+    /// Set the commit id a git repo was synced to.
+    pub(crate) fn with_commit(mut self, commit: Option<String>) -> Self {
+        self.commit = commit;
+        self
+    }
+
+    /// Set the errors of post-sync hooks that failed for the repo.
+    pub(crate) fn with_hook_errors(mut self, errors: Vec<String>) -> Self {
+        self.hook_errors = errors;
+        self
+    }
+
+    /// Return the id of the changed repo.
+    pub fn repo(&self) -> &str {
+        &self.repo
+    }
+
+    /// Return the keys of packages that didn't previously exist.
+    pub fn pkgs_added(&self) -> &[String] {
+        &self.pkgs_added
+    }
+
+    /// Return the keys of packages that no longer exist.
+    pub fn pkgs_removed(&self) -> &[String] {
+        &self.pkgs_removed
+    }
+
+    /// Return the added package versions, including those of new packages.
+    pub fn versions_added(&self) -> &[String] {
+        &self.versions_added
+    }
+
+    /// Return the removed package versions, including those of removed packages.
+    pub fn versions_removed(&self) -> &[String] {
+        &self.versions_removed
+    }
+
+    /// Return keyword changes for package versions existing before and after.
+    pub fn keywords(&self) -> &[KeywordChange] {
+        &self.keywords
+    }
+
+    /// Return atoms newly added to profiles/package.mask.
+    pub fn masks_added(&self) -> &[String] {
+        &self.masks_added
+    }
+
+    /// Return atoms dropped from profiles/package.mask.
+    pub fn masks_removed(&self) -> &[String] {
+        &self.masks_removed
+    }
+
+    /// Return the names of new news items.
+    pub fn news(&self) -> &[String] {
+        &self.news
+    }
+
+    /// Return the commit id for repos synced from git.
+    pub fn commit(&self) -> Option<&str> {
+        self.commit.as_deref()
+    }
+
+    /// Return the errors of post-sync hooks that failed for the repo.
+    pub fn hook_errors(&self) -> &[String] {
+        &self.hook_errors
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.versions_added.is_empty()
+            && self.versions_removed.is_empty()
+            && self.keywords.is_empty()
+            && self.masks_added.is_empty()
+            && self.masks_removed.is_empty()
+            && self.news.is_empty()
+    }
+}
+
+impl fmt::Display for RepoChanges {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.repo)?;
+        if self.is_empty() && self.hook_errors.is_empty() {
+            return write!(f, " no changes");
+        }
+        for (prefix, vals) in [
+            ("new package", &self.pkgs_added),
+            ("removed package", &self.pkgs_removed),
+            ("new version", &self.versions_added),
+            ("removed version", &self.versions_removed),
+            ("masked", &self.masks_added),
+            ("unmasked", &self.masks_removed),
+            ("news", &self.news),
+            ("hook failed", &self.hook_errors),
+        ] {
+            for val in vals {
+                write!(f, "\n  {prefix}: {val}")?;
+            }
+        }
+        for change in &self.keywords {
+            let kws = change
+                .added
+                .iter()
+                .map(|k| format!("+{k}"))
+                .chain(change.removed.iter().map(|k| format!("-{k}")))
+                .collect::<Vec<_>>()
+                .join(" ");
+            write!(f, "\n  keywords: {}: {kws}", change.cpv)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::repo::ebuild::TempRepo;
+
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let path = t.repo.path();
+        for (cpv, keywords) in
+            [("cat/a-1", "amd64 ~x86"), ("cat/a-2", "~amd64"), ("cat/b-1-r1", "amd64")]
+        {
+            t.create_ebuild(cpv, None).unwrap();
+            t.create_metadata(cpv, &[("EAPI", "8"), ("KEYWORDS", keywords)])
+                .unwrap();
+        }
+        fs::write(path.join("profiles/package.mask"), "cat/a\n=cat/b-1-r1\n").unwrap();
+        let (_, repo) = crate::repo::Repo::from_path("test", path).unwrap();
+        let old = match &repo {
+            crate::repo::Repo::Ebuild(r) => Snapshot::new(r).unwrap(),
+            _ => panic!("invalid repo"),
+        };
+        assert_eq!(old.keys.iter().collect::<Vec<_>>(), ["cat/a", "cat/b"]);
+
+        // identical snapshots have no changes
+        let changes = old.diff("test", &old);
+        assert!(changes.is_empty());
+        assert_eq!(changes.to_string(), "test: no changes");
+
+        // modify the repo
+        fs::remove_file(path.join("cat/b/b-1-r1.ebuild")).unwrap();
+        t.create_ebuild("cat/a-3", None).unwrap();
+        t.create_metadata("cat/a-3", &[("EAPI", "8")]).unwrap();
+        t.create_ebuild("cat/c-1", None).unwrap();
+        t.create_metadata("cat/c-1", &[("EAPI", "8")]).unwrap();
+        t.create_metadata("cat/a-1", &[("EAPI", "8"), ("KEYWORDS", "amd64 x86")])
+            .unwrap();
+        fs::write(path.join("profiles/package.mask"), "cat/a\n>=cat/c-1\n").unwrap();
+        fs::create_dir_all(path.join("metadata/news/2022-01-01-item")).unwrap();
+        let news = indoc::indoc! {"
+            Title: News item
+            Author: A Dev <dev@gentoo.org>
+            Posted: 2022-01-01
+            Revision: 1
+            News-Item-Format: 2.0
+
+            Body.
+        "};
+        fs::write(path.join("metadata/news/2022-01-01-item/2022-01-01-item.en.txt"), news).unwrap();
+
+        let (_, repo) = crate::repo::Repo::from_path("test", path).unwrap();
+        let new = match &repo {
+            crate::repo::Repo::Ebuild(r) => Snapshot::new(r).unwrap(),
+            _ => panic!("invalid repo"),
+        };
+        let changes = old.diff("test", &new);
+        assert!(!changes.is_empty());
+        assert_eq!(changes.repo(), "test");
+        assert_eq!(changes.pkgs_added(), ["cat/c"]);
+        assert_eq!(changes.pkgs_removed(), ["cat/b"]);
+        assert_eq!(changes.versions_added(), ["cat/a-3", "cat/c-1"]);
+        assert_eq!(changes.versions_removed(), ["cat/b-1-r1"]);
+        assert_eq!(changes.keywords().len(), 1);
+        let kw = &changes.keywords()[0];
+        assert_eq!(kw.cpv(), "cat/a-1");
+        assert_eq!(kw.added(), ["x86"]);
+        assert_eq!(kw.removed(), ["~x86"]);
+        assert_eq!(changes.masks_added(), [">=cat/c-1"]);
+        assert_eq!(changes.masks_removed(), ["=cat/b-1-r1"]);
+        assert_eq!(changes.news(), ["2022-01-01-item"]);
+        assert_eq!(
+            changes.to_string(),
+            indoc::indoc! {"
+                test:
+                  new package: cat/c
+                  removed package: cat/b
+                  new version: cat/a-3
+                  new version: cat/c-1
+                  removed version: cat/b-1-r1
+                  masked: >=cat/c-1
+                  unmasked: =cat/b-1-r1
+                  news: 2022-01-01-item
+                  keywords: cat/a-1: +x86 -~x86"
+            }
+        );
+
+        // new repos report all their content
+        let changes = Snapshot::default().diff("test", &new);
+        assert_eq!(changes.pkgs_added(), ["cat/a", "cat/c"]);
+        assert_eq!(changes.masks_added(), ["cat/a", ">=cat/c-1"]);
+        assert!(changes.keywords().is_empty());
+
+        // masks aren't diffed when they fail loading
+        fs::write(path.join("profiles/package.mask"), "cat/a\n>=cat/c-1\n=cat/d\n").unwrap();
+        let (_, repo) = crate::repo::Repo::from_path("test", path).unwrap();
+        let broken = match &repo {
+            crate::repo::Repo::Ebuild(r) => Snapshot::new(r).unwrap(),
+            _ => panic!("invalid repo"),
+        };
+        assert!(broken.masks.is_none());
+        for (old, new) in [(&old, &broken), (&broken, &old)] {
+            let changes = old.diff("test", new);
+            assert!(changes.masks_added().is_empty());
+            assert!(changes.masks_removed().is_empty());
+        }
+    }
+}
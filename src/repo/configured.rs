@@ -0,0 +1,681 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{fmt, fs};
+
+use indexmap::{IndexMap, IndexSet};
+use once_cell::sync::OnceCell;
+use scallop::Shell;
+
+use crate::atom::Atom;
+use crate::depspec::{restrict, DepSpec};
+use crate::eapi;
+use crate::files::{is_file, is_hidden, sorted_dir_list};
+use crate::peg::peg_error;
+use crate::pkg::ebuild::DepClass;
+use crate::pkg::{self, Package};
+use crate::pkgsh::{BuildData, PkgShell};
+use crate::repo::mask::PackageMask;
+use crate::repo::profile::{parse_make_defaults, Profile};
+use crate::repo::{ebuild, Repository};
+use crate::restrict::{Restrict, Restriction};
+use crate::{Error, Result};
+
+pub(crate) type AtomEntries = Vec<(Atom, Vec<String>)>;
+
+/// Parse a file of `atom value...` lines, or all files in a directory of them, where USE_EXPAND
+/// values can be specified via `VAR: value...` and are converted to their flag equivalents.
+pub(crate) fn load_atom_entries(path: &Path) -> Result<AtomEntries> {
+    if path.is_dir() {
+        let mut entries = vec![];
+        let files = sorted_dir_list(path)
+            .into_iter()
+            .filter_entry(|e| is_file(e) && !is_hidden(e));
+        for entry in files {
+            let entry = entry.map_err(|e| Error::IO(format!("failed reading {path:?}: {e}")))?;
+            entries.extend(load_atom_entries(entry.path())?);
+        }
+        return Ok(entries);
+    }
+
+    let data = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(Error::IO(format!("failed reading {path:?}: {e}"))),
+    };
+
+    let mut entries = vec![];
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut tokens = line.split_whitespace();
+        let atom = Atom::from_str(tokens.next().unwrap())
+            .map_err(|e| Error::InvalidValue(format!("{path:?}: line {}: {e}", i + 1)))?;
+        let mut prefix = None;
+        let mut vals = vec![];
+        for token in tokens {
+            match token.strip_suffix(':') {
+                Some(var) => prefix = Some(var.to_lowercase()),
+                None => match (&prefix, token.strip_prefix('-')) {
+                    (Some(p), Some(v)) => vals.push(format!("-{p}_{v}")),
+                    (Some(p), None) => vals.push(format!("{p}_{token}")),
+                    (None, _) => vals.push(token.to_string()),
+                },
+            }
+        }
+        entries.push((atom, vals));
+    }
+    Ok(entries)
+}
+
+/// Apply incremental tokens to a set of values, where `-value` removes a value and `-*` removes
+/// all values.
+fn apply_incremental<'a, I>(values: &mut IndexSet<String>, tokens: I)
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    for token in tokens {
+        match token.strip_prefix('-') {
+            Some("*") => values.clear(),
+            Some(v) => {
+                values.shift_remove(v);
+            }
+            None => {
+                values.insert(token.to_string());
+            }
+        }
+    }
+}
+
+/// Determine if a package's keywords are accepted by a set of accepted keywords.
+fn keywords_accepted(accepted: &IndexSet<String>, keywords: &[&str]) -> bool {
+    if accepted.contains("**") {
+        return true;
+    }
+
+    keywords.iter().any(|k| match k.strip_prefix('~') {
+        Some(_) => accepted.contains(*k) || accepted.contains("~*"),
+        None if k.starts_with('-') => false,
+        None => {
+            accepted.contains(*k) || accepted.contains("*") || accepted.contains(&format!("~{k}"))
+        }
+    })
+}
+
+/// User configuration layered over a profile, using the portage-style files from a config
+/// directory: make.conf, package.use, package.accept_keywords, package.env, package.mask, and
+/// package.unmask.
+#[derive(Debug, Default, Clone)]
+pub struct UserConfig {
+    path: PathBuf,
+    vars: IndexMap<String, String>,
+    package_use: AtomEntries,
+    package_accept_keywords: AtomEntries,
+    package_env: AtomEntries,
+    package_mask: PackageMask,
+    package_unmask: PackageMask,
+}
+
+impl UserConfig {
+    /// Load the user configuration from a given directory, ignoring nonexistent files.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        let mut vars = IndexMap::new();
+        let file = path.join("make.conf");
+        if file.exists() {
+            let data = fs::read_to_string(&file)
+                .map_err(|e| Error::IO(format!("failed reading {file:?}: {e}")))?;
+            let assignments = parse_make_defaults(&data, &vars)
+                .map_err(|e| Error::InvalidValue(format!("{file:?}: {e}")))?;
+            vars.extend(assignments);
+        }
+
+        Ok(UserConfig {
+            path: PathBuf::from(path),
+            vars,
+            package_use: load_atom_entries(&path.join("package.use"))?,
+            package_accept_keywords: load_atom_entries(&path.join("package.accept_keywords"))?,
+            package_env: load_atom_entries(&path.join("package.env"))?,
+            package_mask: PackageMask::load(path.join("package.mask"))?,
+            package_unmask: PackageMask::load(path.join("package.unmask"))?,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the final value of a make.conf variable.
+    pub fn get(&self, var: &str) -> Option<&str> {
+        self.vars.get(var).map(|s| s.as_str())
+    }
+
+    fn list(&self, var: &str) -> Vec<&str> {
+        match self.get(var) {
+            None => vec![],
+            Some(s) => s.split_whitespace().collect(),
+        }
+    }
+
+    /// Return the package.use entries in file order.
+    pub fn package_use(&self) -> &[(Atom, Vec<String>)] {
+        &self.package_use
+    }
+
+    /// Return the package.accept_keywords entries in file order.
+    pub fn package_accept_keywords(&self) -> &[(Atom, Vec<String>)] {
+        &self.package_accept_keywords
+    }
+
+    /// Return the package.env entries in file order.
+    pub fn package_env(&self) -> &[(Atom, Vec<String>)] {
+        &self.package_env
+    }
+
+    pub fn package_mask(&self) -> &PackageMask {
+        &self.package_mask
+    }
+
+    pub fn package_unmask(&self) -> &PackageMask {
+        &self.package_unmask
+    }
+}
+
+/// Ebuild repo bound to a profile and user configuration, exposing packages as they would be
+/// built on the configured system.
+#[derive(Debug)]
+pub struct Repo<'a> {
+    repo: &'a ebuild::Repo,
+    profile: Profile,
+    config: UserConfig,
+    use_: IndexSet<String>,
+    use_tokens: Vec<String>,
+    accept_keywords: IndexSet<String>,
+    mask: PackageMask,
+}
+
+impl<'a> Repo<'a> {
+    pub fn new(repo: &'a ebuild::Repo, profile: Profile, config: UserConfig) -> Result<Self> {
+        // USE_EXPAND variables from make.conf are converted to their flag equivalents
+        let mut use_tokens: Vec<String> =
+            config.list("USE").iter().map(|s| s.to_string()).collect();
+        for var in profile.use_expand() {
+            let prefix = var.to_lowercase();
+            for val in config.list(var) {
+                match val.strip_prefix('-') {
+                    Some(v) => use_tokens.push(format!("-{prefix}_{v}")),
+                    None => use_tokens.push(format!("{prefix}_{val}")),
+                }
+            }
+        }
+        for var in profile.use_expand_unprefixed() {
+            use_tokens.extend(config.list(var).iter().map(|s| s.to_string()));
+        }
+
+        // ACCEPT_KEYWORDS defaults to the profile's stable arch
+        let mut accept_keywords = IndexSet::new();
+        match profile.get("ACCEPT_KEYWORDS") {
+            Some(s) => apply_incremental(&mut accept_keywords, s.split_whitespace()),
+            None => accept_keywords.extend(profile.arch().map(|s| s.to_string())),
+        }
+        apply_incremental(&mut accept_keywords, config.list("ACCEPT_KEYWORDS"));
+
+        // masks stack from the repo through the profile stack to the user config
+        let mut masks = vec![repo.package_mask()?];
+        for dir in profile.stack() {
+            masks.push(PackageMask::load(dir.join("package.mask"))?);
+        }
+        let mask = PackageMask::stack(masks.iter().chain([config.package_mask()]));
+
+        Ok(Repo {
+            repo,
+            use_: profile.use_(),
+            profile,
+            config,
+            use_tokens,
+            accept_keywords,
+            mask,
+        })
+    }
+
+    /// Return the underlying ebuild repo.
+    pub fn repo(&self) -> &'a ebuild::Repo {
+        self.repo
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    pub fn config(&self) -> &UserConfig {
+        &self.config
+    }
+
+    /// Return the globally accepted keywords.
+    pub fn accept_keywords(&self) -> &IndexSet<String> {
+        &self.accept_keywords
+    }
+
+    /// Return the combined package masks from the repo, profile stack, and user config.
+    pub fn package_mask(&self) -> &PackageMask {
+        &self.mask
+    }
+
+    /// Bind an ebuild package to the repo's configuration.
+    pub fn pkg(&'a self, pkg: pkg::ebuild::Pkg<'a>) -> Pkg<'a> {
+        Pkg {
+            pkg,
+            repo: self,
+            use_: OnceCell::new(),
+        }
+    }
+
+    pub fn iter(&'a self) -> PkgIter<'a> {
+        self.iter_restrict(Restrict::True)
+    }
+
+    /// Return an iterator over the configured packages matching a given restriction.
+    pub fn iter_restrict<T: Into<Restrict>>(&'a self, val: T) -> PkgIter<'a> {
+        PkgIter {
+            iter: self.repo.iter_restrict(val),
+            repo: self,
+        }
+    }
+}
+
+impl fmt::Display for Repo<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.repo.id())
+    }
+}
+
+pub struct PkgIter<'a> {
+    iter: ebuild::RestrictPkgIter<'a>,
+    repo: &'a Repo<'a>,
+}
+
+impl<'a> Iterator for PkgIter<'a> {
+    type Item = Pkg<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|p| self.repo.pkg(p))
+    }
+}
+
+/// Ebuild package with its configuration applied.
+#[derive(Debug, Clone)]
+pub struct Pkg<'a> {
+    pkg: pkg::ebuild::Pkg<'a>,
+    repo: &'a Repo<'a>,
+    use_: OnceCell<IndexSet<String>>,
+}
+
+impl<'a> Pkg<'a> {
+    /// Return the underlying ebuild package.
+    pub fn pkg(&self) -> &pkg::ebuild::Pkg<'a> {
+        &self.pkg
+    }
+
+    /// Return the configuration entries with atoms matching the package.
+    fn entries<'b>(&self, entries: &'b [(Atom, Vec<String>)]) -> Vec<&'b [String]> {
+        let pkg = pkg::Pkg::Ebuild(self.pkg.clone());
+        entries
+            .iter()
+            .filter(|(a, _)| Restrict::from(a).matches(&pkg))
+            .map(|(_, vals)| vals.as_slice())
+            .collect()
+    }
+
+    /// Return the effective IUSE for the package.
+    pub fn iuse_effective(&self) -> Result<IndexSet<String>> {
+        let iuse = self.pkg.metadata()?.iuse();
+        Ok(self.repo.profile.iuse_effective(iuse))
+    }
+
+    /// Return the flags from profile-wide settings altered by matching package entries.
+    fn profile_flags(
+        &self,
+        flags: &IndexSet<String>,
+        entries: &[(Atom, Vec<String>)],
+    ) -> IndexSet<String> {
+        let mut flags = flags.clone();
+        for vals in self.entries(entries) {
+            apply_incremental(&mut flags, vals.iter().map(|s| s.as_str()));
+        }
+        flags
+    }
+
+    /// Return the masked USE flags for the package.
+    pub fn use_mask(&self) -> IndexSet<String> {
+        let profile = &self.repo.profile;
+        self.profile_flags(profile.use_mask(), profile.package_use_mask())
+    }
+
+    /// Return the forced USE flags for the package.
+    pub fn use_force(&self) -> IndexSet<String> {
+        let profile = &self.repo.profile;
+        self.profile_flags(profile.use_force(), profile.package_use_force())
+    }
+
+    /// Return the final USE flags for the package, combining IUSE defaults, the profile, and the
+    /// user's USE settings in increasing order of precedence. Profile forced flags are then
+    /// enabled and masked flags disabled, with masks taking precedence.
+    pub fn use_(&self) -> Result<&IndexSet<String>> {
+        self.use_.get_or_try_init(|| {
+            let iuse = self.pkg.metadata()?.iuse();
+            let mut flags: IndexSet<String> = iuse
+                .iter()
+                .filter_map(|s| s.strip_prefix('+'))
+                .map(|s| s.to_string())
+                .collect();
+            flags.extend(self.repo.use_.iter().cloned());
+            for vals in self.entries(self.repo.profile.package_use()) {
+                apply_incremental(&mut flags, vals.iter().map(|s| s.as_str()));
+            }
+            apply_incremental(&mut flags, self.repo.use_tokens.iter().map(|s| s.as_str()));
+            for vals in self.entries(self.repo.config.package_use()) {
+                apply_incremental(&mut flags, vals.iter().map(|s| s.as_str()));
+            }
+            flags.extend(self.use_force());
+            let mask = self.use_mask();
+            flags.retain(|f| !mask.contains(f));
+
+            let iuse_effective = self.repo.profile.iuse_effective(iuse);
+            flags.retain(|f| iuse_effective.contains(f));
+            Ok(flags)
+        })
+    }
+
+    /// Return the dependencies for a given class with USE conditionals evaluated.
+    pub fn deps(&self, class: DepClass) -> Result<Option<DepSpec>> {
+        let use_ = self.use_()?;
+        Ok(self
+            .pkg
+            .metadata()?
+            .deps(class)?
+            .and_then(|d| d.evaluate(use_)))
+    }
+
+    /// Return the effective RESTRICT values for the package.
+    pub fn restrict(&self) -> Result<IndexSet<String>> {
+        self.evaluate_strings("RESTRICT")
+    }
+
+    /// Return the effective PROPERTIES values for the package.
+    pub fn properties(&self) -> Result<IndexSet<String>> {
+        self.evaluate_strings("PROPERTIES")
+    }
+
+    fn evaluate_strings(&self, key: &str) -> Result<IndexSet<String>> {
+        let s = match self.pkg.metadata()?.get(key) {
+            Some(s) if !s.trim().is_empty() => s.split_whitespace().collect::<Vec<_>>().join(" "),
+            _ => return Ok(IndexSet::new()),
+        };
+        let spec = restrict::parse(&s)
+            .map_err(|e| peg_error(format!("invalid {key}: {s:?}"), s.as_str(), e))?;
+        Ok(spec
+            .evaluate(self.use_()?)
+            .map(|d| d.strings().into_iter().map(|s| s.to_string()).collect())
+            .unwrap_or_default())
+    }
+
+    /// Return the accepted keywords for the package including package.accept_keywords entries,
+    /// where entries lacking keywords accept the profile's testing arch.
+    pub fn accept_keywords(&self) -> IndexSet<String> {
+        let mut accepted = self.repo.accept_keywords.clone();
+        for vals in self.entries(self.repo.config.package_accept_keywords()) {
+            match vals.is_empty() {
+                true => accepted.extend(self.repo.profile.arch().map(|s| format!("~{s}"))),
+                false => apply_incremental(&mut accepted, vals.iter().map(|s| s.as_str())),
+            }
+        }
+        accepted
+    }
+
+    /// Determine if the package's keywords are accepted.
+    pub fn keywords_accepted(&self) -> Result<bool> {
+        let keywords = self.pkg.metadata()?.keywords();
+        Ok(keywords_accepted(&self.accept_keywords(), &keywords))
+    }
+
+    /// Determine if the package is masked and not unmasked by the user config.
+    pub fn masked(&self) -> bool {
+        let pkg = pkg::Pkg::Ebuild(self.pkg.clone());
+        self.repo
+            .mask
+            .masked(&pkg, Some(self.repo.config.package_unmask()))
+    }
+
+    /// Determine if the package is visible, i.e. it's unmasked with accepted keywords.
+    pub fn visible(&self) -> Result<bool> {
+        Ok(!self.masked() && self.keywords_accepted()?)
+    }
+
+    /// Source the package's ebuild with its configuration applied, returning the package shell
+    /// for running its phases.
+    pub fn source<'s>(&self, sh: &'s mut Shell) -> Result<PkgShell<'s>> {
+        let mut data = BuildData::new();
+        data.set_pkg(self)?;
+        let mut pkgsh = PkgShell::new(sh, data);
+        pkgsh.source_pkg(&self.pkg)?;
+        Ok(pkgsh)
+    }
+
+    /// Return the environment variables from package.env entries matching the package, loaded
+    /// from the user config's env directory.
+    pub fn env(&self) -> Result<IndexMap<String, String>> {
+        let mut vars = IndexMap::new();
+        for vals in self.entries(self.repo.config.package_env()) {
+            for name in vals {
+                let file = self.repo.config.path.join("env").join(name);
+                let data = fs::read_to_string(&file)
+                    .map_err(|e| Error::IO(format!("failed reading {file:?}: {e}")))?;
+                let assignments = parse_make_defaults(&data, &vars)
+                    .map_err(|e| Error::InvalidValue(format!("{file:?}: {e}")))?;
+                vars.extend(assignments);
+            }
+        }
+        Ok(vars)
+    }
+}
+
+impl fmt::Display for Pkg<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pkg)
+    }
+}
+
+impl<'a> Package for Pkg<'a> {
+    type Repo = &'a Repo<'a>;
+
+    fn atom(&self) -> &Atom {
+        self.pkg.atom()
+    }
+
+    fn eapi(&self) -> &eapi::Eapi {
+        self.pkg.eapi()
+    }
+
+    fn repo(&self) -> Self::Repo {
+        self.repo
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusty_fork::rusty_fork_test;
+    use tempfile::TempDir;
+
+    use crate::repo::ebuild::TempRepo;
+
+    use super::*;
+
+    #[test]
+    fn test_load_entries() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("package.use");
+        fs::write(&path, "# comment\ncat/pkg a -b PYTHON_TARGETS: py1 -py2\n").unwrap();
+        let entries = load_atom_entries(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].0.to_string(), "cat/pkg");
+        assert_eq!(entries[0].1, ["a", "-b", "python_targets_py1", "-python_targets_py2"]);
+
+        // directories of files
+        fs::remove_file(&path).unwrap();
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("a"), "cat/a x\n").unwrap();
+        fs::write(path.join("b"), "cat/b y\n").unwrap();
+        let entries = load_atom_entries(&path).unwrap();
+        let atoms: Vec<_> = entries.iter().map(|(a, _)| a.to_string()).collect();
+        assert_eq!(atoms, ["cat/a", "cat/b"]);
+
+        // missing and invalid files
+        assert!(load_atom_entries(&dir.path().join("missing"))
+            .unwrap()
+            .is_empty());
+        fs::write(dir.path().join("invalid"), "cat\n").unwrap();
+        assert!(load_atom_entries(&dir.path().join("invalid")).is_err());
+    }
+
+    #[test]
+    fn test_keywords_accepted() {
+        let accepted =
+            |vals: &[&str]| -> IndexSet<String> { vals.iter().map(|s| s.to_string()).collect() };
+        assert!(keywords_accepted(&accepted(&["amd64"]), &["amd64", "~arm64"]));
+        assert!(!keywords_accepted(&accepted(&["amd64"]), &["~amd64"]));
+        assert!(keywords_accepted(&accepted(&["~amd64"]), &["amd64"]));
+        assert!(keywords_accepted(&accepted(&["~*"]), &["~arm64"]));
+        assert!(keywords_accepted(&accepted(&["*"]), &["arm64"]));
+        assert!(!keywords_accepted(&accepted(&["amd64"]), &["-amd64"]));
+        assert!(keywords_accepted(&accepted(&["**"]), &[]));
+        assert!(!keywords_accepted(&accepted(&["amd64"]), &[]));
+    }
+
+    #[test]
+    fn test_configured() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let profiles = t.repo.path().join("profiles");
+        fs::create_dir(profiles.join("base")).unwrap();
+        let data = indoc::indoc! {r#"
+            ARCH="amd64"
+            ACCEPT_KEYWORDS="amd64"
+            USE="profile"
+            USE_EXPAND="PYTHON_TARGETS"
+            IUSE_IMPLICIT="amd64"
+        "#};
+        fs::write(profiles.join("base/make.defaults"), data).unwrap();
+        fs::write(profiles.join("base/package.mask"), "cat/masked\n").unwrap();
+        fs::write(profiles.join("package.mask"), "cat/c\n").unwrap();
+        fs::write(profiles.join("base/package.use"), "cat/a pkg\n").unwrap();
+        fs::write(profiles.join("base/use.force"), "python_targets_py2\n").unwrap();
+        fs::write(profiles.join("base/use.mask"), "kept\n").unwrap();
+        fs::write(profiles.join("base/package.use.force"), "=cat/a-2 user\n").unwrap();
+        fs::write(profiles.join("base/package.use.mask"), "=cat/a-1 -kept\n=cat/a-2 user\n")
+            .unwrap();
+        let profile = t.repo.profile("base").unwrap();
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path();
+        fs::write(path.join("make.conf"), "USE=\"-def user\"\nPYTHON_TARGETS=\"py1\"\n").unwrap();
+        fs::write(path.join("package.use"), "=cat/a-1 pkg -profile\n").unwrap();
+        fs::write(path.join("package.accept_keywords"), "cat/b\n").unwrap();
+        fs::write(path.join("package.unmask"), "cat/c\n").unwrap();
+        fs::write(path.join("package.env"), "cat/a test.conf\n").unwrap();
+        fs::create_dir(path.join("env")).unwrap();
+        fs::write(path.join("env/test.conf"), "CFLAGS=\"-O2\"\n").unwrap();
+        let config = UserConfig::load(path).unwrap();
+        assert_eq!(config.get("PYTHON_TARGETS"), Some("py1"));
+
+        let iuse = "+def +kept profile user pkg python_targets_py1 python_targets_py2";
+        for cpv in ["cat/a-1", "cat/a-2", "cat/b-1", "cat/c-1", "cat/masked-1"] {
+            t.create_ebuild(cpv, None).unwrap();
+            let data = [
+                ("EAPI", "8"),
+                ("IUSE", iuse),
+                ("KEYWORDS", "amd64"),
+                ("DEPEND", "a/b pkg? ( c/d ) !user? ( e/f )"),
+                ("RESTRICT", "!profile? ( test ) mirror"),
+            ];
+            t.create_metadata(cpv, &data).unwrap();
+        }
+        t.create_metadata("cat/b-1", &[("EAPI", "8"), ("KEYWORDS", "~amd64")])
+            .unwrap();
+
+        let repo = Repo::new(&t.repo, profile, config).unwrap();
+        assert_eq!(repo.to_string(), "test");
+        let pkgs: Vec<_> = repo.iter().collect();
+        assert_eq!(pkgs.len(), 5);
+        let (a1, a2, b, c, masked) = (&pkgs[0], &pkgs[1], &pkgs[2], &pkgs[3], &pkgs[4]);
+        assert_eq!(a1.atom().to_string(), "cat/a-1");
+
+        // USE flags
+        let use_ = |p: &Pkg| -> Vec<String> { p.use_().unwrap().iter().cloned().collect() };
+        assert_eq!(use_(a2), ["profile", "pkg", "python_targets_py1", "python_targets_py2"]);
+        assert_eq!(use_(a1), ["kept", "pkg", "user", "python_targets_py1", "python_targets_py2"]);
+
+        // profile masks take precedence over forced flags and can be unmasked per package
+        assert_eq!(a2.use_mask().into_iter().collect::<Vec<_>>(), ["kept", "user"]);
+        assert_eq!(a2.use_force().into_iter().collect::<Vec<_>>(), ["python_targets_py2", "user"]);
+        assert!(a1.use_mask().is_empty());
+        assert!(a1.iuse_effective().unwrap().contains("amd64"));
+
+        // evaluated dependencies and restrictions
+        let deps = a1.deps(DepClass::Depend).unwrap().unwrap();
+        let expected = crate::depspec::pkgdep::parse("a/b c/d", &eapi::EAPI8).unwrap();
+        assert_eq!(deps, expected);
+        assert!(a1.deps(DepClass::Rdepend).unwrap().is_none());
+        assert_eq!(a1.restrict().unwrap().into_iter().collect::<Vec<_>>(), ["test", "mirror"]);
+        assert_eq!(a2.restrict().unwrap().into_iter().collect::<Vec<_>>(), ["mirror"]);
+        assert!(a2.properties().unwrap().is_empty());
+
+        // visibility
+        assert!(a1.visible().unwrap());
+        assert!(b.accept_keywords().contains("~amd64"));
+        assert!(b.visible().unwrap());
+        assert!(!c.masked());
+        assert!(masked.masked());
+        assert!(!masked.visible().unwrap());
+
+        // package.env
+        assert_eq!(a1.env().unwrap().get("CFLAGS").unwrap(), "-O2");
+        assert!(b.env().unwrap().is_empty());
+
+        // build state
+        let mut data = BuildData::default();
+        data.set_pkg(a1).unwrap();
+        assert_eq!(data.repo, t.repo.path().to_string_lossy());
+        assert_eq!(data.eapi, &*eapi::EAPI8);
+        assert_eq!(data.use_, a1.use_().unwrap().iter().cloned().collect());
+        assert!(data.iuse_effective.contains("amd64"));
+        assert!(data.iuse_effective.contains("python_targets_py2"));
+    }
+
+    rusty_fork_test! {
+        #[test]
+        fn test_source() {
+            let t = TempRepo::new("test", None::<&str>, None).unwrap();
+            let (_, path) = t.create_ebuild("cat/pkg-1", None).unwrap();
+            fs::write(&path, "EAPI=8\nIUSE=\"a b\"\nSLOT=0\nDESCRIPTION=\"${PN}\"\n").unwrap();
+            t.create_metadata("cat/pkg-1", &[("EAPI", "8"), ("IUSE", "a b")])
+                .unwrap();
+            let profiles = t.repo.path().join("profiles");
+            fs::create_dir(profiles.join("base")).unwrap();
+            fs::write(profiles.join("base/make.defaults"), "USE=\"a\"\n").unwrap();
+            let profile = t.repo.profile("base").unwrap();
+            let repo = Repo::new(&t.repo, profile, UserConfig::default()).unwrap();
+            let pkg = repo.iter().next().unwrap();
+
+            // the sourced ebuild runs with the package's configured USE flags
+            let mut sh = Shell::new("pkgcraft", Some(crate::pkgsh::builtins::all()));
+            let _pkgsh = pkg.source(&mut sh).unwrap();
+            crate::pkgsh::BUILD_DATA.with(|d| {
+                let d = d.borrow();
+                assert_eq!(d.use_, ["a".to_string()].into_iter().collect());
+                assert!(d.iuse_effective.contains("b"));
+            });
+            assert_eq!(scallop::variables::string_value("DESCRIPTION").unwrap(), "pkg");
+        }
+    }
+}
@@ -7,7 +7,8 @@ use std::{env, fmt, fs, io};
 use std::{collections::HashMap, io::Write};
 
 use ini::Ini;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
+use rayon::prelude::*;
 use tempfile::TempDir;
 use tracing::warn;
 use walkdir::DirEntry;
@@ -15,6 +16,8 @@ use walkdir::DirEntry;
 use crate::config::Config;
 use crate::files::{has_ext, is_dir, is_file, is_hidden, sorted_dir_list};
 use crate::macros::build_from_paths;
+use crate::repo::Repository;
+use crate::restrict::{Restrict, Restriction};
 use crate::{atom, eapi, pkg, repo, Error, Result};
 
 const DEFAULT_SECTION: Option<String> = None;
@@ -110,7 +113,9 @@ pub struct Repo {
     id: String,
     pub(super) path: PathBuf,
     pub(super) config: Metadata,
-    pkgs: repo::PkgCache,
+    pkgs: OnceCell<repo::PkgCache>,
+    index_path: Option<PathBuf>,
+    indexed: OnceCell<Option<repo::PkgCache>>,
 }
 
 impl Repo {
@@ -125,10 +130,17 @@ impl Repo {
             id: id.as_ref().to_string(),
             path: PathBuf::from(path.as_ref()),
             config,
-            pkgs: repo::PkgCache::default(),
+            ..Default::default()
         })
     }
 
+    /// Use a persistent package index within a given cache directory, if the directory exists.
+    pub(crate) fn set_cache_dir(&mut self, cache: &Path) {
+        if !cache.as_os_str().is_empty() && cache.exists() {
+            self.index_path = Some(repo::index::PkgIndex::path(cache, self));
+        }
+    }
+
     pub(super) fn from_path<S, P>(id: S, path: P) -> Result<Self>
     where
         S: AsRef<str>,
@@ -215,40 +227,8 @@ impl Repo {
         v
     }
 
-    pub(crate) fn path(&self) -> &Path {
-        &self.path
-    }
-
-    pub fn iter(&self) -> PkgIter {
-        self.into_iter()
-    }
-}
-
-impl fmt::Display for Repo {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let (id, path) = (self.id.as_str(), self.path.to_string_lossy());
-        match id == path {
-            true => write!(f, "{id}"),
-            false => write!(f, "{id}: {path}"),
-        }
-    }
-}
-
-fn is_fake_category(entry: &DirEntry) -> bool {
-    entry
-        .file_name()
-        .to_str()
-        .map(|s| FAKE_CATEGORIES.contains(s))
-        .unwrap_or(false)
-}
-
-impl repo::Repository for Repo {
-    fn categories(&self) -> Vec<String> {
-        // TODO: implement reading profiles/categories, falling back to category_dirs()
-        self.category_dirs()
-    }
-
-    fn packages(&self, cat: &str) -> Vec<String> {
+    /// Return the package directories for a category, walking the filesystem.
+    pub fn package_dirs(&self, cat: &str) -> Vec<String> {
         let path = self.path.join(cat.strip_prefix('/').unwrap_or(cat));
         let filter = |e: &DirEntry| -> bool { is_dir(e) && !is_hidden(e) };
         let pkgs = sorted_dir_list(&path).into_iter().filter_entry(filter);
@@ -273,7 +253,8 @@ impl repo::Repository for Repo {
         v
     }
 
-    fn versions(&self, cat: &str, pkg: &str) -> Vec<String> {
+    /// Return the versions of a package's ebuilds, walking the filesystem.
+    pub fn ebuild_versions(&self, cat: &str, pkg: &str) -> Vec<String> {
         let path = build_from_paths!(
             &self.path,
             cat.strip_prefix('/').unwrap_or(cat),
@@ -307,16 +288,345 @@ impl repo::Repository for Repo {
         v
     }
 
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the package cache, populating it by walking the repo on first access. Valid
+    /// persistent indexes are used instead of walking, otherwise the index is updated.
+    pub(super) fn pkgs(&self) -> &repo::PkgCache {
+        if let Some(pkgs) = self.indexed() {
+            return pkgs;
+        }
+
+        self.pkgs.get_or_init(|| {
+            if let Some(path) = &self.index_path {
+                let index = repo::index::PkgIndex::build(self);
+                if let Err(e) = index.save(path) {
+                    warn!("{}: {e}", self.id);
+                }
+                return index.cpvs().iter().map(|s| s.as_str()).collect();
+            }
+
+            let mut cpvs = vec![];
+            for cat in self.categories() {
+                for pkg in self.packages(&cat) {
+                    for ver in self.versions(&cat, &pkg) {
+                        cpvs.push(format!("{cat}/{pkg}-{ver}"));
+                    }
+                }
+            }
+            cpvs.iter().map(|s| s.as_str()).collect()
+        })
+    }
+
+    /// Return the package cache loaded from the repo's persistent index if it's up to date.
+    fn indexed(&self) -> Option<&repo::PkgCache> {
+        self.indexed
+            .get_or_init(|| {
+                let index = repo::index::PkgIndex::load(self.index_path.as_ref()?).ok()?;
+                match index.is_valid(&self.path) {
+                    true => Some(index.cpvs().iter().map(|s| s.as_str()).collect()),
+                    false => None,
+                }
+            })
+            .as_ref()
+    }
+
+    /// Return the path to the repo's persistent package index if a cache directory exists.
+    pub fn index_path(&self) -> Option<&Path> {
+        self.index_path.as_deref()
+    }
+
+    /// Rebuild and save the repo's persistent package index, e.g. after syncing.
+    pub fn update_index(&self) -> Result<()> {
+        match &self.index_path {
+            Some(path) => repo::index::PkgIndex::build(self).save(path),
+            None => Ok(()),
+        }
+    }
+
+    pub fn iter(&self) -> PkgIter {
+        self.into_iter()
+    }
+
+    /// Return the repo's package masks.
+    pub fn package_mask(&self) -> Result<repo::mask::PackageMask> {
+        repo::mask::PackageMask::load(self.path.join("profiles/package.mask"))
+    }
+
+    /// Return the repo's known arches from profiles/arch.list.
+    pub fn arches(&self) -> Result<Vec<String>> {
+        let path = self.path.join("profiles/arch.list");
+        match fs::read_to_string(&path) {
+            Ok(data) => Ok(data
+                .lines()
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty() && !s.starts_with('#'))
+                .map(|s| s.to_string())
+                .collect()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(Error::IO(format!("failed reading {path:?}: {e}"))),
+        }
+    }
+
+    /// Return the repo's package updates from profiles/updates.
+    pub fn updates(&self) -> Result<repo::updates::Updates> {
+        repo::updates::Updates::load(self.path.join("profiles/updates"))
+    }
+
+    /// Return the repo's eclasses sorted by name.
+    pub fn eclasses(&self) -> Vec<repo::eclass::Eclass> {
+        let dir = self.path.join("eclass");
+        let mut eclasses = vec![];
+        if !dir.exists() {
+            return eclasses;
+        }
+
+        let filter = |e: &DirEntry| -> bool { is_file(e) && has_ext(e, "eclass") };
+        for entry in sorted_dir_list(&dir).into_iter().filter_entry(filter) {
+            let path = match entry {
+                Ok(e) => e.into_path(),
+                Err(e) => {
+                    warn!("error walking {dir:?}: {e}");
+                    continue;
+                }
+            };
+            match repo::eclass::Eclass::new(&self.id, &path) {
+                Ok(eclass) => eclasses.push(eclass),
+                Err(e) => warn!("{}: {e}", self.id),
+            }
+        }
+        eclasses
+    }
+
+    /// Return the named eclass if it exists in the repo.
+    pub fn eclass(&self, name: &str) -> Option<repo::eclass::Eclass> {
+        let path = self.path.join(format!("eclass/{name}.eclass"));
+        repo::eclass::Eclass::new(&self.id, path).ok()
+    }
+
+    /// Return the eclass directories for the repo and its masters mapped to their repo ids,
+    /// ordered from highest to lowest precedence.
+    pub fn eclass_dirs(&self) -> Result<Vec<(String, PathBuf)>> {
+        Ok(self.eclass_dirs_from(&self.masters()?))
+    }
+
+    fn eclass_dirs_from(&self, masters: &[Arc<repo::Repo>]) -> Vec<(String, PathBuf)> {
+        // the repo overrides its masters and later masters override earlier ones
+        let mut dirs = vec![(self.id.clone(), self.path.join("eclass"))];
+        for master in masters.iter().rev() {
+            if let repo::Repo::Ebuild(r) = master.as_ref() {
+                dirs.push((r.id.clone(), r.path.join("eclass")));
+            }
+        }
+        dirs
+    }
+
+    /// Return the named eclass from the repo or its masters, respecting inheritance precedence.
+    pub fn find_eclass(&self, name: &str) -> Result<Option<repo::eclass::Eclass>> {
+        Ok(find_eclass(&self.eclass_dirs()?, name))
+    }
+
+    /// Return the repo's security advisories from metadata/glsa, sorted by file name.
+    pub fn glsas(&self) -> Result<Vec<repo::glsa::Glsa>> {
+        repo::glsa::load(self)
+    }
+
+    /// Return the repo's English news items from metadata/news, sorted by name.
+    pub fn news(&self) -> Result<Vec<repo::news::News>> {
+        repo::news::load(self)
+    }
+
+    /// Regenerate missing or outdated metadata cache entries, returning the number of
+    /// regenerated entries.
+    pub fn regen_metadata(&self) -> Result<usize> {
+        repo::cache::regen(self)
+    }
+
+    /// Verify the files listed in the repo's Manifests, returning the number of verified files.
+    pub fn verify_manifests(&self) -> Result<usize> {
+        repo::manifest::verify(self)
+    }
+
+    /// Return the profile stack for a given path relative to the repo's profiles directory.
+    pub fn profile<P: AsRef<Path>>(&self, path: P) -> Result<repo::profile::Profile> {
+        repo::profile::Profile::load(self.path.join("profiles").join(path))
+    }
+
+    /// Return the repo's global, local, and USE_EXPAND flag descriptions.
+    pub fn use_desc(&self) -> Result<repo::use_desc::UseDesc> {
+        repo::use_desc::UseDesc::load(self)
+    }
+
+    /// Bind the repo to a profile and user configuration.
+    pub fn configure(
+        &self,
+        profile: repo::profile::Profile,
+        config: repo::configured::UserConfig,
+    ) -> Result<repo::configured::Repo> {
+        repo::configured::Repo::new(self, profile, config)
+    }
+
+    /// Return an iterator over the packages matching a given restriction.
+    pub fn iter_restrict<T: Into<Restrict>>(&self, val: T) -> RestrictPkgIter {
+        RestrictPkgIter {
+            iter: self.into_iter(),
+            restrict: val.into(),
+        }
+    }
+
+    /// Walk the repo's categories and packages in parallel using the current thread pool,
+    /// returning package versions in the same order as sequential iteration.
+    fn par_walk(&self) -> Vec<String> {
+        self.categories()
+            .into_par_iter()
+            .flat_map(|cat| {
+                self.packages(&cat)
+                    .into_par_iter()
+                    .flat_map(|pkg| {
+                        self.versions(&cat, &pkg)
+                            .into_iter()
+                            .map(|ver| format!("{cat}/{pkg}-{ver}"))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Return the repo's package versions, collected in parallel using a given number of threads
+    /// where 0 uses all available cores.
+    pub fn par_versions(&self, threads: usize) -> Result<Vec<String>> {
+        Ok(thread_pool(threads)?.install(|| self.par_walk()))
+    }
+
+    /// Populate the package cache in parallel if it hasn't been loaded yet.
+    pub fn par_load(&self, threads: usize) -> Result<()> {
+        if self.indexed().is_none() && self.pkgs.get().is_none() {
+            let cpvs = self.par_versions(threads)?;
+            self.pkgs
+                .get_or_init(|| cpvs.iter().map(|s| s.as_str()).collect());
+        }
+        Ok(())
+    }
+
+    /// Apply a function to the packages matching a given restriction in parallel, returning the
+    /// results in repo order.
+    pub fn par_map<'a, T, F, R>(&'a self, val: T, threads: usize, func: F) -> Result<Vec<R>>
+    where
+        T: Into<Restrict>,
+        F: Fn(pkg::ebuild::Pkg<'a>) -> R + Send + Sync,
+        R: Send,
+    {
+        let restrict = val.into();
+        let pool = thread_pool(threads)?;
+        Ok(pool.install(|| {
+            let pkgs = match self.indexed() {
+                Some(pkgs) => pkgs,
+                None => self
+                    .pkgs
+                    .get_or_init(|| self.par_walk().iter().map(|s| s.as_str()).collect()),
+            };
+            let atoms: Vec<_> = pkgs.into_iter().filter(|a| restrict.matches(*a)).collect();
+            atoms
+                .into_par_iter()
+                .filter_map(|a| match pkg::ebuild::Pkg::new(a, self) {
+                    Ok(p) => Some(func(p)),
+                    Err(e) => {
+                        warn!("{}: invalid package: {a}: {e}", self.id);
+                        None
+                    }
+                })
+                .collect()
+        }))
+    }
+
+    /// Return the packages matching a given restriction, created in parallel and returned in
+    /// repo order.
+    pub fn par_iter_restrict<T: Into<Restrict>>(
+        &self,
+        val: T,
+        threads: usize,
+    ) -> Result<Vec<pkg::ebuild::Pkg>> {
+        self.par_map(val, threads, |p| p)
+    }
+}
+
+/// Build a thread pool for parallel repo operations, using all available cores when `threads` is
+/// 0.
+fn thread_pool(threads: usize) -> Result<rayon::ThreadPool> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|e| Error::InvalidValue(format!("failed creating thread pool: {e}")))
+}
+
+impl fmt::Display for Repo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (id, path) = (self.id.as_str(), self.path.to_string_lossy());
+        match id == path {
+            true => write!(f, "{id}"),
+            false => write!(f, "{id}: {path}"),
+        }
+    }
+}
+
+/// Return the first matching eclass from a list of repo ids and eclass directories.
+pub(crate) fn find_eclass(dirs: &[(String, PathBuf)], name: &str) -> Option<repo::eclass::Eclass> {
+    dirs.iter()
+        .map(|(id, dir)| (id, dir.join(format!("{name}.eclass"))))
+        .find(|(_, path)| path.exists())
+        .and_then(|(id, path)| match repo::eclass::Eclass::new(id, &path) {
+            Ok(eclass) => Some(eclass),
+            Err(e) => {
+                warn!("{id}: {e}");
+                None
+            }
+        })
+}
+
+fn is_fake_category(entry: &DirEntry) -> bool {
+    entry
+        .file_name()
+        .to_str()
+        .map(|s| FAKE_CATEGORIES.contains(s))
+        .unwrap_or(false)
+}
+
+impl repo::Repository for Repo {
+    fn categories(&self) -> Vec<String> {
+        // TODO: implement reading profiles/categories, falling back to category_dirs()
+        match self.indexed() {
+            Some(pkgs) => pkgs.categories(),
+            None => self.category_dirs(),
+        }
+    }
+
+    fn packages(&self, cat: &str) -> Vec<String> {
+        match self.indexed() {
+            Some(pkgs) => pkgs.packages(cat),
+            None => self.package_dirs(cat),
+        }
+    }
+
+    fn versions(&self, cat: &str, pkg: &str) -> Vec<String> {
+        match self.indexed() {
+            Some(pkgs) => pkgs.versions(cat, pkg),
+            None => self.ebuild_versions(cat, pkg),
+        }
+    }
+
     fn id(&self) -> &str {
         &self.id
     }
 
     fn len(&self) -> usize {
-        self.pkgs.len()
+        self.pkgs().len()
     }
 
     fn is_empty(&self) -> bool {
-        self.pkgs.is_empty()
+        self.pkgs().is_empty()
     }
 }
 
@@ -337,13 +647,13 @@ impl<T: AsRef<Path>> repo::Contains<T> for Repo {
 
 impl repo::Contains<atom::Atom> for Repo {
     fn contains(&self, atom: atom::Atom) -> bool {
-        self.pkgs.atoms.contains(&atom)
+        self.pkgs().atoms.contains(&atom)
     }
 }
 
 impl repo::Contains<&atom::Atom> for Repo {
     fn contains(&self, atom: &atom::Atom) -> bool {
-        self.pkgs.atoms.contains(atom)
+        self.pkgs().atoms.contains(atom)
     }
 }
 
@@ -353,7 +663,7 @@ impl<'a> IntoIterator for &'a Repo {
 
     fn into_iter(self) -> Self::IntoIter {
         PkgIter {
-            iter: self.pkgs.into_iter(),
+            iter: self.pkgs().into_iter(),
             repo: self,
         }
     }
@@ -368,9 +678,17 @@ impl<'a> Iterator for PkgIter<'a> {
     type Item = pkg::ebuild::Pkg<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        self.next_matching(&Restrict::True)
+    }
+}
+
+impl<'a> PkgIter<'a> {
+    /// Return the next package with an atom matching a given restriction.
+    fn next_matching(&mut self, restrict: &Restrict) -> Option<pkg::ebuild::Pkg<'a>> {
         loop {
             match self.iter.next() {
                 None => return None,
+                Some(a) if !restrict.matches(a) => continue,
                 Some(a) => match pkg::ebuild::Pkg::new(a, self.repo) {
                     Ok(p) => return Some(p),
                     Err(e) => warn!("{}: invalid package: {a}: {e}", self.repo.id),
@@ -380,6 +698,19 @@ impl<'a> Iterator for PkgIter<'a> {
     }
 }
 
+pub struct RestrictPkgIter<'a> {
+    iter: PkgIter<'a>,
+    restrict: Restrict,
+}
+
+impl<'a> Iterator for RestrictPkgIter<'a> {
+    type Item = pkg::ebuild::Pkg<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next_matching(&self.restrict)
+    }
+}
+
 /// A temporary repo that is automatically deleted when it goes out of scope.
 #[derive(Debug)]
 pub(crate) struct TempRepo {
@@ -453,6 +784,24 @@ impl TempRepo {
         Ok((cpv, path))
     }
 
+    /// Create a metadata cache entry in the repo.
+    #[cfg(test)]
+    pub(crate) fn create_metadata(&self, cpv: &str, data: &[(&str, &str)]) -> Result<PathBuf> {
+        let cpv = atom::parse::cpv(cpv)?;
+        let path = self.tempdir.path().join(format!(
+            "metadata/md5-cache/{}/{}-{}",
+            cpv.category(),
+            cpv.package(),
+            cpv.version().unwrap()
+        ));
+        fs::create_dir_all(path.parent().unwrap())
+            .map_err(|e| Error::IO(format!("failed creating {cpv} metadata dir: {e}")))?;
+        let content: String = data.iter().map(|(k, v)| format!("{k}={v}\n")).collect();
+        fs::write(&path, content)
+            .map_err(|e| Error::IO(format!("failed writing {cpv} metadata: {e}")))?;
+        Ok(path)
+    }
+
     /// Attempts to persist the temporary repo to disk, returning the [`PathBuf`] where it is
     /// located.
     pub(crate) fn persist<P: AsRef<Path>>(self, path: Option<P>) -> Result<PathBuf> {
@@ -478,7 +827,10 @@ impl fmt::Display for TempRepo {
 mod tests {
     use std::fs;
 
+    use filetime::{set_file_mtime, FileTime};
+
     use crate::macros::assert_err_re;
+    use crate::pkg::Package;
     use crate::repo::{Contains, Repository};
 
     use super::*;
@@ -497,6 +849,123 @@ mod tests {
         assert_err_re!(r, format!("^.* nonexistent masters: a, b, c$"));
     }
 
+    #[test]
+    fn test_eclass_dirs() {
+        let t1 = TempRepo::new("overlay", None::<&str>, None).unwrap();
+        let t2 = TempRepo::new("primary", None::<&str>, None).unwrap();
+        let t3 = TempRepo::new("secondary", None::<&str>, None).unwrap();
+        for (t, eclasses) in [(&t1, ["a"].as_slice()), (&t2, &["a", "b", "c"]), (&t3, &["b"])] {
+            let dir = t.repo.path().join("eclass");
+            fs::create_dir(&dir).unwrap();
+            for name in eclasses {
+                fs::write(dir.join(format!("{name}.eclass")), "").unwrap();
+            }
+        }
+
+        // no masters
+        let dirs = t1.repo.eclass_dirs().unwrap();
+        assert_eq!(dirs, [("overlay".to_string(), t1.repo.path().join("eclass"))]);
+        assert!(find_eclass(&dirs, "b").is_none());
+
+        // the repo takes precedence over its masters with later masters overriding earlier ones
+        let (t2_path, t3_path) = (t2.repo.path().to_path_buf(), t3.repo.path().to_path_buf());
+        let masters =
+            [Arc::new(repo::Repo::Ebuild(t2.repo)), Arc::new(repo::Repo::Ebuild(t3.repo))];
+        let dirs = t1.repo.eclass_dirs_from(&masters);
+        let ids: Vec<_> = dirs.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, ["overlay", "secondary", "primary"]);
+        for (name, id, path) in [
+            ("a", "overlay", t1.repo.path().join("eclass/a.eclass")),
+            ("b", "secondary", t3_path.join("eclass/b.eclass")),
+            ("c", "primary", t2_path.join("eclass/c.eclass")),
+        ] {
+            let eclass = find_eclass(&dirs, name).unwrap();
+            assert_eq!(eclass.repo(), id);
+            assert_eq!(eclass.path(), path);
+        }
+        assert!(find_eclass(&dirs, "d").is_none());
+    }
+
+    #[test]
+    fn test_par_iter() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let cpvs = ["a/pkg-1", "a/pkg-2", "b/pkg-1", "b/other-1", "c/pkg-1.1", "c/pkg-1.10"];
+        for cpv in cpvs {
+            t.create_ebuild(cpv, None).unwrap();
+        }
+
+        // parallel walking matches sequential walking
+        let mut seq = vec![];
+        for cat in t.repo.categories() {
+            for pkg in t.repo.packages(&cat) {
+                for ver in t.repo.versions(&cat, &pkg) {
+                    seq.push(format!("{cat}/{pkg}-{ver}"));
+                }
+            }
+        }
+        for threads in [0, 1, 4] {
+            assert_eq!(t.repo.par_versions(threads).unwrap(), seq);
+        }
+
+        // parallel package creation matches sequential iteration order
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        for cpv in cpvs {
+            t.create_ebuild(cpv, None).unwrap();
+        }
+        t.repo.par_load(2).unwrap();
+        assert_eq!(t.repo.len(), cpvs.len());
+        let seq: Vec<_> = t.repo.iter().map(|p| p.atom().to_string()).collect();
+        let pkgs = t.repo.par_iter_restrict(Restrict::True, 4).unwrap();
+        let par: Vec<_> = pkgs.iter().map(|p| p.atom().to_string()).collect();
+        assert_eq!(par, seq);
+
+        // restrictions and mapping
+        let restrict = Restrict::package("pkg");
+        let vals = t
+            .repo
+            .par_map(restrict, 0, |p| p.atom().category().to_string())
+            .unwrap();
+        assert_eq!(vals, ["a", "a", "b", "c", "c"]);
+    }
+
+    #[test]
+    fn test_index() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let path = t.repo.path().to_path_buf();
+        t.create_ebuild("cat/pkg-1", None).unwrap();
+        for dir in [&path, &path.join("cat"), &path.join("cat/pkg")] {
+            set_file_mtime(dir, FileTime::from_unix_time(1, 0)).unwrap();
+        }
+        let cache = TempDir::new().unwrap();
+        let open = || -> Repo {
+            let mut repo = Repo::from_path("test", &path).unwrap();
+            repo.set_cache_dir(cache.path());
+            repo
+        };
+
+        // the index is written when the repo is first walked
+        let repo = open();
+        assert!(!repo.index_path().unwrap().exists());
+        assert_eq!(repo.len(), 1);
+        assert!(repo.index_path().unwrap().exists());
+
+        // valid indexes are used instead of walking
+        let repo = open();
+        assert!(repo.indexed().is_some());
+        assert_eq!(repo.versions("cat", "pkg"), ["1"]);
+        assert!(repo.contains(&atom::parse::cpv("cat/pkg-1").unwrap()));
+
+        // outdated indexes are ignored and replaced
+        t.create_ebuild("cat/pkg-2", None).unwrap();
+        let repo = open();
+        assert!(repo.indexed().is_none());
+        assert_eq!(repo.versions("cat", "pkg"), ["1", "2"]);
+        assert_eq!(repo.len(), 2);
+        assert!(open().indexed().is_some());
+        repo.update_index().unwrap();
+        assert_eq!(open().versions("cat", "pkg"), ["1", "2"]);
+    }
+
     #[test]
     fn test_invalid_layout() {
         let t = TempRepo::new("test", None::<&str>, None).unwrap();
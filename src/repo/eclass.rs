@@ -0,0 +1,834 @@
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use md5::{Digest, Md5};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::pkg::Package;
+use crate::repo::ebuild;
+use crate::{Error, Result};
+
+static TAG_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^@(?P<tag>[A-Z][A-Z_-]*)(?::\s*(?P<val>.*))?$").unwrap());
+
+/// Inline markup used within descriptions that isn't a documentation tag.
+const INLINE_TAGS: [&str; 3] = ["CODE", "SUBSECTION", "ROFF"];
+
+/// Eclass file in an ebuild repo.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Eclass {
+    name: String,
+    repo: String,
+    path: PathBuf,
+    chksum: String,
+}
+
+impl Eclass {
+    /// Create an eclass provided by a given repo from a file path, calculating its checksum.
+    pub(crate) fn new<P: AsRef<Path>>(repo: &str, path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let name = match (path.file_stem(), path.extension()) {
+            (Some(name), Some(ext)) if ext == "eclass" => name.to_string_lossy().to_string(),
+            _ => return Err(Error::InvalidValue(format!("invalid eclass: {path:?}"))),
+        };
+        let data =
+            fs::read(path).map_err(|e| Error::IO(format!("failed reading {path:?}: {e}")))?;
+        Ok(Eclass {
+            name,
+            repo: repo.to_string(),
+            path: PathBuf::from(path),
+            chksum: format!("{:x}", Md5::digest(&data)),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the id of the repo providing the eclass.
+    pub fn repo(&self) -> &str {
+        &self.repo
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the MD5 checksum of the eclass as used in the md5-cache.
+    pub fn chksum(&self) -> &str {
+        &self.chksum
+    }
+
+    /// Parse the eclass documentation.
+    pub fn doc(&self) -> Result<EclassDoc> {
+        let data = fs::read_to_string(&self.path)
+            .map_err(|e| Error::IO(format!("failed reading {:?}: {e}", self.path)))?;
+        data.parse()
+            .map_err(|e| Error::InvalidValue(format!("{}.eclass: {e}", self.name)))
+    }
+
+    /// Return the packages in a repo that inherit the eclass, directly or indirectly.
+    pub fn inherited_by(&self, repo: &ebuild::Repo) -> Vec<String> {
+        repo.iter()
+            .filter(|p| {
+                p.metadata()
+                    .map(|m| m.inherited().contains(&self.name.as_str()))
+                    .unwrap_or_default()
+            })
+            .map(|p| p.atom().cpv())
+            .collect()
+    }
+
+    /// Return the packages inheriting the eclass using an EAPI not listed in its
+    /// @SUPPORTED_EAPIS, along with the offending EAPI.
+    pub fn unsupported(&self, repo: &ebuild::Repo) -> Result<Vec<(String, String)>> {
+        let doc = self.doc()?;
+        let mut pkgs = vec![];
+        for pkg in repo.iter() {
+            let meta = match pkg.metadata() {
+                Ok(m) => m,
+                Err(_) => continue,
+            };
+            if meta.inherited().contains(&self.name.as_str()) {
+                let eapi = meta.get("EAPI").unwrap_or("0");
+                if !doc.supports_eapi(eapi) {
+                    pkgs.push((pkg.atom().cpv(), eapi.to_string()));
+                }
+            }
+        }
+        Ok(pkgs)
+    }
+}
+
+/// Documentation for an eclass variable.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VariableDoc {
+    name: String,
+    description: Option<String>,
+    default_unset: bool,
+    internal: bool,
+    required: bool,
+    pre_inherit: bool,
+    user: bool,
+    output: bool,
+    dead: bool,
+    deprecated: Option<String>,
+}
+
+impl VariableDoc {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn default_unset(&self) -> bool {
+        self.default_unset
+    }
+
+    pub fn internal(&self) -> bool {
+        self.internal
+    }
+
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    pub fn pre_inherit(&self) -> bool {
+        self.pre_inherit
+    }
+
+    pub fn user(&self) -> bool {
+        self.user
+    }
+
+    pub fn output(&self) -> bool {
+        self.output
+    }
+
+    pub fn dead(&self) -> bool {
+        self.dead
+    }
+
+    /// Return the deprecation replacement if the variable is deprecated.
+    pub fn deprecated(&self) -> Option<&str> {
+        self.deprecated.as_deref()
+    }
+
+    /// Return the variable's annotations, e.g. required or pre-inherit.
+    fn annotations(&self) -> Vec<&'static str> {
+        [
+            (self.required, "required"),
+            (self.default_unset, "default unset"),
+            (self.pre_inherit, "set before inherit"),
+            (self.user, "user variable"),
+            (self.output, "generated by eclass"),
+            (self.internal, "internal"),
+            (self.dead, "dead"),
+        ]
+        .into_iter()
+        .filter_map(|(set, s)| if set { Some(s) } else { None })
+        .collect()
+    }
+}
+
+/// Documentation for an eclass function.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FunctionDoc {
+    name: String,
+    usage: Option<String>,
+    returns: Option<String>,
+    maintainers: Vec<String>,
+    description: Option<String>,
+    internal: bool,
+    deprecated: Option<String>,
+}
+
+impl FunctionDoc {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn usage(&self) -> Option<&str> {
+        self.usage.as_deref()
+    }
+
+    pub fn returns(&self) -> Option<&str> {
+        self.returns.as_deref()
+    }
+
+    pub fn maintainers(&self) -> &[String] {
+        &self.maintainers
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn internal(&self) -> bool {
+        self.internal
+    }
+
+    /// Return the deprecation replacement if the function is deprecated.
+    pub fn deprecated(&self) -> Option<&str> {
+        self.deprecated.as_deref()
+    }
+
+    fn signature(&self) -> String {
+        match self.usage() {
+            Some(usage) if !usage.is_empty() => format!("{} {usage}", self.name),
+            _ => self.name.clone(),
+        }
+    }
+}
+
+/// Structured eclass documentation parsed from eclassdoc comment tags.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct EclassDoc {
+    name: String,
+    maintainers: Vec<String>,
+    authors: Vec<String>,
+    bugreports: Option<String>,
+    vcsurl: Option<String>,
+    supported_eapis: Vec<String>,
+    provides: Vec<String>,
+    blurb: Option<String>,
+    deprecated: Option<String>,
+    description: Option<String>,
+    example: Option<String>,
+    variables: Vec<VariableDoc>,
+    functions: Vec<FunctionDoc>,
+    function_variables: Vec<VariableDoc>,
+}
+
+impl EclassDoc {
+    /// Return the eclass file name, e.g. foo.eclass.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn maintainers(&self) -> &[String] {
+        &self.maintainers
+    }
+
+    pub fn authors(&self) -> &[String] {
+        &self.authors
+    }
+
+    pub fn bugreports(&self) -> Option<&str> {
+        self.bugreports.as_deref()
+    }
+
+    pub fn vcsurl(&self) -> Option<&str> {
+        self.vcsurl.as_deref()
+    }
+
+    pub fn supported_eapis(&self) -> &[String] {
+        &self.supported_eapis
+    }
+
+    pub fn provides(&self) -> &[String] {
+        &self.provides
+    }
+
+    pub fn blurb(&self) -> Option<&str> {
+        self.blurb.as_deref()
+    }
+
+    /// Return the deprecation replacement if the eclass is deprecated.
+    pub fn deprecated(&self) -> Option<&str> {
+        self.deprecated.as_deref()
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn example(&self) -> Option<&str> {
+        self.example.as_deref()
+    }
+
+    pub fn variables(&self) -> &[VariableDoc] {
+        &self.variables
+    }
+
+    pub fn functions(&self) -> &[FunctionDoc] {
+        &self.functions
+    }
+
+    /// Return the documented variables used by specific functions.
+    pub fn function_variables(&self) -> &[VariableDoc] {
+        &self.function_variables
+    }
+
+    /// Determine if the eclass supports a given EAPI, all EAPIs are supported if the eclass
+    /// doesn't restrict them.
+    pub fn supports_eapi(&self, eapi: &str) -> bool {
+        self.supported_eapis.is_empty() || self.supported_eapis.iter().any(|e| e == eapi)
+    }
+
+    /// Render the documentation as Markdown.
+    pub fn to_markdown(&self) -> String {
+        let mut s = String::new();
+        let _ = writeln!(s, "# {}", self.name);
+        if let Some(blurb) = self.blurb() {
+            let _ = writeln!(s, "\n{blurb}");
+        }
+        if let Some(replacement) = self.deprecated() {
+            let _ = writeln!(s, "\n**Deprecated**: {replacement}");
+        }
+        if let Some(desc) = self.description() {
+            let _ = writeln!(s, "\n## Description\n\n{desc}");
+        }
+        if !self.supported_eapis.is_empty() {
+            let _ = writeln!(s, "\n## Supported EAPIs\n\n{}", self.supported_eapis.join(" "));
+        }
+        if !self.provides.is_empty() {
+            let _ =
+                writeln!(s, "\n## Transitively provided eclasses\n\n{}", self.provides.join(" "));
+        }
+        if let Some(example) = self.example() {
+            let _ = writeln!(s, "\n## Example\n\n```bash\n{example}\n```");
+        }
+
+        let functions: Vec<_> = self.functions.iter().filter(|f| !f.internal).collect();
+        if !functions.is_empty() {
+            let _ = writeln!(s, "\n## Functions");
+            for f in functions {
+                let _ = writeln!(s, "\n### `{}`", f.signature());
+                if let Some(replacement) = f.deprecated() {
+                    let _ = writeln!(s, "\n**Deprecated**: {replacement}");
+                }
+                if let Some(desc) = f.description() {
+                    let _ = writeln!(s, "\n{desc}");
+                }
+                if let Some(returns) = f.returns() {
+                    let _ = writeln!(s, "\nReturns: {returns}");
+                }
+            }
+        }
+
+        for (title, vars) in [
+            ("Eclass variables", &self.variables),
+            ("Function variables", &self.function_variables),
+        ] {
+            let vars: Vec<_> = vars.iter().filter(|v| !v.internal).collect();
+            if !vars.is_empty() {
+                let _ = writeln!(s, "\n## {title}");
+                for v in vars {
+                    let _ = write!(s, "\n### `{}`", v.name);
+                    let annotations = v.annotations();
+                    if !annotations.is_empty() {
+                        let _ = write!(s, " ({})", annotations.join(", "));
+                    }
+                    let _ = writeln!(s);
+                    if let Some(replacement) = v.deprecated() {
+                        let _ = writeln!(s, "\n**Deprecated**: {replacement}");
+                    }
+                    if let Some(desc) = v.description() {
+                        let _ = writeln!(s, "\n{desc}");
+                    }
+                }
+            }
+        }
+
+        for (title, people) in [("Authors", &self.authors), ("Maintainers", &self.maintainers)] {
+            if !people.is_empty() {
+                let _ = writeln!(s, "\n## {title}\n");
+                for p in people {
+                    let _ = writeln!(s, "- {p}");
+                }
+            }
+        }
+        if let Some(url) = self.bugreports() {
+            let _ = writeln!(s, "\n## Reporting bugs\n\n{url}");
+        }
+
+        s
+    }
+
+    /// Render the documentation as a man page in roff format.
+    pub fn to_man(&self) -> String {
+        let mut s = String::new();
+        let _ = writeln!(s, ".TH \"{}\" 5", self.name.to_uppercase());
+        let _ = writeln!(s, ".SH \"NAME\"");
+        match self.blurb() {
+            Some(blurb) => {
+                let _ = writeln!(s, "{} \\- {}", roff(&self.name), roff(blurb));
+            }
+            None => {
+                let _ = writeln!(s, "{}", roff(&self.name));
+            }
+        }
+        if let Some(replacement) = self.deprecated() {
+            let _ = writeln!(s, ".SH \"DEPRECATED\"\n{}", roff(replacement));
+        }
+        if let Some(desc) = self.description() {
+            let _ = writeln!(s, ".SH \"DESCRIPTION\"\n{}", roff(desc));
+        }
+        if !self.supported_eapis.is_empty() {
+            let _ = writeln!(s, ".SH \"SUPPORTED EAPIS\"\n{}", self.supported_eapis.join(" "));
+        }
+        if let Some(example) = self.example() {
+            let _ = writeln!(s, ".SH \"EXAMPLE\"\n.nf\n{}\n.fi", roff(example));
+        }
+
+        let functions: Vec<_> = self.functions.iter().filter(|f| !f.internal).collect();
+        if !functions.is_empty() {
+            let _ = writeln!(s, ".SH \"FUNCTIONS\"");
+            for f in functions {
+                let _ = writeln!(s, ".TP\n.B {}", roff(&f.signature()));
+                let desc = [
+                    f.deprecated().map(|r| format!("Deprecated: {r}")),
+                    f.description().map(String::from),
+                ];
+                let desc: Vec<_> = desc.into_iter().flatten().collect();
+                let _ = writeln!(s, "{}", roff(&desc.join("\n\n")));
+            }
+        }
+
+        for (title, vars) in [
+            ("ECLASS VARIABLES", &self.variables),
+            ("FUNCTION VARIABLES", &self.function_variables),
+        ] {
+            let vars: Vec<_> = vars.iter().filter(|v| !v.internal).collect();
+            if !vars.is_empty() {
+                let _ = writeln!(s, ".SH \"{title}\"");
+                for v in vars {
+                    let annotations = v.annotations();
+                    match annotations.is_empty() {
+                        true => {
+                            let _ = writeln!(s, ".TP\n.B {}", v.name);
+                        }
+                        false => {
+                            let _ = writeln!(s, ".TP\n.B {} ({})", v.name, annotations.join(", "));
+                        }
+                    }
+                    let _ = writeln!(s, "{}", roff(v.description().unwrap_or_default()));
+                }
+            }
+        }
+
+        for (title, people) in [("AUTHORS", &self.authors), ("MAINTAINERS", &self.maintainers)] {
+            if !people.is_empty() {
+                let _ = writeln!(s, ".SH \"{title}\"\n.nf\n{}\n.fi", roff(&people.join("\n")));
+            }
+        }
+        if let Some(url) = self.bugreports() {
+            let _ = writeln!(s, ".SH \"REPORTING BUGS\"\n{}", roff(url));
+        }
+
+        s
+    }
+}
+
+/// Escape text for use in roff output, mapping blank lines to paragraph breaks.
+fn roff(s: &str) -> String {
+    s.lines()
+        .map(|l| {
+            let l = l.replace('\\', "\\\\");
+            if l.is_empty() {
+                ".PP".to_string()
+            } else if l.starts_with('.') || l.starts_with('\'') {
+                format!("\\&{l}")
+            } else {
+                l
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Documentation block tag with its value and starting line number.
+#[derive(Debug)]
+struct Tag {
+    name: String,
+    value: String,
+    lineno: usize,
+}
+
+impl Tag {
+    /// Return the tag value as a single trimmed line.
+    fn line(&self) -> Result<String> {
+        match self.value.trim() {
+            s if s.contains('\n') => Err(self.error("multiline value")),
+            s => Ok(s.to_string()),
+        }
+    }
+
+    /// Return the tag value as text with trailing blank lines removed.
+    fn text(&self) -> Option<String> {
+        let s = self.value.trim_matches('\n');
+        if s.is_empty() {
+            None
+        } else {
+            Some(s.to_string())
+        }
+    }
+
+    fn lines(&self) -> Vec<String> {
+        self.value
+            .lines()
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    fn flag(&self) -> Result<bool> {
+        match self.value.trim().is_empty() {
+            true => Ok(true),
+            false => Err(self.error("unexpected value")),
+        }
+    }
+
+    fn error(&self, msg: &str) -> Error {
+        Error::InvalidValue(format!("line {}: @{}: {msg}", self.lineno, self.name))
+    }
+}
+
+/// Split a documentation block into its tags, keeping inline markup and the contents of
+/// `@CODE` sections as part of the current tag's value.
+fn parse_tags(block: &[(usize, &str)]) -> Result<Vec<Tag>> {
+    let mut tags: Vec<Tag> = vec![];
+    let mut code = false;
+    for (lineno, line) in block {
+        let line = line.strip_prefix('#').unwrap_or(line);
+        let line = line.strip_prefix(' ').unwrap_or(line).trim_end();
+        if line == "@CODE" {
+            code = !code;
+        }
+        let caps = TAG_RE
+            .captures(line)
+            .filter(|caps| !code && !INLINE_TAGS.contains(&&caps["tag"]));
+        match caps {
+            Some(caps) => tags.push(Tag {
+                name: caps["tag"].replace('-', "_"),
+                value: caps
+                    .name("val")
+                    .map(|m| m.as_str().to_string())
+                    .unwrap_or_default(),
+                lineno: *lineno,
+            }),
+            None => match tags.last_mut() {
+                Some(tag) => {
+                    tag.value.push('\n');
+                    tag.value.push_str(line);
+                }
+                None => return Err(Error::InvalidValue(format!("line {lineno}: missing tag"))),
+            },
+        }
+    }
+    Ok(tags)
+}
+
+fn parse_eclass(tags: &[Tag], doc: &mut EclassDoc) -> Result<()> {
+    for tag in tags {
+        match tag.name.as_str() {
+            "ECLASS" => doc.name = tag.line()?,
+            "MAINTAINER" => doc.maintainers.extend(tag.lines()),
+            "AUTHOR" => doc.authors.extend(tag.lines()),
+            "BUGREPORTS" => doc.bugreports = tag.text(),
+            "VCSURL" => doc.vcsurl = Some(tag.line()?),
+            "SUPPORTED_EAPIS" => {
+                doc.supported_eapis = tag.line()?.split_whitespace().map(String::from).collect()
+            }
+            "PROVIDES" => doc.provides = tag.line()?.split_whitespace().map(String::from).collect(),
+            "BLURB" => doc.blurb = Some(tag.line()?),
+            "DEPRECATED" => doc.deprecated = Some(tag.line()?),
+            "DESCRIPTION" => doc.description = tag.text(),
+            "EXAMPLE" => doc.example = tag.text(),
+            _ => return Err(tag.error("unknown eclass tag")),
+        }
+    }
+    Ok(())
+}
+
+fn parse_variable(tags: &[Tag]) -> Result<VariableDoc> {
+    let mut var = VariableDoc::default();
+    for tag in tags {
+        match tag.name.as_str() {
+            "ECLASS_VARIABLE" | "VARIABLE" => var.name = tag.line()?,
+            "DEFAULT_UNSET" => var.default_unset = tag.flag()?,
+            "INTERNAL" => var.internal = tag.flag()?,
+            "REQUIRED" => var.required = tag.flag()?,
+            "PRE_INHERIT" => var.pre_inherit = tag.flag()?,
+            "USER_VARIABLE" => var.user = tag.flag()?,
+            "OUTPUT_VARIABLE" => var.output = tag.flag()?,
+            "DEAD" => var.dead = tag.flag()?,
+            "DEPRECATED" => var.deprecated = Some(tag.line()?),
+            "DESCRIPTION" => var.description = tag.text(),
+            _ => return Err(tag.error("unknown variable tag")),
+        }
+    }
+    Ok(var)
+}
+
+fn parse_function(tags: &[Tag]) -> Result<FunctionDoc> {
+    let mut func = FunctionDoc::default();
+    for tag in tags {
+        match tag.name.as_str() {
+            "FUNCTION" => func.name = tag.line()?,
+            "USAGE" => func.usage = Some(tag.line()?),
+            "RETURN" => func.returns = Some(tag.line()?),
+            "MAINTAINER" => func.maintainers.extend(tag.lines()),
+            "INTERNAL" => func.internal = tag.flag()?,
+            "DEPRECATED" => func.deprecated = Some(tag.line()?),
+            "DESCRIPTION" => func.description = tag.text(),
+            _ => return Err(tag.error("unknown function tag")),
+        }
+    }
+    Ok(func)
+}
+
+impl FromStr for EclassDoc {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut doc = EclassDoc::default();
+        let mut lines = s.lines().enumerate().map(|(i, l)| (i + 1, l)).peekable();
+
+        while let Some((lineno, line)) = lines.next() {
+            let header = match line.strip_prefix("# @") {
+                Some(s) => s.split(':').next().unwrap_or_default(),
+                None => continue,
+            };
+            if !matches!(
+                header,
+                "ECLASS" | "ECLASS_VARIABLE" | "ECLASS-VARIABLE" | "FUNCTION" | "VARIABLE"
+            ) {
+                continue;
+            }
+
+            // documentation blocks continue until the first non-comment line
+            let mut block = vec![(lineno, line)];
+            while let Some((i, l)) = lines.next_if(|(_, l)| l.starts_with('#')) {
+                block.push((i, l));
+            }
+            let tags = parse_tags(&block)?;
+
+            match header {
+                "ECLASS" => parse_eclass(&tags, &mut doc)?,
+                "FUNCTION" => doc.functions.push(parse_function(&tags)?),
+                "VARIABLE" => doc.function_variables.push(parse_variable(&tags)?),
+                _ => doc.variables.push(parse_variable(&tags)?),
+            }
+        }
+
+        if doc.name.is_empty() {
+            return Err(Error::InvalidValue("missing @ECLASS block".to_string()));
+        }
+
+        Ok(doc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::macros::assert_err_re;
+    use crate::repo::ebuild::TempRepo;
+
+    use super::*;
+
+    const ECLASS: &str = indoc::indoc! {r#"
+        # Copyright 2024 Gentoo Authors
+        # Distributed under the terms of the GNU General Public License v2
+
+        # @ECLASS: foo.eclass
+        # @MAINTAINER:
+        # A Person <a@example.com>
+        # B Person <b@example.com>
+        # @SUPPORTED_EAPIS: 7 8
+        # @PROVIDES: bar
+        # @BLURB: Foo helpers
+        # @DESCRIPTION:
+        # Helpers for foo.
+        #
+        # More details.
+        # @SUBSECTION Usage
+        # @CODE
+        # @FOO: not a tag
+        # foo_run
+        # @CODE
+        # @EXAMPLE:
+        # inherit foo
+
+        # @ECLASS_VARIABLE: FOO_OPTS
+        # @PRE_INHERIT
+        # @DEFAULT_UNSET
+        # @DESCRIPTION:
+        # Options for foo.
+        : "${FOO_OPTS:=}"
+
+        # @ECLASS-VARIABLE: _FOO_INTERNAL
+        # @INTERNAL
+        # @DEAD
+
+        # @FUNCTION: foo_run
+        # @USAGE: <arg>
+        # @RETURN: 0 on success
+        # @DESCRIPTION:
+        # Run foo.
+        foo_run() { :; }
+
+        # @VARIABLE: FOO_RUN_ARGS
+        # @DESCRIPTION:
+        # Arguments for foo_run.
+
+        # @FUNCTION: foo_old
+        # @DEPRECATED: foo_run
+        foo_old() { :; }
+    "#};
+
+    #[test]
+    fn test_parse() {
+        let doc: EclassDoc = ECLASS.parse().unwrap();
+        assert_eq!(doc.name(), "foo.eclass");
+        assert_eq!(doc.maintainers(), ["A Person <a@example.com>", "B Person <b@example.com>"]);
+        assert_eq!(doc.supported_eapis(), ["7", "8"]);
+        assert_eq!(doc.provides(), ["bar"]);
+        assert_eq!(doc.blurb(), Some("Foo helpers"));
+        let desc = indoc::indoc! {"
+            Helpers for foo.
+
+            More details.
+            @SUBSECTION Usage
+            @CODE
+            @FOO: not a tag
+            foo_run
+            @CODE"};
+        assert_eq!(doc.description(), Some(desc));
+        assert_eq!(doc.example(), Some("inherit foo"));
+        assert!(doc.supports_eapi("8"));
+        assert!(!doc.supports_eapi("6"));
+
+        let vars: Vec<_> = doc.variables().iter().map(|v| v.name()).collect();
+        assert_eq!(vars, ["FOO_OPTS", "_FOO_INTERNAL"]);
+        let var = &doc.variables()[0];
+        assert!(var.pre_inherit() && var.default_unset() && !var.required());
+        assert_eq!(var.description(), Some("Options for foo."));
+        assert!(doc.variables()[1].internal() && doc.variables()[1].dead());
+        assert_eq!(doc.function_variables()[0].name(), "FOO_RUN_ARGS");
+
+        let func = &doc.functions()[0];
+        assert_eq!(func.name(), "foo_run");
+        assert_eq!(func.usage(), Some("<arg>"));
+        assert_eq!(func.returns(), Some("0 on success"));
+        assert_eq!(doc.functions()[1].deprecated(), Some("foo_run"));
+
+        // eclasses without restrictions support all EAPIs
+        let doc: EclassDoc = "# @ECLASS: a.eclass\n".parse().unwrap();
+        assert!(doc.supports_eapi("0"));
+
+        // invalid docs
+        assert_err_re!("".parse::<EclassDoc>(), "^missing @ECLASS block$");
+        let data = ECLASS.replace("@PROVIDES", "@UNKNOWN");
+        assert_err_re!(data.parse::<EclassDoc>(), "^line 9: @UNKNOWN: unknown eclass tag$");
+        let data = ECLASS.replace("# @PRE_INHERIT", "# @PRE_INHERIT: yes");
+        assert_err_re!(data.parse::<EclassDoc>(), "@PRE_INHERIT: unexpected value$");
+        let data = ECLASS.replace("# @USAGE: <arg>", "# @USAGE: <arg>\n# more");
+        assert_err_re!(data.parse::<EclassDoc>(), "@USAGE: multiline value$");
+    }
+
+    #[test]
+    fn test_render() {
+        let doc: EclassDoc = ECLASS.parse().unwrap();
+        let md = doc.to_markdown();
+        assert!(md.starts_with("# foo.eclass\n\nFoo helpers\n"));
+        assert!(md.contains("\n### `foo_run <arg>`\n\nRun foo.\n\nReturns: 0 on success\n"));
+        assert!(md.contains("\n### `FOO_OPTS` (default unset, set before inherit)\n"));
+        assert!(md.contains("\n### `foo_old`\n\n**Deprecated**: foo_run\n"));
+        assert!(!md.contains("_FOO_INTERNAL"));
+        assert!(md.contains("\n## Maintainers\n\n- A Person <a@example.com>\n"));
+
+        let man = doc.to_man();
+        assert!(man.starts_with(".TH \"FOO.ECLASS\" 5\n.SH \"NAME\"\nfoo.eclass \\- Foo helpers\n"));
+        assert!(man.contains(".SH \"DESCRIPTION\"\nHelpers for foo.\n.PP\nMore details.\n"));
+        assert!(man.contains(".TP\n.B foo_run <arg>\nRun foo.\n"));
+        assert!(!man.contains("_FOO_INTERNAL"));
+        assert_eq!(roff(".a\\b"), "\\&.a\\\\b");
+    }
+
+    #[test]
+    fn test_eclass() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let dir = t.repo.path().join("eclass");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("foo.eclass"), ECLASS).unwrap();
+        fs::write(dir.join("bar.eclass"), "# @ECLASS: bar.eclass\n").unwrap();
+        fs::write(dir.join("README"), "").unwrap();
+
+        let eclasses = t.repo.eclasses();
+        let names: Vec<_> = eclasses.iter().map(|e| e.name()).collect();
+        assert_eq!(names, ["bar", "foo"]);
+        let eclass = t.repo.eclass("foo").unwrap();
+        assert_eq!(eclass.path(), dir.join("foo.eclass"));
+        assert_eq!(eclass.chksum(), format!("{:x}", Md5::digest(ECLASS.as_bytes())));
+        assert_eq!(eclass.doc().unwrap().name(), "foo.eclass");
+        assert!(t.repo.eclass("nonexistent").is_none());
+        assert_eq!(eclass.repo(), "test");
+        assert!(Eclass::new("test", dir.join("README")).is_err());
+
+        for (cpv, eapi) in [("cat/a-1", "8"), ("cat/b-1", "6"), ("cat/c-1", "8")] {
+            t.create_ebuild(cpv, Some(HashMap::from([("eapi", eapi)])))
+                .unwrap();
+        }
+        let chksum = eclass.chksum();
+        t.create_metadata("cat/a-1", &[("EAPI", "8"), ("_eclasses_", &format!("foo\t{chksum}"))])
+            .unwrap();
+        t.create_metadata("cat/b-1", &[("EAPI", "6"), ("_eclasses_", &format!("foo\t{chksum}"))])
+            .unwrap();
+        t.create_metadata("cat/c-1", &[("EAPI", "8")]).unwrap();
+
+        assert_eq!(eclass.inherited_by(&t.repo), ["cat/a-1", "cat/b-1"]);
+        let unsupported = eclass.unsupported(&t.repo).unwrap();
+        assert_eq!(unsupported, [("cat/b-1".to_string(), "6".to_string())]);
+    }
+}
@@ -13,7 +13,7 @@ pub struct Repo {
 impl Repo {
     pub(super) const FORMAT: &'static str = "fake";
 
-    fn new<'a, I>(id: &str, atoms: I) -> Result<Repo>
+    pub(crate) fn new<'a, I>(id: &str, atoms: I) -> Result<Repo>
     where
         I: IntoIterator<Item = &'a str>,
     {
@@ -29,6 +29,10 @@ impl Repo {
         Repo::new(id, data.lines())
     }
 
+    pub(super) fn pkgs(&self) -> &repo::PkgCache {
+        &self.pkgs
+    }
+
     pub fn iter(&self) -> PkgIter {
         self.into_iter()
     }
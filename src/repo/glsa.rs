@@ -0,0 +1,628 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::{fmt, fs};
+
+use roxmltree::{Document, Node};
+use tracing::warn;
+
+use crate::atom::{self, Version};
+use crate::files::{has_ext, is_file, sorted_dir_list};
+use crate::pkg::ebuild::xml::text;
+use crate::pkg::Package;
+use crate::repo::{ebuild, vdb, Repository};
+use crate::{Error, Result};
+
+/// Version range operators used in GLSA package entries.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RangeOp {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+    /// Revision-only variants that require the same base version.
+    RevLess,
+    RevLessOrEqual,
+    RevGreaterOrEqual,
+    RevGreater,
+}
+
+impl FromStr for RangeOp {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lt" => Ok(RangeOp::Less),
+            "le" => Ok(RangeOp::LessOrEqual),
+            "eq" => Ok(RangeOp::Equal),
+            "ge" => Ok(RangeOp::GreaterOrEqual),
+            "gt" => Ok(RangeOp::Greater),
+            "rlt" => Ok(RangeOp::RevLess),
+            "rle" => Ok(RangeOp::RevLessOrEqual),
+            "rge" => Ok(RangeOp::RevGreaterOrEqual),
+            "rgt" => Ok(RangeOp::RevGreater),
+            _ => Err(Error::InvalidValue(format!("invalid range operator: {s}"))),
+        }
+    }
+}
+
+impl RangeOp {
+    /// Return the equivalent atom version operator.
+    fn atom_op(&self) -> &'static str {
+        match self {
+            RangeOp::Less | RangeOp::RevLess => "<",
+            RangeOp::LessOrEqual | RangeOp::RevLessOrEqual => "<=",
+            RangeOp::Equal => "=",
+            RangeOp::GreaterOrEqual | RangeOp::RevGreaterOrEqual => ">=",
+            RangeOp::Greater | RangeOp::RevGreater => ">",
+        }
+    }
+
+    fn is_revision(&self) -> bool {
+        matches!(
+            self,
+            RangeOp::RevLess
+                | RangeOp::RevLessOrEqual
+                | RangeOp::RevGreaterOrEqual
+                | RangeOp::RevGreater
+        )
+    }
+}
+
+/// Vulnerable or unaffected version range for a package.
+#[derive(Debug, Clone)]
+pub struct Range {
+    op: RangeOp,
+    version: String,
+    slot: Option<String>,
+    // version with the equivalent atom operator used for comparisons
+    cmp: Version,
+    // base version restriction used for revision-only ranges
+    base: Option<Version>,
+}
+
+impl PartialEq for Range {
+    fn eq(&self, other: &Self) -> bool {
+        self.op == other.op && self.version == other.version && self.slot == other.slot
+    }
+}
+
+impl Eq for Range {}
+
+impl Range {
+    fn new(op: RangeOp, version: &str, slot: Option<&str>) -> Result<Self> {
+        let cmp = match version.strip_suffix('*') {
+            Some(v) if op == RangeOp::Equal => atom::parse::version_with_op(&format!("={v}*"))?,
+            _ => atom::parse::version_with_op(&format!("{}{version}", op.atom_op()))?,
+        };
+        let base = match op.is_revision() {
+            true => Some(atom::parse::version_with_op(&format!("~{}", cmp.base()))?),
+            false => None,
+        };
+        Ok(Range {
+            op,
+            version: version.to_string(),
+            slot: slot.filter(|s| *s != "*").map(|s| s.to_string()),
+            cmp,
+            base,
+        })
+    }
+
+    pub fn op(&self) -> RangeOp {
+        self.op
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn slot(&self) -> Option<&str> {
+        self.slot.as_deref()
+    }
+
+    /// Determine if a version with an optional slot falls within the range.
+    pub fn matches(&self, version: &Version, slot: Option<&str>) -> bool {
+        if let (Some(s1), Some(s2)) = (self.slot(), slot) {
+            if s1 != s2 {
+                return false;
+            }
+        }
+        self.base
+            .as_ref()
+            .map(|b| b.op_cmp(version))
+            .unwrap_or(true)
+            && self.cmp.op_cmp(version)
+    }
+
+    /// Return the atom string for a package key restricted to the range.
+    pub fn atom(&self, key: &str) -> String {
+        let version = self.version.trim_end_matches('*');
+        let glob = if self.version.ends_with('*') { "*" } else { "" };
+        let slot = self.slot().map(|s| format!(":{s}")).unwrap_or_default();
+        format!("{}{key}-{version}{glob}{slot}", self.op.atom_op())
+    }
+}
+
+/// Affected package entry in a GLSA.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlsaPkg {
+    name: String,
+    auto: bool,
+    arches: Vec<String>,
+    vulnerable: Vec<Range>,
+    unaffected: Vec<Range>,
+}
+
+impl GlsaPkg {
+    /// Return the package key, e.g. dev-libs/openssl.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn auto(&self) -> bool {
+        self.auto
+    }
+
+    /// Return the affected arches, with `*` signifying all arches.
+    pub fn arches(&self) -> &[String] {
+        &self.arches
+    }
+
+    pub fn vulnerable(&self) -> &[Range] {
+        &self.vulnerable
+    }
+
+    pub fn unaffected(&self) -> &[Range] {
+        &self.unaffected
+    }
+
+    fn arch_matches(&self, arch: Option<&str>) -> bool {
+        match arch {
+            None => true,
+            Some(arch) => self.arches.iter().any(|a| a == "*" || a == arch),
+        }
+    }
+
+    /// Determine if a version is vulnerable, i.e. it matches a vulnerable range and no
+    /// unaffected range.
+    pub fn is_vulnerable(&self, version: &Version, slot: Option<&str>) -> bool {
+        self.vulnerable.iter().any(|r| r.matches(version, slot))
+            && !self.unaffected.iter().any(|r| r.matches(version, slot))
+    }
+}
+
+/// Gentoo Linux Security Advisory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glsa {
+    id: String,
+    title: String,
+    synopsis: String,
+    product: String,
+    announced: String,
+    revised: String,
+    revision: u32,
+    bugs: Vec<String>,
+    access: String,
+    severity: String,
+    packages: Vec<GlsaPkg>,
+    background: String,
+    description: String,
+    impact: String,
+    workaround: String,
+    resolution: String,
+}
+
+impl Glsa {
+    /// Load a GLSA from a given XML file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .map_err(|e| Error::IO(format!("failed reading {path:?}: {e}")))?;
+        data.parse()
+            .map_err(|e| Error::InvalidValue(format!("{path:?}: {e}")))
+    }
+
+    /// Return the GLSA identifier, e.g. 202401-01.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn synopsis(&self) -> &str {
+        &self.synopsis
+    }
+
+    pub fn product(&self) -> &str {
+        &self.product
+    }
+
+    pub fn announced(&self) -> &str {
+        &self.announced
+    }
+
+    pub fn revised(&self) -> (&str, u32) {
+        (&self.revised, self.revision)
+    }
+
+    pub fn bugs(&self) -> &[String] {
+        &self.bugs
+    }
+
+    pub fn access(&self) -> &str {
+        &self.access
+    }
+
+    /// Return the impact severity, e.g. high.
+    pub fn severity(&self) -> &str {
+        &self.severity
+    }
+
+    pub fn packages(&self) -> &[GlsaPkg] {
+        &self.packages
+    }
+
+    pub fn background(&self) -> &str {
+        &self.background
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn impact(&self) -> &str {
+        &self.impact
+    }
+
+    pub fn workaround(&self) -> &str {
+        &self.workaround
+    }
+
+    pub fn resolution(&self) -> &str {
+        &self.resolution
+    }
+
+    /// Return the installed packages affected by the GLSA for an optional arch, with all arches
+    /// being checked when no arch is given.
+    pub fn affected(&self, installed: &vdb::Repo, arch: Option<&str>) -> Vec<Affected> {
+        let mut affected = vec![];
+        for pkg in self.packages.iter().filter(|p| p.arch_matches(arch)) {
+            let (cat, name) = match pkg.name.split_once('/') {
+                Some(vals) => vals,
+                None => continue,
+            };
+            // only the installed versions of the package are loaded
+            for ver in installed.versions(cat, name) {
+                let cpv = match atom::parse::cpv(&format!("{cat}/{name}-{ver}")) {
+                    Ok(cpv) => cpv,
+                    Err(e) => {
+                        warn!("{}: {e}", self.id);
+                        continue;
+                    }
+                };
+                let p = match crate::pkg::vdb::Pkg::new(&cpv, installed) {
+                    Ok(p) => p,
+                    Err(e) => {
+                        warn!("{}: invalid package: {cpv}: {e}", self.id);
+                        continue;
+                    }
+                };
+                let version = cpv.version().unwrap();
+                let slot = p.metadata().ok().map(|m| m.slot());
+                if pkg.is_vulnerable(version, slot) {
+                    let upgrade = pkg
+                        .unaffected
+                        .iter()
+                        .filter(|r| {
+                            matches!(r.op, RangeOp::GreaterOrEqual | RangeOp::Greater)
+                                || r.op.is_revision()
+                        })
+                        .map(|r| r.atom(&pkg.name))
+                        .collect();
+                    affected.push(Affected {
+                        glsa: self.id.clone(),
+                        cpv: p.atom().cpv(),
+                        upgrade,
+                    });
+                }
+            }
+        }
+        affected
+    }
+}
+
+fn child<'a, 'b>(node: Node<'a, 'b>, name: &str) -> Option<Node<'a, 'b>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+fn child_text(node: Node, name: &str) -> String {
+    child(node, name).map(text).unwrap_or_default()
+}
+
+fn parse_range(node: Node) -> Result<Range> {
+    let op = node
+        .attribute("range")
+        .ok_or_else(|| Error::InvalidValue("missing range attribute".to_string()))?;
+    let version = text(node);
+    Range::new(op.parse()?, &version, node.attribute("slot"))
+}
+
+impl FromStr for Glsa {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let doc =
+            Document::parse(s).map_err(|e| Error::InvalidValue(format!("invalid GLSA: {e}")))?;
+        let root = doc.root_element();
+        if !root.has_tag_name("glsa") {
+            return Err(Error::InvalidValue("invalid GLSA: missing glsa element".to_string()));
+        }
+        let id = root
+            .attribute("id")
+            .ok_or_else(|| Error::InvalidValue("invalid GLSA: missing id".to_string()))?;
+        let err = |e: Error| Error::InvalidValue(format!("invalid GLSA {id}: {e}"));
+
+        let mut packages = vec![];
+        if let Some(affected) = child(root, "affected") {
+            for node in affected.children().filter(|n| n.has_tag_name("package")) {
+                let name = node
+                    .attribute("name")
+                    .ok_or_else(|| err(Error::InvalidValue("missing package name".to_string())))?;
+                let mut pkg = GlsaPkg {
+                    name: name.to_string(),
+                    auto: node.attribute("auto") == Some("yes"),
+                    arches: node
+                        .attribute("arch")
+                        .unwrap_or("*")
+                        .split_whitespace()
+                        .map(|s| s.to_string())
+                        .collect(),
+                    vulnerable: vec![],
+                    unaffected: vec![],
+                };
+                for n in node.children().filter(|n| n.is_element()) {
+                    match n.tag_name().name() {
+                        "vulnerable" => pkg.vulnerable.push(parse_range(n).map_err(err)?),
+                        "unaffected" => pkg.unaffected.push(parse_range(n).map_err(err)?),
+                        _ => (),
+                    }
+                }
+                packages.push(pkg);
+            }
+        }
+
+        let revised = child(root, "revised");
+        Ok(Glsa {
+            id: id.to_string(),
+            title: child_text(root, "title"),
+            synopsis: child_text(root, "synopsis"),
+            product: child_text(root, "product"),
+            announced: child_text(root, "announced"),
+            revised: revised.map(text).unwrap_or_default(),
+            revision: revised
+                .and_then(|n| n.attribute("count"))
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(1),
+            bugs: root
+                .children()
+                .filter(|n| n.has_tag_name("bug"))
+                .map(text)
+                .collect(),
+            access: child_text(root, "access"),
+            severity: child(root, "impact")
+                .and_then(|n| n.attribute("type"))
+                .unwrap_or_default()
+                .to_string(),
+            packages,
+            background: child_text(root, "background"),
+            description: child_text(root, "description"),
+            impact: child_text(root, "impact"),
+            workaround: child_text(root, "workaround"),
+            resolution: child_text(root, "resolution"),
+        })
+    }
+}
+
+/// Installed package affected by a GLSA.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Affected {
+    glsa: String,
+    cpv: String,
+    upgrade: Vec<String>,
+}
+
+impl Affected {
+    pub fn glsa(&self) -> &str {
+        &self.glsa
+    }
+
+    pub fn cpv(&self) -> &str {
+        &self.cpv
+    }
+
+    /// Return the atoms for unaffected versions the package can be upgraded to.
+    pub fn upgrade(&self) -> &[String] {
+        &self.upgrade
+    }
+}
+
+impl fmt::Display for Affected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "GLSA {}: {}", self.glsa, self.cpv)?;
+        if !self.upgrade.is_empty() {
+            write!(f, " -> {}", self.upgrade.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Load all GLSAs for a repo, sorted by file name.
+pub(crate) fn load(repo: &ebuild::Repo) -> Result<Vec<Glsa>> {
+    let dir = repo.path().join("metadata/glsa");
+    let mut glsas = vec![];
+    if !dir.exists() {
+        return Ok(glsas);
+    }
+
+    let filter = |e: &walkdir::DirEntry| -> bool {
+        is_file(e) && has_ext(e, "xml") && e.file_name().to_string_lossy().starts_with("glsa-")
+    };
+    for entry in sorted_dir_list(&dir).into_iter().filter_entry(filter) {
+        let entry = entry.map_err(|e| Error::IO(format!("failed reading {dir:?}: {e}")))?;
+        match Glsa::load(entry.path()) {
+            Ok(g) => glsas.push(g),
+            Err(e) => warn!("{}: {e}", repo.id()),
+        }
+    }
+
+    Ok(glsas)
+}
+
+/// Return the installed packages affected by any of the given GLSAs.
+pub fn check(glsas: &[Glsa], installed: &vdb::Repo, arch: Option<&str>) -> Vec<Affected> {
+    glsas
+        .iter()
+        .flat_map(|g| g.affected(installed, arch))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::macros::assert_err_re;
+    use crate::repo::ebuild::TempRepo;
+
+    use super::*;
+
+    const GLSA: &str = indoc::indoc! {r#"
+        <?xml version="1.0" encoding="UTF-8"?>
+        <glsa id="202401-01">
+          <title>Foo: Multiple vulnerabilities</title>
+          <synopsis>Several issues in foo.</synopsis>
+          <product type="ebuild">foo</product>
+          <announced>2024-01-01</announced>
+          <revised count="2">2024-01-02</revised>
+          <bug>1</bug>
+          <bug>2</bug>
+          <access>remote</access>
+          <affected>
+            <package name="cat/foo" auto="yes" arch="*">
+              <unaffected range="ge">2</unaffected>
+              <unaffected range="rge">1.5-r2</unaffected>
+              <vulnerable range="lt">2</vulnerable>
+            </package>
+            <package name="cat/bar" auto="no" arch="arm64">
+              <vulnerable range="eq">1*</vulnerable>
+            </package>
+          </affected>
+          <background><p>Foo is a library.</p></background>
+          <description><p>Bad things.</p></description>
+          <impact type="high"><p>Very bad.</p></impact>
+          <workaround><p>None.</p></workaround>
+          <resolution><p>Upgrade.</p></resolution>
+        </glsa>
+    "#};
+
+    fn create_pkg(root: &Path, cpv: &str) {
+        let path = root.join(cpv);
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("SLOT"), "0\n").unwrap();
+    }
+
+    #[test]
+    fn test_parse() {
+        let glsa: Glsa = GLSA.parse().unwrap();
+        assert_eq!(glsa.id(), "202401-01");
+        assert_eq!(glsa.title(), "Foo: Multiple vulnerabilities");
+        assert_eq!(glsa.revised(), ("2024-01-02", 2));
+        assert_eq!(glsa.bugs(), ["1", "2"]);
+        assert_eq!(glsa.severity(), "high");
+        assert_eq!(glsa.resolution(), "Upgrade.");
+        let pkg = &glsa.packages()[0];
+        assert_eq!(pkg.name(), "cat/foo");
+        assert!(pkg.auto());
+        assert_eq!(pkg.arches(), ["*"]);
+        assert_eq!(pkg.unaffected()[1].op(), RangeOp::RevGreaterOrEqual);
+        assert_eq!(pkg.vulnerable()[0].atom("cat/foo"), "<cat/foo-2");
+        assert_eq!(glsa.packages()[1].vulnerable()[0].atom("cat/bar"), "=cat/bar-1*");
+
+        // invalid GLSAs
+        assert_err_re!("<glsa>".parse::<Glsa>(), "^invalid GLSA: ");
+        assert_err_re!("<glsa></glsa>".parse::<Glsa>(), "^invalid GLSA: missing id");
+        let data = GLSA.replace(r#"range="lt""#, r#"range="ne""#);
+        assert_err_re!(data.parse::<Glsa>(), "invalid range operator: ne");
+    }
+
+    #[test]
+    fn test_ranges() {
+        let v = |s: &str| -> Version { s.parse().unwrap() };
+        let r = Range::new(RangeOp::RevGreaterOrEqual, "1.5-r2", None).unwrap();
+        assert!(r.matches(&v("1.5-r2"), None));
+        assert!(r.matches(&v("1.5-r3"), None));
+        assert!(!r.matches(&v("1.5-r1"), None));
+        assert!(!r.matches(&v("1.6"), None));
+
+        let r = Range::new(RangeOp::Equal, "1*", Some("1")).unwrap();
+        assert!(r.matches(&v("1.2"), Some("1")));
+        assert!(r.matches(&v("1.2"), None));
+        assert!(!r.matches(&v("1.2"), Some("0")));
+        assert!(!r.matches(&v("2"), Some("1")));
+        assert_eq!(r.atom("cat/pkg"), "=cat/pkg-1*:1");
+
+        let r = Range::new(RangeOp::LessOrEqual, "1", Some("*")).unwrap();
+        assert_eq!(r.slot(), None);
+        assert!(r.matches(&v("1"), None));
+        assert!(!r.matches(&v("1-r1"), None));
+    }
+
+    #[test]
+    fn test_affected() {
+        let dir = TempDir::new().unwrap();
+        create_pkg(dir.path(), "cat/foo-1");
+        create_pkg(dir.path(), "cat/foo-1.5-r2");
+        create_pkg(dir.path(), "cat/foo-2");
+        create_pkg(dir.path(), "cat/bar-1.2");
+        let installed = vdb::Repo::from_path("vdb", dir.path()).unwrap();
+
+        let glsa: Glsa = GLSA.parse().unwrap();
+        let affected = glsa.affected(&installed, Some("amd64"));
+        assert_eq!(affected.len(), 1);
+        assert_eq!(affected[0].cpv(), "cat/foo-1");
+        assert_eq!(affected[0].upgrade(), [">=cat/foo-2", ">=cat/foo-1.5-r2"]);
+        assert_eq!(
+            affected[0].to_string(),
+            "GLSA 202401-01: cat/foo-1 -> >=cat/foo-2 >=cat/foo-1.5-r2"
+        );
+
+        // arch restricted packages are included when matching or when no arch is given
+        let cpvs: Vec<_> = glsa
+            .affected(&installed, None)
+            .into_iter()
+            .map(|a| a.cpv().to_string())
+            .collect();
+        assert_eq!(cpvs, ["cat/foo-1", "cat/bar-1.2"]);
+        assert_eq!(check(&[glsa], &installed, Some("arm64")).len(), 2);
+    }
+
+    #[test]
+    fn test_load() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let dir = t.repo.path().join("metadata/glsa");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("glsa-202401-02.xml"), GLSA.replace("202401-01", "202401-02")).unwrap();
+        fs::write(dir.join("glsa-202401-01.xml"), GLSA).unwrap();
+        fs::write(dir.join("glsa-202401-03.xml"), "<glsa>").unwrap();
+        fs::write(dir.join("index.xml"), "<index/>").unwrap();
+        let ids: Vec<_> = t
+            .repo
+            .glsas()
+            .unwrap()
+            .iter()
+            .map(|g| g.id().to_string())
+            .collect();
+        assert_eq!(ids, ["202401-01", "202401-02"]);
+    }
+}
@@ -0,0 +1,239 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use md5::{Digest, Md5};
+
+use crate::repo::{ebuild, Repository};
+use crate::{Error, Result};
+
+const HEADER: &str = "pkgcraft-index 2";
+
+/// Return the modification time of a path formatted with nanosecond precision.
+fn mtime(path: &Path) -> Option<String> {
+    let time = fs::metadata(path).ok()?.modified().ok()?;
+    let d = time.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("{}.{:09}", d.as_secs(), d.subsec_nanos()))
+}
+
+/// Return the commit hash HEAD points to for a git checkout.
+pub(crate) fn git_head(path: &Path) -> Option<String> {
+    let git = path.join(".git");
+    let head = fs::read_to_string(git.join("HEAD")).ok()?;
+    let head = head.trim();
+    let name = match head.strip_prefix("ref: ") {
+        Some(name) => name,
+        None => return Some(head.to_string()),
+    };
+    if let Ok(hash) = fs::read_to_string(git.join(name)) {
+        return Some(hash.trim().to_string());
+    }
+    // fall back to packed refs
+    let packed = fs::read_to_string(git.join("packed-refs")).ok()?;
+    packed
+        .lines()
+        .filter_map(|l| l.split_once(' '))
+        .find(|(_, r)| *r == name)
+        .map(|(hash, _)| hash.to_string())
+}
+
+/// On-disk index of an ebuild repo's package versions, allowing repos to be queried without
+/// walking their directories.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PkgIndex {
+    /// Modification times of the repo, category, and package directories relative to the repo.
+    mtimes: Vec<(String, String)>,
+    cpvs: Vec<String>,
+}
+
+impl PkgIndex {
+    /// Build an index by walking a repo, validated against its directory modification times.
+    ///
+    /// Git checkouts are validated the same way since their HEAD commit doesn't reflect
+    /// uncommitted changes to the working tree.
+    pub fn build(repo: &ebuild::Repo) -> Self {
+        let path = repo.path();
+        let mut dirs = vec![];
+        let mut cpvs = vec![];
+        dirs.extend(mtime(path).map(|t| (".".to_string(), t)));
+        for cat in repo.category_dirs() {
+            dirs.extend(mtime(&path.join(&cat)).map(|t| (cat.clone(), t)));
+            for pkg in repo.package_dirs(&cat) {
+                let key = format!("{cat}/{pkg}");
+                dirs.extend(mtime(&path.join(&key)).map(|t| (key.clone(), t)));
+                for ver in repo.ebuild_versions(&cat, &pkg) {
+                    cpvs.push(format!("{key}-{ver}"));
+                }
+            }
+        }
+
+        PkgIndex { mtimes: dirs, cpvs }
+    }
+
+    /// Return the index file path for a repo within a given cache directory.
+    pub fn path<P: AsRef<Path>>(cache: P, repo: &ebuild::Repo) -> PathBuf {
+        let repo_path = repo.path().to_string_lossy();
+        let hash = format!("{:x}", Md5::digest(repo_path.as_bytes()));
+        cache
+            .as_ref()
+            .join("repos")
+            .join(format!("{}-{hash}.index", repo.id()))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .map_err(|e| Error::IO(format!("failed reading index: {path:?}: {e}")))?;
+        PkgIndex::from_str(&data).map_err(|e| Error::InvalidValue(format!("{path:?}: {e}")))
+    }
+
+    /// Atomically write the index to a given path, creating parent directories as needed.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        fs::create_dir_all(dir)
+            .map_err(|e| Error::IO(format!("failed creating index dir: {dir:?}: {e}")))?;
+
+        let mut data = format!("{HEADER}\n");
+        for (dir, time) in &self.mtimes {
+            data.push_str(&format!("mtime {time} {dir}\n"));
+        }
+        for cpv in &self.cpvs {
+            data.push_str(&format!("cpv {cpv}\n"));
+        }
+
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| Error::IO(format!("failed writing index: {path:?}: {e}")))
+    }
+
+    /// Determine if the index is up to date for a given repo path.
+    pub fn is_valid<P: AsRef<Path>>(&self, path: P) -> bool {
+        let path = path.as_ref();
+        self.mtimes.iter().all(|(dir, time)| {
+            let dir = match dir.as_str() {
+                "." => path.to_path_buf(),
+                d => path.join(d),
+            };
+            mtime(&dir).as_ref() == Some(time)
+        })
+    }
+
+    /// Return the recorded directory modification times.
+    pub fn mtimes(&self) -> &[(String, String)] {
+        &self.mtimes
+    }
+
+    /// Return the indexed package versions in walk order.
+    pub fn cpvs(&self) -> &[String] {
+        &self.cpvs
+    }
+}
+
+impl FromStr for PkgIndex {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut lines = s.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(Error::InvalidValue("unsupported index format".to_string()));
+        }
+
+        let (mut dirs, mut cpvs) = (vec![], vec![]);
+        for (i, line) in lines.enumerate() {
+            let err = || Error::InvalidValue(format!("line {}: invalid entry: {line}", i + 2));
+            match line.split_once(' ').ok_or_else(err)? {
+                ("mtime", val) => {
+                    let (time, dir) = val.split_once(' ').ok_or_else(err)?;
+                    dirs.push((dir.to_string(), time.to_string()));
+                }
+                ("cpv", cpv) => cpvs.push(cpv.to_string()),
+                _ => return Err(err()),
+            }
+        }
+
+        Ok(PkgIndex { mtimes: dirs, cpvs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use filetime::{set_file_mtime, FileTime};
+    use tempfile::TempDir;
+
+    use crate::macros::assert_err_re;
+    use crate::repo::ebuild::TempRepo;
+
+    use super::*;
+
+    #[test]
+    fn test_mtimes() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let path = t.repo.path();
+        t.create_ebuild("cat/pkg-1", None).unwrap();
+        t.create_ebuild("cat/pkg-2", None).unwrap();
+        // reset mtimes so changes are detected regardless of timestamp granularity
+        for dir in [path, &path.join("cat"), &path.join("cat/pkg")] {
+            set_file_mtime(dir, FileTime::from_unix_time(1, 0)).unwrap();
+        }
+
+        let index = PkgIndex::build(&t.repo);
+        assert_eq!(index.mtimes().len(), 3);
+        assert_eq!(index.cpvs(), ["cat/pkg-1", "cat/pkg-2"]);
+        assert!(index.is_valid(path));
+
+        // round trip through the cache dir
+        let cache = TempDir::new().unwrap();
+        let file = PkgIndex::path(cache.path(), &t.repo);
+        assert!(file.starts_with(cache.path().join("repos")));
+        index.save(&file).unwrap();
+        assert_eq!(PkgIndex::load(&file).unwrap(), index);
+
+        // adding a version invalidates the index
+        t.create_ebuild("cat/pkg-3", None).unwrap();
+        assert!(!index.is_valid(path));
+
+        // invalid files
+        fs::write(&file, "cpv cat/pkg-1\n").unwrap();
+        assert_err_re!(PkgIndex::load(&file), "unsupported index format$");
+        fs::write(&file, format!("{HEADER}\nunknown\n")).unwrap();
+        assert_err_re!(PkgIndex::load(&file), "line 2: invalid entry: unknown$");
+        assert!(PkgIndex::load(cache.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_git() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let git = t.repo.path().join(".git");
+        fs::create_dir_all(git.join("refs/heads")).unwrap();
+        fs::write(git.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        fs::write(git.join("refs/heads/master"), "abc123\n").unwrap();
+        t.create_ebuild("cat/pkg-1", None).unwrap();
+
+        assert_eq!(git_head(t.repo.path()).unwrap(), "abc123");
+
+        // uncommitted changes invalidate indexes of git checkouts
+        let path = t.repo.path();
+        for dir in [path, &path.join("cat"), &path.join("cat/pkg")] {
+            set_file_mtime(dir, FileTime::from_unix_time(1, 0)).unwrap();
+        }
+        let index = PkgIndex::build(&t.repo);
+        assert!(index.is_valid(path));
+        t.create_ebuild("cat/pkg-2", None).unwrap();
+        assert!(!index.is_valid(path));
+
+        // old git-validated indexes are rebuilt
+        assert!(PkgIndex::from_str("pkgcraft-index 1\ngit abc123\ncpv cat/pkg-1\n").is_err());
+
+        // packed refs
+        fs::remove_file(git.join("refs/heads/master")).unwrap();
+        fs::write(git.join("packed-refs"), "# pack-refs\ndef456 refs/heads/master\n").unwrap();
+        assert_eq!(git_head(t.repo.path()).unwrap(), "def456");
+
+        // detached HEAD
+        fs::write(git.join("HEAD"), "abc123\n").unwrap();
+        assert_eq!(git_head(t.repo.path()).unwrap(), "abc123");
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use blake2::Blake2b512;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+
+use crate::files;
+use crate::repo::{ebuild, Repository};
+use crate::{Error, Result};
+
+/// Hashes supported for verification in order of preference.
+const HASHES: [&str; 5] = ["BLAKE2B", "SHA512", "SHA256", "SHA1", "MD5"];
+
+/// Manifest entry types as defined by GLEP 74.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    Manifest,
+    Data,
+    Misc,
+    Ebuild,
+    Aux,
+    Dist,
+}
+
+impl FromStr for EntryKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "MANIFEST" => Ok(Self::Manifest),
+            "DATA" => Ok(Self::Data),
+            "MISC" => Ok(Self::Misc),
+            "EBUILD" => Ok(Self::Ebuild),
+            "AUX" => Ok(Self::Aux),
+            "DIST" => Ok(Self::Dist),
+            _ => Err(Error::InvalidValue(format!("unknown entry type: {s}"))),
+        }
+    }
+}
+
+/// File entry in a Manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    kind: EntryKind,
+    path: String,
+    size: u64,
+    hashes: HashMap<String, String>,
+}
+
+impl Entry {
+    pub fn kind(&self) -> EntryKind {
+        self.kind
+    }
+
+    /// Return the entry path relative to its Manifest, or the file name for distfiles.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Return the hash for a given algorithm, e.g. BLAKE2B.
+    pub fn hash(&self, name: &str) -> Option<&str> {
+        self.hashes.get(name).map(|s| s.as_str())
+    }
+}
+
+/// Manifest file for a repo tree or package directory.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    entries: Vec<Entry>,
+    ignore: Vec<String>,
+}
+
+impl Manifest {
+    /// Load a Manifest from a given file, decompressing it based on its extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let err = |e: String| Error::InvalidValue(format!("invalid manifest: {path:?}: {e}"));
+        let tool = match path.extension().and_then(|s| s.to_str()) {
+            Some("gz") => Some("gzip"),
+            Some("bz2") => Some("bzip2"),
+            Some("xz") => Some("xz"),
+            _ => None,
+        };
+        let data = match tool {
+            None => fs::read_to_string(path).map_err(|e| err(e.to_string()))?,
+            Some(cmd) => {
+                let output = Command::new(cmd)
+                    .arg("-dc")
+                    .arg(path)
+                    .output()
+                    .map_err(|e| err(format!("failed running {cmd}: {e}")))?;
+                if !output.status.success() {
+                    return Err(err(format!("failed decompressing with {cmd}")));
+                }
+                String::from_utf8(output.stdout).map_err(|e| err(e.to_string()))?
+            }
+        };
+        data.parse().map_err(|e: Error| err(e.to_string()))
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Return the paths excluded from verification.
+    pub fn ignored(&self) -> &[String] {
+        &self.ignore
+    }
+}
+
+impl FromStr for Manifest {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut manifest = Manifest::default();
+        let mut lines = s.lines().peekable();
+
+        // skip the cleartext signature header, the signature itself is ignored
+        if lines
+            .next_if_eq(&"-----BEGIN PGP SIGNED MESSAGE-----")
+            .is_some()
+        {
+            for line in lines.by_ref() {
+                if line.is_empty() {
+                    break;
+                }
+            }
+        }
+
+        for line in lines {
+            if line == "-----BEGIN PGP SIGNATURE-----" {
+                break;
+            }
+            // dash-escaped lines in signed Manifests
+            let line = line.strip_prefix("- ").unwrap_or(line);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let err = || Error::InvalidValue(format!("invalid line: {line:?}"));
+            match fields[..] {
+                [] | ["TIMESTAMP", _] => (),
+                ["IGNORE", path] => manifest.ignore.push(path.to_string()),
+                [kind, path, size, ref hashes @ ..] if hashes.len() % 2 == 0 => {
+                    let kind = kind.parse()?;
+                    let size = size.parse().map_err(|_| err())?;
+                    let hashes = hashes
+                        .chunks(2)
+                        .map(|x| (x[0].to_string(), x[1].to_string()))
+                        .collect();
+                    manifest.entries.push(Entry {
+                        kind,
+                        path: unescape(path),
+                        size,
+                        hashes,
+                    });
+                }
+                _ => return Err(err()),
+            }
+        }
+
+        Ok(manifest)
+    }
+}
+
+/// Decode the `\xNN`, `\uNNNN`, and `\UNNNNNNNN` escapes used for Manifest paths.
+fn unescape(path: &str) -> String {
+    let mut s = String::new();
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        let len = match (c, chars.clone().next()) {
+            ('\\', Some('x')) => 2,
+            ('\\', Some('u')) => 4,
+            ('\\', Some('U')) => 8,
+            _ => {
+                s.push(c);
+                continue;
+            }
+        };
+        let hex: String = chars.clone().skip(1).take(len).collect();
+        match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+            Some(decoded) if hex.len() == len => {
+                s.push(decoded);
+                chars.nth(len);
+            }
+            _ => s.push(c),
+        }
+    }
+    s
+}
+
+/// Collect the local files referenced by a Manifest and any Manifests it references.
+fn collect(path: &Path, files: &mut Vec<(PathBuf, Entry)>) -> Result<()> {
+    let dir = path.parent().unwrap();
+    let manifest = Manifest::load(path)?;
+    for entry in manifest.entries {
+        let file = match entry.kind {
+            EntryKind::Dist => continue,
+            EntryKind::Aux => dir.join("files").join(&entry.path),
+            _ => dir.join(&entry.path),
+        };
+        if entry.kind == EntryKind::Manifest {
+            collect(&file, files)?;
+        }
+        files.push((file, entry));
+    }
+    Ok(())
+}
+
+/// Calculate a supported hash for a file.
+fn digest(hash: &str, path: &Path) -> Result<String> {
+    match hash {
+        "BLAKE2B" => files::digest::<Blake2b512>(path),
+        "SHA512" => files::digest::<Sha512>(path),
+        "SHA256" => files::digest::<Sha256>(path),
+        "SHA1" => files::digest::<Sha1>(path),
+        "MD5" => files::digest::<Md5>(path),
+        _ => Err(Error::InvalidValue(format!("unsupported hash: {hash}"))),
+    }
+}
+
+/// Verify the sizes and hashes of the files listed in a repo's Manifests, returning the number
+/// of verified files.
+///
+/// Repos with a top-level Manifest are verified recursively from it. Otherwise, each package
+/// Manifest is checked which for thin Manifests only covers their syntax since distfiles aren't
+/// stored in the repo. Files missing from Manifests aren't flagged.
+pub(crate) fn verify(repo: &ebuild::Repo) -> Result<usize> {
+    let mut files = vec![];
+    let path = repo.path().join("Manifest");
+    if path.exists() {
+        collect(&path, &mut files)?;
+    } else {
+        for cat in repo.categories() {
+            for pkg in repo.packages(&cat) {
+                let path = repo.path().join(&cat).join(&pkg).join("Manifest");
+                if path.exists() {
+                    collect(&path, &mut files)?;
+                }
+            }
+        }
+    }
+
+    let mut errors = vec![];
+    for (path, entry) in &files {
+        let name = path
+            .strip_prefix(repo.path())
+            .unwrap_or(path)
+            .to_string_lossy();
+        match fs::metadata(path) {
+            Ok(meta) if meta.len() == entry.size => {
+                match HASHES
+                    .iter()
+                    .find_map(|hash| entry.hash(hash).map(|val| (hash, val)))
+                {
+                    Some((hash, val)) => {
+                        if digest(hash, path)? != val {
+                            errors.push(format!("{name}: hash mismatch"));
+                        }
+                    }
+                    None => errors.push(format!("{name}: no supported hashes")),
+                }
+            }
+            Ok(meta) => errors.push(format!(
+                "{name}: size mismatch: expected {}, got {}",
+                entry.size,
+                meta.len()
+            )),
+            Err(_) => errors.push(format!("{name}: missing file")),
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(files.len()),
+        false => {
+            errors.sort();
+            Err(Error::InvalidValue(format!(
+                "invalid manifest entries:\n\t{}",
+                errors.join("\n\t")
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::macros::assert_err_re;
+    use crate::repo::ebuild::TempRepo;
+
+    use super::*;
+
+    #[test]
+    fn test_digest() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("empty");
+        fs::write(&path, "").unwrap();
+        for (hash, prefix) in [
+            ("BLAKE2B", "786a02f742015903"),
+            ("SHA512", "cf83e1357eefb8bd"),
+            ("SHA256", "e3b0c44298fc1c14"),
+            ("SHA1", "da39a3ee5e6b4b0d"),
+            ("MD5", "d41d8cd98f00b204"),
+        ] {
+            let val = digest(hash, &path).unwrap();
+            assert!(val.starts_with(prefix), "invalid {hash}: {val}");
+        }
+        assert_err_re!(digest("SHA3_512", &path), "^unsupported hash: SHA3_512$");
+        assert!(digest("MD5", &dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let data = indoc::indoc! {"
+            -----BEGIN PGP SIGNED MESSAGE-----
+            Hash: SHA256
+
+            TIMESTAMP 2024-01-01T00:00:00Z
+            MANIFEST cat/Manifest.gz 10 SHA512 abc BLAKE2B def
+            IGNORE distfiles
+            - DATA a\\x20b 1 MD5 123
+            -----BEGIN PGP SIGNATURE-----
+            sig
+            -----END PGP SIGNATURE-----
+        "};
+        let manifest = Manifest::from_str(data).unwrap();
+        let entries = manifest.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].kind(), EntryKind::Manifest);
+        assert_eq!(entries[0].size(), 10);
+        assert_eq!(entries[0].hash("BLAKE2B"), Some("def"));
+        assert_eq!(entries[1].path(), "a b");
+        assert_eq!(manifest.ignored(), ["distfiles"]);
+
+        // invalid data
+        for s in ["FOO a 1", "DIST a b", "DIST a 1 SHA512", "DATA"] {
+            assert!(Manifest::from_str(s).is_err(), "{s:?} didn't fail");
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let (_, ebuild) = t.create_ebuild("cat/pkg-1", None).unwrap();
+        let dir = ebuild.parent().unwrap();
+        let data = fs::read(&ebuild).unwrap();
+        let md5 = |data: &[u8]| {
+            use md5::{Digest, Md5};
+            format!("{:x}", Md5::digest(data))
+        };
+
+        // thin manifests only list distfiles
+        fs::write(dir.join("Manifest"), "DIST a.tar.gz 1 SHA512 abc\n").unwrap();
+        assert_eq!(verify(&t.repo).unwrap(), 0);
+
+        // full tree manifests
+        fs::remove_file(dir.join("Manifest")).unwrap();
+        fs::create_dir(dir.join("files")).unwrap();
+        fs::write(dir.join("files/a.patch"), "patch").unwrap();
+        let pkg_manifest = format!(
+            "EBUILD pkg-1.ebuild {} MD5 {}\nAUX a.patch 5 MD5 {}\n",
+            data.len(),
+            md5(&data),
+            md5(b"patch")
+        );
+        fs::write(dir.join("Manifest"), &pkg_manifest).unwrap();
+        let top = format!(
+            "MANIFEST cat/pkg/Manifest {} MD5 {}\n",
+            pkg_manifest.len(),
+            md5(pkg_manifest.as_bytes())
+        );
+        fs::write(t.repo.path().join("Manifest"), top).unwrap();
+        assert_eq!(verify(&t.repo).unwrap(), 3);
+
+        // modified and missing files
+        fs::write(dir.join("files/a.patch"), "PATCH").unwrap();
+        assert_err_re!(verify(&t.repo), "^.+:\n\tcat/pkg/files/a.patch: hash mismatch$");
+        fs::write(dir.join("files/a.patch"), "patch\n").unwrap();
+        assert_err_re!(verify(&t.repo), "cat/pkg/files/a.patch: size mismatch: expected 5, got 6$");
+        fs::remove_file(dir.join("files/a.patch")).unwrap();
+        assert_err_re!(verify(&t.repo), "cat/pkg/files/a.patch: missing file$");
+    }
+}
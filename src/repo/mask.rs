@@ -0,0 +1,438 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::{fmt, fs};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tracing::warn;
+
+use crate::atom::Atom;
+use crate::files::{is_file, is_hidden, sorted_dir_list};
+use crate::pkg;
+use crate::restrict::{Restrict, Restriction};
+use crate::{Error, Result};
+
+static AUTHOR_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?P<author>.+?)\s*<(?P<email>[^>]+)>\s*\((?P<date>\d{4}-\d{2}-\d{2})\)$").unwrap()
+});
+static REMOVAL_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)removal(?: on|:)?\s+(?P<date>\d{4}-\d{2}-\d{2})").unwrap());
+
+/// Calendar date as used in mask comments, e.g. 2026-11-01.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    year: u16,
+    month: u8,
+    day: u8,
+}
+
+impl Date {
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+}
+
+impl FromStr for Date {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let err = || Error::InvalidValue(format!("invalid date: {s:?}"));
+        let mut parts = s.splitn(3, '-');
+        let mut next = || parts.next().ok_or_else(err);
+        let (year, month, day) = (next()?, next()?, next()?);
+        let date = Date {
+            year: year.parse().map_err(|_| err())?,
+            month: month.parse().map_err(|_| err())?,
+            day: day.parse().map_err(|_| err())?,
+        };
+        let leap = date.year % 4 == 0 && (date.year % 100 != 0 || date.year % 400 == 0);
+        let days = match date.month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 if leap => 29,
+            2 => 28,
+            _ => return Err(err()),
+        };
+        match (1..=days).contains(&date.day) {
+            true => Ok(date),
+            false => Err(err()),
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// Group of atoms sharing a mask comment block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaskEntry {
+    atoms: Vec<Atom>,
+    comment: Vec<String>,
+    author: Option<String>,
+    email: Option<String>,
+    date: Option<Date>,
+    removal: Option<Date>,
+}
+
+impl MaskEntry {
+    /// Create an entry, parsing its comment metadata on a best-effort basis since invalid dates
+    /// shouldn't invalidate the masks themselves.
+    fn new(comment: Vec<String>, atoms: Vec<Atom>) -> Self {
+        let parse_date = |s: &str| match s.parse() {
+            Ok(date) => Some(date),
+            Err(e) => {
+                warn!("{}: {e}", atoms[0]);
+                None
+            }
+        };
+
+        let (mut author, mut email, mut date, mut removal) = (None, None, None, None);
+        if let Some(c) = comment.first().and_then(|s| AUTHOR_RE.captures(s)) {
+            author = Some(c["author"].to_string());
+            email = Some(c["email"].to_string());
+            date = parse_date(&c["date"]);
+        }
+        if let Some(c) = comment.iter().find_map(|s| REMOVAL_RE.captures(s)) {
+            removal = parse_date(&c["date"]);
+        }
+
+        MaskEntry {
+            atoms,
+            comment,
+            author,
+            email,
+            date,
+            removal,
+        }
+    }
+
+    pub fn atoms(&self) -> &[Atom] {
+        &self.atoms
+    }
+
+    /// Return the raw comment lines attached to the entry.
+    pub fn comment(&self) -> &[String] {
+        &self.comment
+    }
+
+    /// Return the comment lines following the author line.
+    pub fn reason(&self) -> &[String] {
+        match self.author {
+            Some(_) => &self.comment[1..],
+            None => &self.comment,
+        }
+    }
+
+    pub fn author(&self) -> Option<&str> {
+        self.author.as_deref()
+    }
+
+    pub fn email(&self) -> Option<&str> {
+        self.email.as_deref()
+    }
+
+    /// Return the date the mask was added.
+    pub fn date(&self) -> Option<Date> {
+        self.date
+    }
+
+    /// Return the planned removal date for last rited packages.
+    pub fn removal(&self) -> Option<Date> {
+        self.removal
+    }
+
+    /// Return a restriction matching any of the entry's atoms.
+    pub fn restrict(&self) -> Restrict {
+        Restrict::or(self.atoms.iter().map(Restrict::from))
+    }
+
+    /// Determine if the entry applies to a given package.
+    pub fn matches(&self, pkg: &pkg::Pkg) -> bool {
+        self.restrict().matches(pkg)
+    }
+}
+
+/// Parsed package.mask or package.unmask file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PackageMask {
+    entries: Vec<MaskEntry>,
+    negations: Vec<Atom>,
+}
+
+impl PackageMask {
+    /// Load a package.mask or package.unmask file, or all files in a directory of them.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.is_dir() {
+            return match fs::read_to_string(path) {
+                Ok(data) => {
+                    Self::from_str(&data).map_err(|e| Error::InvalidValue(format!("{path:?}: {e}")))
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+                Err(e) => Err(Error::IO(format!("failed reading {path:?}: {e}"))),
+            };
+        }
+
+        let mut mask = Self::default();
+        let files = sorted_dir_list(path)
+            .into_iter()
+            .filter_entry(|e| is_file(e) && !is_hidden(e));
+        for entry in files {
+            let entry = entry.map_err(|e| Error::IO(format!("failed reading {path:?}: {e}")))?;
+            let file = Self::load(entry.path())?;
+            mask.entries.extend(file.entries);
+            mask.negations.extend(file.negations);
+        }
+        Ok(mask)
+    }
+
+    /// Combine mask files from a profile stack ordered from parent to child, applying each
+    /// file's negations to the masks inherited from its parents.
+    pub fn stack<'a, I>(files: I) -> Self
+    where
+        I: IntoIterator<Item = &'a PackageMask>,
+    {
+        let mut stacked = Self::default();
+        for file in files {
+            for entry in &mut stacked.entries {
+                entry.atoms.retain(|a| !file.negations.contains(a));
+            }
+            stacked.entries.retain(|e| !e.atoms.is_empty());
+            stacked.entries.extend(file.entries.iter().cloned());
+        }
+        stacked
+    }
+
+    pub fn entries(&self) -> &[MaskEntry] {
+        &self.entries
+    }
+
+    /// Return the atoms negated via `-atom` lines.
+    pub fn negations(&self) -> &[Atom] {
+        &self.negations
+    }
+
+    /// Return entries with removal dates, ordered by date.
+    pub fn removals(&self) -> Vec<&MaskEntry> {
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|e| e.removal.is_some())
+            .collect();
+        entries.sort_by_key(|e| e.removal);
+        entries
+    }
+
+    /// Return a restriction matching all masked atoms.
+    pub fn restrict(&self) -> Restrict {
+        Restrict::or(self.entries.iter().map(|e| e.restrict()))
+    }
+
+    /// Return the entry applying to a given package, if any.
+    pub fn matches(&self, pkg: &pkg::Pkg) -> Option<&MaskEntry> {
+        self.entries.iter().rev().find(|e| e.matches(pkg))
+    }
+
+    /// Determine if a package is masked and not unmasked by a package.unmask file.
+    pub fn masked(&self, pkg: &pkg::Pkg, unmask: Option<&PackageMask>) -> bool {
+        self.matches(pkg).is_some() && unmask.and_then(|u| u.matches(pkg)).is_none()
+    }
+}
+
+impl FromStr for PackageMask {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut mask = Self::default();
+        let mut comment = vec![];
+        let mut atoms = vec![];
+
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                // a comment block ends when followed by a blank line or a new comment block
+                if !atoms.is_empty() {
+                    mask.entries.push(MaskEntry::new(comment, atoms));
+                    atoms = vec![];
+                    comment = vec![];
+                }
+                match line.strip_prefix('#') {
+                    Some(c) => comment.push(c.strip_prefix(' ').unwrap_or(c).to_string()),
+                    // unattached comments, e.g. file headers, are dropped
+                    None => comment.clear(),
+                }
+                continue;
+            }
+
+            let (negated, s) = match line.strip_prefix('-') {
+                Some(s) => (true, s),
+                None => (false, line),
+            };
+            let atom = Atom::from_str(s)
+                .map_err(|e| Error::InvalidValue(format!("line {}: {e}", i + 1)))?;
+            match negated {
+                true => mask.negations.push(atom),
+                false => atoms.push(atom),
+            }
+        }
+
+        if !atoms.is_empty() {
+            mask.entries.push(MaskEntry::new(comment, atoms));
+        }
+
+        Ok(mask)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::macros::assert_err_re;
+    use crate::pkg::Package;
+    use crate::repo::ebuild::TempRepo;
+
+    use super::*;
+
+    #[test]
+    fn test_date() {
+        let date = Date::from_str("2026-11-01").unwrap();
+        assert_eq!((date.year(), date.month(), date.day()), (2026, 11, 1));
+        assert_eq!(date.to_string(), "2026-11-01");
+        assert!(date < Date::from_str("2027-01-01").unwrap());
+        for s in ["2028-02-29", "2000-02-29", "2026-12-31", "2026-04-30"] {
+            assert!(Date::from_str(s).is_ok(), "{s:?} failed");
+        }
+        for s in [
+            "",
+            "2026",
+            "2026-11",
+            "2026-13-01",
+            "2026-11-00",
+            "2026-02-31",
+            "2026-02-29",
+            "1900-02-29",
+            "2026-04-31",
+            "a-b-c",
+        ] {
+            assert!(Date::from_str(s).is_err(), "{s:?} didn't fail");
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let data = indoc::indoc! {"
+            # Copyright header
+
+            # Jane Doe <jane@example.org> (2026-10-01)
+            # Unmaintained, multiple vulnerabilities.
+            # Removal on 2026-11-01.  Bug #123.
+            cat/a
+            >=cat/b-2:1
+
+            # Masked for testing
+            =cat/c-1
+            # no blank line separating blocks
+            cat/d
+
+            -cat/e
+        "};
+        let mask = PackageMask::from_str(data).unwrap();
+        assert_eq!(mask.entries().len(), 3);
+        assert_eq!(mask.negations(), [Atom::from_str("cat/e").unwrap()]);
+
+        let e = &mask.entries()[0];
+        assert_eq!(e.atoms().len(), 2);
+        assert_eq!(e.author(), Some("Jane Doe"));
+        assert_eq!(e.email(), Some("jane@example.org"));
+        assert_eq!(e.date(), Some(Date::from_str("2026-10-01").unwrap()));
+        assert_eq!(e.removal(), Some(Date::from_str("2026-11-01").unwrap()));
+        assert_eq!(e.reason().len(), 2);
+
+        let e = &mask.entries()[1];
+        assert_eq!(e.author(), None);
+        assert_eq!(e.removal(), None);
+        assert_eq!(e.reason(), ["Masked for testing"]);
+        assert_eq!(mask.entries()[2].comment(), ["no blank line separating blocks"]);
+        assert_eq!(mask.removals().len(), 1);
+
+        // invalid data
+        assert_err_re!(PackageMask::from_str("cat"), "^line 1: ");
+
+        // invalid comment dates are ignored
+        let data = "# a <b> (2026-02-31)\n# Removal on 2026-13-01.\ncat/pkg\n";
+        let mask = PackageMask::from_str(data).unwrap();
+        let e = &mask.entries()[0];
+        assert_eq!(e.atoms()[0].to_string(), "cat/pkg");
+        assert_eq!(e.author(), Some("a"));
+        assert_eq!(e.date(), None);
+        assert_eq!(e.removal(), None);
+    }
+
+    #[test]
+    fn test_stack() {
+        let parent = PackageMask::from_str("cat/a\ncat/b\n\ncat/c").unwrap();
+        let child = PackageMask::from_str("-cat/a\n-cat/c\n# new\ncat/d").unwrap();
+        let mask = PackageMask::stack([&parent, &child]);
+        let atoms: Vec<String> = mask
+            .entries()
+            .iter()
+            .flat_map(|e| e.atoms().iter().map(|a| a.to_string()))
+            .collect();
+        assert_eq!(atoms, ["cat/b", "cat/d"]);
+        assert!(mask.negations().is_empty());
+    }
+
+    #[test]
+    fn test_matches() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        for (cpv, slot) in [("cat/a-1", "0"), ("cat/b-1", "0"), ("cat/b-2", "1")] {
+            t.create_ebuild(cpv, None).unwrap();
+            t.create_metadata(cpv, &[("SLOT", slot)]).unwrap();
+        }
+        let path = t.repo.path().join("profiles/package.mask");
+        fs::write(&path, "# masked\ncat/a\n>=cat/b-1:1\n").unwrap();
+        let mask = PackageMask::load(&path).unwrap();
+        let unmask = PackageMask::from_str("cat/a").unwrap();
+
+        let masked: Vec<String> = t
+            .repo
+            .iter()
+            .map(pkg::Pkg::Ebuild)
+            .filter(|p| mask.matches(p).is_some())
+            .map(|p| p.atom().cpv())
+            .collect();
+        assert_eq!(masked, ["cat/a-1", "cat/b-2"]);
+
+        let masked: Vec<String> = t
+            .repo
+            .iter()
+            .map(pkg::Pkg::Ebuild)
+            .filter(|p| mask.masked(p, Some(&unmask)))
+            .map(|p| p.atom().cpv())
+            .collect();
+        assert_eq!(masked, ["cat/b-2"]);
+
+        // directories of mask files
+        fs::remove_file(&path).unwrap();
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("a"), "cat/a\n").unwrap();
+        fs::write(path.join("b"), "cat/b\n").unwrap();
+        assert_eq!(PackageMask::load(&path).unwrap().entries().len(), 2);
+
+        // nonexistent files
+        assert!(PackageMask::load(path.join("c"))
+            .unwrap()
+            .entries()
+            .is_empty());
+    }
+}
@@ -0,0 +1,419 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use indexmap::IndexSet;
+use tracing::warn;
+
+use crate::atom::{self, Atom};
+use crate::files::{is_dir, is_hidden, sorted_dir_list};
+use crate::pkg;
+use crate::repo::mask::Date;
+use crate::repo::profile::Profile;
+use crate::repo::{ebuild, vdb, Repository};
+use crate::restrict::{Restrict, Restriction};
+use crate::{Error, Result};
+
+/// News item as defined by GLEP 42.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct News {
+    name: String,
+    title: String,
+    authors: Vec<String>,
+    translators: Vec<String>,
+    posted: Date,
+    revision: u32,
+    format: String,
+    display_if_installed: Vec<Atom>,
+    display_if_keyword: Vec<String>,
+    display_if_profile: Vec<String>,
+    body: String,
+}
+
+impl News {
+    /// Load a news item from a given file, using its parent directory as the item name.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .map_err(|e| Error::IO(format!("failed reading {path:?}: {e}")))?;
+        let mut news: News = data
+            .parse()
+            .map_err(|e| Error::InvalidValue(format!("invalid news item: {path:?}: {e}")))?;
+        if let Some(name) = path.parent().and_then(|p| p.file_name()) {
+            news.name = name.to_string_lossy().to_string();
+        }
+        Ok(news)
+    }
+
+    /// Return the item name, e.g. 2024-01-01-some-change.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn authors(&self) -> &[String] {
+        &self.authors
+    }
+
+    pub fn translators(&self) -> &[String] {
+        &self.translators
+    }
+
+    pub fn posted(&self) -> Date {
+        self.posted
+    }
+
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+
+    /// Return the news item format version, e.g. 2.0.
+    pub fn format(&self) -> &str {
+        &self.format
+    }
+
+    pub fn display_if_installed(&self) -> &[Atom] {
+        &self.display_if_installed
+    }
+
+    pub fn display_if_keyword(&self) -> &[String] {
+        &self.display_if_keyword
+    }
+
+    pub fn display_if_profile(&self) -> &[String] {
+        &self.display_if_profile
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    /// Determine if the news item is relevant to a system with the given installed packages and
+    /// selected profile.
+    ///
+    /// An item is relevant when it has no Display-If-* headers or, for each header type it
+    /// uses, at least one of the listed values matches.
+    pub fn relevant(&self, installed: &vdb::Repo, profile: &Profile) -> bool {
+        let installed_match = || {
+            self.display_if_installed.iter().any(|a| {
+                let restrict = Restrict::from(a);
+                let (cat, name) = (a.category(), a.package());
+                installed
+                    .versions(cat, name)
+                    .iter()
+                    .filter_map(|v| atom::parse::cpv(&format!("{cat}/{name}-{v}")).ok())
+                    .any(|cpv| match pkg::vdb::Pkg::new(&cpv, installed) {
+                        Ok(p) => restrict.matches(&pkg::Pkg::Vdb(p)),
+                        Err(_) => false,
+                    })
+            })
+        };
+        let keyword_match = || match profile.arch() {
+            Some(arch) => self.display_if_keyword.iter().any(|k| k == arch),
+            None => false,
+        };
+        let profile_match = || match profile_name(profile.path()) {
+            Some(name) => self
+                .display_if_profile
+                .iter()
+                .any(|p| match p.strip_suffix("/*") {
+                    Some(prefix) => name == prefix || name.starts_with(&format!("{prefix}/")),
+                    None => &name == p,
+                }),
+            None => false,
+        };
+
+        (self.display_if_installed.is_empty() || installed_match())
+            && (self.display_if_keyword.is_empty() || keyword_match())
+            && (self.display_if_profile.is_empty() || profile_match())
+    }
+}
+
+/// Return a profile's path relative to the profiles directory it belongs to.
+fn profile_name(path: &Path) -> Option<String> {
+    path.ancestors()
+        .find(|p| p.file_name().map(|s| s == "profiles").unwrap_or_default())
+        .and_then(|root| path.strip_prefix(root).ok())
+        .map(|p| p.to_string_lossy().trim_end_matches('/').to_string())
+}
+
+impl FromStr for News {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (headers, body) = match s.split_once("\n\n") {
+            Some((h, b)) => (h, b),
+            None => (s, ""),
+        };
+
+        let (mut title, mut posted, mut revision, mut format) = (None, None, None, None);
+        let mut news = News {
+            name: Default::default(),
+            title: Default::default(),
+            authors: vec![],
+            translators: vec![],
+            posted: "1970-01-01".parse().unwrap(),
+            revision: 0,
+            format: Default::default(),
+            display_if_installed: vec![],
+            display_if_keyword: vec![],
+            display_if_profile: vec![],
+            body: body.trim_end().to_string(),
+        };
+
+        for (i, line) in headers.lines().enumerate() {
+            let err = |e: &str| Error::InvalidValue(format!("line {}: {e}", i + 1));
+            let (key, val) = line
+                .split_once(':')
+                .map(|(k, v)| (k.trim(), v.trim()))
+                .ok_or_else(|| err(&format!("invalid header: {line}")))?;
+            match key {
+                "Title" => title = Some(val.to_string()),
+                "Author" => news.authors.push(val.to_string()),
+                "Translator" => news.translators.push(val.to_string()),
+                "Posted" => posted = Some(val.parse().map_err(|e: Error| err(&e.to_string()))?),
+                "Revision" => {
+                    revision = Some(
+                        val.parse()
+                            .map_err(|_| err(&format!("invalid revision: {val}")))?,
+                    )
+                }
+                "News-Item-Format" => format = Some(val.to_string()),
+                "Content-Type" => (),
+                "Display-If-Installed" => {
+                    let atom = Atom::from_str(val).map_err(|e| err(&e.to_string()))?;
+                    news.display_if_installed.push(atom);
+                }
+                "Display-If-Keyword" => news.display_if_keyword.push(val.to_string()),
+                "Display-If-Profile" => news.display_if_profile.push(val.to_string()),
+                _ => warn!("unknown news header: {key}"),
+            }
+        }
+
+        let missing = |h: &str| Error::InvalidValue(format!("missing required header: {h}"));
+        news.title = title.ok_or_else(|| missing("Title"))?;
+        news.posted = posted.ok_or_else(|| missing("Posted"))?;
+        news.revision = revision.ok_or_else(|| missing("Revision"))?;
+        news.format = format.ok_or_else(|| missing("News-Item-Format"))?;
+        if news.authors.is_empty() {
+            return Err(missing("Author"));
+        }
+
+        Ok(news)
+    }
+}
+
+/// Load all English news items for a repo, sorted by name.
+pub(crate) fn load(repo: &ebuild::Repo) -> Result<Vec<News>> {
+    let dir = repo.path().join("metadata/news");
+    let mut items = vec![];
+    if !dir.exists() {
+        return Ok(items);
+    }
+
+    let filter = |e: &walkdir::DirEntry| -> bool { is_dir(e) && !is_hidden(e) };
+    for entry in sorted_dir_list(&dir).into_iter().filter_entry(filter) {
+        let entry = entry.map_err(|e| Error::IO(format!("failed reading {dir:?}: {e}")))?;
+        let name = entry.file_name().to_string_lossy();
+        let path = entry.path().join(format!("{name}.en.txt"));
+        if path.exists() {
+            match News::load(&path) {
+                Ok(n) => items.push(n),
+                Err(e) => warn!("{}: {e}", repo.id()),
+            }
+        }
+    }
+
+    Ok(items)
+}
+
+/// Read state for a repo's news items, stored under the config db directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewsState {
+    path: PathBuf,
+    read: IndexSet<String>,
+}
+
+impl NewsState {
+    /// Load the read state for a repo from a given db directory, e.g. `ConfigPath::db`.
+    pub fn load<P: AsRef<Path>>(db: P, repo: &str) -> Result<Self> {
+        let path = db.as_ref().join("news").join(format!("{repo}.read"));
+        let read = match fs::read_to_string(&path) {
+            Ok(s) => s
+                .lines()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => IndexSet::new(),
+            Err(e) => return Err(Error::IO(format!("failed reading {path:?}: {e}"))),
+        };
+        Ok(NewsState { path, read })
+    }
+
+    pub fn is_read(&self, name: &str) -> bool {
+        self.read.contains(name)
+    }
+
+    /// Mark a news item as read, returning true if it wasn't previously read.
+    pub fn mark_read(&mut self, name: &str) -> bool {
+        self.read.insert(name.to_string())
+    }
+
+    /// Mark a news item as unread, returning true if it was previously read.
+    pub fn mark_unread(&mut self, name: &str) -> bool {
+        self.read.shift_remove(name)
+    }
+
+    /// Write the read state to disk.
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| Error::IO(format!("failed creating {dir:?}: {e}")))?;
+        }
+        let mut data: String = self.read.iter().map(|s| format!("{s}\n")).collect();
+        if data.is_empty() {
+            data.push('\n');
+        }
+        fs::write(&self.path, data)
+            .map_err(|e| Error::IO(format!("failed writing {:?}: {e}", self.path)))
+    }
+
+    /// Return the unread news items from a given set that are relevant to a system.
+    pub fn unread<'a>(
+        &self,
+        items: &'a [News],
+        installed: &vdb::Repo,
+        profile: &Profile,
+    ) -> Vec<&'a News> {
+        items
+            .iter()
+            .filter(|n| !self.is_read(n.name()) && n.relevant(installed, profile))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::macros::assert_err_re;
+    use crate::repo::ebuild::TempRepo;
+
+    use super::*;
+
+    const ITEM: &str = indoc::indoc! {"
+        Title: Some change
+        Author: A Person <a@example.com>
+        Posted: 2024-01-01
+        Revision: 2
+        News-Item-Format: 2.0
+        Display-If-Installed: <cat/pkg-2
+        Display-If-Profile: default/linux/*
+
+        Body text.
+
+        More text.
+    "};
+
+    #[test]
+    fn test_parse() {
+        let news: News = ITEM.parse().unwrap();
+        assert_eq!(news.title(), "Some change");
+        assert_eq!(news.authors(), ["A Person <a@example.com>"]);
+        assert!(news.translators().is_empty());
+        assert_eq!(news.posted().to_string(), "2024-01-01");
+        assert_eq!(news.revision(), 2);
+        assert_eq!(news.format(), "2.0");
+        assert_eq!(news.display_if_installed()[0].to_string(), "<cat/pkg-2");
+        assert!(news.display_if_keyword().is_empty());
+        assert_eq!(news.display_if_profile(), ["default/linux/*"]);
+        assert_eq!(news.body(), "Body text.\n\nMore text.");
+
+        // invalid items
+        assert_err_re!("Title: a".parse::<News>(), "missing required header: Posted");
+        assert_err_re!("Title".parse::<News>(), "^line 1: invalid header");
+        let data = ITEM.replace("Revision: 2", "Revision: a");
+        assert_err_re!(data.parse::<News>(), "^line 4: invalid revision");
+        let data = ITEM.replace("<cat/pkg-2", "cat/pkg-2");
+        assert!(data.parse::<News>().is_err());
+    }
+
+    #[test]
+    fn test_relevant() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let profiles = t.repo.path().join("profiles");
+        let profile_dir = profiles.join("default/linux/amd64");
+        fs::create_dir_all(&profile_dir).unwrap();
+        fs::write(profile_dir.join("make.defaults"), "ARCH=\"amd64\"\n").unwrap();
+        let profile = t.repo.profile("default/linux/amd64").unwrap();
+
+        let vdb_dir = TempDir::new().unwrap();
+        fs::create_dir_all(vdb_dir.path().join("cat/pkg-1")).unwrap();
+        fs::write(vdb_dir.path().join("cat/pkg-1/SLOT"), "0\n").unwrap();
+        let installed = vdb::Repo::from_path("vdb", vdb_dir.path()).unwrap();
+
+        let news: News = ITEM.parse().unwrap();
+        assert!(news.relevant(&installed, &profile));
+
+        // installed package doesn't match
+        let data = ITEM.replace("<cat/pkg-2", "<cat/pkg-1");
+        assert!(!data.parse::<News>().unwrap().relevant(&installed, &profile));
+
+        // keywords and exact profiles
+        let data = ITEM.replace("default/linux/*", "default/linux/amd64");
+        assert!(data.parse::<News>().unwrap().relevant(&installed, &profile));
+        let data = ITEM.replace("default/linux/*", "default/linux");
+        assert!(!data.parse::<News>().unwrap().relevant(&installed, &profile));
+        let data = format!("Display-If-Keyword: arm64\n{ITEM}");
+        assert!(!data.parse::<News>().unwrap().relevant(&installed, &profile));
+        let data = format!("Display-If-Keyword: arm64\nDisplay-If-Keyword: amd64\n{ITEM}");
+        assert!(data.parse::<News>().unwrap().relevant(&installed, &profile));
+    }
+
+    #[test]
+    fn test_state() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let news_dir = t.repo.path().join("metadata/news");
+        let item = ITEM.replace("Display-If-Installed: <cat/pkg-2\n", "");
+        for name in ["2024-01-01-a", "2024-02-01-b"] {
+            fs::create_dir_all(news_dir.join(name)).unwrap();
+            fs::write(news_dir.join(format!("{name}/{name}.en.txt")), &item).unwrap();
+        }
+        // non-English and invalid items are ignored
+        fs::create_dir_all(news_dir.join("2024-03-01-c")).unwrap();
+        fs::write(news_dir.join("2024-03-01-c/2024-03-01-c.de.txt"), &item).unwrap();
+        fs::create_dir_all(news_dir.join("2024-04-01-d")).unwrap();
+        fs::write(news_dir.join("2024-04-01-d/2024-04-01-d.en.txt"), "Title: a\n").unwrap();
+
+        let items = t.repo.news().unwrap();
+        let names: Vec<_> = items.iter().map(|n| n.name()).collect();
+        assert_eq!(names, ["2024-01-01-a", "2024-02-01-b"]);
+
+        let profile_dir = t.repo.path().join("profiles/default/linux");
+        fs::create_dir_all(&profile_dir).unwrap();
+        let profile = t.repo.profile("default/linux").unwrap();
+        let vdb_dir = TempDir::new().unwrap();
+        let installed = vdb::Repo::from_path("vdb", vdb_dir.path()).unwrap();
+
+        let db = TempDir::new().unwrap();
+        let mut state = NewsState::load(db.path(), "test").unwrap();
+        assert_eq!(state.unread(&items, &installed, &profile).len(), 2);
+        assert!(state.mark_read("2024-01-01-a"));
+        assert!(!state.mark_read("2024-01-01-a"));
+        state.save().unwrap();
+
+        let mut state = NewsState::load(db.path(), "test").unwrap();
+        assert!(state.is_read("2024-01-01-a"));
+        let unread: Vec<_> = state
+            .unread(&items, &installed, &profile)
+            .iter()
+            .map(|n| n.name())
+            .collect();
+        assert_eq!(unread, ["2024-02-01-b"]);
+        assert!(state.mark_unread("2024-01-01-a"));
+        assert!(!state.is_read("2024-01-01-a"));
+    }
+}
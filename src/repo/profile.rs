@@ -0,0 +1,488 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use indexmap::{IndexMap, IndexSet};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+use crate::atom::Atom;
+use crate::repo::configured::{load_atom_entries, AtomEntries};
+use crate::{Error, Result};
+
+static ASSIGN_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?:export\s+)?(?P<var>[A-Za-z_][A-Za-z0-9_]*)=(?P<val>.*)$").unwrap()
+});
+static EXPAND_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\$(?:\{(?P<braced>[A-Za-z_][A-Za-z0-9_]*)\}|(?P<bare>[A-Za-z_][A-Za-z0-9_]*))")
+        .unwrap()
+});
+
+/// Variables that are combined across the profile stack instead of overridden.
+const INCREMENTALS: &[&str] = &[
+    "USE",
+    "USE_EXPAND",
+    "USE_EXPAND_HIDDEN",
+    "USE_EXPAND_IMPLICIT",
+    "USE_EXPAND_UNPREFIXED",
+    "IUSE_IMPLICIT",
+    "CONFIG_PROTECT",
+    "CONFIG_PROTECT_MASK",
+];
+
+/// Parse a make.defaults file into its ordered variable assignments, expanding references to
+/// previously defined variables.
+pub(crate) fn parse_make_defaults(
+    data: &str,
+    vars: &IndexMap<String, String>,
+) -> Result<Vec<(String, String)>> {
+    let mut assignments: Vec<(String, String)> = vec![];
+    let mut lines = data.lines().enumerate();
+
+    while let Some((i, line)) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let caps = ASSIGN_RE.captures(line).ok_or_else(|| {
+            Error::InvalidValue(format!("line {}: invalid assignment: {line}", i + 1))
+        })?;
+        let var = caps["var"].to_string();
+        let mut val = caps["val"].to_string();
+
+        // quoted values can span multiple lines
+        if let Some(quote) = val.chars().next().filter(|c| *c == '"' || *c == '\'') {
+            while val.len() < 2 || !val.ends_with(quote) {
+                match lines.next() {
+                    Some((_, l)) => {
+                        val.push(' ');
+                        val.push_str(l.trim());
+                    }
+                    None => {
+                        return Err(Error::InvalidValue(format!(
+                            "line {}: unterminated quote",
+                            i + 1
+                        )))
+                    }
+                }
+            }
+            val = val[1..val.len() - 1].to_string();
+            if quote == '\'' {
+                assignments.push((var, val));
+                continue;
+            }
+        }
+
+        let lookup = |name: &str| -> String {
+            assignments
+                .iter()
+                .rev()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str())
+                .or_else(|| vars.get(name).map(|s| s.as_str()))
+                .unwrap_or_default()
+                .to_string()
+        };
+        let val = EXPAND_RE
+            .replace_all(&val, |c: &Captures| {
+                let name = c
+                    .name("braced")
+                    .or_else(|| c.name("bare"))
+                    .unwrap()
+                    .as_str();
+                lookup(name)
+            })
+            .to_string();
+        assignments.push((var, val));
+    }
+
+    Ok(assignments)
+}
+
+/// Apply the incremental flag lines of a use.mask or use.force file to a set of flags, ignoring
+/// nonexistent files.
+fn load_flags(path: &Path, flags: &mut IndexSet<String>) -> Result<()> {
+    let data = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(Error::IO(format!("failed reading {path:?}: {e}"))),
+    };
+
+    for flag in data.lines().map(|s| s.trim()) {
+        if flag.is_empty() || flag.starts_with('#') {
+            continue;
+        }
+        match flag.strip_prefix('-') {
+            Some(f) => {
+                flags.shift_remove(f);
+            }
+            None => {
+                flags.insert(flag.to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Profile stack combining the settings of a profile with those of its parents.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Profile {
+    path: PathBuf,
+    stack: Vec<PathBuf>,
+    vars: IndexMap<String, String>,
+    use_mask: IndexSet<String>,
+    use_force: IndexSet<String>,
+    package_use: AtomEntries,
+    package_use_mask: AtomEntries,
+    package_use_force: AtomEntries,
+}
+
+impl Profile {
+    /// Load a profile along with all of its parents.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut stack = vec![];
+        Self::load_stack(path, &mut stack)?;
+
+        let mut profile = Profile {
+            path: PathBuf::from(path),
+            ..Default::default()
+        };
+
+        for dir in stack {
+            let file = dir.join("make.defaults");
+            if file.exists() {
+                let data = fs::read_to_string(&file)
+                    .map_err(|e| Error::IO(format!("failed reading {file:?}: {e}")))?;
+                let assignments = parse_make_defaults(&data, &profile.vars)
+                    .map_err(|e| Error::InvalidValue(format!("{file:?}: {e}")))?;
+                for (var, val) in assignments {
+                    profile.set(var, val);
+                }
+            }
+            load_flags(&dir.join("use.mask"), &mut profile.use_mask)?;
+            load_flags(&dir.join("use.force"), &mut profile.use_force)?;
+            profile
+                .package_use
+                .extend(load_atom_entries(&dir.join("package.use"))?);
+            profile
+                .package_use_mask
+                .extend(load_atom_entries(&dir.join("package.use.mask"))?);
+            profile
+                .package_use_force
+                .extend(load_atom_entries(&dir.join("package.use.force"))?);
+            profile.stack.push(dir);
+        }
+
+        Ok(profile)
+    }
+
+    /// Recursively collect a profile's parents in inheritance order, followed by the profile.
+    fn load_stack(path: &Path, stack: &mut Vec<PathBuf>) -> Result<()> {
+        let path = &path
+            .canonicalize()
+            .map_err(|e| Error::InvalidValue(format!("invalid profile: {path:?}: {e}")))?;
+        if !path.is_dir() {
+            return Err(Error::InvalidValue(format!("invalid profile: {path:?}")));
+        }
+
+        let parent = path.join("parent");
+        if parent.exists() {
+            let data = fs::read_to_string(&parent)
+                .map_err(|e| Error::IO(format!("failed reading {parent:?}: {e}")))?;
+            for line in data.lines().map(|s| s.trim()) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                if line.contains(':') {
+                    return Err(Error::InvalidValue(format!(
+                        "{parent:?}: unsupported repo-relative parent: {line}"
+                    )));
+                }
+                Self::load_stack(&path.join(line), stack)?;
+            }
+        }
+
+        stack.push(PathBuf::from(path));
+        Ok(())
+    }
+
+    /// Determine if a variable is combined incrementally across the profile stack.
+    fn is_incremental(&self, var: &str) -> bool {
+        INCREMENTALS.contains(&var)
+            || self.list("USE_EXPAND").contains(&var)
+            || self.list("USE_EXPAND_UNPREFIXED").contains(&var)
+    }
+
+    fn set(&mut self, var: String, val: String) {
+        if !self.is_incremental(&var) {
+            self.vars.insert(var, val);
+            return;
+        }
+
+        let mut values: IndexSet<String> = self.list(&var).iter().map(|s| s.to_string()).collect();
+        for v in val.split_whitespace() {
+            match v.strip_prefix('-') {
+                Some("*") => values.clear(),
+                Some(s) => {
+                    values.shift_remove(s);
+                }
+                None => {
+                    values.insert(v.to_string());
+                }
+            }
+        }
+        let val = values.into_iter().collect::<Vec<_>>().join(" ");
+        self.vars.insert(var, val);
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the profile directories in inheritance order.
+    pub fn stack(&self) -> &[PathBuf] {
+        &self.stack
+    }
+
+    /// Return the final value of a make.defaults variable.
+    pub fn get(&self, var: &str) -> Option<&str> {
+        self.vars.get(var).map(|s| s.as_str())
+    }
+
+    fn list(&self, var: &str) -> Vec<&str> {
+        match self.get(var) {
+            None => vec![],
+            Some(s) => s.split_whitespace().collect(),
+        }
+    }
+
+    pub fn arch(&self) -> Option<&str> {
+        self.get("ARCH")
+    }
+
+    pub fn use_expand(&self) -> Vec<&str> {
+        self.list("USE_EXPAND")
+    }
+
+    pub fn use_expand_hidden(&self) -> Vec<&str> {
+        self.list("USE_EXPAND_HIDDEN")
+    }
+
+    pub fn use_expand_implicit(&self) -> Vec<&str> {
+        self.list("USE_EXPAND_IMPLICIT")
+    }
+
+    pub fn use_expand_unprefixed(&self) -> Vec<&str> {
+        self.list("USE_EXPAND_UNPREFIXED")
+    }
+
+    pub fn iuse_implicit(&self) -> Vec<&str> {
+        self.list("IUSE_IMPLICIT")
+    }
+
+    /// Return the allowed values for a USE_EXPAND variable.
+    pub fn use_expand_values(&self, var: &str) -> Vec<&str> {
+        self.list(&format!("USE_EXPAND_VALUES_{var}"))
+    }
+
+    /// Return the enabled USE flags including those set via USE_EXPAND variables.
+    pub fn use_(&self) -> IndexSet<String> {
+        let mut flags: IndexSet<String> = self.list("USE").iter().map(|s| s.to_string()).collect();
+        for var in self.use_expand() {
+            let prefix = var.to_lowercase();
+            flags.extend(self.list(var).iter().map(|v| format!("{prefix}_{v}")));
+        }
+        for var in self.use_expand_unprefixed() {
+            flags.extend(self.list(var).iter().map(|v| v.to_string()));
+        }
+        flags
+    }
+
+    /// Return the globally masked USE flags.
+    pub fn use_mask(&self) -> &IndexSet<String> {
+        &self.use_mask
+    }
+
+    /// Return the globally forced USE flags.
+    pub fn use_force(&self) -> &IndexSet<String> {
+        &self.use_force
+    }
+
+    /// Return the package.use entries across the profile stack in inheritance order.
+    pub fn package_use(&self) -> &[(Atom, Vec<String>)] {
+        &self.package_use
+    }
+
+    /// Return the package.use.mask entries across the profile stack in inheritance order.
+    pub fn package_use_mask(&self) -> &[(Atom, Vec<String>)] {
+        &self.package_use_mask
+    }
+
+    /// Return the package.use.force entries across the profile stack in inheritance order.
+    pub fn package_use_force(&self) -> &[(Atom, Vec<String>)] {
+        &self.package_use_force
+    }
+
+    /// Return the effective IUSE for a package with the given IUSE, as defined by PMS.
+    pub fn iuse_effective<I, S>(&self, iuse: I) -> IndexSet<String>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut flags: IndexSet<String> = iuse
+            .into_iter()
+            .map(|s| s.as_ref().trim_start_matches(&['+', '-'][..]).to_string())
+            .collect();
+        flags.extend(self.iuse_implicit().iter().map(|s| s.to_string()));
+
+        let (expand, unprefixed) = (self.use_expand(), self.use_expand_unprefixed());
+        for var in self.use_expand_implicit() {
+            let values = self.use_expand_values(var);
+            if expand.contains(&var) {
+                let prefix = var.to_lowercase();
+                flags.extend(values.iter().map(|v| format!("{prefix}_{v}")));
+            }
+            if unprefixed.contains(&var) {
+                flags.extend(values.iter().map(|v| v.to_string()));
+            }
+        }
+
+        flags
+    }
+
+    /// Split a USE flag into its USE_EXPAND variable and value if it belongs to one, e.g.
+    /// python_targets_python3_12 maps to PYTHON_TARGETS and python3_12.
+    pub fn use_expand_group<'a>(&self, flag: &'a str) -> Option<(&str, &'a str)> {
+        self.use_expand()
+            .into_iter()
+            .filter_map(|var| {
+                let prefix = format!("{}_", var.to_lowercase());
+                flag.strip_prefix(&prefix)
+                    .filter(|v| !v.is_empty())
+                    .map(|v| (var, v))
+            })
+            .max_by_key(|(var, _)| var.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::macros::assert_err_re;
+
+    use super::*;
+
+    #[test]
+    fn test_make_defaults() {
+        let vars = IndexMap::from([("A".to_string(), "a".to_string())]);
+        let data = indoc::indoc! {r#"
+            # comment
+            B="${A} b"
+            C=$B
+            D='$A'
+            E="multi
+                line"
+            export F=f
+        "#};
+        let assignments = parse_make_defaults(data, &vars).unwrap();
+        let expected: Vec<(String, String)> =
+            [("B", "a b"), ("C", "a b"), ("D", "$A"), ("E", "multi line"), ("F", "f")]
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+        assert_eq!(assignments, expected);
+
+        // invalid data
+        assert_err_re!(parse_make_defaults("A", &vars), "^line 1: invalid assignment");
+        assert_err_re!(parse_make_defaults("A=\"a", &vars), "^line 1: unterminated quote");
+    }
+
+    #[test]
+    fn test_profile() {
+        let dir = TempDir::new().unwrap();
+        let dir_path = dir.path().canonicalize().unwrap();
+        let base = dir_path.join("base");
+        let arch = dir_path.join("arch/amd64");
+        fs::create_dir_all(&base).unwrap();
+        fs::create_dir_all(&arch).unwrap();
+        fs::write(
+            base.join("make.defaults"),
+            indoc::indoc! {r#"
+                USE="a b"
+                USE_EXPAND="PYTHON_TARGETS PYTHON_SINGLE_TARGET ELIBC"
+                USE_EXPAND_HIDDEN="ELIBC"
+                USE_EXPAND_IMPLICIT="ARCH ELIBC"
+                USE_EXPAND_UNPREFIXED="ARCH"
+                USE_EXPAND_VALUES_ARCH="amd64 arm64"
+                USE_EXPAND_VALUES_ELIBC="glibc musl"
+                IUSE_IMPLICIT="prefix test"
+                PYTHON_TARGETS="python3_11 python3_12"
+                CHOST="base"
+            "#},
+        )
+        .unwrap();
+        fs::write(arch.join("parent"), "# comment\n../../base\n").unwrap();
+        fs::write(
+            arch.join("make.defaults"),
+            indoc::indoc! {r#"
+                ARCH="amd64"
+                USE="-a c ${ARCH}"
+                PYTHON_TARGETS="-python3_11"
+                ELIBC="glibc"
+                CHOST="x86_64-pc-linux-gnu"
+            "#},
+        )
+        .unwrap();
+
+        let profile = Profile::load(&arch).unwrap();
+        assert_eq!(profile.stack(), [base.clone(), arch.clone()]);
+        assert_eq!(profile.arch(), Some("amd64"));
+        assert_eq!(profile.get("CHOST"), Some("x86_64-pc-linux-gnu"));
+        assert_eq!(profile.use_expand_hidden(), ["ELIBC"]);
+        let flags: Vec<_> = profile.use_().into_iter().collect();
+        assert_eq!(flags, ["b", "c", "amd64", "python_targets_python3_12", "elibc_glibc"]);
+
+        let iuse = profile.iuse_effective(["+x", "-y", "z"]);
+        let iuse: Vec<_> = iuse.iter().map(|s| s.as_str()).collect();
+        assert_eq!(
+            iuse,
+            ["x", "y", "z", "prefix", "test", "amd64", "arm64", "elibc_glibc", "elibc_musl"]
+        );
+
+        assert_eq!(
+            profile.use_expand_group("python_single_target_python3_12"),
+            Some(("PYTHON_SINGLE_TARGET", "python3_12"))
+        );
+        assert_eq!(
+            profile.use_expand_group("python_targets_python3_12"),
+            Some(("PYTHON_TARGETS", "python3_12"))
+        );
+        assert_eq!(profile.use_expand_group("python_targets_"), None);
+        assert_eq!(profile.use_expand_group("ssl"), None);
+
+        // USE masks and forces stack incrementally while package entries are concatenated
+        fs::write(base.join("use.mask"), "# comment\nx\ny\n").unwrap();
+        fs::write(arch.join("use.mask"), "-x\nz\n").unwrap();
+        fs::write(base.join("use.force"), "f\n").unwrap();
+        fs::write(base.join("package.use"), "cat/a a\n").unwrap();
+        fs::write(arch.join("package.use"), "cat/b b\n").unwrap();
+        fs::write(arch.join("package.use.mask"), "cat/a -y\n").unwrap();
+        fs::write(base.join("package.use.force"), "cat/a g\n").unwrap();
+        let profile = Profile::load(&arch).unwrap();
+        assert_eq!(profile.use_mask().iter().collect::<Vec<_>>(), ["y", "z"]);
+        assert_eq!(profile.use_force().iter().collect::<Vec<_>>(), ["f"]);
+        let atoms = |entries: &[(Atom, Vec<String>)]| -> Vec<String> {
+            entries.iter().map(|(a, _)| a.to_string()).collect()
+        };
+        assert_eq!(atoms(profile.package_use()), ["cat/a", "cat/b"]);
+        assert_eq!(profile.package_use_mask()[0].1, ["-y"]);
+        assert_eq!(profile.package_use_force()[0].1, ["g"]);
+
+        // invalid profiles
+        assert!(Profile::load(dir.path().join("nonexistent")).is_err());
+        fs::write(arch.join("parent"), "gentoo:base\n").unwrap();
+        assert_err_re!(Profile::load(&arch), "unsupported repo-relative parent");
+    }
+}
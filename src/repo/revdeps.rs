@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::atom::Atom;
+use crate::config::Config;
+use crate::pkg::ebuild::DepClass;
+use crate::pkg::Package;
+use crate::repo::{ebuild, Repo, Repository};
+use crate::restrict::{Restrict, Restriction};
+use crate::{Error, Result};
+
+/// File name of the persisted index inside the cache directory.
+const CACHE_FILE: &str = "revdeps.toml";
+
+/// A dependency of a package on another package.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct RevDep {
+    repo: String,
+    cpv: String,
+    class: DepClass,
+    atom: String,
+    conditionals: Vec<String>,
+}
+
+impl RevDep {
+    /// Return the id of the repo the dependent package belongs to.
+    pub fn repo(&self) -> &str {
+        &self.repo
+    }
+
+    /// Return the CPV of the dependent package.
+    pub fn cpv(&self) -> &str {
+        &self.cpv
+    }
+
+    /// Return the dependency class the atom was declared in.
+    pub fn class(&self) -> DepClass {
+        self.class
+    }
+
+    /// Return the raw dependency atom.
+    pub fn atom(&self) -> &str {
+        &self.atom
+    }
+
+    /// Return the USE conditionals the dependency is nested under.
+    pub fn conditionals(&self) -> &[String] {
+        &self.conditionals
+    }
+
+    fn parsed_atom(&self) -> Option<Atom> {
+        match Atom::from_str(&self.atom) {
+            Ok(a) => Some(a),
+            Err(e) => {
+                warn!("{}::{}: {e}", self.cpv, self.repo);
+                None
+            }
+        }
+    }
+}
+
+/// Dependencies of a package along with the metadata cache mtime they were parsed from.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+struct PkgEntry {
+    mtime: u64,
+    deps: Vec<RevDep>,
+}
+
+type RepoEntries = IndexMap<String, PkgEntry>;
+
+/// Reverse dependency index mapping package keys to their dependents.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct RevDepIndex {
+    repos: IndexMap<String, RepoEntries>,
+    #[serde(skip)]
+    rdeps: HashMap<String, Vec<RevDep>>,
+}
+
+impl RevDepIndex {
+    /// Return the default location of the persisted index for a given config.
+    pub fn cache_path(config: &Config) -> PathBuf {
+        config.path.cache.join(CACHE_FILE)
+    }
+
+    /// Load a persisted index, returning an empty index if none exists.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(Error::IO(format!("failed reading revdeps index {path:?}: {e}"))),
+        };
+        let mut index: Self = toml::from_str(&data)
+            .map_err(|e| Error::InvalidValue(format!("invalid revdeps index {path:?}: {e}")))?;
+        index.reindex();
+        Ok(index)
+    }
+
+    /// Persist the index to a given path.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let data = toml::to_string(self)
+            .map_err(|e| Error::IO(format!("failed serializing revdeps index: {e}")))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| Error::IO(format!("failed creating cache dir {dir:?}: {e}")))?;
+        }
+        fs::write(path, data)
+            .map_err(|e| Error::IO(format!("failed writing revdeps index {path:?}: {e}")))
+    }
+
+    /// Load the cached index for all configured ebuild repos, updating and persisting it if
+    /// any repo changed.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let path = Self::cache_path(config);
+        let mut index = Self::load(&path)?;
+        let mut changed = false;
+
+        // drop repos that are no longer configured
+        let repos = &config.repos.repos;
+        let len = index.repos.len();
+        index.repos.retain(|id, _| repos.contains_key(id));
+        changed |= index.repos.len() != len;
+
+        for repo in repos.values() {
+            if let Repo::Ebuild(r) = repo.as_ref() {
+                changed |= index.update_repo(r);
+            }
+        }
+
+        if changed {
+            index.reindex();
+            index.save(&path)?;
+        }
+        Ok(index)
+    }
+
+    /// Incrementally update the index for a given repo, only reparsing packages with changed
+    /// metadata.
+    pub fn update(&mut self, repo: &ebuild::Repo) {
+        if self.update_repo(repo) {
+            self.reindex();
+        }
+    }
+
+    fn update_repo(&mut self, repo: &ebuild::Repo) -> bool {
+        let id = repo.id();
+        let old = self.repos.remove(id).unwrap_or_default();
+        let mut entries = RepoEntries::new();
+        let mut changed = false;
+
+        for pkg in repo {
+            let cpv = pkg.atom().cpv();
+            let path = pkg.cache_path();
+            let mtime = match fs::metadata(&path).and_then(|m| m.modified()) {
+                Ok(t) => t
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_nanos() as u64)
+                    .unwrap_or_default(),
+                Err(e) => {
+                    warn!("{id}: {cpv}: missing metadata: {e}");
+                    continue;
+                }
+            };
+
+            if let Some(entry) = old.get(&cpv).filter(|e| e.mtime == mtime) {
+                entries.insert(cpv, entry.clone());
+                continue;
+            }
+
+            let meta = match pkg.metadata() {
+                Ok(m) => m,
+                Err(e) => {
+                    warn!("{id}: {cpv}: {e}");
+                    continue;
+                }
+            };
+
+            let mut deps = vec![];
+            for class in DepClass::ALL {
+                match meta.deps(class) {
+                    Ok(Some(depspec)) => {
+                        for (conditionals, atom) in depspec.flatten_atoms() {
+                            deps.push(RevDep {
+                                repo: id.to_string(),
+                                cpv: cpv.clone(),
+                                class,
+                                atom: atom.to_string(),
+                                conditionals,
+                            });
+                        }
+                    }
+                    Ok(None) => (),
+                    Err(e) => warn!("{id}: {cpv}: {e}"),
+                }
+            }
+
+            changed = true;
+            entries.insert(cpv, PkgEntry { mtime, deps });
+        }
+
+        changed |= entries.len() != old.len();
+        self.repos.insert(id.to_string(), entries);
+        changed
+    }
+
+    /// Regenerate the reverse mapping from the stored forward dependencies.
+    fn reindex(&mut self) {
+        let mut rdeps = HashMap::<String, Vec<RevDep>>::new();
+        for entries in self.repos.values() {
+            for entry in entries.values() {
+                for dep in &entry.deps {
+                    if let Some(atom) = dep.parsed_atom() {
+                        rdeps.entry(atom.key()).or_default().push(dep.clone());
+                    }
+                }
+            }
+        }
+        self.rdeps = rdeps;
+    }
+
+    /// Return all dependencies on packages matching a given atom.
+    ///
+    /// For versioned atoms, only dependencies that could be satisfied by the given version are
+    /// returned.
+    pub fn dependents(&self, atom: &Atom) -> Vec<&RevDep> {
+        let deps = match self.rdeps.get(&atom.key()) {
+            Some(deps) => deps,
+            None => return vec![],
+        };
+
+        deps.iter()
+            .filter(|d| match (atom.version(), d.parsed_atom()) {
+                (Some(ver), Some(dep)) => dep.version().map_or(true, |v| v.op_cmp(ver)),
+                (None, Some(_)) => true,
+                _ => false,
+            })
+            .collect()
+    }
+
+    /// Return all dependencies with atoms matching a given restriction.
+    pub fn matching(&self, restrict: &Restrict) -> Vec<&RevDep> {
+        self.rdeps
+            .values()
+            .flatten()
+            .filter(|d| d.parsed_atom().map_or(false, |a| restrict.matches(&a)))
+            .collect()
+    }
+
+    /// Return the number of indexed dependencies.
+    pub fn len(&self) -> usize {
+        self.rdeps.values().map(|v| v.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rdeps.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::repo::ebuild::TempRepo;
+
+    use super::*;
+
+    fn sorted(deps: Vec<&RevDep>) -> Vec<String> {
+        let mut deps: Vec<String> = deps
+            .iter()
+            .map(|d| format!("{}:{}:{}", d.cpv(), d.class(), d.atom()))
+            .collect();
+        deps.sort();
+        deps
+    }
+
+    #[test]
+    fn test_index() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        t.create_ebuild("cat/a-1", None).unwrap();
+        t.create_metadata("cat/a-1", &[("EAPI", "8"), ("RDEPEND", "cat/b u? ( >=cat/c-2 )")])
+            .unwrap();
+        t.create_ebuild("cat/b-1", None).unwrap();
+        t.create_metadata("cat/b-1", &[("EAPI", "8"), ("BDEPEND", "<cat/c-2")])
+            .unwrap();
+        t.create_ebuild("cat/c-1", None).unwrap();
+        t.create_metadata("cat/c-1", &[("EAPI", "8")]).unwrap();
+        // packages lacking metadata are skipped
+        t.create_ebuild("cat/d-1", None).unwrap();
+
+        let mut index = RevDepIndex::default();
+        index.update(&t.repo);
+        assert_eq!(index.len(), 3);
+
+        let atom = Atom::from_str("cat/b").unwrap();
+        assert_eq!(sorted(index.dependents(&atom)), ["cat/a-1:RDEPEND:cat/b"]);
+        let atom = Atom::from_str("cat/c").unwrap();
+        assert_eq!(
+            sorted(index.dependents(&atom)),
+            ["cat/a-1:RDEPEND:>=cat/c-2", "cat/b-1:BDEPEND:<cat/c-2"]
+        );
+        let dep = index.dependents(&atom)[0];
+        assert_eq!(dep.repo(), "test");
+        assert_eq!(dep.conditionals(), ["u?"]);
+
+        // versioned queries filter out unsatisfiable dependencies
+        let atom = Atom::from_str("=cat/c-3").unwrap();
+        assert_eq!(sorted(index.dependents(&atom)), ["cat/a-1:RDEPEND:>=cat/c-2"]);
+        let atom = Atom::from_str("=cat/c-1").unwrap();
+        assert_eq!(sorted(index.dependents(&atom)), ["cat/b-1:BDEPEND:<cat/c-2"]);
+        let atom = Atom::from_str("cat/a").unwrap();
+        assert!(index.dependents(&atom).is_empty());
+
+        // restriction queries
+        let r = Restrict::package("c");
+        assert_eq!(index.matching(&r).len(), 2);
+        let r = Restrict::category("cat");
+        assert_eq!(index.matching(&r).len(), 3);
+    }
+
+    #[test]
+    fn test_persistence() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        t.create_ebuild("cat/a-1", None).unwrap();
+        t.create_metadata("cat/a-1", &[("EAPI", "8"), ("DEPEND", "!u? ( cat/b:= )")])
+            .unwrap();
+        t.create_ebuild("cat/b-1", None).unwrap();
+        t.create_metadata("cat/b-1", &[("EAPI", "8")]).unwrap();
+
+        let mut index = RevDepIndex::default();
+        index.update(&t.repo);
+        let path = t.repo.path().join("revdeps.toml");
+        index.save(&path).unwrap();
+
+        let loaded = RevDepIndex::load(&path).unwrap();
+        let atom = Atom::from_str("cat/b").unwrap();
+        let deps = loaded.dependents(&atom);
+        assert_eq!(sorted(deps.clone()), ["cat/a-1:DEPEND:cat/b:="]);
+        assert_eq!(deps[0].conditionals(), ["!u?"]);
+
+        // nonexistent index files load as empty
+        let index = RevDepIndex::load(t.repo.path().join("nonexistent")).unwrap();
+        assert!(index.is_empty());
+
+        // unchanged packages are reused while modified ones are reparsed
+        let mut index = loaded;
+        thread::sleep(Duration::from_millis(10));
+        t.create_metadata("cat/a-1", &[("EAPI", "8"), ("RDEPEND", "cat/c")])
+            .unwrap();
+        index.update(&t.repo);
+        assert!(index.dependents(&atom).is_empty());
+        let atom = Atom::from_str("cat/c").unwrap();
+        assert_eq!(sorted(index.dependents(&atom)), ["cat/a-1:RDEPEND:cat/c"]);
+    }
+}
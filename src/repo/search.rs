@@ -0,0 +1,518 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use indexmap::IndexMap;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::Config;
+use crate::pkg::{OwnedPkg, Package};
+use crate::repo::{ebuild, Repo, Repository};
+use crate::{atom, Error, Result};
+
+/// File name of the persisted index inside the cache directory.
+const CACHE_FILE: &str = "search.toml";
+
+/// Searchable data for a package in a repo, taken from its latest version.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SearchEntry {
+    repo: String,
+    key: String,
+    versions: Vec<String>,
+    description: String,
+    homepage: Vec<String>,
+    long_description: Option<String>,
+    maintainers: Vec<String>,
+}
+
+impl SearchEntry {
+    /// Return the id of the repo the package belongs to.
+    pub fn repo(&self) -> &str {
+        &self.repo
+    }
+
+    /// Return the package key, e.g. `cat/pkg`.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Return the package name without its category.
+    pub fn name(&self) -> &str {
+        self.key.split_once('/').map_or(&self.key, |(_, pkg)| pkg)
+    }
+
+    /// Return the package's available versions in ascending order.
+    pub fn versions(&self) -> &[String] {
+        &self.versions
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn homepage(&self) -> &[String] {
+        &self.homepage
+    }
+
+    pub fn long_description(&self) -> Option<&str> {
+        self.long_description.as_deref()
+    }
+
+    pub fn maintainers(&self) -> &[String] {
+        &self.maintainers
+    }
+
+    /// Return the score for the entry's best matching field, if any match.
+    fn score(&self, query: &Query) -> Option<u32> {
+        // package names take precedence over descriptive fields
+        let names = [self.name(), self.key()]
+            .into_iter()
+            .filter_map(|s| query.score(s, true))
+            .map(|s| s * 4);
+        let desc = query.score(&self.description, false).map(|s| s * 3);
+        let long_desc = self
+            .long_description()
+            .and_then(|s| query.score(s, false))
+            .map(|s| s * 2);
+        let other = self
+            .homepage
+            .iter()
+            .chain(&self.maintainers)
+            .filter_map(|s| query.score(s, false));
+
+        names.chain(desc).chain(long_desc).chain(other).max()
+    }
+}
+
+/// Search query types.
+#[derive(Debug, Clone)]
+pub enum Query {
+    /// Case-insensitive substring match.
+    Substring(String),
+    /// Regular expression match.
+    Regex(Regex),
+    /// Case-insensitive match allowing package names to skip characters, e.g. `pkgcft` matches
+    /// `pkgcraft`.
+    Fuzzy(String),
+}
+
+impl Query {
+    pub fn substring(s: &str) -> Self {
+        Query::Substring(s.to_lowercase())
+    }
+
+    pub fn regex(s: &str) -> Result<Self> {
+        Regex::new(s)
+            .map(Query::Regex)
+            .map_err(|e| Error::InvalidValue(format!("invalid regex: {s:?}: {e}")))
+    }
+
+    pub fn fuzzy(s: &str) -> Self {
+        Query::Fuzzy(s.to_lowercase())
+    }
+
+    /// Score a field value from 1 to 100 where exact matches rank highest, followed by
+    /// prefix, substring, and fuzzy matches.
+    fn score(&self, value: &str, name: bool) -> Option<u32> {
+        let substring = |s: &str| -> Option<u32> {
+            let value = value.to_lowercase();
+            if value == s {
+                Some(100)
+            } else if value.starts_with(s) {
+                Some(75)
+            } else if value.contains(s) {
+                Some(50)
+            } else {
+                None
+            }
+        };
+
+        match self {
+            Query::Substring(s) => substring(s),
+            Query::Regex(re) => re.find(value).map(|m| match (m.start(), m.end()) {
+                (0, end) if end == value.len() => 100,
+                (0, _) => 75,
+                _ => 50,
+            }),
+            // fuzzy matching is only applied to names since it matches most long text
+            Query::Fuzzy(s) => substring(s).or_else(|| match name {
+                true => fuzzy(s, &value.to_lowercase()),
+                false => None,
+            }),
+        }
+    }
+}
+
+/// Score a subsequence match by how tightly the pattern's characters are grouped.
+fn fuzzy(pattern: &str, value: &str) -> Option<u32> {
+    let mut chars = pattern.chars().peekable();
+    let (mut start, mut end) = (None, 0);
+    for (i, c) in value.chars().enumerate() {
+        if chars.peek() == Some(&c) {
+            chars.next();
+            start.get_or_insert(i);
+            end = i + 1;
+        }
+    }
+
+    match (chars.peek(), start) {
+        (None, Some(start)) => {
+            let len = pattern.chars().count() as u32;
+            let span = (end - start) as u32;
+            Some((25 * len / span).max(1))
+        }
+        _ => None,
+    }
+}
+
+/// Search result referencing its index entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match<'a> {
+    entry: &'a SearchEntry,
+    score: u32,
+}
+
+impl<'a> Match<'a> {
+    pub fn entry(&self) -> &'a SearchEntry {
+        self.entry
+    }
+
+    /// Return the match score, higher scores denote better matches.
+    pub fn score(&self) -> u32 {
+        self.score
+    }
+
+    /// Return package handles for all versions of the matched package from the configured
+    /// repos.
+    pub fn pkgs(&self, config: &Config) -> Result<Vec<OwnedPkg>> {
+        let id = self.entry.repo();
+        let repo = config
+            .repos
+            .repos
+            .get(id)
+            .ok_or_else(|| Error::Config(format!("nonexistent repo: {id:?}")))?;
+        self.entry
+            .versions
+            .iter()
+            .map(|v| {
+                let cpv = atom::parse::cpv(&format!("{}-{v}", self.entry.key))?;
+                Ok(OwnedPkg::new(cpv, repo.clone()))
+            })
+            .collect()
+    }
+}
+
+/// Full-text package search index covering names, descriptions, homepages, and metadata.xml
+/// data across repos.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SearchIndex {
+    repos: IndexMap<String, Vec<SearchEntry>>,
+}
+
+impl SearchIndex {
+    /// Return the default location of the persisted index for a given config.
+    pub fn cache_path(config: &Config) -> PathBuf {
+        config.path.cache.join(CACHE_FILE)
+    }
+
+    /// Load a persisted index, returning an empty index if none exists.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(Error::IO(format!("failed reading search index {path:?}: {e}"))),
+        };
+        toml::from_str(&data)
+            .map_err(|e| Error::InvalidValue(format!("invalid search index {path:?}: {e}")))
+    }
+
+    /// Atomically persist the index to a given path.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let data = toml::to_string(self)
+            .map_err(|e| Error::IO(format!("failed serializing search index: {e}")))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| Error::IO(format!("failed creating cache dir {dir:?}: {e}")))?;
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| Error::IO(format!("failed writing search index {path:?}: {e}")))
+    }
+
+    /// Load the cached index for all configured ebuild repos, indexing repos that are missing
+    /// from it and dropping ones that are no longer configured.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let path = Self::cache_path(config);
+        let mut index = Self::load(&path)?;
+        let repos = &config.repos.repos;
+        let len = index.repos.len();
+        index.repos.retain(|id, _| repos.contains_key(id));
+        let mut changed = index.repos.len() != len;
+
+        for repo in repos.values() {
+            if let Repo::Ebuild(r) = repo.as_ref() {
+                if !index.repos.contains_key(r.id()) {
+                    index.update(r);
+                    changed = true;
+                }
+            }
+        }
+
+        if changed {
+            index.save(&path)?;
+        }
+        Ok(index)
+    }
+
+    /// Reindex the given configured repos and persist the index.
+    pub fn update_config<S: AsRef<str>>(config: &Config, repos: &[S]) -> Result<Self> {
+        let path = Self::cache_path(config);
+        let mut index = Self::from_config(config)?;
+        for id in repos {
+            if let Some(Repo::Ebuild(r)) = config.repos.repos.get(id.as_ref()).map(|r| r.as_ref()) {
+                index.update(r);
+            }
+        }
+        index.save(&path)?;
+        Ok(index)
+    }
+
+    /// Rebuild the entries for a given repo.
+    pub fn update(&mut self, repo: &ebuild::Repo) {
+        let id = repo.id();
+        let mut entries = vec![];
+        let mut pkgs = repo.iter().peekable();
+        while let Some(pkg) = pkgs.next() {
+            let key = pkg.atom().key();
+            let mut versions = vec![pkg.atom().version().unwrap().to_string()];
+            let mut latest = pkg;
+            while let Some(pkg) = pkgs.next_if(|p| p.atom().key() == key) {
+                versions.push(pkg.atom().version().unwrap().to_string());
+                latest = pkg;
+            }
+
+            let (description, homepage) = match latest.metadata() {
+                Ok(m) => (
+                    m.description().to_string(),
+                    m.homepage().iter().map(|s| s.to_string()).collect(),
+                ),
+                Err(e) => {
+                    warn!("{id}: {}: {e}", latest.atom());
+                    (String::new(), vec![])
+                }
+            };
+            let (long_description, maintainers) = match latest.xml() {
+                Ok(xml) => (
+                    xml.long_description().map(|s| s.to_string()),
+                    xml.maintainers().iter().map(|m| m.to_string()).collect(),
+                ),
+                Err(e) => {
+                    warn!("{id}: {key}: {e}");
+                    (None, vec![])
+                }
+            };
+
+            entries.push(SearchEntry {
+                repo: id.to_string(),
+                key,
+                versions,
+                description,
+                homepage,
+                long_description,
+                maintainers,
+            });
+        }
+        self.repos.insert(id.to_string(), entries);
+    }
+
+    /// Return the entries matching a query ordered by descending score, with ties ordered by
+    /// package key and then by repo.
+    pub fn search(&self, query: &Query) -> Vec<Match> {
+        let mut matches: Vec<Match> = self
+            .repos
+            .values()
+            .flatten()
+            .filter_map(|entry| entry.score(query).map(|score| Match { entry, score }))
+            .collect();
+        // stable sort preserves repo ordering for equal keys
+        matches.sort_by(|a, b| {
+            (Reverse(a.score), a.entry.key()).cmp(&(Reverse(b.score), b.entry.key()))
+        });
+        matches
+    }
+
+    /// Return package handles for all matches of a query in ranked order.
+    pub fn pkgs(&self, query: &Query, config: &Config) -> Result<Vec<OwnedPkg>> {
+        let mut pkgs = vec![];
+        for m in self.search(query) {
+            pkgs.extend(m.pkgs(config)?);
+        }
+        Ok(pkgs)
+    }
+
+    /// Return the number of indexed packages.
+    pub fn len(&self) -> usize {
+        self.repos.values().map(|v| v.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.repos.values().all(|v| v.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::repo::ebuild::TempRepo;
+
+    use super::*;
+
+    fn search<'a>(index: &'a SearchIndex, query: &Query) -> Vec<&'a str> {
+        index
+            .search(query)
+            .iter()
+            .map(|m| m.entry().key())
+            .collect()
+    }
+
+    fn temp_repo() -> TempRepo {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        for (cpv, desc, homepage) in [
+            ("app-misc/pkgcraft-1", "old", "https://old.org"),
+            ("app-misc/pkgcraft-2", "Gentoo library", "https://pkgcraft.org"),
+            ("app-misc/pkgcraft-tools-1", "Tools for pkgcraft", "https://github.com"),
+            ("dev-lang/rust-1", "Rust language", "https://rust-lang.org"),
+            ("sys-apps/portage-1", "Package manager", "https://gentoo.org"),
+        ] {
+            t.create_ebuild(cpv, None).unwrap();
+            t.create_metadata(cpv, &[("EAPI", "8"), ("DESCRIPTION", desc), ("HOMEPAGE", homepage)])
+                .unwrap();
+        }
+        let xml = indoc::indoc! {r#"
+            <?xml version="1.0" encoding="UTF-8"?>
+            <pkgmetadata>
+                <maintainer type="person">
+                    <email>dev@gentoo.org</email>
+                    <name>A Dev</name>
+                </maintainer>
+                <longdescription>Compiler for a systems language</longdescription>
+            </pkgmetadata>
+        "#};
+        fs::write(t.repo.path().join("dev-lang/rust/metadata.xml"), xml).unwrap();
+        t
+    }
+
+    #[test]
+    fn test_search() {
+        let t = temp_repo();
+        let mut index = SearchIndex::default();
+        index.update(&t.repo);
+        assert_eq!(index.len(), 4);
+
+        // entries use data from the latest version
+        let m = &index.search(&Query::substring("app-misc/pkgcraft"))[0];
+        let entry = m.entry();
+        assert_eq!(entry.repo(), "test");
+        assert_eq!(entry.name(), "pkgcraft");
+        assert_eq!(entry.versions(), ["1", "2"]);
+        assert_eq!(entry.description(), "Gentoo library");
+        assert_eq!(entry.homepage(), ["https://pkgcraft.org"]);
+        assert_eq!(m.score(), 400);
+
+        // exact name matches rank above prefix and description matches
+        let q = Query::substring("PKGCRAFT");
+        assert_eq!(search(&index, &q), ["app-misc/pkgcraft", "app-misc/pkgcraft-tools"]);
+        let q = Query::substring("gentoo");
+        assert_eq!(search(&index, &q), ["app-misc/pkgcraft", "dev-lang/rust", "sys-apps/portage"]);
+
+        // metadata.xml data
+        let entry = index.search(&Query::substring("dev-lang/rust"))[0].entry();
+        assert_eq!(entry.long_description(), Some("Compiler for a systems language"));
+        assert_eq!(entry.maintainers(), ["A Dev <dev@gentoo.org>"]);
+        assert_eq!(search(&index, &Query::substring("compiler")), ["dev-lang/rust"]);
+        assert_eq!(search(&index, &Query::substring("a dev")), ["dev-lang/rust"]);
+
+        // regex
+        let q = Query::regex("^(rust|portage)$").unwrap();
+        assert_eq!(search(&index, &q), ["dev-lang/rust", "sys-apps/portage"]);
+        let q = Query::regex("(?i)^package").unwrap();
+        assert_eq!(search(&index, &q), ["sys-apps/portage"]);
+        assert!(Query::regex("(").is_err());
+
+        // fuzzy matching only applies to names
+        let q = Query::fuzzy("pkgcft");
+        assert_eq!(search(&index, &q), ["app-misc/pkgcraft", "app-misc/pkgcraft-tools"]);
+        let q = Query::fuzzy("prtg");
+        assert_eq!(search(&index, &q), ["sys-apps/portage"]);
+        assert!(search(&index, &Query::fuzzy("cmplr")).is_empty());
+        assert!(search(&index, &Query::substring("nonexistent")).is_empty());
+    }
+
+    #[test]
+    fn test_fuzzy() {
+        assert_eq!(fuzzy("abc", "abc"), Some(25));
+        assert_eq!(fuzzy("ac", "abc"), Some(16));
+        assert_eq!(fuzzy("ac", "a-long-c"), Some(6));
+        assert!(fuzzy("ca", "abc").is_none());
+        assert!(fuzzy("", "abc").is_none());
+    }
+
+    #[test]
+    fn test_config() {
+        let t = temp_repo();
+        let cache = tempfile::TempDir::new().unwrap();
+        let mut config = Config::default();
+        config.path.cache = cache.path().to_path_buf();
+        let (_, repo) = Repo::from_path("test", t.repo.path()).unwrap();
+        config
+            .repos
+            .repos
+            .insert("test".to_string(), Arc::new(repo));
+
+        // missing repos are indexed and persisted
+        let path = SearchIndex::cache_path(&config);
+        assert!(!path.exists());
+        let index = SearchIndex::from_config(&config).unwrap();
+        assert_eq!(index.len(), 4);
+        assert_eq!(SearchIndex::load(&path).unwrap(), index);
+
+        // matches return package handles
+        let pkgs = index.pkgs(&Query::substring("pkgcraft"), &config).unwrap();
+        let pkgs: Vec<_> = pkgs.iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            pkgs,
+            [
+                "app-misc/pkgcraft-1::test",
+                "app-misc/pkgcraft-2::test",
+                "app-misc/pkgcraft-tools-1::test"
+            ]
+        );
+
+        // updates pick up repo changes
+        t.create_ebuild("dev-lang/go-1", None).unwrap();
+        t.create_metadata("dev-lang/go-1", &[("EAPI", "8"), ("DESCRIPTION", "Go language")])
+            .unwrap();
+        let (_, repo) = Repo::from_path("test", t.repo.path()).unwrap();
+        config
+            .repos
+            .repos
+            .insert("test".to_string(), Arc::new(repo));
+        assert_eq!(SearchIndex::from_config(&config).unwrap().len(), 4);
+        let index = SearchIndex::update_config(&config, &["test"]).unwrap();
+        assert_eq!(index.len(), 5);
+        assert_eq!(SearchIndex::load(&path).unwrap(), index);
+
+        // unconfigured repos are dropped
+        config.repos.repos.clear();
+        assert!(SearchIndex::from_config(&config).unwrap().is_empty());
+        let m = &index.search(&Query::substring("go"))[0];
+        assert!(m.pkgs(&config).is_err());
+    }
+}
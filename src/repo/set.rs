@@ -0,0 +1,301 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::sync::Arc;
+
+use indexmap::IndexSet;
+
+use crate::pkg::{OwnedPkg, Package, Pkg};
+use crate::repo::{Contains, PackageIter, Repo, Repository};
+use crate::restrict::{AtomAttr, Restrict, Restriction};
+use crate::{atom, Error, Result};
+
+/// Combined view of multiple repos ordered by priority, where the first repo providing a
+/// package takes precedence over the rest.
+#[derive(Debug, Default, Clone)]
+pub struct RepoSet {
+    id: String,
+    repos: Vec<Arc<Repo>>,
+}
+
+impl RepoSet {
+    /// Create a repo set from repos ordered from highest to lowest priority.
+    pub fn new<S: AsRef<str>>(id: S, repos: Vec<Arc<Repo>>) -> Self {
+        RepoSet {
+            id: id.as_ref().to_string(),
+            repos,
+        }
+    }
+
+    /// Return the member repos ordered from highest to lowest priority.
+    pub fn repos(&self) -> &[Arc<Repo>] {
+        &self.repos
+    }
+
+    /// Return the member repo with a given id.
+    pub fn get(&self, id: &str) -> Result<&Repo> {
+        self.repos
+            .iter()
+            .find(|r| r.id() == id)
+            .map(|r| r.as_ref())
+            .ok_or_else(|| Error::InvalidValue(format!("{}: unknown repo: {id}", self.id)))
+    }
+
+    /// Return an iterator over the set's packages and their origin repos, with duplicate
+    /// versions resolved by priority.
+    pub fn iter(&self) -> RepoSetIter {
+        self.into_iter()
+    }
+
+    /// Return an iterator over the packages matching a given restriction, where `::repo` atom
+    /// restrictions match against the origin repo.
+    pub fn iter_restrict<T: Into<Restrict>>(&self, val: T) -> RepoSetIter {
+        let mut iter = self.iter();
+        iter.restrict = Some(val.into());
+        iter
+    }
+
+    /// Merge string lists from all member repos, returning them sorted.
+    fn merge<F>(&self, func: F) -> Vec<String>
+    where
+        F: Fn(&Repo) -> Vec<String>,
+    {
+        let mut vals: IndexSet<String> = self.repos.iter().flat_map(|r| func(r)).collect();
+        vals.sort();
+        vals.into_iter().collect()
+    }
+}
+
+/// Determine if an object from a given repo matches a restriction.
+fn matches<T: Copy>(restrict: &Restrict, repo: &Repo, obj: T) -> bool
+where
+    Restrict: Restriction<T>,
+{
+    match restrict {
+        Restrict::And(vals) => vals.iter().all(|r| matches(r, repo, obj)),
+        Restrict::Or(vals) => vals.iter().any(|r| matches(r, repo, obj)),
+        Restrict::Atom(AtomAttr::Repo(r)) => r.matches(Some(repo.id())),
+        r => r.matches(obj),
+    }
+}
+
+impl fmt::Display for RepoSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.id)
+    }
+}
+
+impl Repository for RepoSet {
+    fn categories(&self) -> Vec<String> {
+        self.merge(|r| r.categories())
+    }
+
+    fn packages(&self, cat: &str) -> Vec<String> {
+        self.merge(|r| r.packages(cat))
+    }
+
+    fn versions(&self, cat: &str, pkg: &str) -> Vec<String> {
+        let mut versions: Vec<atom::Version> = self
+            .repos
+            .iter()
+            .flat_map(|r| r.versions(cat, pkg))
+            .filter_map(|v| v.parse().ok())
+            .collect();
+        versions.sort();
+        versions.dedup();
+        versions.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn len(&self) -> usize {
+        let mut len = 0;
+        for cat in self.categories() {
+            for pkg in self.packages(&cat) {
+                len += self.versions(&cat, &pkg).len();
+            }
+        }
+        len
+    }
+
+    fn is_empty(&self) -> bool {
+        self.repos.iter().all(|r| r.is_empty())
+    }
+}
+
+impl Contains<&atom::Atom> for RepoSet {
+    /// Determine if any member repo has a package matching an atom, respecting `::repo`.
+    ///
+    /// Only the versions of the atom's package are loaded so slot restrictions can be matched
+    /// against package metadata without scanning the entire set.
+    fn contains(&self, atom: &atom::Atom) -> bool {
+        let restrict = Restrict::from(atom);
+        let (cat, pkg) = (atom.category(), atom.package());
+        self.repos.iter().any(|r| {
+            r.versions(cat, pkg)
+                .iter()
+                .filter_map(|v| atom::parse::cpv(&format!("{cat}/{pkg}-{v}")).ok())
+                .map(|cpv| OwnedPkg::new(cpv, r.clone()))
+                .any(|p| p.pkg().map_or(false, |p| matches(&restrict, r, &p)))
+        })
+    }
+}
+
+impl Contains<atom::Atom> for RepoSet {
+    fn contains(&self, atom: atom::Atom) -> bool {
+        self.contains(&atom)
+    }
+}
+
+impl<'a> IntoIterator for &'a RepoSet {
+    type Item = (&'a Repo, Pkg<'a>);
+    type IntoIter = RepoSetIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        RepoSetIter {
+            iters: self
+                .repos
+                .iter()
+                .map(|r| (r.as_ref(), r.iter().peekable()))
+                .collect(),
+            restrict: None,
+            last: None,
+        }
+    }
+}
+
+/// Iterator merging the sorted packages of member repos.
+pub struct RepoSetIter<'a> {
+    iters: Vec<(&'a Repo, Peekable<PackageIter<'a>>)>,
+    restrict: Option<Restrict>,
+    last: Option<atom::Atom>,
+}
+
+impl<'a> Iterator for RepoSetIter<'a> {
+    type Item = (&'a Repo, Pkg<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // pull the lowest package, preferring higher priority repos on ties
+            let mut idx = None;
+            for (i, (_, iter)) in self.iters.iter_mut().enumerate() {
+                if let Some(pkg) = iter.peek() {
+                    let lower = match &idx {
+                        Some((_, atom)) => pkg.atom() < atom,
+                        None => true,
+                    };
+                    if lower {
+                        idx = Some((i, pkg.atom().clone()));
+                    }
+                }
+            }
+            let (i, atom) = idx?;
+            let (repo, iter) = &mut self.iters[i];
+            let (repo, pkg) = (*repo, iter.next().unwrap());
+
+            // skip lower priority duplicates of the last returned package
+            if self.last.as_ref() == Some(&atom) {
+                continue;
+            }
+            if let Some(r) = &self.restrict {
+                if !matches(r, repo, &pkg) {
+                    continue;
+                }
+            }
+
+            self.last = Some(atom);
+            return Some((repo, pkg));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repo::ebuild::TempRepo;
+    use crate::repo::fake;
+
+    use super::*;
+
+    fn repo_set() -> RepoSet {
+        let r1 = fake::Repo::new("overlay", ["cat/a-2", "cat/b-1", "cat/b-1-r1"]).unwrap();
+        let r2 = fake::Repo::new("primary", ["cat/a-1", "cat/a-2", "cat/b-1", "dog/c-1"]).unwrap();
+        RepoSet::new("set", vec![Arc::new(Repo::Fake(r1)), Arc::new(Repo::Fake(r2))])
+    }
+
+    #[test]
+    fn test_repository() {
+        let set = repo_set();
+        assert_eq!(set.id(), "set");
+        assert_eq!(set.to_string(), "set");
+        assert_eq!(set.categories(), ["cat", "dog"]);
+        assert_eq!(set.packages("cat"), ["a", "b"]);
+        assert_eq!(set.versions("cat", "a"), ["1", "2"]);
+        assert_eq!(set.versions("cat", "b"), ["1", "1-r1"]);
+        assert!(set.versions("cat", "z").is_empty());
+        assert_eq!(set.len(), 5);
+        assert!(!set.is_empty());
+        assert!(RepoSet::new("empty", vec![]).is_empty());
+        assert_eq!(set.get("primary").unwrap().id(), "primary");
+        assert!(set.get("nonexistent").is_err());
+
+        let atom = |s: &str| -> atom::Atom { s.parse().unwrap() };
+        assert!(set.contains(&atom("=dog/c-1")));
+        assert!(set.contains(atom("dog/c")));
+        assert!(!set.contains(atom(">dog/c-1")));
+        assert!(set.contains(atom("=cat/b-1-r1::overlay")));
+        assert!(!set.contains(atom("=cat/b-1-r1::primary")));
+        assert!(set.contains(atom("=dog/c-1::primary")));
+        assert!(!set.contains(atom("=dog/c-1::overlay")));
+        assert!(!set.contains(atom("=dog/c-1::nonexistent")));
+    }
+
+    #[test]
+    fn test_contains_slots() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        t.create_ebuild("cat/pkg-1", None).unwrap();
+        t.create_metadata("cat/pkg-1", &[("SLOT", "1/2")]).unwrap();
+        let (_, repo) = Repo::from_path("test", t.repo.path()).unwrap();
+        let set = RepoSet::new("set", vec![Arc::new(repo)]);
+
+        // slot restrictions match against package metadata
+        let atom = |s: &str| -> atom::Atom { s.parse().unwrap() };
+        assert!(set.contains(atom("cat/pkg:1")));
+        assert!(set.contains(atom("cat/pkg:1/2")));
+        assert!(set.contains(atom("=cat/pkg-1:1::test")));
+        assert!(!set.contains(atom("cat/pkg:2")));
+        assert!(!set.contains(atom("cat/pkg:1/1")));
+        assert!(!set.contains(atom("cat/pkg:1::other")));
+    }
+
+    #[test]
+    fn test_iter() {
+        let set = repo_set();
+        let pkgs: Vec<_> = set
+            .iter()
+            .map(|(r, p)| format!("{}::{}", p.atom(), r.id()))
+            .collect();
+        assert_eq!(
+            pkgs,
+            [
+                "cat/a-1::primary",
+                "cat/a-2::overlay",
+                "cat/b-1::overlay",
+                "cat/b-1-r1::overlay",
+                "dog/c-1::primary"
+            ]
+        );
+
+        // repo restrictions match against origin repos including shadowed packages
+        let restrict = |s: &str| -> Vec<String> {
+            let atom: atom::Atom = s.parse().unwrap();
+            set.iter_restrict(&atom)
+                .map(|(r, p)| format!("{}::{}", p.atom(), r.id()))
+                .collect()
+        };
+        assert_eq!(restrict("cat/a"), ["cat/a-1::primary", "cat/a-2::overlay"]);
+        assert_eq!(restrict("cat/a::primary"), ["cat/a-1::primary", "cat/a-2::primary"]);
+        assert_eq!(restrict("=cat/b-1::overlay"), ["cat/b-1::overlay"]);
+        assert!(restrict("dog/c::overlay").is_empty());
+    }
+}
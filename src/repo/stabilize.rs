@@ -0,0 +1,457 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+
+use indexmap::{IndexMap, IndexSet};
+use tracing::warn;
+
+use crate::atom::{self, Atom};
+use crate::depspec::DepSpec;
+use crate::pkg::ebuild::{DepClass, Pkg};
+use crate::pkg::Package;
+use crate::repo::profile::Profile;
+use crate::repo::{ebuild, Repository};
+use crate::{Error, Result};
+
+/// Dependency classes walked when planning keywording or stabilization.
+const DEP_CLASSES: [DepClass; 4] =
+    [DepClass::Depend, DepClass::Bdepend, DepClass::Rdepend, DepClass::Pdepend];
+
+/// Action required for a package on the target arch.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// The package lacks any keyword for the arch and must be keyworded first, even when
+    /// planning stabilization.
+    Keyword,
+    /// The package is keyworded as testing for the arch.
+    Stabilize,
+}
+
+/// Package requiring keywording or stabilization.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    cpv: Atom,
+    action: Action,
+    required_by: Option<String>,
+}
+
+impl Entry {
+    pub fn cpv(&self) -> &Atom {
+        &self.cpv
+    }
+
+    pub fn action(&self) -> Action {
+        self.action
+    }
+
+    /// Return the CPV of the package pulling in this entry, targets have none.
+    pub fn required_by(&self) -> Option<&str> {
+        self.required_by.as_deref()
+    }
+}
+
+/// Keywording or stabilization plan for a set of target packages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    arch: String,
+    entries: Vec<Entry>,
+    missing: Vec<(String, String)>,
+}
+
+impl Plan {
+    pub fn arch(&self) -> &str {
+        &self.arch
+    }
+
+    /// Return the packages requiring changes in the order they were found.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Return dependencies that couldn't be satisfied by any package in the repo, paired with
+    /// the CPV requiring them.
+    pub fn missing(&self) -> &[(String, String)] {
+        &self.missing
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl fmt::Display for Plan {
+    /// Output the plan as a package list suitable for keywording or stabilization requests.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for e in &self.entries {
+            match e.action {
+                Action::Keyword => writeln!(f, "={} ~{}", e.cpv, self.arch)?,
+                Action::Stabilize => writeln!(f, "={} {}", e.cpv, self.arch)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Planner determining the dependencies of target packages that also require keywording or
+/// stabilization for a given arch.
+#[derive(Debug)]
+pub struct Planner<'a> {
+    repo: &'a ebuild::Repo,
+    arch: String,
+    use_: IndexSet<String>,
+    use_force: IndexSet<String>,
+    use_mask: IndexSet<String>,
+}
+
+impl<'a> Planner<'a> {
+    /// Create a planner for a repo and arch, using the USE settings from the repo's
+    /// profiles/arch/{arch} profile stack if it exists.
+    pub fn new(repo: &'a ebuild::Repo, arch: &str) -> Result<Self> {
+        let arches = repo.arches()?;
+        if !arches.is_empty() && !arches.iter().any(|a| a == arch) {
+            return Err(Error::InvalidValue(format!("{}: unknown arch: {arch}", repo.id())));
+        }
+
+        let path = format!("arch/{arch}");
+        let profile = match repo.path().join("profiles").join(&path).exists() {
+            true => repo.profile(&path)?,
+            false => Profile::default(),
+        };
+        let mut use_ = profile.use_();
+        use_.insert(arch.to_string());
+
+        Ok(Planner {
+            repo,
+            arch: arch.to_string(),
+            use_,
+            use_force: profile.use_force().clone(),
+            use_mask: profile.use_mask().clone(),
+        })
+    }
+
+    /// Plan stabilizing the target packages along with all their dependencies.
+    pub fn stabilize(&self, targets: &[Atom]) -> Result<Plan> {
+        self.plan(targets, true)
+    }
+
+    /// Plan keywording the target packages along with all their dependencies.
+    pub fn keyword(&self, targets: &[Atom]) -> Result<Plan> {
+        self.plan(targets, false)
+    }
+
+    fn plan(&self, targets: &[Atom], stable: bool) -> Result<Plan> {
+        let mut plan = Plan {
+            arch: self.arch.clone(),
+            entries: vec![],
+            missing: vec![],
+        };
+        let mut seen = HashSet::new();
+        let mut queue: VecDeque<(Pkg, Option<String>)> = VecDeque::new();
+
+        for atom in targets {
+            match self.resolve(atom, stable) {
+                Resolved::Satisfied => (),
+                Resolved::Pkg(pkg) => queue.push_back((pkg, None)),
+                Resolved::Missing => {
+                    return Err(Error::InvalidValue(format!(
+                        "{}: no matching packages: {atom}",
+                        self.repo.id()
+                    )))
+                }
+            }
+        }
+
+        while let Some((pkg, required_by)) = queue.pop_front() {
+            let cpv = pkg.atom().cpv();
+            if !seen.insert(cpv.clone()) {
+                continue;
+            }
+
+            let action = match self.keyworded(&pkg)? {
+                Some(_) => Action::Stabilize,
+                None => Action::Keyword,
+            };
+            plan.entries.push(Entry {
+                cpv: pkg.atom().clone(),
+                action,
+                required_by,
+            });
+
+            for dep in self.deps(&pkg, stable)? {
+                match self.resolve(&dep, stable) {
+                    Resolved::Satisfied => (),
+                    Resolved::Pkg(p) => queue.push_back((p, Some(cpv.clone()))),
+                    Resolved::Missing => plan.missing.push((dep.to_string(), cpv.clone())),
+                }
+            }
+        }
+
+        Ok(plan)
+    }
+
+    /// Return the arch keyword for a package if it exists, ignoring disabled keywords.
+    fn keyworded(&self, pkg: &Pkg) -> Result<Option<String>> {
+        let meta = pkg.metadata()?;
+        let testing = format!("~{}", self.arch);
+        Ok(meta
+            .keywords()
+            .into_iter()
+            .find(|k| *k == self.arch || *k == testing)
+            .map(|k| k.to_string()))
+    }
+
+    /// Determine whether a package is already acceptable for the planned keyword level.
+    fn satisfied(&self, pkg: &Pkg, stable: bool) -> Result<bool> {
+        Ok(match self.keyworded(pkg)? {
+            Some(k) => !stable || k == self.arch,
+            None => false,
+        })
+    }
+
+    /// Determine if a package explicitly excludes the arch via its keywords.
+    fn excluded(&self, pkg: &Pkg) -> Result<bool> {
+        let meta = pkg.metadata()?;
+        let disabled = format!("-{}", self.arch);
+        Ok(meta.keywords().iter().any(|k| *k == disabled || *k == "-*")
+            && self.keyworded(pkg)?.is_none())
+    }
+
+    /// Resolve a dependency to the package that needs work if it isn't already satisfied.
+    fn resolve(&self, atom: &Atom, stable: bool) -> Resolved<'a> {
+        let (cat, name) = (atom.category(), atom.package());
+
+        // only the package's versions are checked instead of scanning the entire repo
+        let cache = self.repo.pkgs();
+        let cpvs = self
+            .repo
+            .versions(cat, name)
+            .into_iter()
+            .filter_map(|v| atom::parse::cpv(&format!("{cat}/{name}-{v}")).ok())
+            .filter_map(|cpv| cache.atoms.get(&cpv))
+            .filter(|cpv| match (atom.version(), cpv.version()) {
+                (Some(v), Some(ver)) => v.op_cmp(ver),
+                _ => true,
+            });
+
+        let mut candidates = vec![];
+        for cpv in cpvs {
+            let pkg = match Pkg::new(cpv, self.repo) {
+                Ok(p) => p,
+                Err(e) => {
+                    warn!("{}: invalid package: {cpv}: {e}", self.repo.id());
+                    continue;
+                }
+            };
+            let meta = match pkg.metadata() {
+                Ok(m) => m,
+                Err(e) => {
+                    warn!("{}: {pkg}: {e}", self.repo.id());
+                    continue;
+                }
+            };
+            if atom.slot().map_or(false, |s| s != meta.slot())
+                || atom.subslot().map_or(false, |s| s != meta.subslot())
+            {
+                continue;
+            }
+            match (self.satisfied(&pkg, stable), self.excluded(&pkg)) {
+                (Ok(true), _) => return Resolved::Satisfied,
+                (Ok(false), Ok(false)) => candidates.push(pkg),
+                (Ok(false), Ok(true)) => (),
+                (Err(e), _) | (_, Err(e)) => warn!("{}: {pkg}: {e}", self.repo.id()),
+            }
+        }
+
+        // prefer the highest testing version, falling back to the highest unkeyworded version
+        candidates.reverse();
+        let idx = candidates
+            .iter()
+            .position(|p| matches!(self.keyworded(p), Ok(Some(_))))
+            .unwrap_or(0);
+        match candidates.len() {
+            0 => Resolved::Missing,
+            _ => Resolved::Pkg(candidates.remove(idx)),
+        }
+    }
+
+    /// Return the USE flags enabled for a package on the target arch.
+    fn use_flags(&self, pkg: &Pkg) -> Result<HashSet<String>> {
+        let mut flags: HashSet<String> = pkg
+            .metadata()?
+            .iuse()
+            .into_iter()
+            .filter_map(|f| f.strip_prefix('+'))
+            .map(|f| f.to_string())
+            .collect();
+        flags.extend(self.use_.iter().cloned());
+        flags.extend(self.use_force.iter().cloned());
+        flags.retain(|f| !self.use_mask.contains(f));
+        Ok(flags)
+    }
+
+    /// Return the unique, non-blocker dependencies of a package under its arch USE settings.
+    fn deps(&self, pkg: &Pkg, stable: bool) -> Result<Vec<Atom>> {
+        let meta = pkg.metadata()?;
+        let flags = self.use_flags(pkg)?;
+        let mut deps = IndexMap::new();
+        for class in DEP_CLASSES {
+            if let Some(spec) = meta.deps(class)? {
+                let mut atoms = vec![];
+                self.collect(&spec, &flags, stable, &mut atoms);
+                for a in atoms {
+                    deps.entry(a.to_string()).or_insert_with(|| a.clone());
+                }
+            }
+        }
+        Ok(deps.into_values().collect())
+    }
+
+    /// Collect the atoms from a dependency tree that apply under the given USE flags, selecting
+    /// the first already satisfied alternative for any-of groups, or the first alternative if
+    /// none are.
+    fn collect<'d>(
+        &self,
+        spec: &'d DepSpec,
+        flags: &HashSet<String>,
+        stable: bool,
+        atoms: &mut Vec<&'d Atom>,
+    ) {
+        match spec {
+            DepSpec::Atoms(vals) => atoms.extend(vals.iter().filter(|a| a.blocker().is_none())),
+            DepSpec::List(vals) => {
+                for d in vals {
+                    self.collect(d, flags, stable, atoms);
+                }
+            }
+            DepSpec::AllOf(d) => self.collect(d, flags, stable, atoms),
+            DepSpec::ConditionalUse(flag, negate, d) if flags.contains(flag) != *negate => {
+                self.collect(d, flags, stable, atoms)
+            }
+            DepSpec::AnyOf(d) => {
+                let alternatives: Vec<&DepSpec> = match d.as_ref() {
+                    DepSpec::List(vals) => vals.iter().collect(),
+                    d => vec![d],
+                };
+                let mut choices: Vec<Vec<&Atom>> = vec![];
+                for alt in alternatives {
+                    match alt {
+                        // each atom in a bare sequence is a separate alternative
+                        DepSpec::Atoms(vals) => choices.extend(vals.iter().map(|a| vec![a])),
+                        _ => {
+                            let mut v = vec![];
+                            self.collect(alt, flags, stable, &mut v);
+                            choices.push(v);
+                        }
+                    }
+                }
+                let satisfied = choices.iter().position(|c| {
+                    c.iter()
+                        .all(|a| matches!(self.resolve(a, stable), Resolved::Satisfied))
+                });
+                if let Some(choice) = choices.into_iter().nth(satisfied.unwrap_or(0)) {
+                    atoms.extend(choice.into_iter().filter(|a| a.blocker().is_none()));
+                }
+            }
+            // disabled conditionals along with non-dependency variants
+            _ => (),
+        }
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+enum Resolved<'a> {
+    Satisfied,
+    Pkg(Pkg<'a>),
+    Missing,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::str::FromStr;
+
+    use crate::repo::ebuild::TempRepo;
+
+    use super::*;
+
+    fn create_pkg(t: &TempRepo, cpv: &str, data: &[(&str, &str)]) {
+        t.create_ebuild(cpv, None).unwrap();
+        let mut data = data.to_vec();
+        data.push(("EAPI", "8"));
+        t.create_metadata(cpv, &data).unwrap();
+    }
+
+    #[test]
+    fn test_plan() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let profiles = t.repo.path().join("profiles");
+        fs::write(profiles.join("arch.list"), "amd64\narm64\n").unwrap();
+        // USE settings are pulled from the arch profile's stack
+        fs::create_dir_all(profiles.join("base")).unwrap();
+        fs::write(profiles.join("base/make.defaults"), "USE=\"ssl X\"\n").unwrap();
+        fs::create_dir_all(profiles.join("arch/amd64")).unwrap();
+        fs::write(profiles.join("arch/amd64/parent"), "../../base\n").unwrap();
+        fs::write(profiles.join("arch/amd64/use.mask"), "X\n").unwrap();
+
+        create_pkg(
+            &t,
+            "cat/target-1",
+            &[
+                ("KEYWORDS", "~amd64"),
+                ("IUSE", "+doc ssl X"),
+                (
+                    "RDEPEND",
+                    "cat/stable ssl? ( cat/ssl ) X? ( cat/x ) doc? ( cat/doc ) || ( cat/alt1 cat/alt2 )",
+                ),
+                ("BDEPEND", ">=cat/build-2 !cat/blocker"),
+            ],
+        );
+        create_pkg(&t, "cat/stable-1", &[("KEYWORDS", "amd64")]);
+        create_pkg(&t, "cat/ssl-1", &[("KEYWORDS", "~amd64"), ("RDEPEND", "cat/nested")]);
+        create_pkg(&t, "cat/nested-1", &[("KEYWORDS", "~amd64 ~arm64")]);
+        create_pkg(&t, "cat/x-1", &[("KEYWORDS", "~amd64")]);
+        create_pkg(&t, "cat/doc-1", &[]);
+        create_pkg(&t, "cat/alt1-1", &[("KEYWORDS", "~amd64")]);
+        create_pkg(&t, "cat/alt2-1", &[("KEYWORDS", "amd64")]);
+        create_pkg(&t, "cat/build-1", &[("KEYWORDS", "amd64")]);
+        create_pkg(&t, "cat/build-2", &[("KEYWORDS", "~amd64")]);
+        create_pkg(&t, "cat/build-3", &[("KEYWORDS", "-amd64")]);
+        create_pkg(&t, "cat/broken-1", &[("RDEPEND", "cat/nonexistent")]);
+
+        // unknown arch
+        assert!(Planner::new(&t.repo, "sparc").is_err());
+
+        let planner = Planner::new(&t.repo, "amd64").unwrap();
+        let targets = [Atom::from_str("=cat/target-1").unwrap()];
+        let plan = planner.stabilize(&targets).unwrap();
+        assert_eq!(
+            plan.to_string(),
+            indoc::indoc! {"
+                =cat/target-1 amd64
+                =cat/build-2 amd64
+                =cat/ssl-1 amd64
+                =cat/doc-1 ~amd64
+                =cat/nested-1 amd64
+            "}
+        );
+        assert_eq!(plan.entries()[0].required_by(), None);
+        assert_eq!(plan.entries()[4].required_by(), Some("cat/ssl-1"));
+        assert!(plan.missing().is_empty());
+
+        // keywording is satisfied by testing keywords
+        let plan = planner.keyword(&targets).unwrap();
+        assert!(plan.is_empty());
+
+        // missing dependencies are reported
+        let planner = Planner::new(&t.repo, "arm64").unwrap();
+        let plan = planner
+            .keyword(&[Atom::from_str("cat/broken").unwrap()])
+            .unwrap();
+        assert_eq!(plan.to_string(), "=cat/broken-1 ~arm64\n");
+        assert_eq!(plan.missing(), [("cat/nonexistent".to_string(), "cat/broken-1".to_string())]);
+
+        // nonexistent targets
+        assert!(planner
+            .stabilize(&[Atom::from_str("cat/nonexistent").unwrap()])
+            .is_err());
+    }
+}
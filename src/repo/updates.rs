@@ -0,0 +1,480 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{fmt, fs};
+
+use indexmap::IndexSet;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tracing::warn;
+
+use crate::atom::Atom;
+use crate::depspec::DepSpec;
+use crate::files::{is_file, is_hidden, sorted_dir_list};
+use crate::pkg::ebuild::DepClass;
+use crate::pkg::Package;
+use crate::repo::vdb;
+use crate::{Error, Result};
+
+static QUARTER_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(?P<quarter>[1-4])Q-(?P<year>\d{4})$").unwrap());
+
+/// Package update as recorded in profiles/updates.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Update {
+    /// Package rename, e.g. `move cat/old cat/new`.
+    Move(Atom, Atom),
+    /// Slot change for matching packages, e.g. `slotmove cat/pkg 0 1`.
+    SlotMove(Atom, String, String),
+}
+
+impl Update {
+    /// Apply the update to an atom, returning the modified atom if it applied.
+    pub fn apply(&self, atom: &Atom) -> Option<Atom> {
+        match self {
+            Update::Move(from, to) if atom.key() == from.key() => {
+                Some(atom.with_key(to.category(), to.package()))
+            }
+            Update::SlotMove(target, old, new) if atom.key() == target.key() => {
+                if atom.slot() != Some(old) {
+                    return None;
+                }
+                if let Some(v) = target.version() {
+                    match atom.version() {
+                        Some(ver) if v.op_cmp(ver) => (),
+                        _ => return None,
+                    }
+                }
+                Some(atom.with_slot(new))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Update {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Update::Move(from, to) => write!(f, "move {from} {to}"),
+            Update::SlotMove(atom, old, new) => write!(f, "slotmove {atom} {old} {new}"),
+        }
+    }
+}
+
+impl FromStr for Update {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let tokens: Vec<_> = s.split_whitespace().collect();
+        match &tokens[..] {
+            ["move", from, to] => {
+                let (from, to) = (Atom::from_str(from)?, Atom::from_str(to)?);
+                for a in [&from, &to] {
+                    if a.to_string() != a.key() {
+                        return Err(Error::InvalidValue(format!("invalid move atom: {a}")));
+                    }
+                }
+                Ok(Update::Move(from, to))
+            }
+            ["slotmove", atom, old, new] => {
+                let atom = Atom::from_str(atom)?;
+                Ok(Update::SlotMove(atom, old.to_string(), new.to_string()))
+            }
+            _ => Err(Error::InvalidValue(format!("invalid update: {s:?}"))),
+        }
+    }
+}
+
+/// Ordered set of package updates.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Updates {
+    updates: Vec<Update>,
+}
+
+impl Updates {
+    /// Load all update files from a profiles/updates directory in quarter order.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let mut files = vec![];
+        let entries = sorted_dir_list(path)
+            .into_iter()
+            .filter_entry(|e| is_file(e) && !is_hidden(e));
+        for entry in entries {
+            let entry = entry.map_err(|e| Error::IO(format!("failed reading {path:?}: {e}")))?;
+            let name = entry.file_name().to_string_lossy();
+            match QUARTER_RE.captures(&name) {
+                Some(c) => {
+                    let (year, quarter): (u16, u8) =
+                        (c["year"].parse().unwrap(), c["quarter"].parse().unwrap());
+                    files.push(((year, quarter), entry.path().to_path_buf()));
+                }
+                None => warn!("ignoring invalid updates file: {:?}", entry.path()),
+            }
+        }
+        files.sort();
+
+        let mut updates = Self::default();
+        for (_, file) in files {
+            let data = fs::read_to_string(&file)
+                .map_err(|e| Error::IO(format!("failed reading {file:?}: {e}")))?;
+            let file_updates =
+                Self::from_str(&data).map_err(|e| Error::InvalidValue(format!("{file:?}: {e}")))?;
+            updates.updates.extend(file_updates.updates);
+        }
+        Ok(updates)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Update> {
+        self.updates.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.updates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.updates.is_empty()
+    }
+
+    /// Apply all updates in order to an atom, returning the modified atom if any applied.
+    pub fn apply(&self, atom: &Atom) -> Option<Atom> {
+        let mut updated = None;
+        for u in &self.updates {
+            if let Some(a) = u.apply(updated.as_ref().unwrap_or(atom)) {
+                updated = Some(a);
+            }
+        }
+        updated
+    }
+
+    /// Apply all updates to the atoms in a dependency tree.
+    pub fn apply_depspec(&self, spec: &DepSpec) -> DepSpec {
+        let apply = |d: &DepSpec| Box::new(self.apply_depspec(d));
+        match spec {
+            DepSpec::Atoms(vals) => DepSpec::Atoms(
+                vals.iter()
+                    .map(|a| self.apply(a).unwrap_or_else(|| a.clone()))
+                    .collect(),
+            ),
+            DepSpec::List(vals) => {
+                DepSpec::List(vals.iter().map(|d| self.apply_depspec(d)).collect())
+            }
+            DepSpec::AllOf(d) => DepSpec::AllOf(apply(d)),
+            DepSpec::AnyOf(d) => DepSpec::AnyOf(apply(d)),
+            DepSpec::ExactlyOneOf(d) => DepSpec::ExactlyOneOf(apply(d)),
+            DepSpec::AtMostOneOf(d) => DepSpec::AtMostOneOf(apply(d)),
+            DepSpec::ConditionalUse(flag, negate, d) => {
+                DepSpec::ConditionalUse(flag.clone(), *negate, apply(d))
+            }
+            DepSpec::Strings(_) | DepSpec::Uris(_) => spec.clone(),
+        }
+    }
+
+    /// Apply all updates to the atoms in a raw dependency string, returning the modified string
+    /// if any applied.
+    fn apply_str(&self, s: &str) -> Option<String> {
+        let mut changed = false;
+        let tokens: Vec<String> = s
+            .split_whitespace()
+            .map(|t| match t {
+                "(" | ")" | "||" => t.to_string(),
+                _ if t.ends_with('?') => t.to_string(),
+                _ => match Atom::from_str(t).ok().and_then(|a| self.apply(&a)) {
+                    Some(a) => {
+                        changed = true;
+                        a.to_string()
+                    }
+                    None => t.to_string(),
+                },
+            })
+            .collect();
+        match changed {
+            true => Some(tokens.join(" ")),
+            false => None,
+        }
+    }
+
+    /// Rewrite an installed package database, renaming moved packages, updating changed slots,
+    /// and updating the dependencies of all packages. Returns the number of modified packages.
+    pub fn update_vdb<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        let path = path.as_ref();
+        let repo = vdb::Repo::from_path("vdb", path)?;
+        let write = |path: PathBuf, data: &str| -> Result<()> {
+            fs::write(&path, format!("{data}\n"))
+                .map_err(|e| Error::IO(format!("failed writing {path:?}: {e}")))
+        };
+
+        // all changes are determined and validated before modifying anything
+        let mut modified = 0;
+        let mut writes = vec![];
+        let mut moves = vec![];
+        for pkg in &repo {
+            let mut changed = false;
+            let meta = pkg.metadata()?;
+
+            for class in DepClass::ALL {
+                if let Some(deps) = meta.get(class.as_str()).and_then(|s| self.apply_str(s)) {
+                    writes.push((pkg.path().join(class.as_str()), deps));
+                    changed = true;
+                }
+            }
+
+            let (slot, subslot) = (meta.slot(), meta.subslot());
+            let atom = pkg.atom().with_slot(slot);
+            if let Some(a) = self.apply(&atom) {
+                if a.slot() != Some(slot) {
+                    let new = a.slot().unwrap();
+                    let val = match subslot == slot {
+                        true => new.to_string(),
+                        false => format!("{new}/{subslot}"),
+                    };
+                    writes.push((pkg.path().join("SLOT"), val));
+                    changed = true;
+                }
+                if a.key() != atom.key() {
+                    let pf = format!("{}-{}", a.package(), pkg.atom().version().unwrap());
+                    let dest = path.join(a.category()).join(&pf);
+                    moves.push((pkg.path().to_path_buf(), pkg.atom().clone(), a, dest));
+                    changed = true;
+                }
+            }
+
+            if changed {
+                modified += 1;
+            }
+        }
+
+        let mut dests = HashSet::new();
+        for (_, old, _, dest) in &moves {
+            if dest.exists() || !dests.insert(dest) {
+                return Err(Error::IO(format!("failed moving {old}: {dest:?} already exists")));
+            }
+        }
+
+        for (path, data) in writes {
+            write(path, &data)?;
+        }
+
+        for (src, old, new, dest) in moves {
+            let pvr = old.version().unwrap();
+            let pf = format!("{}-{pvr}", new.package());
+            fs::create_dir_all(dest.parent().unwrap())
+                .map_err(|e| Error::IO(format!("failed creating {:?}: {e}", dest.parent())))?;
+            fs::rename(&src, &dest)
+                .map_err(|e| Error::IO(format!("failed moving {src:?} to {dest:?}: {e}")))?;
+
+            let ebuild = dest.join(format!("{}-{pvr}.ebuild", old.package()));
+            if ebuild.exists() {
+                let new_ebuild = dest.join(format!("{pf}.ebuild"));
+                fs::rename(&ebuild, &new_ebuild)
+                    .map_err(|e| Error::IO(format!("failed renaming {ebuild:?}: {e}")))?;
+            }
+            if dest.join("CATEGORY").exists() {
+                write(dest.join("CATEGORY"), new.category())?;
+            }
+            if dest.join("PF").exists() {
+                write(dest.join("PF"), &pf)?;
+            }
+        }
+
+        Ok(modified)
+    }
+
+    /// Rewrite a world file, updating its atoms and dropping resulting duplicates. Returns true
+    /// if the file was modified.
+    pub fn update_world<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .map_err(|e| Error::IO(format!("failed reading {path:?}: {e}")))?;
+
+        let mut changed = false;
+        let mut entries = IndexSet::new();
+        for line in data.lines().map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            let updated = match line.starts_with('@') {
+                true => None,
+                false => match Atom::from_str(line) {
+                    Ok(a) => self.apply(&a).map(|a| a.to_string()),
+                    Err(e) => {
+                        warn!("{path:?}: {e}");
+                        None
+                    }
+                },
+            };
+            match updated {
+                Some(s) => {
+                    changed = true;
+                    entries.insert(s);
+                }
+                None => {
+                    entries.insert(line.to_string());
+                }
+            }
+        }
+
+        if changed {
+            let data: String = entries.iter().map(|s| format!("{s}\n")).collect();
+            fs::write(path, data)
+                .map_err(|e| Error::IO(format!("failed writing {path:?}: {e}")))?;
+        }
+
+        Ok(changed)
+    }
+}
+
+impl FromStr for Updates {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut updates = vec![];
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let update = Update::from_str(line)
+                .map_err(|e| Error::InvalidValue(format!("line {}: {e}", i + 1)))?;
+            updates.push(update);
+        }
+        Ok(Updates { updates })
+    }
+}
+
+impl<'a> IntoIterator for &'a Updates {
+    type Item = &'a Update;
+    type IntoIter = std::slice::Iter<'a, Update>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.updates.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::depspec::pkgdep;
+    use crate::eapi;
+    use crate::macros::assert_err_re;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let updates =
+            Updates::from_str("# comment\nmove a/b c/d\n\nslotmove =a/e-1* 0 1\n").unwrap();
+        assert_eq!(updates.len(), 2);
+        let lines: Vec<String> = updates.iter().map(|u| u.to_string()).collect();
+        assert_eq!(lines, ["move a/b c/d", "slotmove =a/e-1* 0 1"]);
+
+        // invalid data
+        for s in ["move a/b", "move a/b-1 c/d", "move a/b c/d:0", "slotmove a/b 0", "copy a/b c/d"]
+        {
+            assert!(Updates::from_str(s).is_err(), "{s:?} didn't fail");
+        }
+        assert_err_re!(Updates::from_str("move a/b c/d\nmove a"), "^line 2: ");
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("1Q-2024"), "move a/c a/d\n").unwrap();
+        fs::write(dir.path().join("4Q-2023"), "move a/b a/c\n").unwrap();
+        fs::write(dir.path().join("2Q-2023"), "slotmove a/b 0 1\n").unwrap();
+        fs::write(dir.path().join("README"), "ignored\n").unwrap();
+        let updates = Updates::load(dir.path()).unwrap();
+        let lines: Vec<String> = updates.iter().map(|u| u.to_string()).collect();
+        assert_eq!(lines, ["slotmove a/b 0 1", "move a/b a/c", "move a/c a/d"]);
+
+        // updates chain in order
+        let atom = Atom::from_str(">=a/b-1:0[u]").unwrap();
+        assert_eq!(updates.apply(&atom).unwrap().to_string(), ">=a/d-1:1[u]");
+        assert!(updates.apply(&Atom::from_str("a/e").unwrap()).is_none());
+
+        // nonexistent dirs
+        assert!(Updates::load(dir.path().join("nonexistent"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_apply() {
+        let updates = Updates::from_str("move a/b c/d\nslotmove =a/e-1* 0 1").unwrap();
+        for (s, expected) in [
+            ("a/b", Some("c/d")),
+            ("!!<a/b-2:=", Some("!!<c/d-2:=")),
+            ("a/bb", None),
+            ("=a/e-1.2:0", Some("=a/e-1.2:1")),
+            ("=a/e-2:0", None),
+            ("a/e:0", None),
+            ("=a/e-1:2", None),
+        ] {
+            let atom = Atom::from_str(s).unwrap();
+            let updated = updates.apply(&atom).map(|a| a.to_string());
+            assert_eq!(updated.as_deref(), expected, "{s}");
+        }
+
+        let deps = pkgdep::parse("a/b u? ( || ( x/y =a/e-1:0 ) )", &eapi::EAPI_LATEST).unwrap();
+        let expected = pkgdep::parse("c/d u? ( || ( x/y =a/e-1:1 ) )", &eapi::EAPI_LATEST).unwrap();
+        assert_eq!(updates.apply_depspec(&deps), expected);
+    }
+
+    #[test]
+    fn test_update_vdb() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("vdb");
+        for (cpv, data) in [
+            ("a/b-1", vec![("SLOT", "0"), ("CATEGORY", "a"), ("PF", "b-1")]),
+            ("a/e-1", vec![("SLOT", "0/2")]),
+            ("x/y-1", vec![("SLOT", "0"), ("RDEPEND", "a/b:0= u? ( a/e )")]),
+        ] {
+            let path = root.join(cpv);
+            fs::create_dir_all(&path).unwrap();
+            for (k, v) in data {
+                fs::write(path.join(k), format!("{v}\n")).unwrap();
+            }
+        }
+        fs::write(root.join("a/b-1/b-1.ebuild"), "").unwrap();
+
+        let updates = Updates::from_str("move a/b c/d\nslotmove a/e 0 1").unwrap();
+        assert_eq!(updates.update_vdb(&root).unwrap(), 3);
+        assert!(!root.join("a/b-1").exists());
+        let pkg = root.join("c/d-1");
+        assert!(pkg.join("d-1.ebuild").exists());
+        assert_eq!(fs::read_to_string(pkg.join("CATEGORY")).unwrap(), "c\n");
+        assert_eq!(fs::read_to_string(pkg.join("PF")).unwrap(), "d-1\n");
+        assert_eq!(fs::read_to_string(root.join("a/e-1/SLOT")).unwrap(), "1/2\n");
+        assert_eq!(fs::read_to_string(root.join("x/y-1/RDEPEND")).unwrap(), "c/d:0= u? ( a/e )\n");
+
+        // updates are idempotent
+        assert_eq!(updates.update_vdb(&root).unwrap(), 0);
+
+        // conflicting moves fail without modifying anything
+        for (cpv, data) in [
+            ("a/f-1", vec![("SLOT", "0")]),
+            ("g/h-1", vec![("SLOT", "0")]),
+            ("x/z-1", vec![("SLOT", "0"), ("RDEPEND", "a/f")]),
+        ] {
+            let path = root.join(cpv);
+            fs::create_dir_all(&path).unwrap();
+            for (k, v) in data {
+                fs::write(path.join(k), format!("{v}\n")).unwrap();
+            }
+        }
+        let conflict = Updates::from_str("move a/f g/h").unwrap();
+        assert_err_re!(conflict.update_vdb(&root), "failed moving a/f-1: .* already exists$");
+        assert!(root.join("a/f-1").exists());
+        assert_eq!(fs::read_to_string(root.join("x/z-1/RDEPEND")).unwrap(), "a/f\n");
+
+        // world file
+        let world = dir.path().join("world");
+        fs::write(&world, "a/b\nc/d\n@set\nx/y:0\n").unwrap();
+        assert!(updates.update_world(&world).unwrap());
+        assert_eq!(fs::read_to_string(&world).unwrap(), "c/d\n@set\nx/y:0\n");
+        assert!(!updates.update_world(&world).unwrap());
+    }
+}
@@ -0,0 +1,178 @@
+use std::fs;
+use std::path::Path;
+
+use indexmap::IndexMap;
+use tracing::warn;
+
+use crate::files::{has_ext, is_file, sorted_dir_list};
+use crate::pkg::ebuild::xml;
+use crate::repo::{ebuild, Repository};
+use crate::{Error, Result};
+
+type Descriptions = IndexMap<String, String>;
+
+/// Parse a file of `name - description` lines, returning None if the file doesn't exist.
+fn load_file(path: &Path) -> Result<Option<Vec<(String, String)>>> {
+    let data = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Error::IO(format!("failed reading {path:?}: {e}"))),
+    };
+
+    let mut vals = vec![];
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once(" - ") {
+            Some((name, desc)) => vals.push((name.trim().to_string(), desc.trim().to_string())),
+            None => warn!("{path:?}: line {}: invalid description: {line}", i + 1),
+        }
+    }
+    Ok(Some(vals))
+}
+
+/// USE flag descriptions for a repo.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UseDesc {
+    global: Descriptions,
+    local: IndexMap<String, Descriptions>,
+    expand: IndexMap<String, Descriptions>,
+}
+
+impl UseDesc {
+    /// Load the USE flag descriptions from a repo's profiles directory, falling back to
+    /// package metadata.xml files for local flags when use.local.desc doesn't exist.
+    pub fn load(repo: &ebuild::Repo) -> Result<Self> {
+        let profiles = repo.path().join("profiles");
+        let mut desc = UseDesc::default();
+
+        if let Some(vals) = load_file(&profiles.join("use.desc"))? {
+            desc.global.extend(vals);
+        }
+
+        match load_file(&profiles.join("use.local.desc"))? {
+            Some(vals) => {
+                for (name, d) in vals {
+                    match name.split_once(':') {
+                        Some((key, flag)) => {
+                            desc.local
+                                .entry(key.to_string())
+                                .or_insert_with(Descriptions::new)
+                                .insert(flag.to_string(), d);
+                        }
+                        None => warn!("{}: invalid local USE flag: {name}", repo.id()),
+                    }
+                }
+            }
+            None => {
+                for cat in repo.categories() {
+                    for pkg in repo.packages(&cat) {
+                        let path = repo.path().join(&cat).join(&pkg).join("metadata.xml");
+                        match xml::Metadata::load(&path) {
+                            Ok(m) if !m.local_use().is_empty() => {
+                                desc.local
+                                    .insert(format!("{cat}/{pkg}"), m.local_use().clone());
+                            }
+                            Ok(_) => (),
+                            Err(e) => warn!("{}: {e}", repo.id()),
+                        }
+                    }
+                }
+            }
+        }
+
+        let desc_dir = profiles.join("desc");
+        if desc_dir.exists() {
+            let files = sorted_dir_list(&desc_dir)
+                .into_iter()
+                .filter_entry(|e| is_file(e) && has_ext(e, "desc"));
+            for entry in files {
+                let entry =
+                    entry.map_err(|e| Error::IO(format!("failed reading {desc_dir:?}: {e}")))?;
+                let path = entry.path();
+                let var = path.file_stem().unwrap().to_string_lossy().to_uppercase();
+                if let Some(vals) = load_file(path)? {
+                    desc.expand.insert(var, vals.into_iter().collect());
+                }
+            }
+        }
+
+        Ok(desc)
+    }
+
+    /// Return the global USE flag descriptions from use.desc.
+    pub fn global(&self) -> &Descriptions {
+        &self.global
+    }
+
+    /// Return the local USE flag descriptions mapped by package key.
+    pub fn local(&self) -> &IndexMap<String, Descriptions> {
+        &self.local
+    }
+
+    /// Return the USE_EXPAND value descriptions mapped by variable name.
+    pub fn expand(&self) -> &IndexMap<String, Descriptions> {
+        &self.expand
+    }
+
+    /// Return the description for a USE flag, preferring local descriptions for a given package
+    /// key over global and USE_EXPAND descriptions.
+    pub fn get(&self, flag: &str, key: Option<&str>) -> Option<&str> {
+        if let Some(d) = key
+            .and_then(|k| self.local.get(k))
+            .and_then(|d| d.get(flag))
+        {
+            return Some(d);
+        }
+        if let Some(d) = self.global.get(flag) {
+            return Some(d);
+        }
+        self.expand
+            .iter()
+            .filter_map(|(var, vals)| {
+                let prefix = format!("{}_", var.to_lowercase());
+                flag.strip_prefix(&prefix)
+                    .and_then(|v| vals.get(v))
+                    .map(|d| (var.len(), d.as_str()))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, d)| d)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repo::ebuild::TempRepo;
+
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let profiles = t.repo.path().join("profiles");
+        fs::write(profiles.join("use.desc"), "# comment\nssl - Enable SSL\ninvalid\n").unwrap();
+        fs::write(profiles.join("use.local.desc"), "cat/pkg:ssl - Local SSL\n").unwrap();
+        fs::create_dir(profiles.join("desc")).unwrap();
+        fs::write(profiles.join("desc/python_targets.desc"), "python3_12 - Python 3.12\n").unwrap();
+        fs::write(profiles.join("desc/README"), "ignored\n").unwrap();
+
+        let desc = UseDesc::load(&t.repo).unwrap();
+        assert_eq!(desc.global().len(), 1);
+        assert_eq!(desc.get("ssl", None), Some("Enable SSL"));
+        assert_eq!(desc.get("ssl", Some("cat/pkg")), Some("Local SSL"));
+        assert_eq!(desc.get("ssl", Some("cat/other")), Some("Enable SSL"));
+        assert_eq!(desc.get("python_targets_python3_12", None), Some("Python 3.12"));
+        assert_eq!(desc.expand().get("PYTHON_TARGETS").unwrap().len(), 1);
+        assert_eq!(desc.get("nonexistent", None), None);
+
+        // local descriptions fall back to metadata.xml
+        fs::remove_file(profiles.join("use.local.desc")).unwrap();
+        let (_, path) = t.create_ebuild("cat/pkg-1", None).unwrap();
+        let xml = r#"<pkgmetadata><use><flag name="ssl">XML SSL</flag></use></pkgmetadata>"#;
+        fs::write(path.with_file_name("metadata.xml"), xml).unwrap();
+        let desc = UseDesc::load(&t.repo).unwrap();
+        assert_eq!(desc.get("ssl", Some("cat/pkg")), Some("XML SSL"));
+    }
+}
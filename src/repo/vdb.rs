@@ -0,0 +1,399 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use once_cell::sync::OnceCell;
+use tracing::warn;
+use walkdir::DirEntry;
+
+use crate::files::{is_dir, is_hidden, sorted_dir_list};
+use crate::pkg::ebuild::DepClass;
+use crate::pkg::Package;
+use crate::{atom, pkg, repo, Error, Result};
+
+/// Repo of installed packages as stored in a package database, e.g. /var/db/pkg.
+#[derive(Debug, Default)]
+pub struct Repo {
+    id: String,
+    path: PathBuf,
+    pkgs: OnceCell<repo::PkgCache>,
+}
+
+impl Repo {
+    pub(super) const FORMAT: &'static str = "vdb";
+
+    pub(super) fn from_path<S, P>(id: S, path: P) -> Result<Self>
+    where
+        S: AsRef<str>,
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        if !path.is_dir() || path.join("profiles").exists() {
+            return Err(Error::InvalidRepo {
+                path: PathBuf::from(path),
+                error: "not a package database".to_string(),
+            });
+        }
+
+        Ok(Repo {
+            id: id.as_ref().to_string(),
+            path: PathBuf::from(path),
+            pkgs: OnceCell::new(),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the package cache, populating it by walking the database on first access.
+    pub(super) fn pkgs(&self) -> &repo::PkgCache {
+        self.pkgs.get_or_init(|| {
+            let filter = |e: &DirEntry| -> bool { is_dir(e) && !is_hidden(e) };
+            let mut cpvs = vec![];
+            for cat in sorted_dir_list(&self.path).into_iter().filter_entry(filter) {
+                let cat = match cat {
+                    Ok(e) => e,
+                    Err(e) => {
+                        warn!("error walking {:?}: {e}", &self.path);
+                        continue;
+                    }
+                };
+                for pf in sorted_dir_list(cat.path()).into_iter().filter_entry(filter) {
+                    match pf {
+                        Ok(e) => match (cat.file_name().to_str(), e.file_name().to_str()) {
+                            (Some(c), Some(p)) => cpvs.push(format!("{c}/{p}")),
+                            _ => warn!("non-unicode path: {:?}", e.path()),
+                        },
+                        Err(e) => warn!("error walking {:?}: {e}", cat.path()),
+                    }
+                }
+            }
+            cpvs.iter().map(|s| s.as_str()).collect()
+        })
+    }
+
+    pub fn iter(&self) -> PkgIter {
+        self.into_iter()
+    }
+
+    /// Return the installed packages that must be rebuilt if a given package replaces the
+    /// currently installed version, due to slot operator dependencies recorded against a
+    /// different slot or subslot.
+    pub fn rebuilds(&self, pkg: &pkg::ebuild::Pkg) -> Result<Vec<Rebuild>> {
+        let meta = pkg.metadata()?;
+        Ok(self.slot_rebuilds(pkg.atom(), meta.slot(), meta.subslot()))
+    }
+
+    /// Return the installed packages with slot operator dependencies on a given package that
+    /// were recorded against a slot or subslot differing from the specified values.
+    pub fn slot_rebuilds(&self, cpv: &atom::Atom, slot: &str, subslot: &str) -> Vec<Rebuild> {
+        let mut rebuilds = vec![];
+        let key = cpv.key();
+
+        for pkg in self {
+            // a package replacing itself never requires a rebuild
+            if pkg.atom().key() == key {
+                continue;
+            }
+
+            let meta = match pkg.metadata() {
+                Ok(meta) => meta,
+                Err(e) => {
+                    warn!("{}: {pkg}: {e}", self.id);
+                    continue;
+                }
+            };
+
+            for class in DepClass::ALL {
+                let deps = match meta.deps(class) {
+                    Ok(Some(deps)) => deps,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        warn!("{}: {pkg}: {e}", self.id);
+                        continue;
+                    }
+                };
+
+                for (_, dep) in deps.flatten_atoms() {
+                    if dep.key() != key || dep.slot_op() != Some("=") || dep.blocker().is_some() {
+                        continue;
+                    }
+
+                    // ignore dependencies that the new version can't satisfy
+                    if let (Some(v), Some(ver)) = (dep.version(), cpv.version()) {
+                        if !v.op_cmp(ver) {
+                            continue;
+                        }
+                    }
+
+                    let recorded_slot = dep.slot();
+                    let recorded_subslot = dep.subslot().or(recorded_slot);
+                    if recorded_slot != Some(slot) || recorded_subslot != Some(subslot) {
+                        rebuilds.push(Rebuild {
+                            cpv: pkg.atom().cpv(),
+                            class,
+                            atom: dep.to_string(),
+                            recorded_slot: recorded_slot.map(|s| s.to_string()),
+                            recorded_subslot: recorded_subslot.map(|s| s.to_string()),
+                            slot: slot.to_string(),
+                            subslot: subslot.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+
+        rebuilds
+    }
+}
+
+/// An installed package requiring a rebuild due to a slot operator dependency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rebuild {
+    cpv: String,
+    class: DepClass,
+    atom: String,
+    recorded_slot: Option<String>,
+    recorded_subslot: Option<String>,
+    slot: String,
+    subslot: String,
+}
+
+impl Rebuild {
+    /// Return the CPV of the installed package requiring a rebuild.
+    pub fn cpv(&self) -> &str {
+        &self.cpv
+    }
+
+    /// Return the dependency class of the triggering dependency.
+    pub fn class(&self) -> DepClass {
+        self.class
+    }
+
+    /// Return the recorded slot operator dependency.
+    pub fn atom(&self) -> &str {
+        &self.atom
+    }
+
+    /// Return the slot and subslot recorded at build time, if any.
+    pub fn recorded(&self) -> (Option<&str>, Option<&str>) {
+        (self.recorded_slot.as_deref(), self.recorded_subslot.as_deref())
+    }
+
+    /// Return the slot and subslot of the replacing package.
+    pub fn new_slot(&self) -> (&str, &str) {
+        (&self.slot, &self.subslot)
+    }
+}
+
+impl fmt::Display for Rebuild {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let recorded = match self.recorded() {
+            (Some(slot), Some(subslot)) => format!("{slot}/{subslot}"),
+            _ => "unrecorded".to_string(),
+        };
+        write!(
+            f,
+            "{}: {} {}: recorded {recorded}, new {}/{}",
+            self.cpv, self.class, self.atom, self.slot, self.subslot
+        )
+    }
+}
+
+impl fmt::Display for Repo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.id, self.path.to_string_lossy())
+    }
+}
+
+impl repo::Repository for Repo {
+    fn categories(&self) -> Vec<String> {
+        self.pkgs().categories()
+    }
+
+    fn packages(&self, cat: &str) -> Vec<String> {
+        self.pkgs().packages(cat)
+    }
+
+    fn versions(&self, cat: &str, pkg: &str) -> Vec<String> {
+        self.pkgs().versions(cat, pkg)
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn len(&self) -> usize {
+        self.pkgs().len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pkgs().is_empty()
+    }
+}
+
+impl<T: AsRef<Path>> repo::Contains<T> for Repo {
+    fn contains(&self, path: T) -> bool {
+        let path = path.as_ref();
+        match path.is_absolute() {
+            true => path.starts_with(&self.path) && path.exists(),
+            false => self.path.join(path).exists(),
+        }
+    }
+}
+
+impl repo::Contains<atom::Atom> for Repo {
+    fn contains(&self, atom: atom::Atom) -> bool {
+        self.pkgs().atoms.contains(&atom)
+    }
+}
+
+impl repo::Contains<&atom::Atom> for Repo {
+    fn contains(&self, atom: &atom::Atom) -> bool {
+        self.pkgs().atoms.contains(atom)
+    }
+}
+
+impl<'a> IntoIterator for &'a Repo {
+    type Item = pkg::vdb::Pkg<'a>;
+    type IntoIter = PkgIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        PkgIter {
+            iter: self.pkgs().into_iter(),
+            repo: self,
+        }
+    }
+}
+
+pub struct PkgIter<'a> {
+    iter: repo::PkgCacheIter<'a>,
+    repo: &'a Repo,
+}
+
+impl<'a> Iterator for PkgIter<'a> {
+    type Item = pkg::vdb::Pkg<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.iter.next() {
+                None => return None,
+                Some(a) => match pkg::vdb::Pkg::new(a, self.repo) {
+                    Ok(p) => return Some(p),
+                    Err(e) => warn!("{}: invalid package: {a}: {e}", self.repo.id),
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::str::FromStr;
+
+    use tempfile::TempDir;
+
+    use crate::repo::ebuild::TempRepo;
+    use crate::repo::{Contains, Repository};
+
+    use super::*;
+
+    /// Create an installed package entry in a package database.
+    fn create_pkg(root: &Path, cpv: &str, data: &[(&str, &str)]) {
+        let path = root.join(cpv);
+        fs::create_dir_all(&path).unwrap();
+        for (k, v) in data {
+            fs::write(path.join(k), format!("{v}\n")).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_repo() {
+        let dir = TempDir::new().unwrap();
+        create_pkg(dir.path(), "cat/a-1", &[("SLOT", "0")]);
+        create_pkg(dir.path(), "cat/a-2", &[("SLOT", "1")]);
+        create_pkg(dir.path(), "cat/b-1-r1", &[("SLOT", "0"), ("CONTENTS", "obj /a 1 2")]);
+        // hidden entries are ignored
+        create_pkg(dir.path(), "cat/.b-2", &[("SLOT", "0")]);
+
+        let repo = Repo::from_path("vdb", dir.path()).unwrap();
+        assert_eq!(repo.categories(), ["cat"]);
+        assert_eq!(repo.packages("cat"), ["a", "b"]);
+        assert_eq!(repo.versions("cat", "a"), ["1", "2"]);
+        assert_eq!(repo.len(), 3);
+        assert!(repo.contains(&atom::parse::cpv("cat/b-1-r1").unwrap()));
+        assert!(repo.contains("cat/a-1"));
+
+        let slots: Vec<String> = repo
+            .iter()
+            .map(|p| p.metadata().unwrap().slot().to_string())
+            .collect();
+        assert_eq!(slots, ["0", "1", "0"]);
+
+        // installed file lists aren't loaded as metadata
+        let pkg = repo.iter().last().unwrap();
+        assert_eq!(pkg.metadata().unwrap().get("SLOT"), Some("0"));
+        assert!(pkg.metadata().unwrap().get("CONTENTS").is_none());
+
+        // ebuild repos aren't package databases
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        assert!(Repo::from_path("vdb", t.repo.path()).is_err());
+
+        // package databases aren't auto-detected
+        assert!(repo::Repo::from_path("vdb", dir.path()).is_err());
+        let repo = repo::Repo::from_format("vdb", dir.path(), "vdb").unwrap();
+        assert_eq!(repo.format(), "vdb");
+    }
+
+    #[test]
+    fn test_rebuilds() {
+        let dir = TempDir::new().unwrap();
+        create_pkg(dir.path(), "dev-libs/foo-1", &[("EAPI", "8"), ("SLOT", "0/1")]);
+        create_pkg(
+            dir.path(),
+            "cat/a-1",
+            &[("EAPI", "8"), ("SLOT", "0"), ("RDEPEND", "dev-libs/foo:0/1= cat/c")],
+        );
+        create_pkg(
+            dir.path(),
+            "cat/b-1",
+            &[("EAPI", "8"), ("SLOT", "0"), ("DEPEND", ">=dev-libs/foo-1:0/1")],
+        );
+        create_pkg(
+            dir.path(),
+            "cat/c-1",
+            &[("EAPI", "8"), ("SLOT", "0"), ("RDEPEND", "<dev-libs/foo-2:0/1=")],
+        );
+        let repo = Repo::from_path("vdb", dir.path()).unwrap();
+
+        // unchanged subslot
+        let cpv = atom::parse::cpv("dev-libs/foo-1-r1").unwrap();
+        assert!(repo.slot_rebuilds(&cpv, "0", "1").is_empty());
+
+        // subslot bump
+        let rebuilds = repo.slot_rebuilds(&cpv, "0", "2");
+        let cpvs: Vec<&str> = rebuilds.iter().map(|r| r.cpv()).collect();
+        assert_eq!(cpvs, ["cat/a-1", "cat/c-1"]);
+        let r = &rebuilds[0];
+        assert_eq!(r.class(), DepClass::Rdepend);
+        assert_eq!(r.atom(), "dev-libs/foo:0/1=");
+        assert_eq!(r.recorded(), (Some("0"), Some("1")));
+        assert_eq!(r.new_slot(), ("0", "2"));
+        assert_eq!(r.to_string(), "cat/a-1: RDEPEND dev-libs/foo:0/1=: recorded 0/1, new 0/2");
+
+        // versions outside the dependency range don't trigger rebuilds
+        let cpv = atom::Atom::from_str("=dev-libs/foo-2").unwrap();
+        let rebuilds = repo.slot_rebuilds(&cpv, "0", "2");
+        let cpvs: Vec<&str> = rebuilds.iter().map(|r| r.cpv()).collect();
+        assert_eq!(cpvs, ["cat/a-1"]);
+
+        // ebuild package
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        let (a, _) = t.create_ebuild("dev-libs/foo-2", None).unwrap();
+        t.create_metadata("dev-libs/foo-2", &[("EAPI", "8"), ("SLOT", "0/2")])
+            .unwrap();
+        let pkg = pkg::ebuild::Pkg::new(&a, &t.repo).unwrap();
+        assert_eq!(repo.rebuilds(&pkg).unwrap().len(), 1);
+    }
+}
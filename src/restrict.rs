@@ -164,6 +164,33 @@ impl Restriction<&atom::Atom> for Restrict {
     }
 }
 
+impl Restriction<&pkg::Pkg<'_>> for Restrict {
+    fn matches(&self, pkg: &pkg::Pkg) -> bool {
+        match self {
+            // boolean
+            Self::True => true,
+            Self::False => false,
+
+            // boolean combinations
+            Self::And(vals) => vals.iter().all(|r| r.matches(pkg)),
+            Self::Or(vals) => vals.iter().any(|r| r.matches(pkg)),
+
+            // slots are package attributes that unversioned atoms lack
+            Self::Atom(AtomAttr::Slot(r)) => r.matches(pkg.slot()),
+            Self::Atom(AtomAttr::SubSlot(r)) => r.matches(pkg.subslot()),
+
+            // object attributes
+            Self::Atom(r) => r.matches(pkg.atom()),
+            Self::Pkg(r) => r.matches(pkg),
+
+            _ => {
+                warn!("invalid restriction for pkg matches: {self:?}");
+                false
+            }
+        }
+    }
+}
+
 impl Restriction<&str> for Restrict {
     fn matches(&self, s: &str) -> bool {
         match self {
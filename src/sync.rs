@@ -1,26 +1,35 @@
 use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use async_trait::async_trait;
 use serde_with::{DeserializeFromStr, SerializeDisplay};
 
+use crate::repo::Repo;
 use crate::{Error, Result};
 
 #[cfg(feature = "git")]
 mod git;
+mod hook;
 mod local;
+mod rsync;
 #[cfg(feature = "https")]
 mod tar;
+#[cfg(any(feature = "git", feature = "https"))]
+mod verify;
+
+pub(crate) use hook::HookContext;
+pub use hook::{Hook, SyncStatus};
 
 #[derive(Debug, Clone, PartialEq, Eq, DeserializeFromStr, SerializeDisplay)]
 pub(crate) enum Syncer {
     #[cfg(feature = "git")]
     Git(git::Repo),
     Local(local::Repo),
+    Rsync(rsync::Repo),
     #[cfg(feature = "https")]
-    TarHttps(tar::Repo),
+    Tar(tar::Repo),
 }
 
 impl fmt::Display for Syncer {
@@ -29,20 +38,196 @@ impl fmt::Display for Syncer {
             #[cfg(feature = "git")]
             Syncer::Git(repo) => write!(f, "{}", repo.uri),
             #[cfg(feature = "https")]
-            Syncer::TarHttps(repo) => write!(f, "{}", repo.uri),
+            Syncer::Tar(repo) => write!(f, "{}", repo.uri),
+            Syncer::Rsync(repo) => write!(f, "{}", repo.uri),
             Syncer::Local(_) => write!(f, "\"\""),
         }
     }
 }
 
+/// Repo sync phases reported via progress updates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPhase {
+    Started,
+    /// Downloading data, e.g. git objects or a tarball.
+    Fetching,
+    /// Resolving git deltas.
+    Resolving,
+    Unpacking,
+    Merging,
+    Finished,
+    Failed,
+}
+
+/// Progress update for a syncing repo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncProgress {
+    repo: String,
+    phase: SyncPhase,
+    bytes: u64,
+    objects: usize,
+    total_objects: usize,
+}
+
+impl SyncProgress {
+    /// Return the id of the syncing repo.
+    pub fn repo(&self) -> &str {
+        &self.repo
+    }
+
+    pub fn phase(&self) -> SyncPhase {
+        self.phase
+    }
+
+    /// Return the number of bytes received so far.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Return the number of objects processed in the current phase, e.g. git objects or deltas.
+    pub fn objects(&self) -> usize {
+        self.objects
+    }
+
+    /// Return the total number of objects for the current phase if known, otherwise 0.
+    pub fn total_objects(&self) -> usize {
+        self.total_objects
+    }
+}
+
+/// Callback receiving progress updates for syncing repos.
+pub type ProgressFn<'a> = &'a (dyn Fn(&SyncProgress) + Send + Sync);
+
+/// Progress reporter for a single repo forwarding updates to a callback.
+#[derive(Clone, Copy)]
+pub(crate) struct Progress<'a> {
+    repo: &'a str,
+    callback: ProgressFn<'a>,
+}
+
+impl<'a> Progress<'a> {
+    pub(crate) fn new(repo: &'a str, callback: ProgressFn<'a>) -> Self {
+        Progress { repo, callback }
+    }
+
+    pub(crate) fn phase(&self, phase: SyncPhase) {
+        self.update(phase, 0, 0, 0);
+    }
+
+    pub(crate) fn update(&self, phase: SyncPhase, bytes: u64, objects: usize, total: usize) {
+        (self.callback)(&SyncProgress {
+            repo: self.repo.to_string(),
+            phase,
+            bytes,
+            objects,
+            total_objects: total,
+        });
+    }
+}
+
 #[async_trait]
 pub(self) trait Syncable {
     fn uri_to_syncer(uri: &str) -> Result<Syncer>;
-    async fn sync<P: AsRef<Path> + Send>(&self, path: P) -> Result<()>;
+
+    /// Sync the repo at a given path into an empty staging directory, returning false if the
+    /// repo is already up to date and the staging directory is unused.
+    ///
+    /// When a keyring is given, synced content must be signed by one of its keys. Syncers may
+    /// store state such as HTTP ETags in the cache directory if one is given.
+    async fn sync(
+        &self,
+        path: &Path,
+        staging: &Path,
+        keyring: Option<&Path>,
+        cache: Option<&Path>,
+        progress: &Progress,
+    ) -> Result<bool>;
+}
+
+/// Return the path of a hidden sibling directory used when syncing a repo.
+fn sibling(path: &Path, kind: &str) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.{kind}"))
+}
+
+fn remove_dir(path: &Path) -> Result<()> {
+    match path.exists() {
+        true => fs::remove_dir_all(path)
+            .map_err(|e| Error::RepoSync(format!("failed removing {path:?}: {e}"))),
+        false => Ok(()),
+    }
+}
+
+/// Atomically exchange two existing paths, falling back to sequential renames on systems or
+/// filesystems lacking support.
+fn exchange(a: &Path, b: &Path) -> Result<()> {
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    {
+        use nix::errno::Errno;
+        use nix::fcntl::{renameat2, RenameFlags};
+        match renameat2(None, a, None, b, RenameFlags::RENAME_EXCHANGE) {
+            Ok(_) => return Ok(()),
+            Err(Errno::EINVAL | Errno::ENOSYS) => (),
+            Err(e) => return Err(Error::RepoSync(format!("failed swapping {a:?} <-> {b:?}: {e}"))),
+        }
+    }
+
+    let tmp = sibling(a, "swap");
+    fs::rename(a, &tmp)
+        .and_then(|_| fs::rename(b, a))
+        .and_then(|_| fs::rename(&tmp, b))
+        .map_err(|e| Error::RepoSync(format!("failed swapping {a:?} <-> {b:?}: {e}")))
+}
+
+/// Validate a staged repo of a given format, or any supported format if empty, and swap it
+/// into place, keeping the replaced repo for rollback.
+fn activate(path: &Path, staging: &Path, format: &str) -> Result<()> {
+    let id = path.file_name().unwrap_or_default().to_string_lossy();
+    let result = match format {
+        "" => Repo::from_path(id.as_ref(), staging).map(|_| ()),
+        _ => Repo::from_format(id.as_ref(), staging, format).map(|_| ()),
+    };
+    if let Err(e) = result {
+        remove_dir(staging)?;
+        return Err(Error::RepoSync(format!("invalid synced repo: {e}")));
+    }
+
+    if !path.exists() {
+        return fs::rename(staging, path)
+            .map_err(|e| Error::RepoSync(format!("failed moving {staging:?} -> {path:?}: {e}")));
+    }
+
+    let prev = sibling(path, "prev");
+    exchange(staging, path)?;
+    remove_dir(&prev)?;
+    fs::rename(staging, &prev)
+        .map_err(|e| Error::RepoSync(format!("failed moving {staging:?} -> {prev:?}: {e}")))
+}
+
+/// Restore the repo replaced by the last sync, swapping it with the current one so the
+/// rollback itself can be undone.
+pub(crate) fn rollback(path: &Path) -> Result<()> {
+    let prev = sibling(path, "prev");
+    match (prev.exists(), path.exists()) {
+        (false, _) => Err(Error::RepoSync(format!("no previous repo to restore: {path:?}"))),
+        (true, true) => exchange(&prev, path),
+        (true, false) => fs::rename(&prev, path)
+            .map_err(|e| Error::RepoSync(format!("failed moving {prev:?} -> {path:?}: {e}"))),
+    }
 }
 
 impl Syncer {
-    pub(crate) fn sync<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+    /// Sync a repo of a given format, an empty format allowing any supported format, optionally
+    /// verifying its signature against a keyring. Returns false if the repo was already up to
+    /// date.
+    pub(crate) fn sync<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: &str,
+        keyring: Option<&Path>,
+        cache: Option<&Path>,
+        progress: &Progress,
+    ) -> Result<bool> {
         let path = path.as_ref();
 
         // make sure repos dir exists
@@ -53,12 +238,34 @@ impl Syncer {
             })?;
         }
 
-        match self {
+        // repos are synced into a staging dir and only replace the existing repo on success
+        let staging = sibling(path, "staging");
+        remove_dir(&staging)?;
+        let result = match self {
             #[cfg(feature = "git")]
-            Syncer::Git(repo) => futures::executor::block_on(repo.sync(path)),
+            Syncer::Git(repo) => {
+                futures::executor::block_on(repo.sync(path, &staging, keyring, cache, progress))
+            }
             #[cfg(feature = "https")]
-            Syncer::TarHttps(repo) => futures::executor::block_on(repo.sync(path)),
-            Syncer::Local(_) => Ok(()),
+            Syncer::Tar(repo) => {
+                futures::executor::block_on(repo.sync(path, &staging, keyring, cache, progress))
+            }
+            Syncer::Rsync(repo) => {
+                futures::executor::block_on(repo.sync(path, &staging, keyring, cache, progress))
+            }
+            Syncer::Local(repo) => {
+                futures::executor::block_on(repo.sync(path, &staging, keyring, cache, progress))
+            }
+        };
+
+        match result {
+            Ok(true) => activate(path, &staging, format).map(|_| true),
+            Ok(false) => remove_dir(&staging).map(|_| false),
+            Err(e) => {
+                // failed syncs leave the existing repo untouched
+                remove_dir(&staging).ok();
+                Err(e)
+            }
         }
     }
 }
@@ -73,6 +280,7 @@ impl FromStr for Syncer {
             git::Repo::uri_to_syncer,
             #[cfg(feature = "https")]
             tar::Repo::uri_to_syncer,
+            rsync::Repo::uri_to_syncer,
             local::Repo::uri_to_syncer,
         ];
 
@@ -90,3 +298,92 @@ impl FromStr for Syncer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::macros::assert_err_re;
+    use crate::repo::ebuild::TempRepo;
+    use crate::repo::Repository;
+
+    use super::*;
+
+    /// Create a repo with a given package at a path.
+    fn create_repo(dir: &Path, path: &Path, cpv: &str) {
+        let t = TempRepo::new("repo", Some(dir), None).unwrap();
+        t.create_ebuild(cpv, None).unwrap();
+        t.persist(Some(path)).unwrap();
+    }
+
+    fn pkgs(path: &Path) -> Vec<String> {
+        let (_, repo) = Repo::from_path("repo", path).unwrap();
+        repo.categories()
+            .iter()
+            .flat_map(|c| repo.packages(c))
+            .collect()
+    }
+
+    #[test]
+    fn test_activate() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("repo");
+        let (staging, prev) = (sibling(&path, "staging"), sibling(&path, "prev"));
+        assert_eq!(staging, dir.path().join(".repo.staging"));
+
+        // invalid repos are rejected without replacing the existing repo
+        fs::create_dir(&staging).unwrap();
+        let r = activate(&path, &staging, "ebuild");
+        assert_err_re!(r, "^failed syncing repo: invalid synced repo: ");
+        assert!(!staging.exists());
+        assert!(!path.exists());
+        assert_err_re!(rollback(&path), "no previous repo to restore: ");
+
+        // initial sync
+        create_repo(dir.path(), &staging, "cat/a-1");
+        activate(&path, &staging, "").unwrap();
+        assert_eq!(pkgs(&path), ["a"]);
+        assert!(!staging.exists());
+        assert!(!prev.exists());
+
+        // updates keep the previous repo
+        create_repo(dir.path(), &staging, "cat/b-1");
+        activate(&path, &staging, "ebuild").unwrap();
+        assert_eq!(pkgs(&path), ["b"]);
+        assert_eq!(pkgs(&prev), ["a"]);
+        create_repo(dir.path(), &staging, "cat/c-1");
+        activate(&path, &staging, "ebuild").unwrap();
+        assert_eq!(pkgs(&path), ["c"]);
+        assert_eq!(pkgs(&prev), ["b"]);
+
+        // rollbacks swap the current and previous repos
+        rollback(&path).unwrap();
+        assert_eq!(pkgs(&path), ["b"]);
+        assert_eq!(pkgs(&prev), ["c"]);
+        rollback(&path).unwrap();
+        assert_eq!(pkgs(&path), ["c"]);
+
+        // restoring a removed repo
+        fs::remove_dir_all(&path).unwrap();
+        rollback(&path).unwrap();
+        assert_eq!(pkgs(&path), ["b"]);
+        assert!(!prev.exists());
+    }
+
+    #[test]
+    fn test_sync() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("repo");
+        create_repo(dir.path(), &path, "cat/a-1");
+        let staging = sibling(&path, "staging");
+        fs::create_dir(&staging).unwrap();
+
+        // leftover staging dirs from interrupted syncs are removed
+        let syncer = Syncer::from_str(path.to_str().unwrap()).unwrap();
+        syncer
+            .sync(&path, "ebuild", None, None, &Progress::new("repo", &|_| ()))
+            .unwrap();
+        assert!(!staging.exists());
+        assert_eq!(pkgs(&path), ["a"]);
+    }
+}
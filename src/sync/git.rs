@@ -1,86 +1,433 @@
-use std::io::{self, Write};
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
 use std::path::Path;
+use std::process::{Command, Stdio};
 
 use async_trait::async_trait;
+use filetime::{set_file_mtime, FileTime};
 use once_cell::sync::Lazy;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use tracing::debug;
+use walkdir::WalkDir;
 
-use crate::sync::{Syncable, Syncer};
+use crate::command::RunCommand;
+use crate::sync::verify::verify;
+use crate::sync::{Progress, SyncPhase, Syncable, Syncer};
 use crate::Error;
 
-static HANDLED_URI_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(https|git)://.+\.git$").unwrap());
+static HANDLED_URI_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?P<url>(https|git)://[^#]+\.git|file:///[^#]*|/[^#]*)(#(?P<opts>.+))?$").unwrap()
+});
+
+/// Git ref a repo is pinned to, otherwise the remote's default branch is followed.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) enum Ref {
+    Branch(String),
+    Tag(String),
+    Commit(String),
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub(crate) struct Repo {
     pub(crate) uri: String,
+    url: String,
+    reference: Option<Ref>,
+    depth: Option<u32>,
 }
 
 #[async_trait]
 impl Syncable for Repo {
+    /// Create a syncer from a remote URL with optional `&` separated options appended as a
+    /// fragment, e.g. `https://example.com/repo.git#tag=v1&depth=1`. Supported options are
+    /// `branch`, `tag`, or `commit` to pin a ref and `depth` for shallow clones.
     fn uri_to_syncer(uri: &str) -> crate::Result<Syncer> {
-        match HANDLED_URI_RE.is_match(uri) {
-            true => Ok(Syncer::Git(Repo {
-                uri: uri.to_string(),
-            })),
-            false => Err(Error::RepoInit(format!("invalid git repo: {uri:?}"))),
-        }
-    }
-
-    async fn sync<P: AsRef<Path> + Send>(&self, path: P) -> crate::Result<()> {
-        let path = path.as_ref();
-        if path.exists() {
-            let repo = git2::Repository::open(&path).map_err(|e| {
-                Error::RepoSync(format!("failed initializing git repo: {}", e.message()))
-            })?;
-            let head = repo.head().map_err(|e| {
-                Error::RepoSync(format!("failed getting git HEAD: {}", e.message()))
-            })?;
-            let branch = head
-                .shorthand()
-                .ok_or_else(|| Error::RepoSync("not on a git branch".to_string()))?;
-            let mut remote = repo
-                .find_remote("origin")
-                .map_err(|e| Error::RepoSync(format!("invalid remote origin: {}", e.message())))?;
-            let fetch_commit = do_fetch(&repo, &[branch], &mut remote)
-                .map_err(|e| Error::RepoSync(format!("failed fetching: {}", e.message())))?;
-            do_merge(&repo, branch, fetch_commit)
-                .map_err(|e| Error::RepoSync(format!("failed merging: {}", e.message())))?;
+        let err = |msg: &str| Error::RepoInit(format!("invalid git repo: {uri:?}{msg}"));
+        let m = HANDLED_URI_RE.captures(uri).ok_or_else(|| err(""))?;
+        let url = &m["url"];
+        // plain paths must be git repos so they don't shadow local syncers
+        if url.starts_with('/') && git2::Repository::open(url).is_err() {
+            return Err(err(""));
+        }
+
+        let (mut reference, mut depth) = (None, None);
+        for opt in m
+            .name("opts")
+            .map_or("", |m| m.as_str())
+            .split_terminator('&')
+        {
+            let (key, val) = opt
+                .split_once('=')
+                .filter(|(_, v)| !v.is_empty())
+                .ok_or_else(|| err(&format!(": invalid option: {opt}")))?;
+            let pinned = match key {
+                "branch" => Ref::Branch(val.to_string()),
+                "tag" => Ref::Tag(val.to_string()),
+                "commit" => Ref::Commit(val.to_string()),
+                "depth" => {
+                    let val = val.parse().ok().filter(|d| *d > 0);
+                    depth = Some(val.ok_or_else(|| err(&format!(": invalid depth: {opt}")))?);
+                    continue;
+                }
+                _ => return Err(err(&format!(": unknown option: {key}"))),
+            };
+            if reference.replace(pinned).is_some() {
+                return Err(err(": multiple refs specified"));
+            }
+        }
+
+        Ok(Syncer::Git(Repo {
+            uri: uri.to_string(),
+            url: url.to_string(),
+            reference,
+            depth,
+        }))
+    }
+
+    async fn sync(
+        &self,
+        path: &Path,
+        staging: &Path,
+        keyring: Option<&Path>,
+        _cache: Option<&Path>,
+        progress: &Progress,
+    ) -> crate::Result<bool> {
+        if !path.exists() {
+            match self.depth {
+                Some(depth) => self.clone_shallow(staging, depth, *progress)?,
+                None => self.clone(staging, *progress).map_err(|e| {
+                    Error::RepoSync(format!("failed cloning git repo: {}", e.message()))
+                })?,
+            }
         } else {
-            do_clone(&self.uri, path).map_err(|e| {
-                Error::RepoSync(format!("failed cloning git repo: {}", e.message()))
-            })?;
+            // fetch into the existing checkout, leaving its working tree untouched
+            let target = match self.depth {
+                Some(depth) => self.fetch_shallow(path, depth, *progress)?,
+                None => self.fetch(path, *progress)?,
+            };
+            if self.is_current(&open(path)?, target) {
+                return Ok(false);
+            }
+
+            // update a new checkout sharing the existing objects so failures leave the repo
+            // untouched
+            stage(path, staging)?;
+            progress.phase(SyncPhase::Merging);
+            self.checkout(staging, target)?;
+        }
+
+        if let Some(keyring) = keyring {
+            verify_head(staging, keyring)?;
         }
-        Ok(())
+
+        Ok(true)
     }
 }
 
-fn do_clone<P: AsRef<Path>>(url: &str, path: P) -> Result<git2::Repository, git2::Error> {
-    let path = path.as_ref();
-    let mut cb = git2::RemoteCallbacks::new();
+fn open(path: &Path) -> crate::Result<git2::Repository> {
+    git2::Repository::open(path)
+        .map_err(|e| Error::RepoSync(format!("failed initializing git repo: {}", e.message())))
+}
+
+/// Verify the OpenPGP signature of the commit HEAD points to.
+fn verify_head(path: &Path, keyring: &Path) -> crate::Result<()> {
+    let repo = open(path)?;
+    let head = repo
+        .head()
+        .ok()
+        .and_then(|r| r.target())
+        .ok_or_else(|| Error::RepoSync("failed getting git HEAD".to_string()))?;
+    let (signature, data) = repo
+        .extract_signature(&head, None)
+        .map_err(|_| Error::RepoSync(format!("unsigned commit: {head}")))?;
+    verify(keyring, &signature, &data)
+}
+
+/// Run a git command within a given repo.
+fn git(path: &Path, args: &[&str]) -> crate::Result<()> {
+    let mut cmd = Command::new("git");
+    cmd.arg("-C").arg(path).args(args);
+    cmd.stdout(Stdio::null()).stderr(Stdio::null());
+    cmd.run().map_err(|e| Error::RepoSync(e.to_string()))
+}
+
+impl Repo {
+    fn clone(&self, path: &Path, progress: Progress) -> Result<(), git2::Error> {
+        let branch = match &self.reference {
+            Some(Ref::Branch(branch)) => Some(branch.as_str()),
+            _ => None,
+        };
+        let repo = do_clone(&self.url, branch, path, progress)?;
+        match self.fetch_pinned(&repo, progress)? {
+            Some(id) => {
+                progress.phase(SyncPhase::Merging);
+                detach(&repo, id)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Return the local branch an existing checkout follows.
+    fn branch(&self, repo: &git2::Repository) -> crate::Result<String> {
+        match &self.reference {
+            Some(Ref::Branch(branch)) => Ok(branch.clone()),
+            _ => {
+                let head = repo.head().map_err(|e| {
+                    Error::RepoSync(format!("failed getting git HEAD: {}", e.message()))
+                })?;
+                match (head.is_branch(), head.shorthand()) {
+                    (true, Some(branch)) => Ok(branch.to_string()),
+                    _ => Err(Error::RepoSync("not on a git branch".to_string())),
+                }
+            }
+        }
+    }
+
+    /// Fetch the configured ref into an existing checkout, returning the commit to update to.
+    fn fetch(&self, path: &Path, progress: Progress) -> crate::Result<git2::Oid> {
+        let repo = open(path)?;
+        let err = |e: git2::Error| Error::RepoSync(format!("failed fetching: {}", e.message()));
+        // existing checkouts track the configured remote in case it changed
+        repo.remote_set_url("origin", &self.url)
+            .map_err(|e| Error::RepoSync(format!("invalid remote origin: {}", e.message())))?;
+
+        if let Some(id) = self.fetch_pinned(&repo, progress).map_err(err)? {
+            return Ok(id);
+        }
+
+        let branch = self.branch(&repo)?;
+        let mut remote = repo
+            .find_remote("origin")
+            .map_err(|e| Error::RepoSync(format!("invalid remote origin: {}", e.message())))?;
+        do_fetch(&repo, &[&branch], &mut remote, progress)
+            .map(|c| c.id())
+            .map_err(err)
+    }
+
+    /// Fetch the tag or commit the repo is pinned to as needed, returning its commit id.
+    fn fetch_pinned(
+        &self,
+        repo: &git2::Repository,
+        progress: Progress,
+    ) -> Result<Option<git2::Oid>, git2::Error> {
+        let mut remote = repo.find_remote("origin")?;
+        let rev = match &self.reference {
+            Some(Ref::Tag(tag)) => {
+                // tags are always refetched in case they were moved
+                let refspec = format!("+refs/tags/{tag}:refs/tags/{tag}");
+                do_fetch(repo, &[&refspec], &mut remote, progress)?;
+                format!("refs/tags/{tag}")
+            }
+            Some(Ref::Commit(commit)) => {
+                if repo.revparse_single(commit).is_err() {
+                    do_fetch(repo, &[], &mut remote, progress)?;
+                }
+                commit.clone()
+            }
+            _ => return Ok(None),
+        };
+
+        let commit = repo.revparse_single(&rev)?.peel_to_commit()?;
+        debug!("resolved {rev}: {}", commit.id());
+        Ok(Some(commit.id()))
+    }
+
+    /// Determine if an existing checkout is already at a fetched commit.
+    fn is_current(&self, repo: &git2::Repository, target: git2::Oid) -> bool {
+        let head = match repo.head() {
+            Ok(head) => head,
+            Err(_) => return false,
+        };
+        let id = match head.target() {
+            Some(id) => id,
+            None => return false,
+        };
 
-    // show transfer progress
-    cb.transfer_progress(|stats| {
+        match (&self.reference, self.depth) {
+            // pinned and shallow checkouts are detached at the fetched commit
+            (Some(Ref::Tag(_) | Ref::Commit(_)), _) | (_, Some(_)) => id == target,
+            (Some(Ref::Branch(branch)), _)
+                if !head.is_branch() || head.shorthand() != Some(branch) =>
+            {
+                false
+            }
+            // branches with local commits are current if they contain the fetched commit
+            _ => id == target || repo.graph_descendant_of(id, target).unwrap_or_default(),
+        }
+    }
+
+    /// Update a staged checkout to a fetched commit.
+    fn checkout(&self, path: &Path, target: git2::Oid) -> crate::Result<()> {
+        if self.depth.is_some() {
+            let target = target.to_string();
+            return git(path, &["checkout", "--quiet", "--force", "--detach", &target]);
+        }
+
+        let repo = open(path)?;
+        if let Some(Ref::Tag(_) | Ref::Commit(_)) = &self.reference {
+            return detach(&repo, target)
+                .map_err(|e| Error::RepoSync(format!("failed checking out: {}", e.message())));
+        }
+
+        let branch = self.branch(&repo)?;
+        switch_branch(&repo, &branch)
+            .map_err(|e| Error::RepoSync(format!("failed switching branches: {}", e.message())))?;
+        let err = |e: git2::Error| Error::RepoSync(format!("failed merging: {}", e.message()));
+        let fetch_commit = repo.find_annotated_commit(target).map_err(err)?;
+        do_merge(&repo, &branch, fetch_commit).map_err(err)
+    }
+
+    /// Fetch the configured ref with a limited history depth into a new repo and check it out.
+    ///
+    /// This uses the git command as libgit2 doesn't support shallow clones.
+    fn clone_shallow(&self, path: &Path, depth: u32, progress: Progress) -> crate::Result<()> {
+        fs::create_dir_all(path)
+            .map_err(|e| Error::RepoSync(format!("failed creating {path:?}: {e}")))?;
+        git(path, &["init", "--quiet"])?;
+        git(path, &["remote", "add", "origin", &self.shallow_url()])?;
+        self.do_fetch_shallow(path, depth, progress)?;
+        progress.phase(SyncPhase::Merging);
+        git(path, &["checkout", "--quiet", "--force", "--detach", "FETCH_HEAD"])
+    }
+
+    /// Fetch the configured ref with a limited history depth into an existing checkout,
+    /// returning the fetched commit.
+    fn fetch_shallow(
+        &self,
+        path: &Path,
+        depth: u32,
+        progress: Progress,
+    ) -> crate::Result<git2::Oid> {
+        git(path, &["remote", "set-url", "origin", &self.shallow_url()])?;
+        self.do_fetch_shallow(path, depth, progress)?;
+        fetch_head(&open(path)?)
+            .map_err(|e| Error::RepoSync(format!("failed fetching: {}", e.message())))
+    }
+
+    /// Return the remote URL used for shallow fetches since git ignores the depth for local
+    /// paths.
+    fn shallow_url(&self) -> String {
+        match self.url.starts_with('/') {
+            true => format!("file://{}", self.url),
+            false => self.url.clone(),
+        }
+    }
+
+    fn do_fetch_shallow(&self, path: &Path, depth: u32, progress: Progress) -> crate::Result<()> {
+        let rev = match &self.reference {
+            None => "HEAD".to_string(),
+            Some(Ref::Branch(branch)) => format!("refs/heads/{branch}"),
+            Some(Ref::Tag(tag)) => format!("refs/tags/{tag}"),
+            Some(Ref::Commit(commit)) => commit.clone(),
+        };
+        progress.phase(SyncPhase::Fetching);
+        git(path, &["fetch", "--quiet", "--no-tags", &format!("--depth={depth}"), "origin", &rev])
+    }
+}
+
+/// Point HEAD directly at a commit and check it out.
+fn detach(repo: &git2::Repository, id: git2::Oid) -> Result<(), git2::Error> {
+    debug!("checking out {id}");
+    repo.set_head_detached(id)?;
+    repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))
+}
+
+/// Point HEAD at a local branch if it isn't already, leaving it unborn when the branch
+/// doesn't exist so the next merge creates it.
+fn switch_branch(repo: &git2::Repository, branch: &str) -> Result<(), git2::Error> {
+    let refname = format!("refs/heads/{branch}");
+    let head = repo
+        .head()
+        .ok()
+        .and_then(|h| h.name().map(|s| s.to_string()));
+    if head.as_deref() != Some(refname.as_str()) {
+        repo.set_head(&refname)?;
+        if repo.find_reference(&refname).is_ok() {
+            repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Create a new checkout of an existing repo's current commit in a staging directory.
+///
+/// Only the git directory is copied, the working tree is checked out from its index. Staged
+/// checkouts replace the existing repo so its objects can't be shared via worktrees or
+/// alternates that would refer to the rollback snapshot afterwards.
+fn stage(path: &Path, staging: &Path) -> crate::Result<()> {
+    copy_git_dir(&path.join(".git"), &staging.join(".git"))
+        .map_err(|e| Error::RepoSync(format!("failed copying repo {path:?}: {e}")))?;
+    let repo = open(staging)?;
+    repo.checkout_index(None, Some(git2::build::CheckoutBuilder::default().force()))
+        .map_err(|e| Error::RepoSync(format!("failed checking out: {}", e.message())))
+}
+
+/// Recursively copy a git directory, preserving symlinks and modification times.
+///
+/// Git objects are never modified once written so they're hard linked when possible.
+fn copy_git_dir(src: &Path, dest: &Path) -> io::Result<()> {
+    let objects = src.join("objects");
+    let mut dirs = vec![];
+    for entry in WalkDir::new(src) {
+        let entry = entry?;
+        let target = dest.join(entry.path().strip_prefix(src).unwrap());
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            fs::create_dir_all(&target)?;
+            dirs.push((target, entry.metadata()?));
+            continue;
+        } else if file_type.is_symlink() {
+            symlink(fs::read_link(entry.path())?, &target)?;
+            continue;
+        } else if entry.path().starts_with(&objects) && fs::hard_link(entry.path(), &target).is_ok()
+        {
+            continue;
+        }
+        fs::copy(entry.path(), &target)?;
+        let meta = entry.metadata()?;
+        set_file_mtime(&target, FileTime::from_last_modification_time(&meta))?;
+    }
+
+    // directory times are set last since adding their entries modifies them
+    for (dir, meta) in dirs.iter().rev() {
+        set_file_mtime(dir, FileTime::from_last_modification_time(meta))?;
+    }
+    Ok(())
+}
+
+/// Forward git transfer progress to a sync progress reporter.
+fn transfer_callbacks(progress: Progress) -> git2::RemoteCallbacks {
+    let mut cb = git2::RemoteCallbacks::new();
+    cb.transfer_progress(move |stats| {
+        let bytes = stats.received_bytes() as u64;
         if stats.received_objects() == stats.total_objects() {
-            print!("Resolving deltas {}/{}\r", stats.indexed_deltas(), stats.total_deltas());
+            let (deltas, total) = (stats.indexed_deltas(), stats.total_deltas());
+            progress.update(SyncPhase::Resolving, bytes, deltas, total);
         } else if stats.total_objects() > 0 {
-            print!(
-                "Received {}/{} objects ({}) in {} bytes\r",
-                stats.received_objects(),
-                stats.total_objects(),
-                stats.indexed_objects(),
-                stats.received_bytes()
-            );
-        }
-        io::stdout().flush().unwrap();
+            let (objects, total) = (stats.received_objects(), stats.total_objects());
+            progress.update(SyncPhase::Fetching, bytes, objects, total);
+        }
         true
     });
+    cb
+}
 
+fn do_clone<P: AsRef<Path>>(
+    url: &str,
+    branch: Option<&str>,
+    path: P,
+    progress: Progress,
+) -> Result<git2::Repository, git2::Error> {
+    let path = path.as_ref();
     let mut fo = git2::FetchOptions::new();
-    fo.remote_callbacks(cb);
+    fo.remote_callbacks(transfer_callbacks(progress));
 
     let mut builder = git2::build::RepoBuilder::new();
     builder.fetch_options(fo);
+    if let Some(branch) = branch {
+        builder.branch(branch);
+    }
     builder.clone(url, path)?;
 
     git2::Repository::open(path)
@@ -90,57 +437,56 @@ fn do_fetch<'a>(
     repo: &'a git2::Repository,
     refs: &[&str],
     remote: &'a mut git2::Remote,
+    progress: Progress,
 ) -> Result<git2::AnnotatedCommit<'a>, git2::Error> {
-    let mut cb = git2::RemoteCallbacks::new();
-
-    // show transfer progress
-    cb.transfer_progress(|stats| {
-        if stats.received_objects() == stats.total_objects() {
-            print!("Resolving deltas {}/{}\r", stats.indexed_deltas(), stats.total_deltas());
-        } else if stats.total_objects() > 0 {
-            print!(
-                "Received {}/{} objects ({}) in {} bytes\r",
-                stats.received_objects(),
-                stats.total_objects(),
-                stats.indexed_objects(),
-                stats.received_bytes()
-            );
-        }
-        io::stdout().flush().unwrap();
-        true
-    });
-
     let mut fo = git2::FetchOptions::new();
-    fo.remote_callbacks(cb);
+    fo.remote_callbacks(transfer_callbacks(progress));
     // Always fetch all tags.
     // Perform a download and also update tips
     fo.download_tags(git2::AutotagOption::All);
-    println!("Fetching {} for repo", remote.name().unwrap());
+    debug!("fetching {} for repo", remote.name().unwrap());
     remote.fetch(refs, Some(&mut fo), None)?;
 
     // If there are local objects (we got a thin pack), then tell the user
     // how many objects we saved from having to cross the network.
     let stats = remote.stats();
     if stats.local_objects() > 0 {
-        println!(
-            "\rReceived {}/{} objects in {} bytes (used {} local \
-             objects)",
+        debug!(
+            "received {}/{} objects in {} bytes (used {} local objects)",
             stats.indexed_objects(),
             stats.total_objects(),
             stats.received_bytes(),
             stats.local_objects()
         );
     } else {
-        println!(
-            "\rReceived {}/{} objects in {} bytes",
+        debug!(
+            "received {}/{} objects in {} bytes",
             stats.indexed_objects(),
             stats.total_objects(),
             stats.received_bytes()
         );
     }
 
-    let fetch_head = repo.find_reference("FETCH_HEAD")?;
-    repo.reference_to_annotated_commit(&fetch_head)
+    repo.find_annotated_commit(fetch_head(repo)?)
+}
+
+/// Return the commit fetched for merging.
+fn fetch_head(repo: &git2::Repository) -> Result<git2::Oid, git2::Error> {
+    // FETCH_HEAD can list fetched tags first so find the entry marked for merging
+    let mut merge_id = None;
+    repo.fetchhead_foreach(|_, _, id, is_merge| {
+        if is_merge && merge_id.is_none() {
+            merge_id = Some(*id);
+        }
+        true
+    })?;
+    match merge_id {
+        Some(id) => Ok(id),
+        None => repo
+            .find_reference("FETCH_HEAD")?
+            .peel_to_commit()
+            .map(|c| c.id()),
+    }
 }
 
 fn fast_forward(
@@ -153,7 +499,7 @@ fn fast_forward(
         None => String::from_utf8_lossy(lb.name_bytes()).to_string(),
     };
     let msg = format!("Fast-Forward: Setting {} to id: {}", name, rc.id());
-    println!("{}", msg);
+    debug!("{msg}");
     lb.set_target(rc.id(), &msg)?;
     repo.set_head(&name)?;
     repo.checkout_head(Some(
@@ -179,9 +525,14 @@ fn normal_merge(
     let mut idx = repo.merge_trees(&ancestor, &local_tree, &remote_tree, None)?;
 
     if idx.has_conflicts() {
-        println!("Merge conficts detected...");
-        repo.checkout_index(Some(&mut idx), None)?;
-        return Ok(());
+        let mut paths = vec![];
+        for conflict in idx.conflicts()? {
+            let conflict = conflict?;
+            if let Some(entry) = conflict.our.or(conflict.their).or(conflict.ancestor) {
+                paths.push(String::from_utf8_lossy(&entry.path).to_string());
+            }
+        }
+        return Err(git2::Error::from_str(&format!("conflicts in: {}", paths.join(", "))));
     }
     let result_tree = repo.find_tree(idx.write_tree_to(repo)?)?;
     // now create the merge commit
@@ -213,7 +564,7 @@ fn do_merge<'a>(
 
     // 2. Do the appopriate merge
     if analysis.0.is_fast_forward() {
-        println!("Doing a fast forward");
+        debug!("doing a fast forward");
         // do a fast forward
         let refname = format!("refs/heads/{}", remote_branch);
         match repo.find_reference(&refname) {
@@ -244,7 +595,276 @@ fn do_merge<'a>(
         let head_commit = repo.reference_to_annotated_commit(&repo.head()?)?;
         normal_merge(repo, &head_commit, &fetch_commit)?;
     } else {
-        println!("Nothing to do...");
+        debug!("nothing to do");
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::MetadataExt;
+    use std::str::FromStr;
+
+    use tempfile::TempDir;
+
+    use crate::command::{last_command, run_commands};
+    use crate::macros::assert_err_re;
+    use crate::repo::ebuild::TempRepo;
+    use crate::repo::index::git_head;
+    use crate::sync::verify::tests::Key;
+
+    use super::*;
+
+    /// Commit all files in a git repo on a given ref, returning the commit id.
+    fn commit(repo: &git2::Repository, refname: &str, parent: Option<&str>) -> String {
+        commit_signed(repo, refname, parent, None)
+    }
+
+    /// Commit all files in a git repo on a given ref, optionally signing the commit.
+    fn commit_signed(
+        repo: &git2::Repository,
+        refname: &str,
+        parent: Option<&str>,
+        key: Option<&Key>,
+    ) -> String {
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let parent = parent.map(|id| repo.revparse_single(id).unwrap().peel_to_commit().unwrap());
+        let parents: Vec<_> = parent.iter().collect();
+        let key = match key {
+            Some(key) => key,
+            None => {
+                return repo
+                    .commit(Some(refname), &sig, &sig, "commit", &tree, &parents)
+                    .unwrap()
+                    .to_string()
+            }
+        };
+
+        let buf = repo
+            .commit_create_buffer(&sig, &sig, "commit", &tree, &parents)
+            .unwrap();
+        let signature = key.sign(&buf);
+        let id = repo
+            .commit_signed(buf.as_str().unwrap(), std::str::from_utf8(&signature).unwrap(), None)
+            .unwrap();
+        let refname = match refname {
+            "HEAD" => {
+                let head = repo.find_reference("HEAD").unwrap();
+                head.symbolic_target().unwrap().to_string()
+            }
+            name => name.to_string(),
+        };
+        repo.reference(&refname, id, true, "commit").unwrap();
+        id.to_string()
+    }
+
+    fn sync(uri: &str, path: &Path) -> crate::Result<bool> {
+        let syncer = Syncer::from_str(uri).unwrap();
+        assert!(matches!(syncer, Syncer::Git(_)), "invalid syncer: {syncer:?}");
+        syncer.sync(path, "ebuild", None, None, &Progress::new("repo", &|_| ()))
+    }
+
+    #[test]
+    fn test_uri() {
+        let dir = TempDir::new().unwrap();
+        git2::Repository::init(dir.path()).unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        for (uri, url, reference, depth) in [
+            ("https://a.com/repo.git", "https://a.com/repo.git", None, None),
+            ("git://a.com/repo.git#depth=1", "git://a.com/repo.git", None, Some(1)),
+            ("file:///repo#branch=dev", "file:///repo", Some(Ref::Branch("dev".into())), None),
+            (&format!("{path}#tag=v1&depth=5"), path, Some(Ref::Tag("v1".into())), Some(5)),
+            (&format!("{path}#commit=abc"), path, Some(Ref::Commit("abc".into())), None),
+        ] {
+            match Syncer::from_str(uri) {
+                Ok(Syncer::Git(repo)) => {
+                    assert_eq!(repo.uri, uri);
+                    assert_eq!(repo.url, url);
+                    assert_eq!(repo.reference, reference);
+                    assert_eq!(repo.depth, depth);
+                }
+                r => panic!("{uri:?} failed: {r:?}"),
+            }
+        }
+
+        for (uri, err) in [
+            ("https://a.com/repo.git#depth=0", "invalid depth: depth=0$"),
+            ("https://a.com/repo.git#tag=v1&commit=abc", "multiple refs specified$"),
+            ("https://a.com/repo.git#tag=", "invalid option: tag=$"),
+            ("https://a.com/repo.git#foo=bar", "unknown option: foo$"),
+            ("https://a.com/repo", "invalid git repo: \"https://a.com/repo\"$"),
+        ] {
+            assert_err_re!(Repo::uri_to_syncer(uri), err);
+        }
+
+        // plain paths that aren't git repos are left to other syncers
+        let dir = TempDir::new().unwrap();
+        assert!(Repo::uri_to_syncer(dir.path().to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_sync() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("src");
+        let t = TempRepo::new("src", Some(dir.path()), None).unwrap();
+        t.create_ebuild("cat/a-1", None).unwrap();
+        t.persist(Some(&src)).unwrap();
+        let git = git2::Repository::init(&src).unwrap();
+        let c1 = commit(&git, "HEAD", None);
+        git.tag_lightweight("v1", &git.revparse_single(&c1).unwrap(), false)
+            .unwrap();
+        fs::write(src.join("cat/a/a-2.ebuild"), "").unwrap();
+        let c2 = commit(&git, "HEAD", Some(&c1));
+        let src = src.to_str().unwrap();
+
+        // follow the default branch
+        let path = dir.path().join("head");
+        sync(src, &path).unwrap();
+        assert_eq!(git_head(&path).unwrap(), c2);
+        // unchanged checkouts aren't staged
+        assert!(!sync(src, &path).unwrap());
+        assert!(!dir.path().join(".head.prev").exists());
+        fs::write(path.join("untracked"), "").unwrap();
+        fs::write(dir.path().join("src/cat/a/a-3.ebuild"), "").unwrap();
+        let c3 = commit(&git, "HEAD", Some(&c2));
+        assert!(sync(&format!("file://{src}"), &path).unwrap());
+        assert_eq!(git_head(&path).unwrap(), c3);
+        assert!(path.join("cat/a/a-3.ebuild").exists());
+        // the working tree is checked out instead of copied
+        assert!(!path.join("untracked").exists());
+        assert!(dir.path().join(".head.prev/untracked").exists());
+        // git objects are shared with the previous checkout
+        let object = WalkDir::new(path.join(".git/objects/pack"))
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .find(|e| e.file_type().is_file())
+            .unwrap();
+        assert!(object.metadata().unwrap().nlink() > 1);
+
+        // merge conflicts with local commits leave the existing checkout untouched
+        let local = git2::Repository::open(&path).unwrap();
+        fs::write(path.join("cat/a/a-1.ebuild"), "local").unwrap();
+        let local_commit = commit(&local, "HEAD", Some(&c3));
+        fs::write(dir.path().join("src/cat/a/a-1.ebuild"), "remote").unwrap();
+        commit(&git, "HEAD", Some(&c3));
+        let r = sync(src, &path);
+        assert_err_re!(r, "failed merging: conflicts in: cat/a/a-1.ebuild$");
+        assert_eq!(git_head(&path).unwrap(), local_commit);
+        assert_eq!(fs::read_to_string(path.join("cat/a/a-1.ebuild")).unwrap(), "local");
+        // reset the remote for the following syncs
+        git.reset(&git.revparse_single(&c3).unwrap(), git2::ResetType::Hard, None)
+            .unwrap();
+
+        // pinned tags and commits
+        let path = dir.path().join("pinned");
+        sync(&format!("{src}#tag=v1"), &path).unwrap();
+        assert_eq!(git_head(&path).unwrap(), c1);
+        assert!(!path.join("cat/a/a-2.ebuild").exists());
+        sync(&format!("{src}#commit={}", &c2[..12]), &path).unwrap();
+        assert_eq!(git_head(&path).unwrap(), c2);
+        assert_err_re!(sync(&format!("{src}#commit=1234567"), &path), "failed fetching: ");
+        assert_eq!(git_head(&path).unwrap(), c2);
+
+        // switching to a pinned branch
+        let c4 = commit(&git, "refs/heads/dev", Some(&c1));
+        sync(&format!("{src}#branch=dev"), &path).unwrap();
+        assert_eq!(git_head(&path).unwrap(), c4);
+        let head = fs::read_to_string(path.join(".git/HEAD")).unwrap();
+        assert_eq!(head.trim(), "ref: refs/heads/dev");
+
+        // shallow clones use the git command
+        let staging = dir.path().join("staging");
+        let repo = match Syncer::from_str(&format!("{src}#tag=v1&depth=1")).unwrap() {
+            Syncer::Git(repo) => repo,
+            s => panic!("invalid syncer: {s:?}"),
+        };
+        let progress = Progress::new("repo", &|_| ());
+        let missing = dir.path().join("missing");
+        futures::executor::block_on(repo.sync(&missing, &staging, None, None, &progress)).unwrap();
+        let staging_str = staging.to_str().unwrap();
+        assert_eq!(
+            last_command().unwrap(),
+            ["git", "-C", staging_str, "checkout", "--quiet", "--force", "--detach", "FETCH_HEAD"]
+        );
+        assert_eq!(
+            last_command().unwrap(),
+            [
+                "git",
+                "-C",
+                staging_str,
+                "fetch",
+                "--quiet",
+                "--no-tags",
+                "--depth=1",
+                "origin",
+                "refs/tags/v1"
+            ]
+        );
+        assert_eq!(
+            last_command().unwrap(),
+            ["git", "-C", staging_str, "remote", "add", "origin", &format!("file://{src}")]
+        );
+
+        let path = dir.path().join("shallow");
+        run_commands(|| {
+            if Command::new("git").arg("--version").output().is_ok() {
+                sync(&format!("{src}#depth=1"), &path).unwrap();
+                assert_eq!(git_head(&path).unwrap(), c3);
+                assert!(path.join(".git/shallow").exists());
+                // updating a shallow checkout
+                fs::write(dir.path().join("src/cat/a/a-4.ebuild"), "").unwrap();
+                let c5 = commit(&git, "HEAD", Some(&c3));
+                assert!(sync(&format!("{src}#depth=1"), &path).unwrap());
+                assert_eq!(git_head(&path).unwrap(), c5);
+                assert!(path.join("cat/a/a-4.ebuild").exists());
+            }
+        });
+    }
+
+    #[test]
+    fn test_verify() {
+        let key = Key::new();
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("src");
+        let t = TempRepo::new("src", Some(dir.path()), None).unwrap();
+        t.create_ebuild("cat/a-1", None).unwrap();
+        t.persist(Some(&src)).unwrap();
+        let git = git2::Repository::init(&src).unwrap();
+        let c1 = commit_signed(&git, "HEAD", None, Some(&key));
+
+        let path = dir.path().join("repo");
+        let sync = || {
+            let syncer = Syncer::from_str(src.to_str().unwrap()).unwrap();
+            let progress = Progress::new("repo", &|_| ());
+            syncer.sync(&path, "ebuild", Some(&key.keyring), None, &progress)
+        };
+        sync().unwrap();
+        assert_eq!(git_head(&path).unwrap(), c1);
+
+        // unsigned commits are rejected, leaving the existing repo untouched
+        fs::write(src.join("cat/a/a-2.ebuild"), "").unwrap();
+        let c2 = commit(&git, "HEAD", Some(&c1));
+        assert_err_re!(sync(), format!("unsigned commit: {c2}$"));
+        assert_eq!(git_head(&path).unwrap(), c1);
+        assert!(!path.join("cat/a/a-2.ebuild").exists());
+
+        // commits signed by unknown keys
+        let other = Key::new();
+        let c3 = commit_signed(&git, "HEAD", Some(&c2), Some(&other));
+        assert_err_re!(sync(), "failed verifying signature: ERRSIG ");
+        assert_eq!(git_head(&path).unwrap(), c1);
+
+        // signed commits are synced
+        let c4 = commit_signed(&git, "HEAD", Some(&c3), Some(&key));
+        sync().unwrap();
+        assert_eq!(git_head(&path).unwrap(), c4);
+        assert!(path.join("cat/a/a-2.ebuild").exists());
+    }
+}
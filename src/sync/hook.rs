@@ -0,0 +1,195 @@
+use std::fmt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+
+use crate::repo::Repo;
+use crate::{Error, Result};
+
+/// Action run after syncing a repo.
+///
+/// Built-in actions are specified by name while anything else is treated as an external command
+/// split on whitespace, e.g. `notify-send synced`. There's no search index action since
+/// [`Config::sync`](crate::config::Config::sync) always updates the index for synced repos.
+#[derive(Debug, Clone, PartialEq, Eq, DeserializeFromStr, SerializeDisplay)]
+pub enum Hook {
+    /// Regenerate missing or outdated metadata cache entries.
+    Regen,
+    /// Verify the files listed in the repo's Manifests.
+    Manifests,
+    /// External command run with the sync details in its environment.
+    Command(Vec<String>),
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Hook::Regen => write!(f, "regen"),
+            Hook::Manifests => write!(f, "manifests"),
+            Hook::Command(args) => write!(f, "{}", args.join(" ")),
+        }
+    }
+}
+
+impl FromStr for Hook {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "" => Err(Error::InvalidValue("empty hook".to_string())),
+            "regen" => Ok(Hook::Regen),
+            "manifests" => Ok(Hook::Manifests),
+            s => Ok(Hook::Command(s.split_whitespace().map(|s| s.to_string()).collect())),
+        }
+    }
+}
+
+/// Result of syncing a repo as passed to hooks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncStatus {
+    Updated,
+    Unchanged,
+    Failed,
+}
+
+impl SyncStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncStatus::Updated => "updated",
+            SyncStatus::Unchanged => "unchanged",
+            SyncStatus::Failed => "failed",
+        }
+    }
+}
+
+impl fmt::Display for SyncStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Details of a repo sync passed to hooks.
+pub(crate) struct HookContext<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) path: &'a Path,
+    /// Reloaded repo, unset for failed syncs.
+    pub(crate) repo: Option<&'a Repo>,
+    pub(crate) status: SyncStatus,
+    pub(crate) error: Option<String>,
+    pub(crate) commit: Option<String>,
+}
+
+impl Hook {
+    /// Run the hook for a synced repo.
+    ///
+    /// Built-in actions only apply to successfully synced ebuild repos while external commands
+    /// always run, getting the sync status via `PKGCRAFT_SYNC_RESULT`.
+    pub(crate) fn run(&self, ctx: &HookContext) -> Result<()> {
+        let repo = match ctx.repo {
+            Some(Repo::Ebuild(repo)) if ctx.status != SyncStatus::Failed => Some(repo),
+            _ => None,
+        };
+
+        match (self, repo) {
+            (Hook::Command(args), _) => run_command(args, ctx),
+            (_, None) => Ok(()),
+            (Hook::Regen, Some(repo)) => repo.regen_metadata().map(|_| ()),
+            (Hook::Manifests, Some(repo)) => repo.verify_manifests().map(|_| ()),
+        }
+    }
+}
+
+fn run_command(args: &[String], ctx: &HookContext) -> Result<()> {
+    let mut cmd = Command::new(&args[0]);
+    cmd.args(&args[1..])
+        .stdin(Stdio::null())
+        .env("PKGCRAFT_REPO", ctx.name)
+        .env("PKGCRAFT_REPO_PATH", ctx.path)
+        .env("PKGCRAFT_SYNC_RESULT", ctx.status.as_str());
+    if let Some(commit) = &ctx.commit {
+        cmd.env("PKGCRAFT_SYNC_COMMIT", commit);
+    }
+    if let Some(error) = &ctx.error {
+        cmd.env("PKGCRAFT_SYNC_ERROR", error);
+    }
+
+    match cmd.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(Error::IO(format!("{}: {status}", args[0]))),
+        Err(e) => Err(Error::IO(format!("failed running {}: {e}", args[0]))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use crate::macros::assert_err_re;
+    use crate::repo::ebuild::TempRepo;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        for (s, hook) in [
+            ("regen", Hook::Regen),
+            ("manifests", Hook::Manifests),
+            ("notify-send  synced ", Hook::Command(vec!["notify-send".into(), "synced".into()])),
+        ] {
+            assert_eq!(Hook::from_str(s).unwrap(), hook);
+        }
+        assert_eq!(Hook::from_str("a  b").unwrap().to_string(), "a b");
+        assert!(Hook::from_str(" ").is_err());
+    }
+
+    #[test]
+    fn test_run() {
+        let t = TempRepo::new("test", None::<&str>, None).unwrap();
+        t.create_ebuild("cat/pkg-1", None).unwrap();
+        let (_, repo) = Repo::from_path("test", t.repo.path()).unwrap();
+        let dir = TempDir::new().unwrap();
+        let env = dir.path().join("env");
+        let mut ctx = HookContext {
+            name: "test",
+            path: t.repo.path(),
+            repo: Some(&repo),
+            status: SyncStatus::Updated,
+            error: None,
+            commit: Some("abc".to_string()),
+        };
+
+        // external commands get the sync details via the environment
+        let hook = Hook::Command(vec!["sh".into(), "-c".into(), format!("env > {env:?}")]);
+        hook.run(&ctx).unwrap();
+        let data = fs::read_to_string(&env).unwrap();
+        let path = t.repo.path().to_str().unwrap();
+        for var in [
+            "PKGCRAFT_REPO=test".to_string(),
+            format!("PKGCRAFT_REPO_PATH={path}"),
+            "PKGCRAFT_SYNC_RESULT=updated".to_string(),
+            "PKGCRAFT_SYNC_COMMIT=abc".to_string(),
+        ] {
+            assert!(data.lines().any(|l| l == var), "missing {var:?}");
+        }
+        let hook = Hook::from_str("false").unwrap();
+        assert_err_re!(hook.run(&ctx), "^false: exit status: 1$");
+        let hook = Hook::from_str("nonexistent-hook-command").unwrap();
+        assert_err_re!(hook.run(&ctx), "failed running nonexistent-hook-command: ");
+
+        // built-in actions are skipped for failed syncs
+        fs::write(t.repo.path().join("Manifest"), "invalid").unwrap();
+        assert!(Hook::Manifests.run(&ctx).is_err());
+        ctx.status = SyncStatus::Failed;
+        ctx.error = Some("error".to_string());
+        Hook::Manifests.run(&ctx).unwrap();
+        let hook = Hook::Command(vec!["sh".into(), "-c".into(), format!("env > {env:?}")]);
+        hook.run(&ctx).unwrap();
+        let data = fs::read_to_string(&env).unwrap();
+        assert!(data.lines().any(|l| l == "PKGCRAFT_SYNC_RESULT=failed"));
+        assert!(data.lines().any(|l| l == "PKGCRAFT_SYNC_ERROR=error"));
+    }
+}
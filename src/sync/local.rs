@@ -3,7 +3,7 @@ use std::path::{Path, PathBuf};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
-use crate::sync::{Syncable, Syncer};
+use crate::sync::{Progress, Syncable, Syncer};
 use crate::{Error, Result};
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -23,7 +23,17 @@ impl Syncable for Repo {
         }
     }
 
-    async fn sync<P: AsRef<Path> + Send>(&self, _path: P) -> Result<()> {
-        Ok(())
+    async fn sync(
+        &self,
+        _path: &Path,
+        _staging: &Path,
+        keyring: Option<&Path>,
+        _cache: Option<&Path>,
+        _progress: &Progress,
+    ) -> Result<bool> {
+        match keyring {
+            Some(_) => Err(Error::RepoSync("local repos can't be verified".to_string())),
+            None => Ok(false),
+        }
     }
 }
@@ -0,0 +1,310 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::command::RunCommand;
+use crate::sync::{Progress, SyncPhase, Syncable, Syncer};
+use crate::{Error, Result};
+
+static HANDLED_URI_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(rsync://.+|rsync\+file://(?P<path>/.*))$").unwrap());
+
+static MONTHS: [&str; 12] =
+    ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Options matching Gentoo's default rsync sync settings.
+static RSYNC_OPTS: &[&str] = &[
+    "--recursive",
+    "--links",
+    "--safe-links",
+    "--perms",
+    "--times",
+    "--omit-dir-times",
+    "--compress",
+    "--force",
+    "--whole-file",
+    "--delete",
+    "--delete-delay",
+    "--quiet",
+    "--timeout=180",
+    "--exclude=/distfiles",
+    "--exclude=/local",
+    "--exclude=/packages",
+    "--exclude=/.git",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct Repo {
+    pub(crate) uri: String,
+    mirrors: Vec<String>,
+}
+
+#[async_trait]
+impl Syncable for Repo {
+    /// Create a syncer from a whitespace-separated list of mirrors tried in order.
+    fn uri_to_syncer(uri: &str) -> Result<Syncer> {
+        let mut mirrors = vec![];
+        for mirror in uri.split_whitespace() {
+            let m = HANDLED_URI_RE
+                .captures(mirror)
+                .ok_or_else(|| Error::RepoInit(format!("invalid rsync repo: {uri:?}")))?;
+            // local mirrors are passed to rsync as paths
+            let src = m.name("path").map_or(mirror, |p| p.as_str());
+            mirrors.push(format!("{}/", src.trim_end_matches('/')));
+        }
+
+        match mirrors.is_empty() {
+            true => Err(Error::RepoInit(format!("invalid rsync repo: {uri:?}"))),
+            false => Ok(Syncer::Rsync(Repo {
+                uri: uri.to_string(),
+                mirrors,
+            })),
+        }
+    }
+
+    async fn sync(
+        &self,
+        path: &Path,
+        staging: &Path,
+        keyring: Option<&Path>,
+        _cache: Option<&Path>,
+        progress: &Progress,
+    ) -> Result<bool> {
+        if keyring.is_some() {
+            return Err(Error::RepoSync("rsync repos can't be verified".to_string()));
+        }
+
+        let mut errors = vec![];
+        for mirror in &self.mirrors {
+            progress.phase(SyncPhase::Fetching);
+            fs::create_dir_all(staging)
+                .map_err(|e| Error::RepoSync(format!("failed creating {staging:?}: {e}")))?;
+
+            let result = rsync(mirror, path, staging).and_then(|_| check_timestamp(path, staging));
+            match result {
+                Ok(changed) => return Ok(changed),
+                Err(e) => {
+                    warn!("{mirror}: {e}");
+                    errors.push(format!("{mirror}: {e}"));
+                    // start from scratch for the next mirror
+                    fs::remove_dir_all(staging).map_err(|e| {
+                        Error::RepoSync(format!("failed removing {staging:?}: {e}"))
+                    })?;
+                }
+            }
+        }
+
+        Err(Error::RepoSync(format!("all mirrors failed:\n\t{}", errors.join("\n\t"))))
+    }
+}
+
+/// Run rsync for a mirror, copying unchanged files from the existing repo.
+///
+/// Unchanged files aren't hardlinked since the existing repo is kept as a rollback snapshot
+/// that would otherwise share inodes with the synced repo.
+fn rsync(mirror: &str, path: &Path, staging: &Path) -> Result<()> {
+    let mut cmd = Command::new("rsync");
+    cmd.args(RSYNC_OPTS);
+    if let Ok(path) = path.canonicalize() {
+        cmd.arg(format!("--copy-dest={}", path.to_string_lossy()));
+    }
+    cmd.arg(mirror).arg(staging);
+    cmd.stdout(Stdio::null()).stderr(Stdio::null());
+    cmd.run()
+}
+
+/// Return the time a repo was last updated according to its metadata/timestamp.chk file.
+fn timestamp(path: &Path) -> Result<Option<i64>> {
+    let path = path.join("metadata/timestamp.chk");
+    match fs::read_to_string(&path) {
+        Ok(s) => parse_timestamp(&s)
+            .map(Some)
+            .ok_or_else(|| Error::RepoSync(format!("invalid timestamp: {path:?}: {}", s.trim()))),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Reject synced repos older than the existing one as happens with stale mirrors, returning
+/// false if the repo is unchanged.
+fn check_timestamp(path: &Path, staging: &Path) -> Result<bool> {
+    match (timestamp(path)?, timestamp(staging)?) {
+        (Some(_), None) => Err(Error::RepoSync("missing timestamp".to_string())),
+        (Some(old), Some(new)) if new < old => Err(Error::RepoSync("stale mirror".to_string())),
+        (Some(old), Some(new)) => Ok(new > old),
+        _ => Ok(true),
+    }
+}
+
+/// Parse a timestamp in RFC 2822 format, e.g. `Sat, 01 Jan 2022 00:45:01 +0000`, into
+/// seconds since the epoch.
+fn parse_timestamp(s: &str) -> Option<i64> {
+    let s = s.trim();
+    // drop the optional weekday
+    let s = s.split_once(", ").map_or(s, |(_, s)| s);
+    let (day, month, year, time, offset) = match s.split_whitespace().collect::<Vec<_>>()[..] {
+        [day, month, year, time, offset] => (day, month, year, time, offset),
+        _ => return None,
+    };
+
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let (day, year): (i64, i64) = (day.parse().ok()?, year.parse().ok()?);
+    let time: Vec<i64> = time
+        .split(':')
+        .map(|s| s.parse().ok())
+        .collect::<Option<_>>()?;
+    let (hours, mins, secs) = match time[..] {
+        [h, m, s] => (h, m, s),
+        _ => return None,
+    };
+    let (sign, offset) = match (offset.strip_prefix('+'), offset.strip_prefix('-')) {
+        (Some(o), _) => (1, o),
+        (_, Some(o)) => (-1, o),
+        _ => return None,
+    };
+    let offset: i64 = offset.parse().ok().filter(|_| offset.len() == 4)?;
+    let offset = sign * (offset / 100 * 3600 + offset % 100 * 60);
+
+    // convert the civil date to days since the epoch
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let days = era * 146097 + yoe * 365 + yoe / 4 - yoe / 100 + doy - 719468;
+
+    Some(days * 86400 + hours * 3600 + mins * 60 + secs - offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use tempfile::TempDir;
+
+    use crate::command::{last_command, run_commands};
+    use crate::macros::assert_err_re;
+
+    use super::*;
+
+    fn syncer(uri: &str) -> Repo {
+        match Syncer::from_str(uri).unwrap() {
+            Syncer::Rsync(repo) => repo,
+            s => panic!("invalid syncer: {s:?}"),
+        }
+    }
+
+    #[test]
+    fn test_uri() {
+        let repo = syncer("rsync://rsync.gentoo.org/gentoo-portage");
+        assert_eq!(repo.mirrors, ["rsync://rsync.gentoo.org/gentoo-portage/"]);
+        let repo = syncer("rsync://a/gentoo-portage/ rsync+file:///mirror/gentoo");
+        assert_eq!(repo.uri, "rsync://a/gentoo-portage/ rsync+file:///mirror/gentoo");
+        assert_eq!(repo.mirrors, ["rsync://a/gentoo-portage/", "/mirror/gentoo/"]);
+
+        for uri in ["rsync://a/repo https://a/repo", "rsync+file://relative"] {
+            assert!(!matches!(Syncer::from_str(uri), Ok(Syncer::Rsync(_))), "{uri:?} didn't fail");
+        }
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("Thu, 01 Jan 1970 00:00:00 +0000"), Some(0));
+        assert_eq!(parse_timestamp("Sat, 01 Jan 2022 00:45:01 +0000\n"), Some(1640997901));
+        assert_eq!(parse_timestamp("01 Jan 2022 01:45:01 +0100"), Some(1640997901));
+        assert_eq!(parse_timestamp("Fri, 31 Dec 2021 23:45:01 -0100"), Some(1640997901));
+        assert_eq!(parse_timestamp("Tue, 29 Feb 2000 12:00:00 +0000"), Some(951825600));
+        for s in ["", "Sat, 01 Foo 2022 00:45:01 +0000", "01 Jan 2022 00:45 +0000", "01 Jan 2022"] {
+            assert!(parse_timestamp(s).is_none(), "{s:?} didn't fail");
+        }
+    }
+
+    #[test]
+    fn test_check_timestamp() {
+        let (old, new) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let write = |dir: &TempDir, s: &str| {
+            fs::create_dir_all(dir.path().join("metadata")).unwrap();
+            fs::write(dir.path().join("metadata/timestamp.chk"), s).unwrap();
+        };
+
+        // repos without timestamps are always updated
+        assert!(check_timestamp(old.path(), new.path()).unwrap());
+        write(&new, "Sat, 01 Jan 2022 00:45:01 +0000");
+        assert!(check_timestamp(old.path(), new.path()).unwrap());
+
+        // unchanged, newer, and stale repos
+        write(&old, "Sat, 01 Jan 2022 00:45:01 +0000");
+        assert!(!check_timestamp(old.path(), new.path()).unwrap());
+        write(&new, "Sun, 02 Jan 2022 00:45:01 +0000");
+        assert!(check_timestamp(old.path(), new.path()).unwrap());
+        write(&new, "Fri, 31 Dec 2021 00:45:01 +0000");
+        assert_err_re!(check_timestamp(old.path(), new.path()), "stale mirror$");
+        fs::remove_file(new.path().join("metadata/timestamp.chk")).unwrap();
+        assert_err_re!(check_timestamp(old.path(), new.path()), "missing timestamp$");
+        write(&new, "invalid");
+        assert_err_re!(check_timestamp(old.path(), new.path()), "invalid timestamp: .+: invalid$");
+    }
+
+    #[test]
+    fn test_sync() {
+        let dir = TempDir::new().unwrap();
+        let (path, staging) = (dir.path().join("repo"), dir.path().join(".repo.staging"));
+        let progress = Progress::new("repo", &|_| ());
+        let repo = syncer("rsync://a/repo");
+
+        // initial syncs don't copy from an existing repo
+        futures::executor::block_on(repo.sync(&path, &staging, None, None, &progress)).unwrap();
+        let cmd = last_command().unwrap();
+        assert_eq!(cmd[0], "rsync");
+        assert!(cmd.contains(&"--delete".to_string()));
+        assert!(!cmd.iter().any(|s| s.starts_with("--copy-dest")));
+        assert_eq!(cmd[cmd.len() - 2..], ["rsync://a/repo/", staging.to_str().unwrap()]);
+
+        // unchanged files are copied from the existing repo without sharing inodes
+        fs::create_dir(&path).unwrap();
+        futures::executor::block_on(repo.sync(&path, &staging, None, None, &progress)).unwrap();
+        let cmd = last_command().unwrap();
+        let copy_dest = format!("--copy-dest={}", path.canonicalize().unwrap().to_str().unwrap());
+        assert!(cmd.contains(&copy_dest));
+        assert!(!cmd.iter().any(|s| s.starts_with("--link-dest")));
+
+        // failover to the next mirror when rsync fails
+        fs::remove_dir_all(&path).unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(src.join("metadata")).unwrap();
+        fs::write(src.join("metadata/timestamp.chk"), "Sat, 01 Jan 2022 00:45:01 +0000").unwrap();
+        fs::write(src.join("metadata/layout.conf"), "masters =\n").unwrap();
+        let uri = format!("rsync+file://{0}/nonexistent rsync+file://{0}", src.to_str().unwrap());
+        let repo = syncer(&uri);
+        run_commands(|| {
+            let result =
+                futures::executor::block_on(repo.sync(&path, &staging, None, None, &progress));
+            match Command::new("rsync").arg("--version").output() {
+                Ok(_) => {
+                    assert!(result.unwrap());
+                    assert!(staging.join("metadata/timestamp.chk").exists());
+
+                    // modifying the existing repo leaves the synced files intact
+                    fs::rename(&staging, &path).unwrap();
+                    let timestamp = "Sun, 02 Jan 2022 00:45:01 +0000";
+                    fs::write(src.join("metadata/timestamp.chk"), timestamp).unwrap();
+                    let result = futures::executor::block_on(
+                        repo.sync(&path, &staging, None, None, &progress),
+                    );
+                    assert!(result.unwrap());
+                    fs::write(path.join("metadata/layout.conf"), "modified").unwrap();
+                    let data = fs::read_to_string(staging.join("metadata/layout.conf")).unwrap();
+                    assert_eq!(data, "masters =\n");
+                }
+                // all mirrors fail when rsync is unavailable
+                Err(_) => {
+                    assert_err_re!(result, "all mirrors failed:\n\t.+\n\t.+$");
+                }
+            }
+        });
+    }
+}
@@ -1,161 +1,577 @@
 use std::fs;
-use std::io::Write;
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-use std::time::Duration;
+use std::time::{Duration, UNIX_EPOCH};
 
 use async_trait::async_trait;
+use bzip2::read::BzDecoder;
 use flate2::read::GzDecoder;
 use futures::StreamExt;
+use md5::Md5;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use reqwest::header::{HeaderMap, HeaderValue, ETAG};
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use sha2::Sha512;
 use tar::Archive;
-use tempfile::Builder;
+use tempfile::{Builder, NamedTempFile};
+use tracing::warn;
+use xz2::read::XzDecoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
 
-use crate::sync::{Syncable, Syncer};
+use crate::files;
+use crate::sync::verify::verify_file;
+use crate::sync::{Progress, SyncPhase, Syncable, Syncer};
 use crate::{Error, Result};
 
-static HANDLED_URI_RE: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r"^tar\+(?P<url>https://.+)$").unwrap());
+static HANDLED_URI_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^tar\+(?P<url>https://[^#]+|file://(?P<path>/[^#]+))(#(?P<opts>.+))?$").unwrap()
+});
+
+/// Tarball compression, determined by file extension and defaulting to gzip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+enum Compression {
+    None,
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    fn from_name(name: &str) -> Self {
+        let ext = name.rsplit_once('.').map_or("", |(_, ext)| ext);
+        match ext {
+            "tar" => Self::None,
+            "bz2" | "tbz2" => Self::Bzip2,
+            "xz" | "txz" => Self::Xz,
+            "zst" | "tzst" => Self::Zstd,
+            _ => Self::Gzip,
+        }
+    }
+
+    /// Return the tar option selecting the decompressor.
+    fn tar_arg(&self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Gzip => Some("--gzip"),
+            Self::Bzip2 => Some("--bzip2"),
+            Self::Xz => Some("--xz"),
+            Self::Zstd => Some("--zstd"),
+        }
+    }
+}
+
+/// Checksum file published alongside a tarball.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+enum Checksum {
+    Md5,
+    Sha512,
+}
+
+impl Checksum {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "md5" => Some(Self::Md5),
+            "sha512" => Some(Self::Sha512),
+            _ => None,
+        }
+    }
+
+    /// Return the extension of the checksum file.
+    fn ext(&self) -> &'static str {
+        match self {
+            Self::Md5 => "md5sum",
+            Self::Sha512 => "sha512",
+        }
+    }
+
+    /// Calculate the checksum of a file.
+    fn digest(&self, path: &Path) -> Result<String> {
+        match self {
+            Self::Md5 => files::digest::<Md5>(path),
+            Self::Sha512 => files::digest::<Sha512>(path),
+        }
+    }
+
+    /// Verify a file against the contents of a checksum file, e.g. `<hash>  <filename>`.
+    fn verify(&self, data: &[u8], path: &Path) -> Result<()> {
+        let data = String::from_utf8_lossy(data);
+        let expected = data
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .find_map(|l| l.split_whitespace().next())
+            .map(|s| s.to_lowercase())
+            .ok_or_else(|| Error::RepoSync(format!("invalid {} file", self.ext())))?;
+        let hash = self.digest(path)?;
+        match hash == expected {
+            true => Ok(()),
+            false => Err(Error::RepoSync(format!(
+                "{} mismatch: expected {expected}, got {hash}",
+                self.ext()
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+enum Source {
+    Https(String),
+    File(PathBuf),
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub(crate) struct Repo {
     pub(crate) uri: String,
-    url: String,
+    source: Source,
+    compression: Compression,
+    checksum: Option<Checksum>,
 }
 
 #[async_trait]
 impl Syncable for Repo {
+    /// Create a syncer from a remote or local tarball URL with an optional checksum file
+    /// appended as a fragment, e.g. `tar+https://example.com/repo.tar.xz#checksum=sha512`.
     fn uri_to_syncer(uri: &str) -> Result<Syncer> {
-        match HANDLED_URI_RE.captures(uri) {
-            Some(m) => Ok(Syncer::TarHttps(Repo {
-                uri: uri.to_string(),
-                url: m.name("url").unwrap().as_str().to_string(),
-            })),
-            None => Err(Error::RepoInit(format!("invalid tar+https repo: {uri:?}"))),
+        let err = |msg: &str| Error::RepoInit(format!("invalid tar repo: {uri:?}{msg}"));
+        let m = HANDLED_URI_RE.captures(uri).ok_or_else(|| err(""))?;
+        let source = match m.name("path") {
+            Some(path) => Source::File(PathBuf::from(path.as_str())),
+            None => Source::Https(m["url"].to_string()),
+        };
+
+        let mut checksum = None;
+        for opt in m
+            .name("opts")
+            .map_or("", |m| m.as_str())
+            .split_terminator('&')
+        {
+            match opt.split_once('=') {
+                Some(("checksum", val)) => {
+                    let val = Checksum::from_name(val);
+                    checksum = Some(val.ok_or_else(|| err(&format!(": invalid checksum: {opt}")))?);
+                }
+                _ => return Err(err(&format!(": unknown option: {opt}"))),
+            }
         }
+
+        Ok(Syncer::Tar(Repo {
+            uri: uri.to_string(),
+            source,
+            compression: Compression::from_name(&m["url"]),
+            checksum,
+        }))
     }
 
-    async fn sync<P: AsRef<Path> + Send>(&self, path: P) -> Result<()> {
-        let path = path.as_ref();
+    async fn sync(
+        &self,
+        path: &Path,
+        staging: &Path,
+        keyring: Option<&Path>,
+        cache: Option<&Path>,
+        progress: &Progress,
+    ) -> Result<bool> {
         let repos_dir = path.parent().unwrap();
         let repo_name = path.file_name().unwrap().to_str().unwrap();
+        let etag_path = cache.map(|p| p.join("sync").join(format!("{repo_name}.etag")));
+        let previous_etag = etag_path.as_ref().and_then(|p| load_etag(p, path));
 
-        // use cached ETag to check if update exists
-        let etag_path = path.join(".etag");
-        let mut req_headers = HeaderMap::new();
-        if let Ok(previous_etag) = fs::read_to_string(&etag_path) {
-            if let Ok(value) = HeaderValue::from_str(&previous_etag) {
-                req_headers.insert("If-None-Match", value);
+        // remote tarballs are downloaded to a temporary file while local ones are used directly
+        let mut temp_file: Option<NamedTempFile> = None;
+        let (archive, etag) = match &self.source {
+            Source::Https(url) => {
+                let mut req_headers = HeaderMap::new();
+                if let Some(value) = previous_etag.and_then(|s| HeaderValue::from_str(&s).ok()) {
+                    req_headers.insert("If-None-Match", value);
+                }
+
+                let resp = reqwest::Client::new()
+                    .get(url)
+                    .headers(req_headers)
+                    .timeout(Duration::from_secs(5))
+                    .send()
+                    .await
+                    .map_err(|e| Error::RepoSync(e.to_string()))?
+                    .error_for_status()
+                    .map_err(|e| Error::RepoSync(e.to_string()))?;
+
+                // content is unchanged
+                if resp.status() == StatusCode::NOT_MODIFIED {
+                    return Ok(false);
+                }
+
+                let etag = resp
+                    .headers()
+                    .get(ETAG)
+                    .and_then(|v| v.to_str().ok())
+                    .map(|s| s.to_string());
+                let file = temp_file.insert(
+                    Builder::new()
+                        .suffix(&format!(".{repo_name}.tar"))
+                        .tempfile_in(&repos_dir)
+                        .map_err(|e| Error::RepoSync(e.to_string()))?,
+                );
+
+                // download tarball to tempfile
+                let mut stream = resp.bytes_stream();
+                let mut bytes = 0;
+                while let Some(item) = stream.next().await {
+                    let chunk =
+                        item.map_err(|e| Error::RepoSync(format!("failed downloading repo: {e}")))?;
+                    file.write_all(&chunk)
+                        .map_err(|e| Error::RepoSync(format!("failed writing repo: {e}")))?;
+                    bytes += chunk.len() as u64;
+                    progress.update(SyncPhase::Fetching, bytes, 0, 0);
+                }
+
+                (file.path().to_path_buf(), etag)
+            }
+            Source::File(file) => {
+                // local tarballs are identified by their modification time and size
+                let meta = fs::metadata(file)
+                    .map_err(|e| Error::RepoSync(format!("invalid tarball: {file:?}: {e}")))?;
+                let mtime = meta
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok());
+                let etag = mtime
+                    .map(|t| format!("{}.{:09}-{}", t.as_secs(), t.subsec_nanos(), meta.len()));
+                if etag.is_some() && etag == previous_etag {
+                    return Ok(false);
+                }
+                (file.clone(), etag)
             }
+        };
+
+        // verify the tarball before unpacking anything from it
+        if let Some(checksum) = &self.checksum {
+            let data = self
+                .fetch(checksum.ext())
+                .await?
+                .ok_or_else(|| Error::RepoSync(format!("missing {} file", checksum.ext())))?;
+            checksum.verify(&data, &archive)?;
+        }
+        if let Some(keyring) = keyring {
+            let signature = self.signature().await?;
+            verify_file(keyring, &signature, &archive)?;
         }
 
-        let resp = reqwest::Client::new()
-            .get(&self.url)
-            .headers(req_headers)
-            .timeout(Duration::from_secs(5))
-            .send()
-            .await
-            .map_err(|e| Error::RepoSync(e.to_string()))?
-            .error_for_status()
-            .map_err(|e| Error::RepoSync(e.to_string()))?;
+        progress.phase(SyncPhase::Unpacking);
+        fs::create_dir_all(staging)
+            .map_err(|e| Error::RepoSync(format!("failed creating {staging:?}: {e}")))?;
+        self.unpack(&archive, staging)?;
+        drop(temp_file);
 
-        // content is unchanged
-        if resp.status().as_u16() == 304 {
-            return Ok(());
+        if let (Some(etag_path), Some(etag)) = (etag_path, etag) {
+            save_etag(&etag_path, staging, &etag)?;
         }
 
-        // Clone headers used to later extract ETAG data since streaming the response body consumes
-        // the response object.
-        let resp_headers = &resp.headers().clone();
-
-        // create tempfile
-        let mut temp_file = Builder::new()
-            .suffix(&format!(".{repo_name}.tar.gz"))
-            .tempfile_in(&repos_dir)
-            .map_err(|e| Error::RepoSync(e.to_string()))?;
-
-        // download tarball to tempfile
-        let mut stream = resp.bytes_stream();
-        while let Some(item) = stream.next().await {
-            let chunk =
-                item.map_err(|e| Error::RepoSync(format!("failed downloading repo: {e}")))?;
-            temp_file
-                .write(&chunk)
-                .map_err(|e| Error::RepoSync(format!("failed writing repo: {e}")))?;
-        }
-
-        // unpack repo data to tempdir
-        let tmp_dir = Builder::new()
-            .suffix(&format!(".{repo_name}.update"))
-            .tempdir_in(&repos_dir)
-            .map_err(|e| Error::RepoSync(e.to_string()))?;
-        let tmp_dir_old = Builder::new()
-            .suffix(&format!(".{repo_name}.old"))
-            .tempdir_in(&repos_dir)
-            .map_err(|e| Error::RepoSync(e.to_string()))?;
+        Ok(true)
+    }
+}
 
+impl Repo {
+    /// Fetch a small file published alongside the tarball, returning None if it doesn't exist.
+    async fn fetch(&self, ext: &str) -> Result<Option<Vec<u8>>> {
+        match &self.source {
+            Source::Https(url) => {
+                let resp = reqwest::Client::new()
+                    .get(format!("{url}.{ext}"))
+                    .timeout(Duration::from_secs(5))
+                    .send()
+                    .await
+                    .map_err(|e| Error::RepoSync(e.to_string()))?;
+                if !resp.status().is_success() {
+                    return Ok(None);
+                }
+                let data = resp
+                    .bytes()
+                    .await
+                    .map_err(|e| Error::RepoSync(format!("failed downloading {ext} file: {e}")))?;
+                Ok(Some(data.to_vec()))
+            }
+            Source::File(path) => {
+                let mut path = path.clone().into_os_string();
+                path.push(format!(".{ext}"));
+                match fs::read(&path) {
+                    Ok(data) => Ok(Some(data)),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(Error::RepoSync(format!("failed reading {path:?}: {e}"))),
+                }
+            }
+        }
+    }
+
+    /// Fetch the detached signature for the tarball, trying common file extensions in order.
+    async fn signature(&self) -> Result<Vec<u8>> {
+        for ext in ["gpgsig", "asc"] {
+            if let Some(data) = self.fetch(ext).await? {
+                return Ok(data);
+            }
+        }
+        Err(Error::RepoSync(format!("missing signature: {}", self.uri)))
+    }
+
+    /// Unpack a tarball into a directory, dropping the top-level directory of its entries.
+    fn unpack(&self, archive: &Path, dest: &Path) -> Result<()> {
         // try unpacking via tar first since it's a lot faster for large repos
-        let tar_unpack = Command::new("tar")
-            .args(&[
-                "--extract",
-                "--gzip",
-                "-f",
-                temp_file.path().to_str().unwrap(),
-                "--strip-components=1",
-                "--no-same-owner",
-                "-C",
-                tmp_dir.path().to_str().unwrap(),
-            ])
-            .stderr(Stdio::null())
-            .status();
+        let mut cmd = Command::new("tar");
+        cmd.arg("--extract");
+        cmd.args(self.compression.tar_arg());
+        cmd.arg("-f")
+            .arg(archive)
+            .args(["--strip-components=1", "--no-same-owner", "-C"])
+            .arg(dest)
+            .stderr(Stdio::null());
+        if matches!(cmd.status(), Ok(s) if s.success()) {
+            return Ok(());
+        }
 
         // fallback to built-in support on tar failure
-        if tar_unpack.is_err() || !tar_unpack.unwrap().success() {
-            let tar_file =
-                fs::File::open(temp_file.path()).map_err(|e| Error::RepoSync(e.to_string()))?;
-            // TODO: run decompression in a separate thread
-            let mut archive = Archive::new(GzDecoder::new(tar_file));
-            archive
-                .entries()
-                .map_err(|e| Error::RepoSync(e.to_string()))?
-                .filter_map(|e| e.ok())
-                .map(|mut entry| -> Result<PathBuf> {
-                    // drop first directory component in archive paths
-                    let stripped_path: PathBuf = entry
-                        .path()
-                        .map_err(|e| Error::RepoSync(format!("failed unpacking archive: {e}")))
-                        .iter()
-                        .skip(1)
-                        .collect();
-                    entry
-                        .unpack(&tmp_dir.path().join(&stripped_path))
-                        .map_err(|e| Error::RepoSync(format!("failed unpacking archive: {e}")))?;
-                    Ok(stripped_path)
-                })
-                .filter_map(|e| e.ok())
-                .for_each(drop);
-        }
-
-        // move old repo out of the way if it exists and replace with unpacked repo
-        if path.exists() {
-            fs::rename(&path, &tmp_dir_old).map_err(|e| {
-                Error::RepoSync(format!("failed moving old repo {path:?} -> {tmp_dir_old:?}: {e}"))
-            })?;
-        }
-        fs::rename(&tmp_dir, &path).map_err(|e| {
-            Error::RepoSync(format!("failed moving repo {tmp_dir:?} -> {path:?}: {e}"))
-        })?;
-
-        // TODO: store this in cache instead of repo file
-        // update cached ETag value
-        if let Some(etag) = resp_headers.get(ETAG) {
-            fs::write(&etag_path, etag.as_bytes())
-                .map_err(|e| Error::RepoSync(format!("failed writing etag {etag_path:?}: {e}")))?;
-        }
+        self.unpack_builtin(archive, dest)
+    }
 
+    /// Unpack a tarball into a directory using built-in decompression support.
+    fn unpack_builtin(&self, archive: &Path, dest: &Path) -> Result<()> {
+        let err = |e: io::Error| Error::RepoSync(format!("failed unpacking archive: {e}"));
+        let file = fs::File::open(archive).map_err(err)?;
+        let reader: Box<dyn io::Read> = match self.compression {
+            Compression::None => Box::new(file),
+            Compression::Gzip => Box::new(GzDecoder::new(file)),
+            Compression::Bzip2 => Box::new(BzDecoder::new(file)),
+            Compression::Xz => Box::new(XzDecoder::new(file)),
+            Compression::Zstd => Box::new(ZstdDecoder::new(file).map_err(err)?),
+        };
+        // TODO: run decompression in a separate thread
+        let mut archive = Archive::new(reader);
+        for entry in archive.entries().map_err(err)? {
+            let mut entry = entry.map_err(err)?;
+            // drop first directory component in archive paths
+            let stripped_path: PathBuf = entry.path().map_err(err)?.iter().skip(1).collect();
+            entry.unpack(dest.join(&stripped_path)).map_err(err)?;
+        }
         Ok(())
     }
 }
+
+/// Load the cached ETag for a repo if it was recorded for the repo currently at the path.
+///
+/// The repo directory's inode is stored alongside the ETag since synced repos are moved into
+/// place, so failed syncs and rollbacks invalidate it.
+fn load_etag(etag_path: &Path, path: &Path) -> Option<String> {
+    let data = fs::read_to_string(etag_path).ok()?;
+    let (ino, etag) = data.trim_end().split_once(' ')?;
+    let current = fs::metadata(path).ok()?.ino();
+    match ino.parse::<u64>().ok()? == current {
+        true => Some(etag.to_string()),
+        false => None,
+    }
+}
+
+fn save_etag(etag_path: &Path, staging: &Path, etag: &str) -> Result<()> {
+    let ino = fs::metadata(staging)
+        .map_err(|e| Error::RepoSync(format!("invalid staging dir {staging:?}: {e}")))?
+        .ino();
+    let dir = etag_path.parent().unwrap();
+    let result =
+        fs::create_dir_all(dir).and_then(|_| fs::write(etag_path, format!("{ino} {etag}")));
+    if let Err(e) = result {
+        // failing to cache the ETag only causes the next sync to redownload the tarball
+        warn!("failed writing etag {etag_path:?}: {e}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use tempfile::TempDir;
+
+    use crate::macros::assert_err_re;
+    use crate::repo::ebuild::TempRepo;
+    use crate::sync::sibling;
+    use crate::sync::verify::tests::Key;
+
+    use super::*;
+
+    /// Create a tarball of a repo containing the given package, compressed according to its
+    /// file extension.
+    fn create_tarball(dir: &Path, name: &str, cpv: &str) -> PathBuf {
+        let t = TempRepo::new("repo", Some(dir), None).unwrap();
+        t.create_ebuild(cpv, None).unwrap();
+        let tarball = dir.join(name);
+        let status = Command::new("tar")
+            .arg("--auto-compress")
+            .arg("-cf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(dir)
+            .arg(t.repo.path().file_name().unwrap())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        tarball
+    }
+
+    fn sync(uri: &str, path: &Path, keyring: Option<&Path>, cache: Option<&Path>) -> Result<bool> {
+        let syncer = Syncer::from_str(uri).unwrap();
+        let progress = Progress::new("repo", &|_| ());
+        syncer.sync(path, "ebuild", keyring, cache, &progress)
+    }
+
+    #[test]
+    fn test_uri() {
+        for (uri, source, compression, checksum) in [
+            (
+                "tar+https://a.com/repo.tar.xz",
+                Source::Https("https://a.com/repo.tar.xz".to_string()),
+                Compression::Xz,
+                None,
+            ),
+            (
+                "tar+https://a.com/tarball/master#checksum=md5",
+                Source::Https("https://a.com/tarball/master".to_string()),
+                Compression::Gzip,
+                Some(Checksum::Md5),
+            ),
+            (
+                "tar+file:///snapshots/repo.tar.zst#checksum=sha512",
+                Source::File(PathBuf::from("/snapshots/repo.tar.zst")),
+                Compression::Zstd,
+                Some(Checksum::Sha512),
+            ),
+            (
+                "tar+file:///snapshots/repo.tbz2",
+                Source::File(PathBuf::from("/snapshots/repo.tbz2")),
+                Compression::Bzip2,
+                None,
+            ),
+        ] {
+            match Syncer::from_str(uri) {
+                Ok(Syncer::Tar(repo)) => {
+                    assert_eq!(repo.uri, uri);
+                    assert_eq!(repo.source, source);
+                    assert_eq!(repo.compression, compression);
+                    assert_eq!(repo.checksum, checksum);
+                }
+                r => panic!("{uri:?} failed: {r:?}"),
+            }
+        }
+
+        for (uri, err) in [
+            ("tar+https://a.com/repo.tar.xz#checksum=sha1", "invalid checksum: checksum=sha1$"),
+            ("tar+https://a.com/repo.tar.xz#foo", "unknown option: foo$"),
+            ("tar+file://repo.tar", "invalid tar repo: \"tar\\+file://repo.tar\"$"),
+            ("tar+http://a.com/repo.tar", "invalid tar repo: "),
+        ] {
+            assert_err_re!(Repo::uri_to_syncer(uri), err);
+        }
+    }
+
+    #[test]
+    fn test_compression() {
+        let dir = TempDir::new().unwrap();
+        for ext in ["tar", "tar.gz", "tar.bz2", "tar.xz", "tar.zst"] {
+            let tarball = create_tarball(dir.path(), &format!("repo.{ext}"), "cat/pkg-1");
+            let path = dir.path().join(ext);
+            sync(&format!("tar+file://{}", tarball.display()), &path, None, None).unwrap();
+            assert!(path.join("cat/pkg/pkg-1.ebuild").exists(), "{ext} failed");
+        }
+    }
+
+    #[test]
+    fn test_unpack_builtin() {
+        let dir = TempDir::new().unwrap();
+        for ext in ["tar", "tar.gz", "tar.bz2", "tar.xz", "tar.zst"] {
+            let name = format!("repo.{ext}");
+            let tarball = create_tarball(dir.path(), &name, "cat/pkg-1");
+            let uri = format!("tar+file://{}", tarball.display());
+            let repo = match Syncer::from_str(&uri) {
+                Ok(Syncer::Tar(repo)) => repo,
+                r => panic!("{uri:?} failed: {r:?}"),
+            };
+            let path = dir.path().join(ext);
+            fs::create_dir(&path).unwrap();
+            repo.unpack_builtin(&tarball, &path).unwrap();
+            assert!(path.join("cat/pkg/pkg-1.ebuild").exists(), "{ext} failed");
+        }
+    }
+
+    #[test]
+    fn test_etag() {
+        let dir = TempDir::new().unwrap();
+        let cache = TempDir::new().unwrap();
+        let tarball = create_tarball(dir.path(), "repo.tar.xz", "cat/pkg-1");
+        let uri = format!("tar+file://{}", tarball.display());
+        let path = dir.path().join("repo");
+        let prev = sibling(&path, "prev");
+        let etag_path = cache.path().join("sync/repo.etag");
+
+        sync(&uri, &path, None, Some(cache.path())).unwrap();
+        assert!(etag_path.exists());
+        assert!(!path.join(".etag").exists());
+
+        // unchanged tarballs aren't unpacked again
+        sync(&uri, &path, None, Some(cache.path())).unwrap();
+        assert!(!prev.exists());
+
+        // updated tarballs are
+        create_tarball(dir.path(), "repo.tar.xz", "cat/pkg-2");
+        sync(&uri, &path, None, Some(cache.path())).unwrap();
+        assert!(path.join("cat/pkg/pkg-2.ebuild").exists());
+        assert!(prev.join("cat/pkg/pkg-1.ebuild").exists());
+
+        // rollbacks invalidate the cached ETag
+        crate::sync::rollback(&path).unwrap();
+        assert!(path.join("cat/pkg/pkg-1.ebuild").exists());
+        sync(&uri, &path, None, Some(cache.path())).unwrap();
+        assert!(path.join("cat/pkg/pkg-2.ebuild").exists());
+    }
+
+    #[test]
+    fn test_verify() {
+        let dir = TempDir::new().unwrap();
+        let tarball = create_tarball(dir.path(), "repo.tar.gz", "cat/pkg-1");
+        let path = dir.path().join("repo");
+        let file = |ext: &str| PathBuf::from(format!("{}.{ext}", tarball.display()));
+
+        // checksums
+        let empty = dir.path().join("empty");
+        fs::write(&empty, "").unwrap();
+        let md5 = Checksum::Md5.digest(&empty).unwrap();
+        assert_eq!(md5, "d41d8cd98f00b204e9800998ecf8427e");
+        let sha512 = Checksum::Sha512.digest(&empty).unwrap();
+        assert!(sha512.starts_with("cf83e1357eefb8bd"));
+        for (checksum, ext) in [("sha512", "sha512"), ("md5", "md5sum")] {
+            let uri = format!("tar+file://{}#checksum={checksum}", tarball.display());
+            assert_err_re!(sync(&uri, &path, None, None), format!("missing {ext} file$"));
+            fs::write(file(ext), "0123456789abcdef  repo.tar.gz\n").unwrap();
+            assert_err_re!(sync(&uri, &path, None, None), format!("{ext} mismatch: "));
+            assert!(!path.exists());
+            let hash = Checksum::from_name(checksum)
+                .unwrap()
+                .digest(&tarball)
+                .unwrap();
+            fs::write(file(ext), format!("# {checksum} hash\n{hash}  repo.tar.gz\n")).unwrap();
+            sync(&uri, &path, None, None).unwrap();
+            assert!(path.join("cat/pkg/pkg-1.ebuild").exists());
+            fs::remove_dir_all(&path).unwrap();
+        }
+
+        // signatures
+        let key = Key::new();
+        let uri = format!("tar+file://{}", tarball.display());
+        assert_err_re!(sync(&uri, &path, Some(&key.keyring), None), "missing signature: ");
+        let other = Key::new();
+        fs::write(file("asc"), other.sign(&fs::read(&tarball).unwrap())).unwrap();
+        assert_err_re!(sync(&uri, &path, Some(&key.keyring), None), "ERRSIG ");
+        assert!(!path.exists());
+        fs::write(file("asc"), key.sign(&fs::read(&tarball).unwrap())).unwrap();
+        sync(&uri, &path, Some(&key.keyring), None).unwrap();
+        assert!(path.join("cat/pkg/pkg-1.ebuild").exists());
+    }
+}
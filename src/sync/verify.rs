@@ -0,0 +1,150 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use tempfile::TempDir;
+
+use crate::{Error, Result};
+
+/// Verify a detached OpenPGP signature for a file using the keys from a given keyring, either
+/// armored or binary.
+///
+/// The keyring is imported into a temporary GnuPG home so the user's keys and trust settings
+/// are never consulted.
+pub(super) fn verify_file(keyring: &Path, signature: &[u8], data: &Path) -> Result<()> {
+    let err = |msg: String| Error::RepoSync(format!("failed verifying signature: {msg}"));
+    let home = TempDir::new().map_err(|e| err(format!("failed creating gpg home: {e}")))?;
+    let gpg = || {
+        let mut cmd = Command::new("gpg");
+        cmd.arg("--homedir")
+            .arg(home.path())
+            .args(["--batch", "--no-tty", "--quiet"])
+            .stdin(Stdio::null());
+        cmd
+    };
+
+    let output = gpg()
+        .arg("--import")
+        .arg(keyring)
+        .output()
+        .map_err(|e| err(format!("failed running gpg: {e}")))?;
+    if !output.status.success() {
+        return Err(err(format!("invalid keyring: {keyring:?}")));
+    }
+
+    let sig_path = home.path().join("signature");
+    fs::write(&sig_path, signature).map_err(|e| err(format!("failed writing signature: {e}")))?;
+    let output = gpg()
+        .args(["--status-fd", "1", "--verify"])
+        .arg(&sig_path)
+        .arg(data)
+        .output()
+        .map_err(|e| err(format!("failed running gpg: {e}")))?;
+
+    // require a valid signature from a known key, not just a well-formed one
+    let status = String::from_utf8_lossy(&output.stdout);
+    let valid = status.lines().any(|l| l.starts_with("[GNUPG:] VALIDSIG "));
+    match output.status.success() && valid {
+        true => Ok(()),
+        false => {
+            let reason = status
+                .lines()
+                .filter_map(|l| l.strip_prefix("[GNUPG:] "))
+                .find(|l| {
+                    ["BADSIG", "ERRSIG", "NODATA", "EXPSIG", "REVKEYSIG"]
+                        .iter()
+                        .any(|s| l.starts_with(s))
+                })
+                .unwrap_or("invalid signature");
+            Err(err(reason.to_string()))
+        }
+    }
+}
+
+/// Verify a detached OpenPGP signature for in-memory data.
+#[cfg(feature = "git")]
+pub(super) fn verify(keyring: &Path, signature: &[u8], data: &[u8]) -> Result<()> {
+    let dir =
+        TempDir::new().map_err(|e| Error::RepoSync(format!("failed verifying signature: {e}")))?;
+    let path = dir.path().join("data");
+    fs::write(&path, data)
+        .map_err(|e| Error::RepoSync(format!("failed verifying signature: {e}")))?;
+    verify_file(keyring, signature, &path)
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::path::PathBuf;
+
+    use crate::macros::assert_err_re;
+
+    use super::*;
+
+    /// Signing key for tests with its exported public keyring.
+    pub(crate) struct Key {
+        home: TempDir,
+        pub(crate) keyring: PathBuf,
+    }
+
+    impl Key {
+        /// Generate a key, panicking if gpg is unavailable since signature tests require it.
+        pub(crate) fn new() -> Self {
+            let home = TempDir::new().unwrap();
+            let gpg = |args: &[&str]| {
+                let output = Command::new("gpg")
+                    .arg("--homedir")
+                    .arg(home.path())
+                    .args(["--batch", "--quiet", "--passphrase", ""])
+                    .args(args)
+                    .output()
+                    .unwrap_or_else(|e| panic!("gpg is required for signature tests: {e}"));
+                assert!(output.status.success(), "gpg {args:?} failed: {output:?}");
+                output
+            };
+            gpg(&["--quick-gen-key", "test <test@example.com>", "ed25519", "sign", "never"]);
+            let keyring = home.path().join("keyring.asc");
+            let key = gpg(&["--armor", "--export", "test@example.com"]);
+            fs::write(&keyring, key.stdout).unwrap();
+            Key { home, keyring }
+        }
+
+        /// Return an armored detached signature for the given data.
+        pub(crate) fn sign(&self, data: &[u8]) -> Vec<u8> {
+            let path = self.home.path().join("data");
+            fs::write(&path, data).unwrap();
+            let output = Command::new("gpg")
+                .arg("--homedir")
+                .arg(self.home.path())
+                .args(["--batch", "--quiet", "--passphrase", "", "--armor", "--detach-sign"])
+                .args(["--output", "-"])
+                .arg(&path)
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            output.stdout
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let key = Key::new();
+        let dir = TempDir::new().unwrap();
+        let data = dir.path().join("data");
+        fs::write(&data, "data").unwrap();
+        let sig = key.sign(b"data");
+        verify_file(&key.keyring, &sig, &data).unwrap();
+
+        // modified data
+        fs::write(&data, "modified").unwrap();
+        assert_err_re!(verify_file(&key.keyring, &sig, &data), "BADSIG ");
+
+        // unknown key
+        let other = Key::new();
+        fs::write(&data, "data").unwrap();
+        assert_err_re!(verify_file(&other.keyring, &sig, &data), "ERRSIG ");
+
+        // invalid signature and keyring
+        assert_err_re!(verify_file(&key.keyring, b"sig", &data), "NODATA ");
+        assert_err_re!(verify_file(&data, &sig, &data), "invalid keyring: ");
+    }
+}